@@ -0,0 +1,131 @@
+//! Abandon helper functions
+
+use std::io::{Read, Write};
+
+use protocol::ber::common;
+use protocol::ber::types::encode_integer;
+use protocol::error::LDAPError;
+
+use LDAP;
+use Result;
+
+impl<S: Read + Write> LDAP<S>
+{
+    /// Ask the server to stop processing the operation sent with `message_id`
+    ///
+    /// Like unbind there is no response, the server simply stops sending results for the
+    /// abandoned operation. Message IDs that haven't been assigned yet are rejected with
+    /// `UnknownMessageId`.
+    ///
+    /// Responses to the operation that were already received or arrive before the server got
+    /// the AbandonRequest are dropped.
+    pub fn abandon(&mut self, message_id: i32) -> Result<()>
+    {
+        if message_id < 1 || (message_id > self.msgid && !self.msgid_wrapped)
+        {
+            return Err(LDAPError::UnknownMessageId(message_id));
+        }
+
+        try!(self.send(build_abandon_request(message_id), &[]));
+        try!(self.stream.flush());
+
+        self.pending.retain(|&(id, _)| id != message_id);
+        if !self.abandoned.contains(&message_id)
+        {
+            self.abandoned.push(message_id);
+        }
+
+        Ok(())
+    }
+}
+
+fn build_abandon_request(message_id: i32) -> common::Tag
+{
+    // AbandonRequest ::= [APPLICATION 16] MessageID, the INTEGER is the tag itself
+    let class = common::Class::Application(16);
+    let pl = common::Payload::Primitive(encode_integer(message_id as i64));
+
+    common::construct(class, pl)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use protocol::ber::{self, common};
+    use protocol::error::LDAPError;
+
+    use mock::MockStream;
+    use LDAP;
+
+    #[test]
+    fn abandon_request_layout()
+    {
+        let tag = super::build_abandon_request(300);
+
+        assert_eq!(tag._type.class, common::Class::Application(16));
+        assert_eq!(tag._type.structure, common::Structure::Primitive);
+        assert_eq!(tag._value, common::Payload::Primitive(vec![0x01, 0x2c]));
+
+        let mut ld = LDAP::from_stream(MockStream::new(Vec::new()));
+        ld.msgid = 4;
+        ld.abandon(3).unwrap();
+
+        // Sent as message 5, abandoning message 3
+        assert_eq!(ld.stream.output, vec![0x30, 0x06, 0x02, 0x01, 0x05, 0x50, 0x01, 0x03]);
+    }
+
+    #[test]
+    fn abandon_rejects_unknown_ids()
+    {
+        let mut ld = LDAP::from_stream(MockStream::new(Vec::new()));
+        ld.msgid = 4;
+
+        for &id in &[0, 5, -1]
+        {
+            match ld.abandon(id)
+            {
+                Err(LDAPError::UnknownMessageId(x)) => assert_eq!(x, id),
+                x => panic!("Expected UnknownMessageId, got {:?}", x),
+            }
+        }
+
+        assert!(ld.stream.output.is_empty());
+    }
+
+    #[test]
+    fn abandon_after_msgid_wraparound()
+    {
+        let mut ld = LDAP::from_stream(MockStream::new(Vec::new()));
+        ld.msgid = i32::max_value() - 1;
+
+        // The operation sent last before the IDs wrapped is still running
+        let last = ld.next_msgid();
+        ld.next_msgid();
+        ld.abandon(last).unwrap();
+
+        assert_eq!(ld.abandoned, vec![i32::max_value()]);
+    }
+
+    #[test]
+    fn abandon_drops_responses()
+    {
+        let op = |id| {
+            let class = common::Class::Application(id);
+            let pl = common::Payload::Primitive(Vec::new());
+
+            common::construct(class, pl)
+        };
+
+        // A late response to message 1 and the response to message 2
+        let mut input = ber::encode(op(4), 1).unwrap();
+        input.extend(ber::encode(op(7), 2).unwrap());
+
+        let mut ld = LDAP::from_stream(MockStream::new(input));
+        ld.msgid = 2;
+        ld.pending.push_back((1, ber::decode(&ber::encode(op(4), 1).unwrap()).unwrap()));
+        ld.abandon(1).unwrap();
+
+        assert!(ld.pending.is_empty());
+        assert_eq!(ld.recv().unwrap(), ber::decode(&ber::encode(op(7), 2).unwrap()).unwrap());
+    }
+}
@@ -0,0 +1,175 @@
+//! Add helper functions
+
+use std::io::{Read, Write};
+
+use protocol::ber::common;
+use protocol::control::Control;
+use protocol::error::{LDAPError, LdapError};
+use protocol::result::ResultCode;
+use protocol::schema::Schema;
+
+use build_attribute;
+use LDAP;
+use Result;
+
+impl<S: Read + Write> LDAP<S>
+{
+    /// Create the entry `dn` with the given attributes
+    ///
+    /// Every attribute needs at least one value, otherwise `EmptyAttribute` is returned
+    /// without contacting the server. Values are raw bytes so binary attributes like jpegPhoto
+    /// can be added as well. A resultCode other than success is returned as a `Server` error.
+    pub fn add(&mut self, dn: &str, attrs: Vec<(&str, Vec<&[u8]>)>, controls: &[Control]) -> Result<ResultCode>
+    {
+        if let Some(&(attr, _)) = attrs.iter().find(|&&(_, ref values)| values.is_empty())
+        {
+            return Err(LDAPError::EmptyAttribute(attr.to_string()));
+        }
+
+        let result = try!(self.exchange(build_add_request(dn, &attrs), controls, 9));
+
+        match result.result_code
+        {
+            ResultCode::success => Ok(result.result_code),
+            _ => Err(LDAPError::from(LdapError::from(result))),
+        }
+    }
+
+    /// Like `add`, but first checks the entry against the object classes it lists in `schema`
+    ///
+    /// An entry missing a MUST attribute or having one its classes don't allow fails with a
+    /// `ContentRule` error without contacting the server, see `Schema::check_entry`.
+    pub fn add_validated(&mut self, schema: &Schema, dn: &str, attrs: Vec<(&str, Vec<&[u8]>)>, controls: &[Control])
+        -> Result<ResultCode>
+    {
+        {
+            let classes: Vec<String> = attrs.iter()
+                .filter(|&&(attr, _)| attr.eq_ignore_ascii_case("objectClass"))
+                .flat_map(|&(_, ref values)| values.iter().map(|value| String::from_utf8_lossy(value).into_owned()))
+                .collect();
+            let classes: Vec<&str> = classes.iter().map(|class| &class[..]).collect();
+            let names: Vec<&str> = attrs.iter().map(|&(attr, _)| attr).collect();
+
+            try!(schema.check_entry(&classes, &names));
+        }
+
+        self.add(dn, attrs, controls)
+    }
+}
+
+fn build_add_request(dn: &str, attrs: &[(&str, Vec<&[u8]>)]) -> common::Tag
+{
+    let entrytag = {
+        let class = common::Class::Universal(common::UniversalTypes::OctetString);
+        let pl = common::Payload::Primitive(dn.as_bytes().to_vec());
+
+        common::construct(class, pl)
+    };
+
+    let attrstag = {
+        let attrs = attrs.iter().map(|&(attr, ref values)| build_attribute(attr, values.iter().cloned())).collect();
+
+        let class = common::Class::Universal(common::UniversalTypes::Sequence);
+        let pl = common::Payload::Constructed(attrs);
+
+        common::construct(class, pl)
+    };
+
+    let class = common::Class::Application(8);
+    let pl = common::Payload::Constructed(vec![entrytag, attrstag]);
+
+    common::construct(class, pl)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use protocol::error::LDAPError;
+    use protocol::result::ResultCode;
+    use protocol::schema::{ObjectClass, Schema};
+
+    use mock::MockStream;
+    use LDAP;
+
+    #[test]
+    fn add_inet_org_person()
+    {
+        // AddResponse: success
+        let response = vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x69, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00];
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        let attrs: Vec<(&str, Vec<&[u8]>)> = vec![
+            ("objectClass", vec![b"inetOrgPerson"]),
+            ("cn", vec![b"alice"]),
+            ("sn", vec![b"Liddell"]),
+            ("jpegPhoto", vec![&[0xff, 0xd8, 0xff]]),
+        ];
+        let code = ld.add("cn=alice,dc=example,dc=com", attrs, &[]).unwrap();
+
+        assert_eq!(code, ResultCode::success);
+
+        let expected = [
+            0x68, 0x72, 0x04, 0x1a, 0x63, 0x6e, 0x3d, 0x61, 0x6c, 0x69, 0x63, 0x65, 0x2c, 0x64, 0x63, 0x3d,
+            0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2c, 0x64, 0x63, 0x3d, 0x63, 0x6f, 0x6d, 0x30, 0x54,
+            // objectClass: inetOrgPerson
+            0x30, 0x1e, 0x04, 0x0b, 0x6f, 0x62, 0x6a, 0x65, 0x63, 0x74, 0x43, 0x6c, 0x61, 0x73, 0x73, 0x31,
+            0x0f, 0x04, 0x0d, 0x69, 0x6e, 0x65, 0x74, 0x4f, 0x72, 0x67, 0x50, 0x65, 0x72, 0x73, 0x6f, 0x6e,
+            // cn: alice
+            0x30, 0x0d, 0x04, 0x02, 0x63, 0x6e, 0x31, 0x07, 0x04, 0x05, 0x61, 0x6c, 0x69, 0x63, 0x65,
+            // sn: Liddell
+            0x30, 0x0f, 0x04, 0x02, 0x73, 0x6e, 0x31, 0x09, 0x04, 0x07, 0x4c, 0x69, 0x64, 0x64, 0x65, 0x6c,
+            0x6c,
+            // jpegPhoto
+            0x30, 0x12, 0x04, 0x09, 0x6a, 0x70, 0x65, 0x67, 0x50, 0x68, 0x6f, 0x74, 0x6f, 0x31, 0x05, 0x04,
+            0x03, 0xff, 0xd8, 0xff,
+        ];
+
+        assert!(ld.stream.output.ends_with(&expected));
+    }
+
+    #[test]
+    fn add_rejects_empty_value_sets()
+    {
+        let mut ld = LDAP::from_stream(MockStream::new(Vec::new()));
+        let attrs: Vec<(&str, Vec<&[u8]>)> = vec![("objectClass", vec![b"person"]), ("sn", vec![])];
+
+        match ld.add("cn=alice,dc=example,dc=com", attrs, &[])
+        {
+            Err(LDAPError::EmptyAttribute(ref attr)) if attr == "sn" => {},
+            x => panic!("Expected EmptyAttribute, got {:?}", x),
+        }
+
+        // Nothing may have been sent
+        assert!(ld.stream.output.is_empty());
+    }
+
+    #[test]
+    fn add_validated_checks_required_attributes()
+    {
+        let schema = Schema
+        {
+            object_classes: vec![
+                ObjectClass::parse("( 2.5.6.0 NAME 'top' ABSTRACT MUST objectClass )").unwrap(),
+                ObjectClass::parse("( 2.5.6.6 NAME 'person' SUP top STRUCTURAL MUST ( sn $ cn ) MAY description )").unwrap(),
+            ],
+            ..Schema::default()
+        };
+
+        let mut ld = LDAP::from_stream(MockStream::new(Vec::new()));
+        let attrs: Vec<(&str, Vec<&[u8]>)> = vec![("objectClass", vec![b"person"]), ("cn", vec![b"alice"])];
+
+        match ld.add_validated(&schema, "cn=alice,dc=example,dc=com", attrs, &[])
+        {
+            Err(LDAPError::ContentRule { ref attr, .. }) if attr == "sn" => {},
+            x => panic!("Expected ContentRule, got {:?}", x),
+        }
+        assert!(ld.stream.output.is_empty());
+
+        // AddResponse: success
+        let response = vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x69, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00];
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        let attrs: Vec<(&str, Vec<&[u8]>)> = vec![("objectClass", vec![b"person"]), ("cn", vec![b"alice"]), ("sn", vec![b"Liddell"])];
+
+        assert_eq!(ld.add_validated(&schema, "cn=alice,dc=example,dc=com", attrs, &[]).unwrap(), ResultCode::success);
+    }
+}
@@ -0,0 +1,386 @@
+//! Non-blocking client on top of tokio
+//!
+//! Only available with the `tokio` feature. `AsyncLDAP` mirrors `LDAP`, but its operations
+//! return futures instead of blocking. Messages are encoded and decoded exactly like for the
+//! blocking client, only the transport differs.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+use protocol;
+use protocol::ber::{self, common};
+use protocol::ber::error::ASN1Error;
+use protocol::error::LDAPError;
+
+use extended;
+use Result;
+use DEFAULT_MAX_MESSAGE_SIZE;
+
+/// Asynchronous counterpart of `LDAP`
+///
+/// Only one operation can be in flight at a time, as every operation future borrows the
+/// connection mutably until the response arrived.
+#[derive(Debug)]
+pub struct AsyncLDAP<S = TcpStream>
+{
+    stream: S,
+
+    msgid: i32,
+
+    /// Bytes read from the stream that aren't a complete message yet
+    buf: Vec<u8>,
+
+    /// Largest message accepted from the server
+    max_message_size: usize,
+
+    /// Unsolicited notifications received and not taken yet, oldest first
+    notifications: VecDeque<extended::Notification>,
+}
+
+impl AsyncLDAP<TcpStream>
+{
+    /// Connect to the LDAP-Server found at `addr` using plain unencrypted TCP
+    pub fn connect<A: ToSocketAddrs + Send + 'static>(addr: A) -> Connect
+    {
+        Connect { inner: Box::pin(TcpStream::connect(addr)) }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncLDAP<S>
+{
+    /// Use an already established `stream` as transport for the LDAP connection
+    pub fn from_stream(stream: S) -> AsyncLDAP<S>
+    {
+        AsyncLDAP
+        {
+            stream: stream,
+            msgid: 0,
+            buf: Vec::new(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            notifications: VecDeque::new(),
+        }
+    }
+
+    /// Unsolicited notifications the server sent so far, oldest first
+    ///
+    /// Same as `LDAP::take_notifications`, a Notice of Disconnection fails the pending
+    /// request with the `Server` error the notice carries.
+    pub fn take_notifications(&mut self) -> Vec<extended::Notification>
+    {
+        self.notifications.drain(..).collect()
+    }
+
+    /// Refuse messages from the server bigger than `max` bytes
+    ///
+    /// Defaults to `DEFAULT_MAX_MESSAGE_SIZE`. A message over the limit fails the request
+    /// with `SizeLimitExceeded` before its content is read.
+    pub fn set_max_message_size(&mut self, max: usize)
+    {
+        self.max_message_size = max;
+    }
+
+    /// Send `op` as protocolOp of a new LDAPMessage and have `parse` turn the protocolOp of
+    /// the response into the result of the future.
+    pub(crate) fn request<'a, T>(&'a mut self, op: common::Tag, parse: fn(common::Tag) -> Result<T>) -> Request<'a, S, T>
+    {
+        let msgid = self.next_msgid();
+
+        // Encoding into memory can't really fail, but don't lose the error if it does
+        let (out, error) = match ber::encode(op, msgid)
+        {
+            Ok(out) => (out, None),
+            Err(err) => (Vec::new(), Some(LDAPError::from(err))),
+        };
+
+        Request
+        {
+            out: out,
+            error: error,
+            written: 0,
+            flushed: false,
+            msgid: msgid,
+            ld: self,
+            parse: parse,
+        }
+    }
+
+    // Same numbering as the blocking client
+    fn next_msgid(&mut self) -> i32
+    {
+        self.msgid = if self.msgid == i32::max_value() { 1 } else { self.msgid + 1 };
+        self.msgid
+    }
+}
+
+/// Future returned by `AsyncLDAP::connect`
+pub struct Connect
+{
+    inner: Pin<Box<dyn Future<Output = io::Result<TcpStream>> + Send>>,
+}
+
+impl Future for Connect
+{
+    type Output = Result<AsyncLDAP<TcpStream>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output>
+    {
+        match self.inner.as_mut().poll(cx)
+        {
+            Poll::Ready(Ok(stream)) => Poll::Ready(Ok(AsyncLDAP::from_stream(stream))),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(LDAPError::from(err))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Future of a single request/response exchange
+pub struct Request<'a, S: 'a, T>
+{
+    ld: &'a mut AsyncLDAP<S>,
+    out: Vec<u8>,
+    error: Option<LDAPError>,
+    written: usize,
+    flushed: bool,
+    msgid: i32,
+    parse: fn(common::Tag) -> Result<T>,
+}
+
+impl<'a, S: AsyncRead + AsyncWrite + Unpin, T> Future for Request<'a, S, T>
+{
+    type Output = Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T>>
+    {
+        let this = &mut *self;
+
+        if let Some(err) = this.error.take()
+        {
+            return Poll::Ready(Err(err));
+        }
+
+        while this.written < this.out.len()
+        {
+            match Pin::new(&mut this.ld.stream).poll_write(cx, &this.out[this.written..])
+            {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(LDAPError::from(io::Error::from(io::ErrorKind::WriteZero)))),
+                Poll::Ready(Ok(n)) => this.written += n,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(LDAPError::from(err))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        if !this.flushed
+        {
+            match Pin::new(&mut this.ld.stream).poll_flush(cx)
+            {
+                Poll::Ready(Ok(())) => this.flushed = true,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(LDAPError::from(err))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        loop
+        {
+            let len = match message_len(&this.ld.buf, this.ld.max_message_size)
+            {
+                Ok(len) => len,
+                Err(err) => return Poll::Ready(Err(err)),
+            };
+
+            if let Some(len) = len
+            {
+                if this.ld.buf.len() >= len
+                {
+                    let message: Vec<u8> = this.ld.buf.drain(..len).collect();
+
+                    let (msgid, protocol_op) = match ber::decode(&message).map_err(LDAPError::from).and_then(protocol::deconstruct_envelope)
+                    {
+                        Ok((msgid, protocol_op, _)) => (msgid, protocol_op),
+                        Err(err) => return Poll::Ready(Err(err)),
+                    };
+
+                    if msgid == 0
+                    {
+                        let notification = match extended::parse_extended_response(protocol_op)
+                        {
+                            Ok(response) => extended::Notification { response: response },
+                            Err(err) => return Poll::Ready(Err(err)),
+                        };
+
+                        let disconnect = notification.disconnect_error();
+                        this.ld.notifications.push_back(notification);
+
+                        if let Some(err) = disconnect
+                        {
+                            return Poll::Ready(Err(LDAPError::from(err)));
+                        }
+
+                        continue;
+                    }
+
+                    // Late replies to earlier requests have nobody waiting for them
+                    if msgid != this.msgid
+                    {
+                        continue;
+                    }
+
+                    return Poll::Ready((this.parse)(protocol_op));
+                }
+            }
+
+            let mut chunk = [0u8; 4096];
+            let mut readbuf = ReadBuf::new(&mut chunk);
+
+            match Pin::new(&mut this.ld.stream).poll_read(cx, &mut readbuf)
+            {
+                Poll::Ready(Ok(())) =>
+                {
+                    if readbuf.filled().is_empty()
+                    {
+                        return Poll::Ready(Err(LDAPError::from(io::Error::from(io::ErrorKind::UnexpectedEof))));
+                    }
+
+                    this.ld.buf.extend_from_slice(readbuf.filled());
+                },
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(LDAPError::from(err))),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Total length of the message at the start of `buf`, if enough of it was read to tell
+fn message_len(buf: &[u8], max_size: usize) -> Result<Option<usize>>
+{
+    let (header_len, length) = match try!(ber::decode_header(buf))
+    {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+
+    let length = match length
+    {
+        Some(length) => length,
+        // LDAP only uses definite lengths (RFC 4511 Section 5.1)
+        None => return Err(LDAPError::from(ASN1Error::IndefiniteLength)),
+    };
+
+    if length > max_size as u64
+    {
+        return Err(LDAPError::from(ASN1Error::SizeLimitExceeded));
+    }
+
+    match header_len.checked_add(length as usize)
+    {
+        Some(len) => Ok(Some(len)),
+        None => Err(LDAPError::from(ASN1Error::SizeLimitExceeded)),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use tokio::runtime::Builder;
+
+    use protocol::ber::error::ASN1Error;
+    use protocol::error::LDAPError;
+    use protocol::result::ResultCode;
+
+    use mock::AsyncMockStream;
+
+    use super::AsyncLDAP;
+
+    #[test]
+    fn message_len_waits_for_the_header()
+    {
+        assert_eq!(super::message_len(&[], 1024).unwrap(), None);
+        assert_eq!(super::message_len(&[0x30], 1024).unwrap(), None);
+        assert_eq!(super::message_len(&[0x30, 0x05, 0x02], 1024).unwrap(), Some(7));
+        assert_eq!(super::message_len(&[0x30, 0x82, 0x01], 1024).unwrap(), None);
+        assert_eq!(super::message_len(&[0x30, 0x82, 0x01, 0x00], 1024).unwrap(), Some(260));
+        assert_eq!(super::message_len(&[0x7F, 0x87], 1024).unwrap(), None);
+        assert_eq!(super::message_len(&[0x7F, 0x87, 0x68, 0x01], 1024).unwrap(), Some(5));
+        assert!(super::message_len(&[0x30, 0x80], 1024).is_err());
+    }
+
+    #[test]
+    fn message_len_enforces_the_limit()
+    {
+        assert_eq!(super::message_len(&[0x30, 0x82, 0x04, 0x00], 1024).unwrap(), Some(1028));
+        match super::message_len(&[0x30, 0x82, 0x04, 0x01], 1024)
+        {
+            Err(LDAPError::ASN1(ASN1Error::SizeLimitExceeded)) => {},
+            x => panic!("Expected SizeLimitExceeded, got {:?}", x),
+        }
+
+        // Close to the top of the address space, where adding the header length would overflow
+        let huge = [0x30, 0x88, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        match super::message_len(&huge, usize::max_value())
+        {
+            Err(LDAPError::ASN1(ASN1Error::SizeLimitExceeded)) => {},
+            x => panic!("Expected SizeLimitExceeded, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn request_skips_messages_for_other_ids()
+    {
+        // A notification with responseName 1.2.3.4 and a late reply to some earlier bind
+        let mut response = vec![0x30, 0x15, 0x02, 0x01, 0x00, 0x78, 0x10, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00,
+                                0x8a, 0x07, 0x31, 0x2e, 0x32, 0x2e, 0x33, 0x2e, 0x34];
+        response.extend_from_slice(&[0x30, 0x0c, 0x02, 0x01, 0x07, 0x61, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00]);
+        // The actual answer, invalidCredentials for message ID 1
+        response.extend_from_slice(&[0x30, 0x0c, 0x02, 0x01, 0x01, 0x61, 0x07, 0x0a, 0x01, 0x31, 0x04, 0x00, 0x04, 0x00]);
+
+        let mut ld = AsyncLDAP::from_stream(AsyncMockStream::new(response));
+        let runtime = Builder::new_current_thread().build().unwrap();
+        let res = runtime.block_on(ld.simple_bind("cn=admin,dc=example,dc=com", "wrong")).unwrap();
+
+        assert_eq!(res.result_code, ResultCode::invalidCredentials);
+
+        let notifications = ld.take_notifications();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].response.response_name, Some("1.2.3.4".to_string()));
+    }
+
+    #[test]
+    fn notice_of_disconnection_fails_the_request()
+    {
+        // Notice of Disconnection: unavailable, "Server is shutting down"
+        let response = vec![
+            0x30, 0x3b, 0x02, 0x01, 0x00, 0x78, 0x36, 0x0a, 0x01, 0x34, 0x04, 0x00, 0x04, 0x17, 0x53, 0x65,
+            0x72, 0x76, 0x65, 0x72, 0x20, 0x69, 0x73, 0x20, 0x73, 0x68, 0x75, 0x74, 0x74, 0x69, 0x6e, 0x67,
+            0x20, 0x64, 0x6f, 0x77, 0x6e, 0x8a, 0x16, 0x31, 0x2e, 0x33, 0x2e, 0x36, 0x2e, 0x31, 0x2e, 0x34,
+            0x2e, 0x31, 0x2e, 0x31, 0x34, 0x36, 0x36, 0x2e, 0x32, 0x30, 0x30, 0x33, 0x36,
+        ];
+
+        let mut ld = AsyncLDAP::from_stream(AsyncMockStream::new(response));
+        let runtime = Builder::new_current_thread().build().unwrap();
+
+        match runtime.block_on(ld.simple_bind("cn=admin,dc=example,dc=com", "secret"))
+        {
+            Err(LDAPError::Server(ref err)) =>
+            {
+                assert_eq!(err.result_code, ResultCode::unavailable);
+                assert_eq!(err.diagnostic_message, "Server is shutting down");
+            },
+            x => panic!("Expected the notice as Server error, got {:?}", x),
+        }
+
+        assert!(ld.take_notifications()[0].is_disconnection());
+    }
+
+    #[test]
+    fn connect_can_be_spawned()
+    {
+        fn assert_send<T: Send>(_: &T) {}
+
+        assert_send(&AsyncLDAP::connect("127.0.0.1:389"));
+    }
+}
@@ -1,52 +1,523 @@
 //! Bind / Unbind helper functions
 
+use std::io::{Read, Write};
+
 use protocol::ber::common;
+use protocol::ber::types::ASNType;
+use protocol::error::LDAPError;
+use protocol::result::{LDAPResult, ResultCode};
+
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncWrite};
 
+#[cfg(feature = "tokio")]
+use async_ldap::{AsyncLDAP, Request};
 use LDAP;
+use Result;
+
+/// Protocol version binds are made with by default, LDAPv3 (RFC4511)
+pub const LDAP_VERSION: u8 = 3;
+
+/// How many BindRequests a SASL bind sends before giving up on a server that never finishes
+pub const MAX_SASL_ROUNDS: usize = 10;
+
+/// Result of a bind operation as sent by the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindResponse
+{
+    /// resultCode of the operation
+    pub result_code: ResultCode,
+    /// matchedDN as returned by the server
+    pub matched_dn: String,
+    /// Human readable diagnosticMessage, may be empty
+    pub diagnostic_message: String,
+    /// URLs of other servers to bind at, for the resultCode referral
+    pub referral: Option<Vec<String>>,
+    /// serverSaslCreds, the challenge or final data of a SASL mechanism
+    pub server_sasl_creds: Option<Vec<u8>>,
+}
+
+impl<S: Read + Write> LDAP<S>
+{
+    /// Authenticate using the simple authentication method
+    ///
+    /// Sends a BindRequest for `dn` with `password` and waits for the servers response.
+    /// An error is only returned if the exchange itself failed. A rejected bind is reported
+    /// through the `result_code` of the returned `BindResponse`, except for protocolError,
+    /// which servers answer binds with a version they don't speak with. That results in an
+    /// `UnsupportedVersion` error.
+    pub fn simple_bind(&mut self, dn: &str, password: &str) -> Result<BindResponse>
+    {
+        let msgid = try!(self.send(build_simple_bind(self.version, dn, password), &[]));
+        let protocol_op = try!(self.recv_response(msgid));
+
+        self.check_version(try!(parse_bind_response(protocol_op)))
+    }
+
+    /// Authenticate with the SASL EXTERNAL mechanism
+    ///
+    /// The server takes the identity from outside of LDAP, usually the client certificate of
+    /// the TLS connection. `authz_id` requests to act as a different identity instead.
+    pub fn sasl_bind_external(&mut self, authz_id: Option<&str>) -> Result<BindResponse>
+    {
+        // Without initial credentials the server asks for the authorization identity with an
+        // empty challenge
+        let initial = authz_id.map(|id| id.as_bytes());
+        self.sasl_bind("EXTERNAL", initial, |_| Ok(authz_id.unwrap_or("").as_bytes().to_vec()))
+    }
+
+    /// Authenticate with the SASL CRAM-MD5 mechanism (RFC2195)
+    ///
+    /// The password never leaves the client, but the server has to know it in plain text.
+    #[cfg(feature = "cram-md5")]
+    pub fn sasl_bind_cram_md5(&mut self, username: &str, password: &str) -> Result<BindResponse>
+    {
+        self.sasl_bind("CRAM-MD5", None, |challenge| Ok(cram_md5_response(username, password, challenge)))
+    }
+
+    /// Authenticate with an arbitrary SASL mechanism
+    ///
+    /// `initial` is sent along with the first BindRequest. As long as the server responds with
+    /// saslBindInProgress, `respond` is called with the servers challenge and its result is
+    /// sent back as the next credentials. The last BindResponse is returned.
+    ///
+    /// Like with `simple_bind`, protocolError results in an `UnsupportedVersion` error. A server
+    /// still asking for more after `MAX_SASL_ROUNDS` requests fails the bind with `Protocol`.
+    pub fn sasl_bind<F>(&mut self, mechanism: &str, initial: Option<&[u8]>, mut respond: F) -> Result<BindResponse>
+        where F: FnMut(&[u8]) -> Result<Vec<u8>>
+    {
+        let msgid = try!(self.send(build_sasl_bind(self.version, mechanism, initial), &[]));
+        let response = try!(parse_bind_response(try!(self.recv_response(msgid))));
+        let mut response = try!(self.check_version(response));
+        let mut rounds = 1;
+
+        while response.result_code == ResultCode::saslBindInProgress
+        {
+            if rounds == MAX_SASL_ROUNDS
+            {
+                return Err(LDAPError::Protocol);
+            }
+            rounds += 1;
+
+            let credentials = try!(respond(response.server_sasl_creds.as_ref().map(|c| &c[..]).unwrap_or(&[])));
+
+            let msgid = try!(self.send(build_sasl_bind(self.version, mechanism, Some(&credentials)), &[]));
+            response = try!(parse_bind_response(try!(self.recv_response(msgid))));
+        }
+
+        Ok(response)
+    }
+
+    // A server not speaking the version of a bind answers with protocolError (RFC4511 4.2.2)
+    fn check_version(&self, response: BindResponse) -> Result<BindResponse>
+    {
+        if response.result_code == ResultCode::protocolError
+        {
+            return Err(LDAPError::UnsupportedVersion(self.version));
+        }
+
+        Ok(response)
+    }
+
+    /// Tell the server we're done with this connection
+    ///
+    /// The UnbindRequest has no response. Afterwards the server will close the connection so
+    /// no further operations should be made on it.
+    pub fn unbind(&mut self) -> Result<()>
+    {
+        self.unbound = true;
+        try!(self.send(build_unbind(), &[]));
+        try!(self.stream.flush());
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncLDAP<S>
+{
+    /// Asynchronous version of `LDAP::simple_bind`
+    pub fn simple_bind<'a>(&'a mut self, dn: &str, password: &str) -> Request<'a, S, BindResponse>
+    {
+        self.request(build_simple_bind(LDAP_VERSION, dn, password), parse_bind_response)
+    }
+}
 
 /// Send bind request (only simple auth currently)
-pub fn ldap_bind(ld: &mut LDAP, dn: String, password: String)
+pub fn ldap_bind<S: Read + Write>(ld: &mut LDAP<S>, dn: String, password: String)
 {
-    let versiontag = {
-        let class = common::Class::Universal(common::UniversalTypes::Integer);
-        let pl = common::Payload::Primitive(vec![0x03]);
+    let version = ld.version;
+    let _ = ld.send(build_simple_bind(version, &dn, &password), &[]);
+}
+
+/// Send unbind request
+pub fn ldap_unbind<S: Read + Write>(ld: &mut LDAP<S>)
+{
+    let _ = ld.send(build_unbind(), &[]);
+}
+
+fn build_simple_bind(version: u8, dn: &str, password: &str) -> common::Tag
+{
+    let authtag = {
+        let class = common::Class::ContextSpecific(0);
+        let pl = common::Payload::Primitive(password.as_bytes().to_vec());
 
         common::construct(class, pl)
     };
 
-    let nametag = {
+    build_bind(version, dn, authtag)
+}
+
+// SASL binds leave the name empty, the mechanism determines the identity
+pub(crate) fn build_sasl_bind(version: u8, mechanism: &str, credentials: Option<&[u8]>) -> common::Tag
+{
+    let mut sasl = vec![{
         let class = common::Class::Universal(common::UniversalTypes::OctetString);
-        let pl = common::Payload::Primitive(dn.into_bytes());
+        let pl = common::Payload::Primitive(mechanism.as_bytes().to_vec());
 
         common::construct(class, pl)
-    };
+    }];
+
+    if let Some(credentials) = credentials
+    {
+        let class = common::Class::Universal(common::UniversalTypes::OctetString);
+        let pl = common::Payload::Primitive(credentials.to_vec());
+
+        sasl.push(common::construct(class, pl));
+    }
 
     let authtag = {
-        let class = common::Class::ContextSpecific(0);
-        let pl = common::Payload::Primitive(password.into_bytes());
+        let class = common::Class::ContextSpecific(3);
+        let pl = common::Payload::Constructed(sasl);
 
         common::construct(class, pl)
     };
 
-    let bindrequest = {
-        let class = common::Class::Application(0);
-        let pl = common::Payload::Constructed(vec![versiontag, nametag, authtag]);
+    build_bind(version, "", authtag)
+}
+
+fn build_bind(version: u8, dn: &str, authtag: common::Tag) -> common::Tag
+{
+    let versiontag = (version as i32).into_ber_universal();
+
+    let nametag = {
+        let class = common::Class::Universal(common::UniversalTypes::OctetString);
+        let pl = common::Payload::Primitive(dn.as_bytes().to_vec());
 
         common::construct(class, pl)
     };
 
-    let _ = ld.send(bindrequest);
+    let class = common::Class::Application(0);
+    let pl = common::Payload::Constructed(vec![versiontag, nametag, authtag]);
+
+    common::construct(class, pl)
 }
 
-/// Send unbind request
-pub fn ldap_unbind(ld: &mut LDAP)
+// The username followed by the hex encoded HMAC-MD5 of the challenge keyed with the password
+#[cfg(feature = "cram-md5")]
+fn cram_md5_response(username: &str, password: &str, challenge: &[u8]) -> Vec<u8>
 {
-    let unbindrequest = {
-        let class = common::Class::Application(2);
-        let pl = common::Payload::Primitive(Vec::new());
+    use hmac::{Hmac, Mac};
 
-        common::construct(class, pl)
-    };
+    let mut mac = Hmac::<md5::Md5>::new_from_slice(password.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(challenge);
+
+    let mut response = username.as_bytes().to_vec();
+    response.push(b' ');
+    for byte in mac.finalize().into_bytes().iter()
+    {
+        response.extend_from_slice(format!("{:02x}", byte).as_bytes());
+    }
+
+    response
+}
+
+fn build_unbind() -> common::Tag
+{
+    let class = common::Class::Application(2);
+    let pl = common::Payload::Primitive(Vec::new());
+
+    common::construct(class, pl)
+}
+
+fn parse_bind_response(tag: common::Tag) -> Result<BindResponse>
+{
+    if tag._type.class != common::Class::Application(1)
+    {
+        return Err(LDAPError::UnexpectedTag { expected: common::Class::Application(1), found: tag._type.class });
+    }
+
+    let (result, rest) = try!(LDAPResult::from_tag(tag));
+
+    let mut server_sasl_creds = None;
+    for tag in rest
+    {
+        if let common::Tag { _type: common::Type { class: common::Class::ContextSpecific(7), .. }, _value: common::Payload::Primitive(creds), .. } = tag
+        {
+            server_sasl_creds = Some(creds);
+        }
+    }
+
+    Ok(BindResponse
+    {
+        result_code: result.result_code,
+        matched_dn: result.matched_dn,
+        diagnostic_message: result.diagnostic_message,
+        referral: result.referral,
+        server_sasl_creds: server_sasl_creds,
+    })
+}
+
+#[cfg(test)]
+mod tests
+{
+    use protocol::ber::{self, common};
+    use protocol::error::LDAPError;
+    use protocol::result::ResultCode;
+
+    use mock::MockStream;
+    use LDAP;
+
+    #[test]
+    fn simple_bind_request_layout()
+    {
+        let tag = super::build_simple_bind(3, "cn=admin,dc=example,dc=com", "secret");
+        let bytes = ber::encode(tag, 1).unwrap();
+
+        // BindRequest of ldapsearch -x -D cn=admin,dc=example,dc=com -w secret, encoded by hand
+        let expected = [
+            0x60, 0x27, 0x02, 0x01, 0x03, 0x04, 0x1a, 0x63,
+            0x6e, 0x3d, 0x61, 0x64, 0x6d, 0x69, 0x6e, 0x2c,
+            0x64, 0x63, 0x3d, 0x65, 0x78, 0x61, 0x6d, 0x70,
+            0x6c, 0x65, 0x2c, 0x64, 0x63, 0x3d, 0x63, 0x6f,
+            0x6d, 0x80, 0x06, 0x73, 0x65, 0x63, 0x72, 0x65,
+            0x74,
+        ];
+
+        assert!(bytes.ends_with(&expected));
+    }
+
+    #[test]
+    fn simple_bind_parses_response()
+    {
+        // BindResponse: invalidCredentials with a diagnostic message
+        let response = vec![
+            0x30, 0x12, 0x02, 0x01, 0x01, 0x61, 0x0d, 0x0a,
+            0x01, 0x31, 0x04, 0x00, 0x04, 0x06, 0x6e, 0x6f,
+            0x70, 0x65, 0x21, 0x21,
+        ];
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        let res = ld.simple_bind("cn=admin,dc=example,dc=com", "wrong").unwrap();
+
+        assert_eq!(res.result_code, ResultCode::invalidCredentials);
+        assert_eq!(res.matched_dn, "");
+        assert_eq!(res.diagnostic_message, "nope!!");
+    }
+
+    #[test]
+    fn bind_carries_the_version()
+    {
+        // BindResponse: success
+        let response = vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x61, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00];
+
+        let mut ld = LDAP::from_stream(MockStream::new(response.clone()));
+        ld.simple_bind("", "").unwrap();
+        // BindRequest { version 3, name "", simple "" }
+        assert_eq!(ld.stream.output, vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x60, 0x07, 0x02, 0x01, 0x03, 0x04, 0x00, 0x80, 0x00]);
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        ld.set_version(2);
+        ld.simple_bind("", "").unwrap();
+        assert_eq!(ld.stream.output, vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x60, 0x07, 0x02, 0x01, 0x02, 0x04, 0x00, 0x80, 0x00]);
+    }
+
+    #[test]
+    fn bind_protocol_error_means_unsupported_version()
+    {
+        // BindResponse: protocolError, as sent by LDAPv2-only servers
+        let response = vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x61, 0x07, 0x0a, 0x01, 0x02, 0x04, 0x00, 0x04, 0x00];
+
+        let mut ld = LDAP::from_stream(MockStream::new(response.clone()));
+        match ld.simple_bind("cn=admin,dc=example,dc=com", "secret")
+        {
+            Err(LDAPError::UnsupportedVersion(3)) => {},
+            x => panic!("Expected UnsupportedVersion, got {:?}", x),
+        }
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        match ld.sasl_bind_external(None)
+        {
+            Err(LDAPError::UnsupportedVersion(3)) => {},
+            x => panic!("Expected UnsupportedVersion, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn bind_response_with_referral_and_creds()
+    {
+        let tag = ber::decode(&[
+            0x61, 0x20,
+            // resultCode referral, matchedDN, diagnosticMessage
+            0x0a, 0x01, 0x0a, 0x04, 0x00, 0x04, 0x00,
+            // referral [3] { "ldap://b.example" }
+            0xa3, 0x12, 0x04, 0x10, 0x6c, 0x64, 0x61, 0x70, 0x3a, 0x2f, 0x2f, 0x62, 0x2e, 0x65, 0x78, 0x61,
+            0x6d, 0x70, 0x6c, 0x65,
+            // serverSaslCreds [7]
+            0x87, 0x03, 0x01, 0x02, 0x03,
+        ]).unwrap();
+
+        let res = super::parse_bind_response(tag).unwrap();
+
+        assert_eq!(res.result_code, ResultCode::referral);
+        assert_eq!(res.referral, Some(vec!["ldap://b.example".to_string()]));
+        assert_eq!(res.server_sasl_creds, Some(vec![0x01, 0x02, 0x03]));
+    }
+
+    #[test]
+    fn simple_bind_rejects_other_responses()
+    {
+        // SearchResultDone instead of a BindResponse
+        let response = vec![
+            0x30, 0x0c, 0x02, 0x01, 0x01, 0x65, 0x07, 0x0a,
+            0x01, 0x00, 0x04, 0x00, 0x04, 0x00,
+        ];
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+
+        match ld.simple_bind("cn=admin,dc=example,dc=com", "secret")
+        {
+            Err(LDAPError::UnexpectedTag { expected, found }) =>
+            {
+                assert_eq!(expected, common::Class::Application(1));
+                assert_eq!(found, common::Class::Application(5));
+            },
+            x => panic!("Expected UnexpectedTag, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn sasl_external_request_layout()
+    {
+        let bytes = ber::encode(super::build_sasl_bind(3, "EXTERNAL", None), 1).unwrap();
+        let expected = [
+            0x60, 0x11, 0x02, 0x01, 0x03, 0x04, 0x00, 0xa3, 0x0a, 0x04, 0x08, 0x45, 0x58, 0x54, 0x45, 0x52,
+            0x4e, 0x41, 0x4c,
+        ];
+        assert!(bytes.ends_with(&expected));
+
+        let bytes = ber::encode(super::build_sasl_bind(3, "EXTERNAL", Some(b"u:bob")), 1).unwrap();
+        let expected = [
+            0x60, 0x18, 0x02, 0x01, 0x03, 0x04, 0x00, 0xa3, 0x11, 0x04, 0x08, 0x45, 0x58, 0x54, 0x45, 0x52,
+            0x4e, 0x41, 0x4c, 0x04, 0x05, 0x75, 0x3a, 0x62, 0x6f, 0x62,
+        ];
+        assert!(bytes.ends_with(&expected));
+    }
+
+    #[test]
+    fn sasl_external_answers_empty_challenge()
+    {
+        // saslBindInProgress, then success
+        let mut response = vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x61, 0x07, 0x0a, 0x01, 0x0e, 0x04, 0x00, 0x04, 0x00];
+        response.extend_from_slice(&[0x30, 0x0c, 0x02, 0x01, 0x02, 0x61, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00]);
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        let res = ld.sasl_bind_external(None).unwrap();
+        assert_eq!(res.result_code, ResultCode::success);
+
+        let mut expected = ber::encode(super::build_sasl_bind(3, "EXTERNAL", None), 1).unwrap();
+        expected.extend(ber::encode(super::build_sasl_bind(3, "EXTERNAL", Some(b"")), 2).unwrap());
+        assert_eq!(ld.stream.output, expected);
+    }
+
+    #[test]
+    fn sasl_bind_two_rounds()
+    {
+        // saslBindInProgress with the challenge "abc", then success
+        let mut response = vec![
+            0x30, 0x11, 0x02, 0x01, 0x01, 0x61, 0x0c, 0x0a, 0x01, 0x0e, 0x04, 0x00, 0x04, 0x00, 0x87, 0x03,
+            0x61, 0x62, 0x63,
+        ];
+        response.extend_from_slice(&[0x30, 0x0c, 0x02, 0x01, 0x02, 0x61, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00]);
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        let mut challenges = Vec::new();
+        let res = ld.sasl_bind("TEST", Some(b"hello"), |challenge| {
+            challenges.push(challenge.to_vec());
+            Ok(b"answer".to_vec())
+        }).unwrap();
+
+        assert_eq!(res.result_code, ResultCode::success);
+        assert_eq!(res.server_sasl_creds, None);
+        assert_eq!(challenges, vec![b"abc".to_vec()]);
+
+        let mut expected = ber::encode(super::build_sasl_bind(3, "TEST", Some(b"hello")), 1).unwrap();
+        expected.extend(ber::encode(super::build_sasl_bind(3, "TEST", Some(b"answer")), 2).unwrap());
+        assert_eq!(ld.stream.output, expected);
+    }
+
+    #[test]
+    fn sasl_bind_gives_up_eventually()
+    {
+        // saslBindInProgress for every request
+        let mut response = Vec::new();
+        for msgid in 1..super::MAX_SASL_ROUNDS as u8 + 2
+        {
+            response.extend_from_slice(&[0x30, 0x0c, 0x02, 0x01, msgid, 0x61, 0x07, 0x0a, 0x01, 0x0e, 0x04, 0x00, 0x04, 0x00]);
+        }
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        let mut rounds = 0;
+        match ld.sasl_bind("TEST", None, |_| { rounds += 1; Ok(Vec::new()) })
+        {
+            Err(LDAPError::Protocol) => {},
+            x => panic!("Expected Protocol, got {:?}", x),
+        }
+
+        assert_eq!(rounds, super::MAX_SASL_ROUNDS - 1);
+        assert_eq!(ld.msgid, super::MAX_SASL_ROUNDS as i32);
+    }
+
+    #[cfg(feature = "cram-md5")]
+    #[test]
+    fn cram_md5_response()
+    {
+        // Example from RFC2195
+        let response = super::cram_md5_response("tim", "tanstaaftanstaaf", b"<1896.697170952@postoffice.reston.mci.net>");
+        assert_eq!(response, b"tim b913a602c7eda7a495b4e6e7334d3890".to_vec());
+    }
+
+    #[test]
+    fn unbind_request_layout()
+    {
+        let mut ld = LDAP::from_stream(MockStream::new(Vec::new()));
+        ld.unbind().unwrap();
+
+        // LDAPMessage envelope, message ID 1 and the empty [APPLICATION 2]
+        assert_eq!(ld.stream.output, vec![0x30, 0x05, 0x02, 0x01, 0x01, 0x42, 0x00]);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn async_simple_bind_parses_response()
+    {
+        use tokio::runtime::Builder;
+
+        use async_ldap::AsyncLDAP;
+        use mock::AsyncMockStream;
+
+        // BindResponse: invalidCredentials with a diagnostic message
+        let response = vec![
+            0x30, 0x12, 0x02, 0x01, 0x01, 0x61, 0x0d, 0x0a,
+            0x01, 0x31, 0x04, 0x00, 0x04, 0x06, 0x6e, 0x6f,
+            0x70, 0x65, 0x21, 0x21,
+        ];
+
+        let mut ld = AsyncLDAP::from_stream(AsyncMockStream::new(response));
+        let runtime = Builder::new_current_thread().build().unwrap();
+        let res = runtime.block_on(ld.simple_bind("cn=admin,dc=example,dc=com", "wrong")).unwrap();
 
-    let _ = ld.send(unbindrequest);
+        assert_eq!(res.result_code, ResultCode::invalidCredentials);
+        assert_eq!(res.diagnostic_message, "nope!!");
+    }
 }
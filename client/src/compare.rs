@@ -0,0 +1,129 @@
+//! Compare helper functions
+
+use std::io::{Read, Write};
+
+use protocol::ber::common;
+use protocol::control::Control;
+use protocol::error::{LDAPError, LdapError};
+use protocol::filter;
+use protocol::result::ResultCode;
+
+use LDAP;
+use Result;
+
+impl<S: Read + Write> LDAP<S>
+{
+    /// Check whether the entry `dn` has `value` in its attribute `attr`
+    ///
+    /// The server compares using the equality matching rule of the attribute, so e.g. `cn`
+    /// ignores case. Any resultCode other than compareTrue or compareFalse is an error.
+    pub fn compare(&mut self, dn: &str, attr: &str, value: &[u8], controls: &[Control]) -> Result<bool>
+    {
+        let result = try!(self.exchange(build_compare_request(dn, attr, value), controls, 15));
+
+        match result.result_code
+        {
+            ResultCode::compareTrue => Ok(true),
+            ResultCode::compareFalse => Ok(false),
+            _ => Err(LDAPError::from(LdapError::from(result))),
+        }
+    }
+}
+
+fn build_compare_request(dn: &str, attr: &str, value: &[u8]) -> common::Tag
+{
+    let entrytag = {
+        let class = common::Class::Universal(common::UniversalTypes::OctetString);
+        let pl = common::Payload::Primitive(dn.as_bytes().to_vec());
+
+        common::construct(class, pl)
+    };
+
+    let avatag = filter::encode_ava(attr, value);
+
+    let class = common::Class::Application(14);
+    let pl = common::Payload::Constructed(vec![entrytag, avatag]);
+
+    common::construct(class, pl)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use protocol::error::LDAPError;
+    use protocol::result::ResultCode;
+
+    use mock::MockStream;
+    use LDAP;
+
+    fn response(code: u8) -> Vec<u8>
+    {
+        vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x6f, 0x07, 0x0a, 0x01, code, 0x04, 0x00, 0x04, 0x00]
+    }
+
+    #[test]
+    fn compare_true()
+    {
+        let mut ld = LDAP::from_stream(MockStream::new(response(6)));
+
+        assert_eq!(ld.compare("cn=admins,dc=example,dc=com", "member", b"cn=alice,dc=example,dc=com", &[]).unwrap(), true);
+
+        let expected = vec![
+            0x30, 0x48, 0x02, 0x01, 0x01, 0x6e, 0x43, 0x04, 0x1b, 0x63, 0x6e, 0x3d, 0x61, 0x64, 0x6d, 0x69,
+            0x6e, 0x73, 0x2c, 0x64, 0x63, 0x3d, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2c, 0x64, 0x63,
+            0x3d, 0x63, 0x6f, 0x6d,
+            // member=cn=alice,dc=example,dc=com
+            0x30, 0x24, 0x04, 0x06, 0x6d, 0x65, 0x6d, 0x62, 0x65, 0x72, 0x04, 0x1a,
+            0x63, 0x6e, 0x3d, 0x61, 0x6c, 0x69, 0x63, 0x65, 0x2c, 0x64, 0x63, 0x3d, 0x65, 0x78, 0x61, 0x6d,
+            0x70, 0x6c, 0x65, 0x2c, 0x64, 0x63, 0x3d, 0x63, 0x6f, 0x6d,
+        ];
+
+        assert_eq!(ld.stream.output, expected);
+    }
+
+    #[test]
+    fn compare_false()
+    {
+        let mut ld = LDAP::from_stream(MockStream::new(response(5)));
+
+        assert_eq!(ld.compare("cn=admins,dc=example,dc=com", "member", b"cn=bob,dc=example,dc=com", &[]).unwrap(), false);
+    }
+
+    #[test]
+    fn compare_fails_on_other_result_codes()
+    {
+        // noSuchObject
+        let mut ld = LDAP::from_stream(MockStream::new(response(32)));
+
+        assert!(ld.compare("cn=nobody,dc=example,dc=com", "member", b"cn=bob", &[]).is_err());
+
+        // success isn't a valid answer to a compare either
+        let mut ld = LDAP::from_stream(MockStream::new(response(0)));
+
+        assert!(ld.compare("cn=admins,dc=example,dc=com", "member", b"cn=bob", &[]).is_err());
+    }
+
+    #[test]
+    fn compare_reports_server_diagnostics()
+    {
+        // noSuchObject, matchedDN dc=example,dc=com and the message "no such entry"
+        let response = vec![
+            0x30, 0x2a, 0x02, 0x01, 0x01, 0x6f, 0x25, 0x0a, 0x01, 0x20, 0x04, 0x11, 0x64, 0x63, 0x3d, 0x65,
+            0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2c, 0x64, 0x63, 0x3d, 0x63, 0x6f, 0x6d, 0x04, 0x0d, 0x6e,
+            0x6f, 0x20, 0x73, 0x75, 0x63, 0x68, 0x20, 0x65, 0x6e, 0x74, 0x72, 0x79,
+        ];
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+
+        match ld.compare("cn=nobody,dc=example,dc=com", "member", b"cn=bob", &[])
+        {
+            Err(LDAPError::Server(err)) =>
+            {
+                assert_eq!(err.result_code, ResultCode::noSuchObject);
+                assert_eq!(err.matched_dn, "dc=example,dc=com");
+                assert_eq!(err.diagnostic_message, "no such entry");
+                assert_eq!(err.to_string(), "noSuchObject: no such entry (matched dc=example,dc=com)");
+            },
+            x => panic!("Expected Server error, got {:?}", x),
+        }
+    }
+}
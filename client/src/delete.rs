@@ -0,0 +1,126 @@
+//! Delete helper functions
+
+use std::io::{Read, Write};
+
+use protocol::ber::common;
+use protocol::control::Control;
+use protocol::error::{LDAPError, LdapError};
+use protocol::result::ResultCode;
+
+use LDAP;
+use Result;
+
+impl<S: Read + Write> LDAP<S>
+{
+    /// Delete the entry `dn`
+    ///
+    /// Only leaf entries can be deleted, servers refuse entries with children with
+    /// notAllowedOnNonLeaf. Like every resultCode but success, that comes back as a `Server`
+    /// error.
+    pub fn delete(&mut self, dn: &str, controls: &[Control]) -> Result<ResultCode>
+    {
+        let result = try!(self.exchange(build_delete_request(dn), controls, 11));
+
+        match result.result_code
+        {
+            ResultCode::success => Ok(result.result_code),
+            _ => Err(LDAPError::from(LdapError::from(result))),
+        }
+    }
+}
+
+fn build_delete_request(dn: &str) -> common::Tag
+{
+    // DelRequest ::= [APPLICATION 10] LDAPDN, so the DN is the tag itself and not wrapped
+    // in a SEQUENCE
+    let class = common::Class::Application(10);
+    let pl = common::Payload::Primitive(dn.as_bytes().to_vec());
+
+    common::construct(class, pl)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use protocol::ber::{self, common};
+    use protocol::control::Control;
+    use protocol::error::{LDAPError, LdapError};
+    use protocol::filter::Filter;
+    use protocol::result::ResultCode;
+
+    use mock::MockStream;
+    use LDAP;
+
+    #[test]
+    fn delete_request_is_primitive()
+    {
+        let tag = super::build_delete_request("cn=alice,dc=example,dc=com");
+
+        assert_eq!(tag._type.class, common::Class::Application(10));
+        assert_eq!(tag._type.structure, common::Structure::Primitive);
+        assert_eq!(tag._value, common::Payload::Primitive(b"cn=alice,dc=example,dc=com".to_vec()));
+    }
+
+    #[test]
+    fn delete_sends_request_and_reports_failure()
+    {
+        // DelResponse: notAllowedOnNonLeaf
+        let response = vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x6b, 0x07, 0x0a, 0x01, 0x42, 0x04, 0x00, 0x04, 0x00];
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+
+        match ld.delete("dc=example,dc=com", &[])
+        {
+            Err(LDAPError::Server(LdapError { result_code: ResultCode::notAllowedOnNonLeaf, .. })) => {},
+            x => panic!("Expected notAllowedOnNonLeaf, got {:?}", x),
+        }
+
+        let mut expected = vec![0x30, 0x16, 0x02, 0x01, 0x01, 0x4a, 0x11];
+        expected.extend_from_slice(b"dc=example,dc=com");
+
+        assert_eq!(ld.stream.output, expected);
+    }
+
+    #[test]
+    fn delete_sends_and_returns_controls()
+    {
+        let control = Control { oid: "1.3.6.1.4.1.4203.1.10.2".to_string(), criticality: true, value: Some(vec![0x01]) };
+
+        // DelResponse: success, carrying the control back
+        let done = {
+            let class = common::Class::Application(11);
+            let pl = common::Payload::Constructed(vec![
+                common::construct(common::Class::Universal(common::UniversalTypes::Enumerated), common::Payload::Primitive(vec![0x00])),
+                common::construct(common::Class::Universal(common::UniversalTypes::OctetString), common::Payload::Primitive(Vec::new())),
+                common::construct(common::Class::Universal(common::UniversalTypes::OctetString), common::Payload::Primitive(Vec::new())),
+            ]);
+
+            common::construct(class, pl)
+        };
+        let response = ber::encode_with_controls(done, 1, &[control.clone()]).unwrap();
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+
+        assert_eq!(ld.delete("cn=alice,dc=example,dc=com", &[control.clone()]).unwrap(), ResultCode::success);
+        assert_eq!(ld.response_controls(), &[control.clone()][..]);
+
+        let request = super::build_delete_request("cn=alice,dc=example,dc=com");
+        assert_eq!(ld.stream.output, ber::encode_with_controls(request, 1, &[control]).unwrap());
+    }
+
+    #[test]
+    fn delete_with_failed_assertion()
+    {
+        // DelResponse: assertionFailed
+        let response = vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x6b, 0x07, 0x0a, 0x01, 0x7a, 0x04, 0x00, 0x04, 0x00];
+        let assertion = Control::assertion(Filter::parse("(entryCSN=20261014000000.000000Z#000000#000#000000)").unwrap());
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+
+        match ld.delete("cn=alice,dc=example,dc=com", &[assertion])
+        {
+            Err(LDAPError::Server(LdapError { result_code: ResultCode::assertionFailed, .. })) => {},
+            x => panic!("Expected assertionFailed, got {:?}", x),
+        }
+    }
+}
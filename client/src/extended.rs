@@ -0,0 +1,536 @@
+//! Extended operation helper functions
+
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use protocol::ber::{self, common};
+use protocol::error::{LDAPError, LdapError};
+use protocol::result::{LDAPResult, ResultCode};
+
+use LDAP;
+use ReadTimeout;
+use Result;
+
+/// OID of the StartTLS extended operation (RFC 4511 Section 4.14)
+pub const STARTTLS_OID: &'static str = "1.3.6.1.4.1.1466.20037";
+/// OID of the "Who am I?" extended operation (RFC 4532)
+pub const WHOAMI_OID: &'static str = "1.3.6.1.4.1.4203.1.11.3";
+/// OID of the Password Modify extended operation (RFC 3062)
+pub const PASSWORD_MODIFY_OID: &'static str = "1.3.6.1.4.1.4203.1.11.1";
+/// OID of the Notice of Disconnection unsolicited notification (RFC 4511 Section 4.4.1)
+pub const NOTICE_OF_DISCONNECTION_OID: &'static str = "1.3.6.1.4.1.1466.20036";
+
+/// ExtendedResponse as sent by the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtendedResponse
+{
+    /// resultCode of the operation
+    pub result_code: ResultCode,
+    /// matchedDN as returned by the server
+    pub matched_dn: String,
+    /// Human readable diagnosticMessage, may be empty
+    pub diagnostic_message: String,
+    /// responseName, the OID of the extended response if the server chose to send one
+    pub response_name: Option<String>,
+    /// responseValue, its syntax depends on the operation
+    pub response_value: Option<Vec<u8>>,
+}
+
+/// An unsolicited notification, an ExtendedResponse the server sent with message ID 0 without
+/// being asked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification
+{
+    /// The notification itself, its `response_name` tells what it is about
+    pub response: ExtendedResponse,
+}
+
+impl Notification
+{
+    /// Whether this is a Notice of Disconnection, after which the server closes the connection
+    pub fn is_disconnection(&self) -> bool
+    {
+        self.response.response_name.as_ref().map(|name| &name[..]) == Some(NOTICE_OF_DISCONNECTION_OID)
+    }
+
+    // What the operation waiting for a response fails with once the server is about to hang up
+    pub(crate) fn disconnect_error(&self) -> Option<LdapError>
+    {
+        if !self.is_disconnection()
+        {
+            return None;
+        }
+
+        Some(LdapError
+        {
+            result_code: self.response.result_code,
+            matched_dn: self.response.matched_dn.clone(),
+            diagnostic_message: self.response.diagnostic_message.clone(),
+        })
+    }
+}
+
+impl<S: Read + Write> LDAP<S>
+{
+    /// Ask the server to start TLS on this connection
+    ///
+    /// On success the next bytes on the connection are the TLS handshake, so nothing but
+    /// `into_tls` (available with the `tls` feature) may be called afterwards. If the server
+    /// refuses an error is returned and the connection stays usable in plaintext.
+    pub fn start_tls(&mut self) -> Result<()>
+    {
+        try!(self.extended_request(STARTTLS_OID, None));
+
+        Ok(())
+    }
+
+    /// Ask the server which authorization identity this connection has
+    ///
+    /// Returns an authzId like `dn:cn=admin,dc=example,dc=com` or `u:alice`, the empty string
+    /// for an anonymous connection.
+    pub fn whoami(&mut self) -> Result<String>
+    {
+        let response = try!(self.extended_request(WHOAMI_OID, None));
+
+        String::from_utf8(response.response_value.unwrap_or_default()).map_err(|_| LDAPError::Protocol)
+    }
+
+    /// Check that the server still answers on this connection by sending it a WhoAmI request
+    ///
+    /// Any response counts, even a refusal by servers not implementing WhoAmI. Returns false
+    /// if the connection broke.
+    ///
+    /// This waits for the response as long as any other read does. A connection silently
+    /// dropped by a firewall never answers, so without a read timeout on the connection this
+    /// blocks forever. `is_alive_within` bounds the wait on its own.
+    pub fn is_alive(&mut self) -> bool
+    {
+        self.extended(WHOAMI_OID, None).is_ok()
+    }
+
+    /// Like `is_alive`, but declares the connection dead if the response takes longer than
+    /// `timeout`
+    ///
+    /// The read timeout of the connection is restored afterwards. A connection that timed out
+    /// may still get the late response, so it is best closed.
+    pub fn is_alive_within(&mut self, timeout: Duration) -> bool
+        where S: ReadTimeout
+    {
+        let previous = match self.stream.read_timeout()
+        {
+            Ok(previous) => previous,
+            Err(_) => return false,
+        };
+        if self.stream.set_read_timeout(Some(timeout)).is_err()
+        {
+            return false;
+        }
+
+        let alive = self.is_alive();
+
+        self.stream.set_read_timeout(previous).is_ok() && alive
+    }
+
+    /// Change the password of `user`, or of the bound user if it is None
+    ///
+    /// `old` is required by most servers unless an administrator changes the password. If `new`
+    /// is None the server generates a password, which is returned.
+    pub fn password_modify(&mut self, user: Option<&str>, old: Option<&str>, new: Option<&str>) -> Result<Option<String>>
+    {
+        let value = build_password_modify(user, old, new);
+        let response = try!(self.extended_request(PASSWORD_MODIFY_OID, Some(&value)));
+
+        match response.response_value
+        {
+            Some(value) => parse_password_modify_response(&value),
+            None => Ok(None),
+        }
+    }
+
+    /// Invoke the extended operation `oid` with an optional requestValue
+    ///
+    /// An error is only returned if the exchange itself failed, the servers verdict is the
+    /// `result_code` of the returned `ExtendedResponse`.
+    pub fn extended(&mut self, oid: &str, value: Option<&[u8]>) -> Result<ExtendedResponse>
+    {
+        let msgid = try!(self.send(build_extended_request(oid, value), &[]));
+        let protocol_op = try!(self.recv_response(msgid));
+
+        parse_extended_response(protocol_op)
+    }
+
+    // Exchange an ExtendedRequest, treating any resultCode but success as an error
+    fn extended_request(&mut self, oid: &str, value: Option<&[u8]>) -> Result<ExtendedResponse>
+    {
+        let response = try!(self.extended(oid, value));
+        if response.result_code != ResultCode::success
+        {
+            return Err(LDAPError::from(LdapError
+            {
+                result_code: response.result_code,
+                matched_dn: response.matched_dn,
+                diagnostic_message: response.diagnostic_message,
+            }));
+        }
+
+        Ok(response)
+    }
+}
+
+fn build_extended_request(oid: &str, value: Option<&[u8]>) -> common::Tag
+{
+    let nametag = {
+        let class = common::Class::ContextSpecific(0);
+        let pl = common::Payload::Primitive(oid.as_bytes().to_vec());
+
+        common::construct(class, pl)
+    };
+
+    let mut children = vec![nametag];
+
+    if let Some(value) = value
+    {
+        let class = common::Class::ContextSpecific(1);
+        let pl = common::Payload::Primitive(value.to_vec());
+
+        children.push(common::construct(class, pl));
+    }
+
+    let class = common::Class::Application(23);
+    let pl = common::Payload::Constructed(children);
+
+    common::construct(class, pl)
+}
+
+// PasswdModifyRequestValue, a sequence of the optional [0] userIdentity, [1] oldPasswd and [2] newPasswd
+fn build_password_modify(user: Option<&str>, old: Option<&str>, new: Option<&str>) -> Vec<u8>
+{
+    let mut children = Vec::new();
+
+    for (number, field) in [user, old, new].iter().enumerate()
+    {
+        if let Some(field) = *field
+        {
+            let class = common::Class::ContextSpecific(number as i64);
+            let pl = common::Payload::Primitive(field.as_bytes().to_vec());
+
+            children.push(common::construct(class, pl));
+        }
+    }
+
+    let class = common::Class::Universal(common::UniversalTypes::Sequence);
+    let pl = common::Payload::Constructed(children);
+
+    ber::encode_tag(&common::construct(class, pl)).expect("Encoding into a Vec can't fail")
+}
+
+// PasswdModifyResponseValue, a sequence with the optional [0] genPasswd
+fn parse_password_modify_response(value: &[u8]) -> Result<Option<String>>
+{
+    let children = match try!(ber::decode(value))._value
+    {
+        common::Payload::Constructed(children) => children,
+        common::Payload::Primitive(_) => return Err(LDAPError::Protocol),
+    };
+
+    for tag in children
+    {
+        if let common::Tag { _type: common::Type { class: common::Class::ContextSpecific(0), .. }, _value: common::Payload::Primitive(bytes), .. } = tag
+        {
+            return String::from_utf8(bytes).map(Some).map_err(|_| LDAPError::Protocol);
+        }
+    }
+
+    Ok(None)
+}
+
+pub(crate) fn parse_extended_response(tag: common::Tag) -> Result<ExtendedResponse>
+{
+    if tag._type.class != common::Class::Application(24)
+    {
+        return Err(LDAPError::UnexpectedTag { expected: common::Class::Application(24), found: tag._type.class });
+    }
+
+    let (result, rest) = try!(LDAPResult::from_tag(tag));
+
+    let mut name = None;
+    let mut value = None;
+
+    for tag in rest
+    {
+        let bytes = match tag._value
+        {
+            common::Payload::Primitive(bytes) => bytes,
+            common::Payload::Constructed(_) => return Err(LDAPError::Protocol),
+        };
+
+        match tag._type.class
+        {
+            common::Class::ContextSpecific(10) =>
+                name = Some(try!(String::from_utf8(bytes).map_err(|_| LDAPError::Protocol))),
+            common::Class::ContextSpecific(11) => value = Some(bytes),
+            _ => return Err(LDAPError::Protocol),
+        }
+    }
+
+    Ok(ExtendedResponse
+    {
+        result_code: result.result_code,
+        matched_dn: result.matched_dn,
+        diagnostic_message: result.diagnostic_message,
+        response_name: name,
+        response_value: value,
+    })
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::net::TcpListener;
+    use std::time::{Duration, Instant};
+
+    use protocol::ber::common;
+    use protocol::error::LDAPError;
+    use protocol::result::ResultCode;
+
+    use mock::MockStream;
+    use LDAP;
+    use ReadTimeout;
+
+    #[test]
+    fn start_tls_request_layout()
+    {
+        // ExtendedResponse: success
+        let response = vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x78, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00];
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        ld.start_tls().unwrap();
+
+        let mut expected = vec![0x30, 0x1d, 0x02, 0x01, 0x01, 0x77, 0x18, 0x80, 0x16];
+        expected.extend_from_slice(b"1.3.6.1.4.1.1466.20037");
+
+        assert_eq!(ld.stream.output, expected);
+    }
+
+    #[test]
+    fn start_tls_fails_when_refused()
+    {
+        // ExtendedResponse: unwillingToPerform, with the responseName set
+        let mut response = vec![0x30, 0x24, 0x02, 0x01, 0x01, 0x78, 0x1f, 0x0a, 0x01, 0x35, 0x04, 0x00, 0x04, 0x00,
+                                0x8a, 0x16];
+        response.extend_from_slice(b"1.3.6.1.4.1.1466.20037");
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+
+        match ld.start_tls()
+        {
+            Err(LDAPError::Server(err)) => assert_eq!(err.result_code, ResultCode::unwillingToPerform),
+            x => panic!("Expected the refusal to be reported, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn whoami_returns_authz_id()
+    {
+        // ExtendedResponse: success, responseValue dn:cn=admin,dc=example,dc=com
+        let mut response = vec![0x30, 0x2b, 0x02, 0x01, 0x01, 0x78, 0x26, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00,
+                                0x8b, 0x1d];
+        response.extend_from_slice(b"dn:cn=admin,dc=example,dc=com");
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        assert_eq!(ld.whoami().unwrap(), "dn:cn=admin,dc=example,dc=com");
+
+        let mut expected = vec![0x30, 0x1e, 0x02, 0x01, 0x01, 0x77, 0x19, 0x80, 0x17];
+        expected.extend_from_slice(b"1.3.6.1.4.1.4203.1.11.3");
+        assert_eq!(ld.stream.output, expected);
+    }
+
+    #[test]
+    fn whoami_anonymous()
+    {
+        // Servers may leave out the empty responseValue
+        let response = vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x78, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00];
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        assert_eq!(ld.whoami().unwrap(), "");
+    }
+
+    #[test]
+    fn is_alive_on_any_response()
+    {
+        // ExtendedResponse: success, then protocolError from a server without WhoAmI
+        let mut responses = vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x78, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00];
+        responses.extend_from_slice(&[0x30, 0x0c, 0x02, 0x01, 0x02, 0x78, 0x07, 0x0a, 0x01, 0x02, 0x04, 0x00, 0x04, 0x00]);
+
+        let mut ld = LDAP::from_stream(MockStream::new(responses));
+        assert!(ld.is_alive());
+        assert!(ld.is_alive());
+
+        // The server hung up, nothing left to read
+        assert!(!ld.is_alive());
+    }
+
+    #[test]
+    fn is_alive_within_gives_up_on_silent_server()
+    {
+        // Accepts the connection, but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut ld = LDAP::connect(listener.local_addr().unwrap()).unwrap();
+        let _peer = listener.accept().unwrap();
+
+        ld.set_read_timeout(Some(Duration::from_secs(30))).unwrap();
+        let start = Instant::now();
+        assert!(!ld.is_alive_within(Duration::from_millis(50)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(ReadTimeout::read_timeout(&ld.stream).unwrap(), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn is_alive_within_restores_timeout()
+    {
+        let response = vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x78, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00];
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        assert!(ld.is_alive_within(Duration::from_secs(1)));
+        assert_eq!(ld.stream.read_timeout, None);
+    }
+
+    #[test]
+    fn password_modify_with_new_password()
+    {
+        let response = vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x78, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00];
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        assert_eq!(ld.password_modify(Some("uid=bob"), Some("old"), Some("new")).unwrap(), None);
+
+        let mut expected = vec![0x30, 0x35, 0x02, 0x01, 0x01, 0x77, 0x30, 0x80, 0x17];
+        expected.extend_from_slice(b"1.3.6.1.4.1.4203.1.11.1");
+        // requestValue: userIdentity, oldPasswd and newPasswd
+        expected.extend_from_slice(&[
+            0x81, 0x15, 0x30, 0x13, 0x80, 0x07, 0x75, 0x69, 0x64, 0x3d, 0x62, 0x6f, 0x62, 0x81, 0x03, 0x6f,
+            0x6c, 0x64, 0x82, 0x03, 0x6e, 0x65, 0x77,
+        ]);
+        assert_eq!(ld.stream.output, expected);
+    }
+
+    #[test]
+    fn password_modify_generated_password()
+    {
+        // ExtendedResponse: success, genPasswd Xy7!q
+        let response = vec![
+            0x30, 0x17, 0x02, 0x01, 0x01, 0x78, 0x12, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00, 0x8b, 0x09,
+            0x30, 0x07, 0x80, 0x05, 0x58, 0x79, 0x37, 0x21, 0x71,
+        ];
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        assert_eq!(ld.password_modify(None, None, None).unwrap(), Some("Xy7!q".to_string()));
+
+        // Only the empty sequence is sent
+        assert!(ld.stream.output.ends_with(&[0x81, 0x02, 0x30, 0x00]));
+    }
+
+    #[test]
+    fn parse_extended_response_fields()
+    {
+        let tag = {
+            let string = |class, value: &[u8]| common::construct(class, common::Payload::Primitive(value.to_vec()));
+            let children = vec![
+                string(common::Class::Universal(common::UniversalTypes::Enumerated), &[0x00]),
+                string(common::Class::Universal(common::UniversalTypes::OctetString), b""),
+                string(common::Class::Universal(common::UniversalTypes::OctetString), b""),
+                string(common::Class::ContextSpecific(10), b"1.2.3"),
+                string(common::Class::ContextSpecific(11), &[0x01, 0x02]),
+            ];
+
+            common::construct(common::Class::Application(24), common::Payload::Constructed(children))
+        };
+
+        let response = super::parse_extended_response(tag).unwrap();
+
+        assert_eq!(response.response_name, Some("1.2.3".to_string()));
+        assert_eq!(response.response_value, Some(vec![0x01, 0x02]));
+    }
+
+    #[test]
+    fn extended_passes_through_response()
+    {
+        // ExtendedResponse: protocolError, responseName 1.2.3.4 and responseValue 0x2a
+        let response = vec![
+            0x30, 0x1b, 0x02, 0x01, 0x01, 0x78, 0x16, 0x0a, 0x01, 0x02, 0x04, 0x00, 0x04, 0x03, 0x6e, 0x6f,
+            0x21, 0x8a, 0x07, 0x31, 0x2e, 0x32, 0x2e, 0x33, 0x2e, 0x34, 0x8b, 0x01, 0x2a,
+        ];
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        let response = ld.extended("1.2.3.4", Some(&[0x01])).unwrap();
+
+        assert_eq!(response.result_code, ResultCode::protocolError);
+        assert_eq!(response.diagnostic_message, "no!");
+        assert_eq!(response.response_name, Some("1.2.3.4".to_string()));
+        assert_eq!(response.response_value, Some(vec![0x2a]));
+
+        // requestName [0] and requestValue [1]
+        let expected = vec![
+            0x30, 0x11, 0x02, 0x01, 0x01, 0x77, 0x0c, 0x80, 0x07, 0x31, 0x2e, 0x32, 0x2e, 0x33, 0x2e, 0x34,
+            0x81, 0x01, 0x01,
+        ];
+        assert_eq!(ld.stream.output, expected);
+    }
+
+    // Unsolicited notification with responseName 1.2.3.4
+    const NOTIFICATION: [u8; 23] = [
+        0x30, 0x15, 0x02, 0x01, 0x00, 0x78, 0x10, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00, 0x8a, 0x07,
+        0x31, 0x2e, 0x32, 0x2e, 0x33, 0x2e, 0x34,
+    ];
+
+    #[test]
+    fn notifications_are_kept_apart()
+    {
+        let mut input = NOTIFICATION.to_vec();
+        // ExtendedResponse: success, "u:alice"
+        input.extend_from_slice(&[0x30, 0x15, 0x02, 0x01, 0x01, 0x78, 0x10, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00,
+                                  0x8b, 0x07, 0x75, 0x3a, 0x61, 0x6c, 0x69, 0x63, 0x65]);
+
+        let mut ld = LDAP::from_stream(MockStream::new(input));
+        assert_eq!(ld.whoami().unwrap(), "u:alice");
+
+        let notifications = ld.take_notifications();
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].response.response_name, Some("1.2.3.4".to_string()));
+        assert!(!notifications[0].is_disconnection());
+        assert!(ld.take_notifications().is_empty());
+    }
+
+    #[test]
+    fn notice_of_disconnection_fails_the_operation()
+    {
+        let mut input = NOTIFICATION.to_vec();
+        // Notice of Disconnection: unavailable, "Server is shutting down"
+        input.extend_from_slice(&[
+            0x30, 0x3b, 0x02, 0x01, 0x00, 0x78, 0x36, 0x0a, 0x01, 0x34, 0x04, 0x00, 0x04, 0x17, 0x53, 0x65,
+            0x72, 0x76, 0x65, 0x72, 0x20, 0x69, 0x73, 0x20, 0x73, 0x68, 0x75, 0x74, 0x74, 0x69, 0x6e, 0x67,
+            0x20, 0x64, 0x6f, 0x77, 0x6e, 0x8a, 0x16, 0x31, 0x2e, 0x33, 0x2e, 0x36, 0x2e, 0x31, 0x2e, 0x34,
+            0x2e, 0x31, 0x2e, 0x31, 0x34, 0x36, 0x36, 0x2e, 0x32, 0x30, 0x30, 0x33, 0x36,
+        ]);
+
+        let mut ld = LDAP::from_stream(MockStream::new(input));
+        match ld.whoami()
+        {
+            Err(LDAPError::Server(ref err)) =>
+            {
+                assert_eq!(err.result_code, ResultCode::unavailable);
+                assert_eq!(err.diagnostic_message, "Server is shutting down");
+            },
+            x => panic!("Expected the notice as Server error, got {:?}", x),
+        }
+
+        let notifications = ld.take_notifications();
+        assert_eq!(notifications.len(), 2);
+        assert!(!notifications[0].is_disconnection());
+        assert!(notifications[1].is_disconnection());
+
+        // recv routes them the same way
+        let mut ld = LDAP::from_stream(MockStream::new(NOTIFICATION.to_vec()));
+        assert!(ld.recv().is_err());
+        assert_eq!(ld.take_notifications().len(), 1);
+    }
+}
@@ -0,0 +1,427 @@
+//! SASL GSSAPI authentication (RFC4752), usually Kerberos against Active Directory
+//!
+//! Only available with the `gssapi` feature. The GSS-API itself is left to the Kerberos
+//! library of choice: implement `GssContext` on top of its client security context, created
+//! for the service principal of the server like `ldap/dc1.example.com`. With the `krb5`
+//! feature `Krb5Context` does that using the system GSS-API library. This module does the
+//! SASL side, the token exchange over BindRequests, negotiating a security layer and wrapping
+//! the traffic once one was chosen.
+
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+#[cfg(feature = "krb5")]
+use libgssapi::context::{ClientCtx, CtxFlags, SecurityContext};
+#[cfg(feature = "krb5")]
+use libgssapi::name::Name;
+#[cfg(feature = "krb5")]
+use libgssapi::oid::{GSS_MECH_KRB5, GSS_NT_HOSTBASED_SERVICE};
+use protocol::error::LDAPError;
+use protocol::result::ResultCode;
+
+use bind::BindResponse;
+use LDAP;
+use ReadTimeout;
+use Result;
+
+/// Largest buffer we accept from the server, announced during negotiation
+pub const MAX_BUFFER: u32 = 0x10000;
+
+/// Room left for the GSS-API header and padding when splitting data into wrapped buffers
+const WRAP_HEADROOM: u32 = 128;
+
+/// An initiating GSS-API security context, as provided by a Kerberos library
+pub trait GssContext
+{
+    /// Feed the latest token of the server to `GSS_Init_sec_context`, empty for the first call
+    ///
+    /// Returns the token to send back, if any.
+    fn step(&mut self, token: &[u8]) -> io::Result<Option<Vec<u8>>>;
+
+    /// Whether the context is established and needs no more tokens
+    fn is_complete(&self) -> bool;
+
+    /// `GSS_Wrap` `data`, integrity protected and also encrypted if `encrypt` is set
+    fn wrap(&mut self, encrypt: bool, data: &[u8]) -> io::Result<Vec<u8>>;
+
+    /// `GSS_Unwrap` a token wrapped by the server
+    fn unwrap(&mut self, token: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+/// `GssContext` using the Kerberos mechanism of the system GSS-API library
+///
+/// Only available with the `krb5` feature. The default credentials are used, usually the
+/// ticket `kinit` put into the credential cache.
+#[cfg(feature = "krb5")]
+pub struct Krb5Context
+{
+    inner: ClientCtx,
+}
+
+#[cfg(feature = "krb5")]
+impl Krb5Context
+{
+    /// Security context for the LDAP service of `host`, like `dc1.example.com`
+    pub fn new(host: &str) -> io::Result<Krb5Context>
+    {
+        let service = try!(Name::new(format!("ldap@{}", host).as_bytes(), Some(&GSS_NT_HOSTBASED_SERVICE)).map_err(gss_error));
+        // Servers only offer the security layers the context supports
+        let flags = CtxFlags::GSS_C_MUTUAL_FLAG | CtxFlags::GSS_C_SEQUENCE_FLAG | CtxFlags::GSS_C_INTEG_FLAG | CtxFlags::GSS_C_CONF_FLAG;
+
+        Ok(Krb5Context { inner: ClientCtx::new(None, service, flags, Some(&GSS_MECH_KRB5)) })
+    }
+}
+
+#[cfg(feature = "krb5")]
+impl GssContext for Krb5Context
+{
+    fn step(&mut self, token: &[u8]) -> io::Result<Option<Vec<u8>>>
+    {
+        // The first call has no token of the server to go on
+        let token = if token.is_empty() { None } else { Some(token) };
+
+        self.inner.step(token, None).map(|out| out.map(|buf| buf.to_vec())).map_err(gss_error)
+    }
+
+    fn is_complete(&self) -> bool
+    {
+        self.inner.is_complete()
+    }
+
+    fn wrap(&mut self, encrypt: bool, data: &[u8]) -> io::Result<Vec<u8>>
+    {
+        self.inner.wrap(encrypt, data).map(|buf| buf.to_vec()).map_err(gss_error)
+    }
+
+    fn unwrap(&mut self, token: &[u8]) -> io::Result<Vec<u8>>
+    {
+        self.inner.unwrap(token).map(|buf| buf.to_vec()).map_err(gss_error)
+    }
+}
+
+#[cfg(feature = "krb5")]
+fn gss_error(err: ::libgssapi::error::Error) -> io::Error
+{
+    io::Error::new(io::ErrorKind::Other, err)
+}
+
+/// Protection of the traffic after a GSSAPI bind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityLayer
+{
+    /// Messages are sent as they are, as usual over TLS
+    None = 1,
+    /// Messages are signed
+    Integrity = 2,
+    /// Messages are signed and encrypted
+    Confidentiality = 4,
+}
+
+/// Outcome of `sasl_bind_gssapi`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GssapiBind
+{
+    /// The last BindResponse of the exchange
+    pub response: BindResponse,
+    /// Largest wrapped buffer the server accepts, to pass on to `into_sasl_layer`
+    pub max_buffer: u32,
+}
+
+impl<S: Read + Write> LDAP<S>
+{
+    /// Authenticate with the SASL GSSAPI mechanism
+    ///
+    /// `context` does the Kerberos part and has to be fresh. Once it is established the server
+    /// offers its security layers, of which `layer` is chosen, and `authz_id` may request to
+    /// act as a different identity. The bind fails with an `Io` error if the server doesn't
+    /// offer `layer`. Unless that is `SecurityLayer::None`, switch the connection over with
+    /// `into_sasl_layer` after the bind succeeded.
+    pub fn sasl_bind_gssapi<C: GssContext>(&mut self, context: &mut C, layer: SecurityLayer, authz_id: Option<&str>)
+        -> Result<GssapiBind>
+    {
+        let initial = try!(context.step(&[])).unwrap_or_default();
+        let mut max_buffer = 0;
+
+        let response = try!(self.sasl_bind("GSSAPI", Some(&initial), |challenge| {
+            if !context.is_complete()
+            {
+                return Ok(try!(context.step(challenge)).unwrap_or_default());
+            }
+
+            // Security layers the server supports as bit mask, then its maximum buffer size
+            let offer = try!(context.unwrap(challenge));
+            if offer.len() != 4
+            {
+                return Err(LDAPError::Protocol);
+            }
+            if offer[0] & layer as u8 == 0
+            {
+                return Err(LDAPError::from(io::Error::new(io::ErrorKind::Other, "Server doesn't offer the security layer")));
+            }
+            if layer != SecurityLayer::None
+            {
+                max_buffer = (offer[1] as u32) << 16 | (offer[2] as u32) << 8 | offer[3] as u32;
+            }
+
+            let ours = if layer == SecurityLayer::None { 0 } else { MAX_BUFFER };
+            let mut choice = vec![layer as u8, (ours >> 16) as u8, (ours >> 8) as u8, ours as u8];
+            choice.extend_from_slice(authz_id.unwrap_or("").as_bytes());
+
+            Ok(try!(context.wrap(false, &choice)))
+        }));
+
+        if response.result_code != ResultCode::success
+        {
+            max_buffer = 0;
+        }
+
+        Ok(GssapiBind { response: response, max_buffer: max_buffer })
+    }
+}
+
+impl LDAP<TcpStream>
+{
+    /// Protect all further traffic with the security layer chosen in `sasl_bind_gssapi`
+    ///
+    /// `context` is the one the bind established and `max_buffer` comes from its `GssapiBind`.
+    pub fn into_sasl_layer<C: GssContext>(mut self, context: C, layer: SecurityLayer, max_buffer: u32)
+        -> Result<LDAP<SaslStream<TcpStream, C>>>
+    {
+        let stream = SaslStream::new(try!(self.stream.try_clone()), context, layer, max_buffer);
+
+        Ok(self.continue_on(stream))
+    }
+}
+
+/// A stream sending and receiving SASL buffers, each a 4 byte length followed by a token
+/// wrapped by the GSS-API context
+///
+/// Written data is collected until `flush`, which the connection does after every message.
+pub struct SaslStream<S, C>
+{
+    inner: S,
+    context: C,
+    encrypt: bool,
+    // Most plain bytes going into one buffer
+    chunk: usize,
+    // Unwrapped data not read yet
+    received: Vec<u8>,
+    read_pos: usize,
+    // Data written since the last flush
+    unsent: Vec<u8>,
+}
+
+impl<S: Read + Write, C: GssContext> SaslStream<S, C>
+{
+    /// Wrap `inner` with the established `context`, sending buffers of at most `max_buffer` bytes
+    pub fn new(inner: S, context: C, layer: SecurityLayer, max_buffer: u32) -> SaslStream<S, C>
+    {
+        SaslStream
+        {
+            inner: inner,
+            context: context,
+            encrypt: layer == SecurityLayer::Confidentiality,
+            chunk: cmp::max(max_buffer.saturating_sub(WRAP_HEADROOM), 1) as usize,
+            received: Vec::new(),
+            read_pos: 0,
+            unsent: Vec::new(),
+        }
+    }
+}
+
+impl<S: ReadTimeout, C> ReadTimeout for SaslStream<S, C>
+{
+    fn read_timeout(&self) -> io::Result<Option<Duration>>
+    {
+        self.inner.read_timeout()
+    }
+
+    fn set_read_timeout(&mut self, dur: Option<Duration>) -> io::Result<()>
+    {
+        self.inner.set_read_timeout(dur)
+    }
+}
+
+impl<S: Read + Write, C: GssContext> Read for SaslStream<S, C>
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        while self.read_pos == self.received.len()
+        {
+            // The connection may only end between buffers
+            let mut len = [0; 4];
+            if try!(self.inner.read(&mut len[..1])) == 0
+            {
+                return Ok(0);
+            }
+            try!(self.inner.read_exact(&mut len[1..]));
+            let len = (len[0] as u32) << 24 | (len[1] as u32) << 16 | (len[2] as u32) << 8 | len[3] as u32;
+            if len > MAX_BUFFER
+            {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "SASL buffer larger than announced"));
+            }
+
+            let mut token = vec![0; len as usize];
+            try!(self.inner.read_exact(&mut token));
+            self.received = try!(self.context.unwrap(&token));
+            self.read_pos = 0;
+        }
+
+        let amount = cmp::min(buf.len(), self.received.len() - self.read_pos);
+        buf[..amount].copy_from_slice(&self.received[self.read_pos..self.read_pos + amount]);
+        self.read_pos += amount;
+
+        Ok(amount)
+    }
+}
+
+impl<S: Read + Write, C: GssContext> Write for SaslStream<S, C>
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        self.unsent.extend_from_slice(buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        for chunk in self.unsent.chunks(self.chunk)
+        {
+            let token = try!(self.context.wrap(self.encrypt, chunk));
+            let len = token.len() as u32;
+
+            try!(self.inner.write_all(&[(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8]));
+            try!(self.inner.write_all(&token));
+        }
+        self.unsent.clear();
+
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::io::{self, Read, Write};
+
+    use protocol::ber;
+    use protocol::result::ResultCode;
+
+    use bind::build_sasl_bind;
+    use mock::MockStream;
+    use super::{GssContext, SaslStream, SecurityLayer};
+    use LDAP;
+
+    #[cfg(feature = "krb5")]
+    #[test]
+    fn krb5_context_starts_incomplete()
+    {
+        // Importing the service name needs neither credentials nor a KDC
+        let context = super::Krb5Context::new("dc1.example.com").unwrap();
+        assert!(!context.is_complete());
+    }
+
+    // Context exchanging two tokens, "wrapping" by prefixing a byte telling whether it encrypted
+    struct MockContext
+    {
+        round: usize,
+        received: Vec<Vec<u8>>,
+    }
+
+    impl GssContext for MockContext
+    {
+        fn step(&mut self, token: &[u8]) -> io::Result<Option<Vec<u8>>>
+        {
+            self.received.push(token.to_vec());
+            self.round += 1;
+
+            Ok(if self.round == 1 { Some(b"krb-req".to_vec()) } else { None })
+        }
+
+        fn is_complete(&self) -> bool
+        {
+            self.round == 2
+        }
+
+        fn wrap(&mut self, encrypt: bool, data: &[u8]) -> io::Result<Vec<u8>>
+        {
+            let mut token = vec![if encrypt { b'E' } else { b'S' }];
+            token.extend_from_slice(data);
+
+            Ok(token)
+        }
+
+        fn unwrap(&mut self, token: &[u8]) -> io::Result<Vec<u8>>
+        {
+            Ok(token[1..].to_vec())
+        }
+    }
+
+    // BindResponse with message ID `msgid`, resultCode `code` and serverSaslCreds `creds`
+    fn bind_response(msgid: u8, code: u8, creds: &[u8]) -> Vec<u8>
+    {
+        let mut op = vec![0x0a, 0x01, code, 0x04, 0x00, 0x04, 0x00, 0x87, creds.len() as u8];
+        op.extend_from_slice(creds);
+
+        let mut message = vec![0x30, op.len() as u8 + 5, 0x02, 0x01, msgid, 0x61, op.len() as u8];
+        message.extend(op);
+        message
+    }
+
+    #[test]
+    fn gssapi_bind_framing()
+    {
+        // The servers Kerberos reply, then its offer of all layers with a 4 KiB buffer
+        let mut response = bind_response(1, 0x0e, b"krb-rep");
+        response.extend(bind_response(2, 0x0e, &[b'S', 0x07, 0x00, 0x10, 0x00]));
+        response.extend(bind_response(3, 0x00, b""));
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        let mut context = MockContext { round: 0, received: Vec::new() };
+        let bind = ld.sasl_bind_gssapi(&mut context, SecurityLayer::Confidentiality, Some("u:alice")).unwrap();
+
+        assert_eq!(bind.response.result_code, ResultCode::success);
+        assert_eq!(bind.max_buffer, 0x1000);
+        assert_eq!(context.received, vec![b"".to_vec(), b"krb-rep".to_vec()]);
+
+        // Initial token, empty answer completing the context, then the signed layer choice
+        let mut expected = ber::encode(build_sasl_bind(3, "GSSAPI", Some(b"krb-req")), 1).unwrap();
+        expected.extend(ber::encode(build_sasl_bind(3, "GSSAPI", Some(b"")), 2).unwrap());
+        expected.extend(ber::encode(build_sasl_bind(3, "GSSAPI", Some(b"S\x04\x01\x00\x00u:alice")), 3).unwrap());
+        assert_eq!(ld.stream.output, expected);
+    }
+
+    #[test]
+    fn gssapi_bind_requires_offered_layer()
+    {
+        // Only integrity offered
+        let mut response = bind_response(1, 0x0e, b"krb-rep");
+        response.extend(bind_response(2, 0x0e, &[b'S', 0x02, 0x00, 0x10, 0x00]));
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        let mut context = MockContext { round: 0, received: Vec::new() };
+
+        assert!(ld.sasl_bind_gssapi(&mut context, SecurityLayer::Confidentiality, None).is_err());
+    }
+
+    #[test]
+    fn sasl_stream_wraps_buffers()
+    {
+        let context = MockContext { round: 2, received: Vec::new() };
+        let input = vec![0x00, 0x00, 0x00, 0x04, b'E', b'a', b'b', b'c', 0x00, 0x00, 0x00, 0x02, b'E', b'd'];
+        // Buffers of at most 130 bytes leave room for 2 bytes of data
+        let mut stream = SaslStream::new(MockStream::new(input), context, SecurityLayer::Confidentiality, 130);
+
+        stream.write_all(b"xyz").unwrap();
+        assert!(stream.inner.output.is_empty());
+        stream.flush().unwrap();
+        assert_eq!(stream.inner.output, vec![0x00, 0x00, 0x00, 0x03, b'E', b'x', b'y', 0x00, 0x00, 0x00, 0x02, b'E', b'z']);
+
+        let mut read = Vec::new();
+        stream.read_to_end(&mut read).unwrap();
+        assert_eq!(read, b"abcd".to_vec());
+    }
+}
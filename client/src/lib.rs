@@ -8,66 +8,985 @@
 
 #[doc(no_inline)]
 extern crate ldap_protocol as protocol;
+#[cfg(feature = "cram-md5")]
+extern crate hmac;
+#[cfg(feature = "krb5")]
+extern crate libgssapi;
+#[cfg(feature = "cram-md5")]
+extern crate md5;
 extern crate mio;
+#[cfg(feature = "log")]
+#[macro_use]
+extern crate log;
+#[cfg(feature = "tls")]
+extern crate native_tls;
+#[cfg(feature = "tokio")]
+extern crate tokio;
 
+use std::collections::VecDeque;
+#[cfg(any(feature = "tls", feature = "gssapi"))]
+use std::mem;
 use std::net::TcpStream;
-use std::net::ToSocketAddrs;
+use std::net::{Shutdown, SocketAddr, ToSocketAddrs};
+use std::time::Duration;
 
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 
 use protocol::ber::{self, common};
+use protocol::control::{self, Control};
+use protocol::error::LDAPError;
+use protocol::result::LDAPResult;
 pub use protocol::Result;
 
+pub mod abandon;
+pub mod add;
+#[cfg(feature = "tokio")]
+pub mod async_ldap;
 pub mod bind;
+pub mod compare;
+pub mod delete;
+pub mod extended;
+#[cfg(feature = "gssapi")]
+pub mod gssapi;
+pub mod modify;
+pub mod modifydn;
+pub mod pool;
+pub mod search;
+pub mod socks;
+#[cfg(feature = "tls")]
+pub mod tls;
 mod queue;
 
+#[cfg(test)]
+mod mock;
+
+/// Largest message in bytes a connection accepts unless told otherwise
+///
+/// Anything bigger than this is refused before it is buffered, so a broken or hostile
+/// server can't make the client allocate without bounds.
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Transports able to give up on a read after some time
+///
+/// Liveness checks and deadlines use this to not wait forever for a server that stopped
+/// answering. Implemented for TCP and the streams layered on top of it.
+pub trait ReadTimeout
+{
+    /// The current read timeout, `None` meaning reads block until data arrives
+    fn read_timeout(&self) -> io::Result<Option<Duration>>;
+
+    /// Fail reads with `WouldBlock` or `TimedOut` after `dur`, `None` waits forever
+    fn set_read_timeout(&mut self, dur: Option<Duration>) -> io::Result<()>;
+}
+
+impl ReadTimeout for TcpStream
+{
+    fn read_timeout(&self) -> io::Result<Option<Duration>>
+    {
+        TcpStream::read_timeout(self)
+    }
+
+    fn set_read_timeout(&mut self, dur: Option<Duration>) -> io::Result<()>
+    {
+        TcpStream::set_read_timeout(self, dur)
+    }
+}
+
 /// Core LDAP struct
 ///
 /// This struct contains all state of the LDAP connection this crate establishes.
 /// It is used in all LDAP functions.
+///
+/// The connection is generic over its transport so anything implementing `Read` and `Write`
+/// (TLS streams, unix sockets, in-memory buffers) can carry LDAP. It defaults to plain TCP.
+///
+/// Dropping a connection sends an UnbindRequest unless `unbind` or `close` was called before.
 #[derive(Debug)]
-pub struct LDAP
+pub struct LDAP<S: Read + Write = TcpStream>
 {
-    // TODO: Later abstract over io::Read / io::Write (for LDAPS and LDAPI implementation)
-    stream: TcpStream,
+    stream: S,
 
     msgid: i32,
+
+    // Responses read while waiting for a different message ID, oldest first
+    pending: VecDeque<(i32, common::Tag)>,
+
+    // Controls attached to the last response
+    response_controls: Vec<Control>,
+
+    // Encoded form of the message being sent, kept around to reuse its allocation
+    scratch: Vec<u8>,
+
+    // Whether searches chase referrals to other servers
+    follow_referrals: bool,
+
+    // Protocol version sent with binds
+    version: u8,
+
+    // Unsolicited notifications received and not taken yet, oldest first
+    notifications: VecDeque<extended::Notification>,
+
+    // Whether an UnbindRequest was sent, so dropping the connection must not send another
+    unbound: bool,
+
+    // Message IDs of abandoned operations, whose late responses are dropped
+    abandoned: Vec<i32>,
+
+    // Whether message IDs wrapped around, after which every positive ID has been assigned
+    msgid_wrapped: bool,
+
+    // Largest message accepted from the server
+    max_message_size: usize,
 }
 
-impl LDAP
+impl LDAP<TcpStream>
 {
-
     /// Connect to the LDAP-Server found at `addr` using plain unencrypted TCP
-    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<LDAP>
+    ///
+    /// Every address `addr` resolves to is tried in turn. If none accepts a connection, the
+    /// `Connect` error tells why each of them failed.
+    ///
+    /// No timeouts are set, so a server that stops answering blocks forever. Use
+    /// `connect_timeout` or `set_read_timeout` to bound that.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<LDAP<TcpStream>>
+    {
+        let stream = try!(connect_first(addr, |addr| TcpStream::connect(addr)));
+
+        Ok(LDAP::from_stream(stream))
+    }
+
+    /// Like `connect`, but gives up connecting after `timeout` and uses it as read and write
+    /// timeout from then on
+    ///
+    /// The timeout applies to each address `addr` resolves to, so a host name with an
+    /// unreachable IPv6 address still connects over IPv4 in time.
+    pub fn connect_timeout<A: ToSocketAddrs>(addr: A, timeout: Duration) -> Result<LDAP<TcpStream>>
+    {
+        let stream = try!(connect_first(addr, |addr| TcpStream::connect_timeout(addr, timeout)));
+
+        let mut ld = LDAP::from_stream(stream);
+        try!(ld.set_read_timeout(Some(timeout)));
+        try!(ld.set_write_timeout(Some(timeout)));
+
+        Ok(ld)
+    }
+
+    /// Give up waiting for the server after `dur`, `None` waits forever
+    ///
+    /// Once a read timed out the operation fails with an `Io` error of kind `WouldBlock` or
+    /// `TimedOut`, depending on the platform.
+    pub fn set_read_timeout(&mut self, dur: Option<Duration>) -> Result<()>
+    {
+        try!(self.stream.set_read_timeout(dur));
+
+        Ok(())
+    }
+
+    /// Give up sending requests after `dur`, `None` waits forever
+    pub fn set_write_timeout(&mut self, dur: Option<Duration>) -> Result<()>
     {
-        let stream = try!(TcpStream::connect(addr));
+        try!(self.stream.set_write_timeout(dur));
+
+        Ok(())
+    }
+
+    /// Unbind and shut down both halves of the TCP connection
+    ///
+    /// Unlike just dropping the connection this reports whether the UnbindRequest went out.
+    pub fn close(mut self) -> Result<()>
+    {
+        try!(self.unbind());
+        try!(self.stream.shutdown(Shutdown::Both));
+
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> Drop for LDAP<S>
+{
+    // Best effort only, the stream itself closes the connection once it is dropped
+    fn drop(&mut self)
+    {
+        if self.unbound
+        {
+            return;
+        }
 
-        Ok(LDAP
+        if let Err(_err) = self.unbind()
+        {
+            #[cfg(feature = "log")]
+            warn!("Unbinding a dropped connection failed: {:?}", _err);
+        }
+    }
+}
+
+// Connect to the first of the addresses `addr` resolves to that accepts, in their order
+//
+// If none does the error lists every address with the reason it failed.
+fn connect_first<A, F>(addr: A, connect: F) -> Result<TcpStream>
+    where A: ToSocketAddrs, F: Fn(&SocketAddr) -> io::Result<TcpStream>
+{
+    let mut failed = Vec::new();
+
+    for addr in try!(addr.to_socket_addrs())
+    {
+        match connect(&addr)
+        {
+            Ok(stream) => return Ok(stream),
+            Err(err) => failed.push((addr, err)),
+        }
+    }
+
+    if failed.is_empty()
+    {
+        return Err(LDAPError::from(io::Error::new(io::ErrorKind::InvalidInput, "No address to connect to")));
+    }
+
+    Err(LDAPError::Connect(failed))
+}
+
+impl<S: Read + Write> LDAP<S>
+{
+    /// Use an already established `stream` as transport for the LDAP connection
+    pub fn from_stream(stream: S) -> LDAP<S>
+    {
+        LDAP
         {
             stream: stream,
             msgid: 0,
-        })
+            pending: VecDeque::new(),
+            response_controls: Vec::new(),
+            scratch: Vec::new(),
+            follow_referrals: false,
+            version: bind::LDAP_VERSION,
+            notifications: VecDeque::new(),
+            unbound: false,
+            abandoned: Vec::new(),
+            msgid_wrapped: false,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
     }
 
-    fn send(&mut self, tag: common::Tag) -> Result<()>
+    // Carry the state of this connection over to `stream`, which continues it from here
+    //
+    // Dropping `self` afterwards won't unbind, `stream` is still using the connection.
+    #[cfg(any(feature = "tls", feature = "gssapi"))]
+    fn continue_on<T: Read + Write>(&mut self, stream: T) -> LDAP<T>
     {
-        println!("Sending tag: {:?}", tag);
-        let tagbuf = try!(ber::encode(tag, self.msgid));
-        try!(self.stream.write(tagbuf.as_slice()));
+        self.unbound = true;
 
-        Ok(())
+        LDAP
+        {
+            stream: stream,
+            msgid: self.msgid,
+            pending: mem::replace(&mut self.pending, VecDeque::new()),
+            response_controls: mem::replace(&mut self.response_controls, Vec::new()),
+            scratch: mem::replace(&mut self.scratch, Vec::new()),
+            follow_referrals: self.follow_referrals,
+            version: self.version,
+            notifications: mem::replace(&mut self.notifications, VecDeque::new()),
+            unbound: false,
+            abandoned: mem::replace(&mut self.abandoned, Vec::new()),
+            msgid_wrapped: self.msgid_wrapped,
+            max_message_size: self.max_message_size,
+        }
+    }
+
+    /// Controls the server attached to the response of the last operation
+    ///
+    /// For a search these are the controls of the SearchResultDone.
+    pub fn response_controls(&self) -> &[Control]
+    {
+        &self.response_controls
     }
 
+    /// Make searches follow referrals to other servers instead of failing or skipping them
+    ///
+    /// The referred servers are searched over new, anonymous connections, and at most
+    /// `search::MAX_REFERRAL_HOPS` referrals are followed in a row. Off by default.
+    pub fn set_follow_referrals(&mut self, follow: bool)
+    {
+        self.follow_referrals = follow;
+    }
+
+    /// Refuse messages from the server with more than `max` bytes of content
+    ///
+    /// Defaults to `DEFAULT_MAX_MESSAGE_SIZE`. Receiving a bigger message fails with
+    /// `SizeLimitExceeded` before its content is read, which leaves the connection unusable.
+    pub fn set_max_message_size(&mut self, max: usize)
+    {
+        self.max_message_size = max;
+    }
+
+    /// Bind with protocol `version` instead of the default `bind::LDAP_VERSION`
+    ///
+    /// Only needed for the rare server still speaking nothing but LDAPv2. The requests stay
+    /// the same, so features v2 lacks like controls must not be used then.
+    pub fn set_version(&mut self, version: u8)
+    {
+        self.version = version;
+    }
+
+    /// Send `tag` as protocolOp of a new LDAPMessage with `controls`, returning the message ID
+    /// assigned to it.
+    fn send(&mut self, tag: common::Tag, controls: &[Control]) -> Result<i32>
+    {
+        let msgid = self.next_msgid();
+
+        #[cfg(feature = "log")]
+        {
+            if log_enabled!(log::Level::Trace)
+            {
+                let mut logged = tag.clone();
+                redact(&mut logged);
+                trace!("Sending message {}:\n{}", msgid, logged.pretty());
+            }
+        }
+
+        // Encode the whole message first instead of doing a syscall for each tag
+        self.scratch.clear();
+        try!(ber::encode_into_with_controls(&tag, msgid, controls, &mut self.scratch));
+        try!(self.stream.write_all(&self.scratch));
+        try!(self.stream.flush());
+
+        Ok(msgid)
+    }
+
+    // Message ID 0 is reserved for unsolicited notifications, so we start counting at 1 and wrap
+    // around back to 1 once we run out of IDs.
+    fn next_msgid(&mut self) -> i32
+    {
+        if self.msgid == i32::max_value()
+        {
+            self.msgid = 1;
+            self.msgid_wrapped = true;
+        }
+        else
+        {
+            self.msgid += 1;
+        }
+
+        // Responses with a reused ID belong to the new operation
+        let msgid = self.msgid;
+        self.abandoned.retain(|&id| id != msgid);
+
+        self.msgid
+    }
+
+    /// Unsolicited notifications the server sent so far, oldest first
+    ///
+    /// They are never returned by `recv` or `recv_matching` but kept here until taken. Once a
+    /// Notice of Disconnection arrives, the operation waiting for a response fails with the
+    /// `Server` error the notice carries.
+    pub fn take_notifications(&mut self) -> Vec<extended::Notification>
+    {
+        self.notifications.drain(..).collect()
+    }
+
+    /// Receive the next LDAPMessage, handing out messages queued by `recv_matching` first
     pub fn recv(&mut self) -> Result<common::Tag>
     {
-        let mut buf = [0; 500];
+        if let Some((_, tag)) = self.pending.pop_front()
+        {
+            return Ok(tag);
+        }
+
+        loop
+        {
+            let tag = try!(self.read_message());
+            let id = try!(envelope_msgid(&tag));
+
+            if id == 0
+            {
+                try!(self.notification(tag));
+                continue;
+            }
+
+            if !self.abandoned.contains(&id)
+            {
+                return Ok(tag);
+            }
+        }
+    }
+
+    /// Receive the LDAPMessage with message ID `msgid`
+    ///
+    /// Messages for other IDs read in the meantime are queued and returned by later calls to
+    /// `recv` or `recv_matching`. Unsolicited notifications go to `take_notifications`.
+    pub fn recv_matching(&mut self, msgid: i32) -> Result<common::Tag>
+    {
+        if let Some(pos) = self.pending.iter().position(|&(id, _)| id == msgid)
+        {
+            let (_, tag) = self.pending.remove(pos).unwrap();
+            return Ok(tag);
+        }
+
+        loop
+        {
+            let tag = try!(self.read_message());
+            let id = try!(envelope_msgid(&tag));
 
-        let readamount = try!(self.stream.read(&mut buf));
-        println!("read: {}", readamount);
+            if id == msgid
+            {
+                return Ok(tag);
+            }
+
+            if id == 0
+            {
+                try!(self.notification(tag));
+                continue;
+            }
+
+            if !self.abandoned.contains(&id)
+            {
+                self.pending.push_back((id, tag));
+            }
+        }
+    }
+
+    // Keep the unsolicited notification in `envelope`, failing if the server is about to hang up
+    fn notification(&mut self, envelope: common::Tag) -> Result<()>
+    {
+        let (_, protocol_op, _) = try!(protocol::deconstruct_envelope(envelope));
+        let notification = extended::Notification { response: try!(extended::parse_extended_response(protocol_op)) };
 
-        let tag = try!(ber::decode(&mut buf));
-        println!("Received tag: {:?}", tag);
+        let disconnect = notification.disconnect_error();
+        self.notifications.push_back(notification);
+
+        match disconnect
+        {
+            Some(err) => Err(LDAPError::from(err)),
+            None => Ok(()),
+        }
+    }
+
+    fn read_message(&mut self) -> Result<common::Tag>
+    {
+        // Decoding straight off the stream reads responses whole, up to the size limit
+        let tag = try!(ber::decode_from_reader_bounded(&mut self.stream, self.max_message_size));
+        #[cfg(feature = "log")]
+        {
+            if log_enabled!(log::Level::Trace)
+            {
+                let mut logged = tag.clone();
+                if let common::Payload::Constructed(ref mut children) = logged._value
+                {
+                    if let Some(op) = children.get_mut(1)
+                    {
+                        redact(op);
+                    }
+                }
+                logged.recompute_size();
+                trace!("Received message:\n{}", logged.pretty());
+            }
+        }
 
         Ok(tag)
     }
+
+    /// Receive the response to `msgid`, returning its protocolOp and keeping its controls
+    fn recv_response(&mut self, msgid: i32) -> Result<common::Tag>
+    {
+        let envelope = try!(self.recv_matching(msgid));
+        let (_, protocol_op, controls) = try!(protocol::deconstruct_envelope(envelope));
+
+        self.response_controls = try!(control::parse_controls(controls));
+
+        Ok(protocol_op)
+    }
+
+    /// Send `op` and wait for its response, which has to be an [APPLICATION `response`] tag
+    /// holding only an LDAPResult.
+    fn exchange(&mut self, op: common::Tag, controls: &[Control], response: i64) -> Result<LDAPResult>
+    {
+        let msgid = try!(self.send(op, controls));
+        let protocol_op = try!(self.recv_response(msgid));
+
+        if protocol_op._type.class != common::Class::Application(response)
+        {
+            return Err(LDAPError::UnexpectedTag { expected: common::Class::Application(response), found: protocol_op._type.class });
+        }
+
+        let (result, _) = try!(LDAPResult::from_tag(protocol_op));
+
+        Ok(result)
+    }
+}
+
+/// Peek at the message ID of an LDAPMessage without taking it apart
+fn envelope_msgid(envelope: &common::Tag) -> Result<i32>
+{
+    let first = match envelope._value
+    {
+        common::Payload::Constructed(ref tags) => tags.first(),
+        common::Payload::Primitive(_) => None,
+    };
+
+    match first
+    {
+        Some(&common::Tag { _value: common::Payload::Primitive(ref bytes), .. }) =>
+        {
+            let id = try!(ber::types::decode_integer(bytes));
+            if id < 0 || id > i32::max_value() as i64
+            {
+                return Err(LDAPError::Protocol);
+            }
+
+            Ok(id as i32)
+        },
+        _ => Err(LDAPError::Protocol),
+    }
+}
+
+/// Replace the credentials in the protocolOp `op` of a bind before it gets logged
+///
+/// That covers the password of simple binds and the SASL credentials both client and
+/// server send. Other operations are left alone.
+#[cfg(feature = "log")]
+fn redact(op: &mut common::Tag)
+{
+    fn hide(tag: &mut common::Tag)
+    {
+        tag._value = common::Payload::Primitive(b"<redacted>".to_vec());
+    }
+
+    let class = op._type.class;
+    if let common::Payload::Constructed(ref mut children) = op._value
+    {
+        for (i, child) in children.iter_mut().enumerate()
+        {
+            match (class, i, child._type.class)
+            {
+                // BindRequest with a simple password
+                (common::Class::Application(0), 2, common::Class::ContextSpecific(0)) => hide(child),
+                // BindRequest with SaslCredentials, the credentials follow the mechanism
+                (common::Class::Application(0), 2, common::Class::ContextSpecific(3)) =>
+                {
+                    if let common::Payload::Constructed(ref mut sasl) = child._value
+                    {
+                        for credentials in sasl.iter_mut().skip(1)
+                        {
+                            hide(credentials);
+                        }
+                    }
+                },
+                // BindResponse with serverSaslCreds
+                (common::Class::Application(1), _, common::Class::ContextSpecific(7)) => hide(child),
+                _ => {},
+            }
+        }
+    }
+
+    op.recompute_size();
+}
+
+/// Encode an attribute description with its set of values, as used by Add and Modify
+fn build_attribute<'a, I: IntoIterator<Item = &'a [u8]>>(attr: &str, values: I) -> common::Tag
+{
+    let typetag = {
+        let class = common::Class::Universal(common::UniversalTypes::OctetString);
+        let pl = common::Payload::Primitive(attr.as_bytes().to_vec());
+
+        common::construct(class, pl)
+    };
+
+    let valstag = {
+        let vals = values.into_iter().map(|value| {
+            let class = common::Class::Universal(common::UniversalTypes::OctetString);
+            let pl = common::Payload::Primitive(value.to_vec());
+
+            common::construct(class, pl)
+        }).collect();
+
+        let class = common::Class::Universal(common::UniversalTypes::Set);
+        let pl = common::Payload::Constructed(vals);
+
+        common::construct(class, pl)
+    };
+
+    let class = common::Class::Universal(common::UniversalTypes::Sequence);
+    let pl = common::Payload::Constructed(vec![typetag, valstag]);
+
+    common::construct(class, pl)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::cmp;
+    use std::io::{self, Cursor, Read, Write};
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    use protocol::error::LDAPError;
+
+    use protocol::ber::{self, common};
+    use protocol::ber::error::ASN1Error;
+
+    use LDAP;
+
+    /// Reader handing out its data in chunks of at most `chunk` bytes
+    struct ChunkedReader
+    {
+        data: Vec<u8>,
+        pos: usize,
+        chunk: usize,
+    }
+
+    impl Read for ChunkedReader
+    {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+        {
+            let amount = cmp::min(cmp::min(self.chunk, buf.len()), self.data.len() - self.pos);
+            buf[..amount].copy_from_slice(&self.data[self.pos..self.pos + amount]);
+            self.pos += amount;
+
+            Ok(amount)
+        }
+    }
+
+    impl Write for ChunkedReader
+    {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+        {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()>
+        {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn send_and_recv_over_cursor()
+    {
+        let tag = {
+            let class = common::Class::Application(2);
+            let pl = common::Payload::Primitive(Vec::new());
+
+            common::construct(class, pl)
+        };
+        let expected = ber::encode(tag, 1).unwrap();
+
+        let mut ld = LDAP::from_stream(Cursor::new(Vec::new()));
+        ld.send({
+            let class = common::Class::Application(2);
+            let pl = common::Payload::Primitive(Vec::new());
+
+            common::construct(class, pl)
+        }, &[]).unwrap();
+
+        assert_eq!(ld.stream.get_ref(), &expected);
+
+        // Rewind so we read back what we just wrote
+        ld.stream.set_position(0);
+        let received = ld.recv().unwrap();
+
+        assert_eq!(received, ber::decode(&expected).unwrap());
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn traffic_goes_to_the_logger()
+    {
+        use std::sync::Mutex;
+
+        use log::{self, LevelFilter, Log, Metadata, Record};
+
+        use mock::MockStream;
+
+        struct TestLogger(Mutex<Vec<String>>);
+
+        impl Log for TestLogger
+        {
+            fn enabled(&self, _: &Metadata) -> bool { true }
+            fn log(&self, record: &Record) { self.0.lock().unwrap().push(record.args().to_string()); }
+            fn flush(&self) {}
+        }
+
+        static LOGGER: TestLogger = TestLogger(Mutex::new(Vec::new()));
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(LevelFilter::Trace);
+
+        let tag = {
+            let class = common::Class::Application(2);
+            let pl = common::Payload::Primitive(Vec::new());
+
+            common::construct(class, pl)
+        };
+
+        let mut ld = LDAP::from_stream(Cursor::new(Vec::new()));
+        ld.send(tag, &[]).unwrap();
+        ld.stream.set_position(0);
+        ld.recv().unwrap();
+
+        {
+            let records = LOGGER.0.lock().unwrap();
+            assert!(records.iter().any(|r| r == "Sending message 1:\n[APPLICATION 2] (len 0)\n"));
+            assert!(records.iter().any(|r| r.starts_with("Received message:\nSequence (len 5)\n")));
+        }
+
+        // A simple bind, then a SASL bind with a round of challenge and response
+        let mut response = vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x61, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00];
+        response.extend_from_slice(&[0x30, 0x1b, 0x02, 0x01, 0x02, 0x61, 0x16, 0x0a, 0x01, 0x0e, 0x04, 0x00, 0x04, 0x00, 0x87, 0x0d]);
+        response.extend_from_slice(b"server-secret");
+        response.extend_from_slice(&[0x30, 0x0c, 0x02, 0x01, 0x03, 0x61, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00]);
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        ld.simple_bind("cn=admin,dc=example,dc=com", "hunter2").unwrap();
+        ld.sasl_bind("DIGEST-MD5", Some(b"initial-secret"), |challenge| {
+            assert_eq!(challenge, b"server-secret");
+            Ok(b"client-secret".to_vec())
+        }).unwrap();
+
+        let records = LOGGER.0.lock().unwrap();
+        assert!(records.iter().any(|r| r.contains("cn=admin,dc=example,dc=com") && r.contains("<redacted>")));
+        assert!(records.iter().any(|r| r.contains("DIGEST-MD5") && r.contains("<redacted>")));
+        for secret in &["hunter2", "initial-secret", "client-secret", "server-secret"]
+        {
+            let hex: Vec<String> = secret.bytes().map(|b| format!("{:02x}", b)).collect();
+            for record in records.iter()
+            {
+                assert!(!record.contains(secret), "{:?} was logged: {}", secret, record);
+                assert!(!record.contains(&hex.join(" ")), "{:?} was logged: {}", secret, record);
+            }
+        }
+    }
+
+    #[test]
+    fn send_assigns_incrementing_msgids()
+    {
+        let mut ld = LDAP::from_stream(Cursor::new(Vec::new()));
+
+        for expected in 1..4
+        {
+            let tag = {
+                let class = common::Class::Application(2);
+                let pl = common::Payload::Primitive(Vec::new());
+
+                common::construct(class, pl)
+            };
+
+            assert_eq!(ld.send(tag, &[]).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn msgid_wraps_around_to_one()
+    {
+        let mut ld = LDAP::from_stream(Cursor::new(Vec::new()));
+        ld.msgid = i32::max_value() - 1;
+
+        assert_eq!(ld.next_msgid(), i32::max_value());
+        assert_eq!(ld.next_msgid(), 1);
+    }
+
+    #[test]
+    fn read_tag_reassembles_chunks()
+    {
+        let tag = {
+            let class = common::Class::Universal(common::UniversalTypes::OctetString);
+            let pl = common::Payload::Primitive(vec![0x61; 1000]);
+
+            common::construct(class, pl)
+        };
+        let bytes = ber::encode(tag, 1).unwrap();
+
+        // Trailing data of the next message must be left alone
+        let mut data = bytes.clone();
+        data.extend_from_slice(&[0x30, 0x00]);
+
+        let mut ld = LDAP::from_stream(ChunkedReader { data: data, pos: 0, chunk: 7 });
+        let read = ld.recv().unwrap();
+
+        assert_eq!(read, ber::decode(&bytes).unwrap());
+        assert_eq!(ld.stream.pos, bytes.len());
+    }
+
+    #[test]
+    fn recv_enforces_size_limit()
+    {
+        let tag = {
+            let class = common::Class::Universal(common::UniversalTypes::OctetString);
+            let pl = common::Payload::Primitive(vec![0x61; 1000]);
+
+            common::construct(class, pl)
+        };
+        let bytes = ber::encode(tag, 1).unwrap();
+
+        let mut ld = LDAP::from_stream(ChunkedReader { data: bytes, pos: 0, chunk: 7 });
+        ld.set_max_message_size(512);
+        match ld.recv()
+        {
+            Err(LDAPError::ASN1(ASN1Error::SizeLimitExceeded)) => {},
+            x => panic!("Expected SizeLimitExceeded, got {:?}", x),
+        }
+
+        // Given up after the length, before any of the content was read
+        assert!(ld.stream.pos < 10);
+    }
+
+    #[test]
+    fn read_tag_fails_on_short_stream()
+    {
+        let mut ld = LDAP::from_stream(ChunkedReader { data: vec![0x30, 0x05, 0x02, 0x01], pos: 0, chunk: 1 });
+
+        assert!(ld.recv().is_err());
+    }
+
+    #[test]
+    fn recv_matching_queues_other_responses()
+    {
+        let op = |id| {
+            let class = common::Class::Application(id);
+            let pl = common::Payload::Primitive(Vec::new());
+
+            common::construct(class, pl)
+        };
+
+        // The response to message 2 arrives before the one to message 1
+        let mut data = ber::encode(op(11), 2).unwrap();
+        data.extend(ber::encode(op(7), 1).unwrap());
+
+        let mut ld = LDAP::from_stream(ChunkedReader { data: data, pos: 0, chunk: 3 });
+
+        let first = ld.recv_matching(1).unwrap();
+        assert_eq!(first, ber::decode(&ber::encode(op(7), 1).unwrap()).unwrap());
+        assert_eq!(ld.pending.len(), 1);
+
+        let second = ld.recv_matching(2).unwrap();
+        assert_eq!(second, ber::decode(&ber::encode(op(11), 2).unwrap()).unwrap());
+        assert!(ld.pending.is_empty());
+    }
+
+    #[test]
+    fn recv_times_out()
+    {
+        // Accepts the connection but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+        let mut ld = LDAP::connect(listener.local_addr().unwrap()).unwrap();
+        ld.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+        ld.set_write_timeout(Some(Duration::from_millis(50))).unwrap();
+
+        match ld.recv()
+        {
+            Err(LDAPError::Io(ref err)) =>
+                assert!(err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut),
+            x => panic!("Expected a timeout, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn zero_timeouts_are_rejected()
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut ld = LDAP::connect(listener.local_addr().unwrap()).unwrap();
+
+        assert!(ld.set_read_timeout(Some(Duration::from_secs(0))).is_err());
+        assert!(ld.set_write_timeout(Some(Duration::from_secs(0))).is_err());
+    }
+
+    #[test]
+    fn connect_timeout_sets_timeouts()
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let timeout = Duration::from_millis(200);
+
+        let ld = LDAP::connect_timeout(listener.local_addr().unwrap(), timeout).unwrap();
+
+        assert_eq!(ld.stream.read_timeout().unwrap(), Some(timeout));
+        assert_eq!(ld.stream.write_timeout().unwrap(), Some(timeout));
+    }
+
+    #[test]
+    fn connect_timeout_reports_errors()
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+
+        // A zero timeout is refused by the socket
+        assert!(LDAP::connect_timeout(listener.local_addr().unwrap(), Duration::from_secs(0)).is_err());
+        // Nothing to connect to
+        assert!(LDAP::connect_timeout(&[][..] as &[::std::net::SocketAddr], Duration::from_secs(1)).is_err());
+    }
+
+    // An address nothing listens on, found by binding and closing a listener
+    fn dead_addr() -> ::std::net::SocketAddr
+    {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+    }
+
+    #[test]
+    fn connect_skips_dead_addresses()
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addrs = [dead_addr(), listener.local_addr().unwrap()];
+
+        let ld = LDAP::connect(&addrs[..]).unwrap();
+        assert_eq!(ld.stream.peer_addr().unwrap(), addrs[1]);
+
+        let ld = LDAP::connect_timeout(&addrs[..], Duration::from_secs(1)).unwrap();
+        assert_eq!(ld.stream.peer_addr().unwrap(), addrs[1]);
+    }
+
+    #[test]
+    fn connect_lists_failed_addresses()
+    {
+        let addrs = [dead_addr(), dead_addr()];
+
+        match LDAP::connect_timeout(&addrs[..], Duration::from_secs(1))
+        {
+            Err(LDAPError::Connect(ref failed)) =>
+            {
+                let tried: Vec<_> = failed.iter().map(|&(addr, _)| addr).collect();
+                assert_eq!(tried, addrs);
+                assert!(failed.iter().all(|&(_, ref err)| err.kind() == io::ErrorKind::ConnectionRefused));
+            },
+            x => panic!("Expected Connect error, got {:?}", x),
+        }
+    }
+
+    // UnbindRequest as message 1
+    const UNBIND: [u8; 7] = [0x30, 0x05, 0x02, 0x01, 0x01, 0x42, 0x00];
+
+    #[test]
+    fn close_shuts_down_the_socket()
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let ld = LDAP::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        // Shares the socket but keeps it open, so only a shutdown ends the connection
+        let mut clone = ld.stream.try_clone().unwrap();
+
+        ld.close().unwrap();
+
+        let mut received = Vec::new();
+        server.read_to_end(&mut received).unwrap();
+        assert_eq!(received, UNBIND);
+        assert_eq!(clone.read(&mut [0; 1]).unwrap(), 0);
+        assert!(clone.write_all(&[0]).is_err());
+    }
+
+    #[test]
+    fn drop_unbinds_once()
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let received = |unbind_first: bool| {
+            let mut ld = LDAP::connect(listener.local_addr().unwrap()).unwrap();
+            let (mut server, _) = listener.accept().unwrap();
+            if unbind_first
+            {
+                ld.unbind().unwrap();
+            }
+            drop(ld);
+
+            let mut received = Vec::new();
+            server.read_to_end(&mut received).unwrap();
+            received
+        };
+
+        assert_eq!(received(false), UNBIND);
+        assert_eq!(received(true), UNBIND);
+    }
 }
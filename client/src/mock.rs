@@ -0,0 +1,132 @@
+//! In-memory transport for testing
+
+use std::io::{self, Cursor, Read, Write};
+#[cfg(feature = "tokio")]
+use std::cmp;
+#[cfg(feature = "tokio")]
+use std::pin::Pin;
+#[cfg(feature = "tokio")]
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use ReadTimeout;
+
+/// Stream replaying canned server responses and recording everything written to it
+#[derive(Debug)]
+pub struct MockStream
+{
+    pub input: Cursor<Vec<u8>>,
+    pub output: Vec<u8>,
+    pub read_timeout: Option<Duration>,
+}
+
+impl MockStream
+{
+    pub fn new(input: Vec<u8>) -> MockStream
+    {
+        MockStream
+        {
+            input: Cursor::new(input),
+            output: Vec::new(),
+            read_timeout: None,
+        }
+    }
+}
+
+impl ReadTimeout for MockStream
+{
+    fn read_timeout(&self) -> io::Result<Option<Duration>>
+    {
+        Ok(self.read_timeout)
+    }
+
+    fn set_read_timeout(&mut self, dur: Option<Duration>) -> io::Result<()>
+    {
+        self.read_timeout = dur;
+
+        Ok(())
+    }
+}
+
+impl Read for MockStream
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        self.input.read(buf)
+    }
+}
+
+impl Write for MockStream
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+    {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()>
+    {
+        Ok(())
+    }
+}
+
+/// Async version of `MockStream`, returning `Pending` before every read to exercise wakeups
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct AsyncMockStream
+{
+    pub inner: MockStream,
+    ready: bool,
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncMockStream
+{
+    pub fn new(input: Vec<u8>) -> AsyncMockStream
+    {
+        AsyncMockStream
+        {
+            inner: MockStream::new(input),
+            ready: false,
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl ::tokio::io::AsyncRead for AsyncMockStream
+{
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context, buf: &mut ::tokio::io::ReadBuf) -> Poll<io::Result<()>>
+    {
+        if !self.ready
+        {
+            self.ready = true;
+            cx.waker().wake_by_ref();
+
+            return Poll::Pending;
+        }
+        self.ready = false;
+
+        // Hand out a few bytes at a time so messages arrive in pieces
+        let mut chunk = [0u8; 3];
+        let amount = cmp::min(3, buf.remaining());
+        Poll::Ready(self.inner.read(&mut chunk[..amount]).map(|amount| buf.put_slice(&chunk[..amount])))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl ::tokio::io::AsyncWrite for AsyncMockStream
+{
+    fn poll_write(mut self: Pin<&mut Self>, _: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>>
+    {
+        Poll::Ready(self.inner.write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>>
+    {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>>
+    {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,230 @@
+//! Modify helper functions
+
+use std::io::{Read, Write};
+
+use protocol::ber::common;
+use protocol::ber::types;
+use protocol::control::Control;
+use protocol::error::{LDAPError, LdapError};
+use protocol::result::ResultCode;
+use protocol::schema::Schema;
+
+use build_attribute;
+use LDAP;
+use Result;
+
+/// A single change to an entry, made up of an attribute description and values
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Modification
+{
+    /// Add values to the attribute, creating it if necessary
+    Add(String, Vec<Vec<u8>>),
+    /// Delete the listed values, or the whole attribute if none are listed
+    Delete(String, Vec<Vec<u8>>),
+    /// Replace all values of the attribute. No values deletes the attribute.
+    Replace(String, Vec<Vec<u8>>),
+}
+
+impl<S: Read + Write> LDAP<S>
+{
+    /// Apply `changes` to the entry `dn`
+    ///
+    /// The server applies the changes in order and as a whole: either all of them succeed or
+    /// the entry is left untouched. Any resultCode but success comes back as a `Server` error.
+    pub fn modify(&mut self, dn: &str, changes: Vec<Modification>, controls: &[Control]) -> Result<ResultCode>
+    {
+        let result = try!(self.exchange(build_modify_request(dn, &changes), controls, 7));
+
+        match result.result_code
+        {
+            ResultCode::success => Ok(result.result_code),
+            _ => Err(LDAPError::from(LdapError::from(result))),
+        }
+    }
+
+    /// Like `modify`, but first checks `changes` against the object `classes` of the entry
+    ///
+    /// Attributes that get values have to be allowed by the classes and no MUST attribute may
+    /// be deleted or replaced with nothing, see `Schema::check_modification`. A change that
+    /// breaks these rules fails with a `ContentRule` error without contacting the server.
+    /// Deleting single values isn't checked, as only the server knows whether they are the last
+    /// ones. `classes` are taken as they are after the change.
+    pub fn modify_validated(&mut self, schema: &Schema, dn: &str, classes: &[&str], changes: Vec<Modification>,
+                            controls: &[Control]) -> Result<ResultCode>
+    {
+        {
+            let mut added = Vec::new();
+            let mut removed = Vec::new();
+
+            for change in &changes
+            {
+                match *change
+                {
+                    Modification::Add(ref attr, _) => added.push(&attr[..]),
+                    Modification::Replace(ref attr, ref values) if !values.is_empty() => added.push(&attr[..]),
+                    Modification::Replace(ref attr, _) => removed.push(&attr[..]),
+                    Modification::Delete(ref attr, ref values) if values.is_empty() => removed.push(&attr[..]),
+                    Modification::Delete(..) => {},
+                }
+            }
+
+            try!(schema.check_modification(classes, &added, &removed));
+        }
+
+        self.modify(dn, changes, controls)
+    }
+}
+
+fn build_modify_request(dn: &str, changes: &[Modification]) -> common::Tag
+{
+    let objecttag = {
+        let class = common::Class::Universal(common::UniversalTypes::OctetString);
+        let pl = common::Payload::Primitive(dn.as_bytes().to_vec());
+
+        common::construct(class, pl)
+    };
+
+    let changestag = {
+        let changes = changes.iter().map(|change| {
+            let (operation, attr, values) = match *change
+            {
+                Modification::Add(ref attr, ref values) => (0, attr, values),
+                Modification::Delete(ref attr, ref values) => (1, attr, values),
+                Modification::Replace(ref attr, ref values) => (2, attr, values),
+            };
+
+            let operationtag = types::encode_enumerated(operation);
+
+            let class = common::Class::Universal(common::UniversalTypes::Sequence);
+            let pl = common::Payload::Constructed(vec![operationtag,
+                                                       build_attribute(attr, values.iter().map(|v| &v[..]))]);
+
+            common::construct(class, pl)
+        }).collect();
+
+        let class = common::Class::Universal(common::UniversalTypes::Sequence);
+        let pl = common::Payload::Constructed(changes);
+
+        common::construct(class, pl)
+    };
+
+    let class = common::Class::Application(6);
+    let pl = common::Payload::Constructed(vec![objecttag, changestag]);
+
+    common::construct(class, pl)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use protocol::control::Control;
+    use protocol::error::{LDAPError, LdapError};
+    use protocol::result::ResultCode;
+
+    use protocol::schema::{ObjectClass, Schema};
+
+    use mock::MockStream;
+    use LDAP;
+
+    use super::Modification;
+
+    #[test]
+    fn modify_replace_layout()
+    {
+        // ModifyResponse: success
+        let response = vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x67, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00];
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        let changes = vec![Modification::Replace("mail".to_string(), vec![b"alice@example.com".to_vec()])];
+        let code = ld.modify("cn=alice,dc=example,dc=com", changes, &[]).unwrap();
+
+        assert_eq!(code, ResultCode::success);
+
+        let expected = [
+            0x66, 0x40, 0x04, 0x1a, 0x63, 0x6e, 0x3d, 0x61, 0x6c, 0x69, 0x63, 0x65, 0x2c, 0x64, 0x63, 0x3d,
+            0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2c, 0x64, 0x63, 0x3d, 0x63, 0x6f, 0x6d,
+            // changes, a single replace
+            0x30, 0x22, 0x30, 0x20, 0x0a, 0x01, 0x02,
+            // mail: alice@example.com
+            0x30, 0x1b, 0x04, 0x04, 0x6d, 0x61, 0x69, 0x6c, 0x31, 0x13, 0x04, 0x11, 0x61, 0x6c, 0x69, 0x63,
+            0x65, 0x40, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2e, 0x63, 0x6f, 0x6d,
+        ];
+
+        assert!(ld.stream.output.ends_with(&expected));
+    }
+
+    #[test]
+    fn modify_reports_failure()
+    {
+        // ModifyResponse: noSuchAttribute
+        let response = vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x67, 0x07, 0x0a, 0x01, 0x10, 0x04, 0x00, 0x04, 0x00];
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        let changes = vec![Modification::Delete("mail".to_string(), vec![b"bob@example.com".to_vec()])];
+
+        match ld.modify("cn=alice,dc=example,dc=com", changes, &[])
+        {
+            Err(LDAPError::Server(LdapError { result_code: ResultCode::noSuchAttribute, .. })) => {},
+            x => panic!("Expected noSuchAttribute, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn modify_as_other_user_denied()
+    {
+        // ModifyResponse: authorizationDenied
+        let response = vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x67, 0x07, 0x0a, 0x01, 0x7b, 0x04, 0x00, 0x04, 0x00];
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        let changes = vec![Modification::Replace("mail".to_string(), vec![b"bob@example.com".to_vec()])];
+        let proxy = Control::proxy_auth("dn:cn=bob,dc=example,dc=com");
+
+        match ld.modify("cn=alice,dc=example,dc=com", changes, &[proxy])
+        {
+            Err(LDAPError::Server(LdapError { result_code: ResultCode::authorizationDenied, .. })) => {},
+            x => panic!("Expected authorizationDenied, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn modify_validated_keeps_required_attributes()
+    {
+        let schema = Schema
+        {
+            object_classes: vec![
+                ObjectClass::parse("( 2.5.6.0 NAME 'top' ABSTRACT MUST objectClass )").unwrap(),
+                ObjectClass::parse("( 2.5.6.6 NAME 'person' SUP top STRUCTURAL MUST ( sn $ cn ) MAY description )").unwrap(),
+            ],
+            ..Schema::default()
+        };
+
+        let mut ld = LDAP::from_stream(MockStream::new(Vec::new()));
+        let changes = vec![Modification::Replace("sn".to_string(), vec![])];
+
+        match ld.modify_validated(&schema, "cn=alice,dc=example,dc=com", &["person"], changes, &[])
+        {
+            Err(LDAPError::ContentRule { ref attr, .. }) if attr == "sn" => {},
+            x => panic!("Expected ContentRule, got {:?}", x),
+        }
+
+        let changes = vec![Modification::Add("mail".to_string(), vec![b"alice@example.com".to_vec()])];
+        match ld.modify_validated(&schema, "cn=alice,dc=example,dc=com", &["person"], changes, &[])
+        {
+            Err(LDAPError::ContentRule { ref attr, .. }) if attr == "mail" => {},
+            x => panic!("Expected ContentRule, got {:?}", x),
+        }
+        assert!(ld.stream.output.is_empty());
+
+        // ModifyResponse: success
+        let response = vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x67, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00];
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        let changes = vec![
+            Modification::Replace("description".to_string(), vec![b"Wonderland".to_vec()]),
+            // Only some values, the server knows whether any are left
+            Modification::Delete("cn".to_string(), vec![b"Alice".to_vec()]),
+        ];
+
+        assert_eq!(ld.modify_validated(&schema, "cn=alice,dc=example,dc=com", &["person"], changes, &[]).unwrap(),
+                   ResultCode::success);
+    }
+}
@@ -0,0 +1,122 @@
+//! Modify DN helper functions
+
+use std::io::{Read, Write};
+
+use protocol::ber::common;
+use protocol::ber::types;
+use protocol::control::Control;
+use protocol::error::{LDAPError, LdapError};
+use protocol::result::ResultCode;
+
+use LDAP;
+use Result;
+
+impl<S: Read + Write> LDAP<S>
+{
+    /// Rename the entry `dn` to `new_rdn` and optionally move it below `new_superior`
+    ///
+    /// With `delete_old_rdn` the attribute values of the old RDN are removed from the entry,
+    /// otherwise they are kept as ordinary attribute values. A resultCode other than success is
+    /// returned as a `Server` error.
+    pub fn modify_dn(&mut self, dn: &str, new_rdn: &str, delete_old_rdn: bool, new_superior: Option<&str>, controls: &[Control]) -> Result<ResultCode>
+    {
+        let result = try!(self.exchange(build_modify_dn_request(dn, new_rdn, delete_old_rdn, new_superior), controls, 13));
+
+        match result.result_code
+        {
+            ResultCode::success => Ok(result.result_code),
+            _ => Err(LDAPError::from(LdapError::from(result))),
+        }
+    }
+}
+
+fn build_modify_dn_request(dn: &str, new_rdn: &str, delete_old_rdn: bool, new_superior: Option<&str>) -> common::Tag
+{
+    let entrytag = {
+        let class = common::Class::Universal(common::UniversalTypes::OctetString);
+        let pl = common::Payload::Primitive(dn.as_bytes().to_vec());
+
+        common::construct(class, pl)
+    };
+
+    let newrdntag = {
+        let class = common::Class::Universal(common::UniversalTypes::OctetString);
+        let pl = common::Payload::Primitive(new_rdn.as_bytes().to_vec());
+
+        common::construct(class, pl)
+    };
+
+    let deleteoldrdntag = {
+        let class = common::Class::Universal(common::UniversalTypes::Boolean);
+        let pl = common::Payload::Primitive(types::encode_boolean(delete_old_rdn));
+
+        common::construct(class, pl)
+    };
+
+    let mut children = vec![entrytag, newrdntag, deleteoldrdntag];
+
+    // Only present when moving the entry
+    if let Some(new_superior) = new_superior
+    {
+        let class = common::Class::ContextSpecific(0);
+        let pl = common::Payload::Primitive(new_superior.as_bytes().to_vec());
+
+        children.push(common::construct(class, pl));
+    }
+
+    let class = common::Class::Application(12);
+    let pl = common::Payload::Constructed(children);
+
+    common::construct(class, pl)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use protocol::ber;
+    use protocol::result::ResultCode;
+
+    use mock::MockStream;
+    use LDAP;
+
+    #[test]
+    fn modify_dn_rename()
+    {
+        // ModifyDNResponse: success
+        let response = vec![0x30, 0x0c, 0x02, 0x01, 0x01, 0x6d, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00];
+
+        let mut ld = LDAP::from_stream(MockStream::new(response));
+        let code = ld.modify_dn("cn=old,dc=example,dc=com", "cn=new", true, None, &[]).unwrap();
+
+        assert_eq!(code, ResultCode::success);
+
+        let expected = vec![
+            0x30, 0x2a, 0x02, 0x01, 0x01, 0x6c, 0x25, 0x04, 0x18, 0x63, 0x6e, 0x3d, 0x6f, 0x6c, 0x64, 0x2c,
+            0x64, 0x63, 0x3d, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2c, 0x64, 0x63, 0x3d, 0x63, 0x6f,
+            0x6d,
+            // newrdn and deleteoldrdn
+            0x04, 0x06, 0x63, 0x6e, 0x3d, 0x6e, 0x65, 0x77, 0x01, 0x01, 0xff,
+        ];
+
+        assert_eq!(ld.stream.output, expected);
+    }
+
+    #[test]
+    fn modify_dn_move()
+    {
+        let tag = super::build_modify_dn_request("cn=old,dc=example,dc=com", "cn=old", false, Some("ou=people,dc=example,dc=com"));
+        let bytes = ber::encode(tag, 1).unwrap();
+
+        let expected = [
+            0x6c, 0x42, 0x04, 0x18, 0x63, 0x6e, 0x3d, 0x6f, 0x6c, 0x64, 0x2c, 0x64, 0x63, 0x3d, 0x65, 0x78,
+            0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2c, 0x64, 0x63, 0x3d, 0x63, 0x6f, 0x6d, 0x04, 0x06, 0x63, 0x6e,
+            0x3d, 0x6f, 0x6c, 0x64, 0x01, 0x01, 0x00,
+            // newSuperior
+            0x80, 0x1b, 0x6f, 0x75, 0x3d, 0x70, 0x65, 0x6f, 0x70,
+            0x6c, 0x65, 0x2c, 0x64, 0x63, 0x3d, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2c, 0x64, 0x63,
+            0x3d, 0x63, 0x6f, 0x6d,
+        ];
+
+        assert!(bytes.ends_with(&expected));
+    }
+}
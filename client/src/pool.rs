@@ -0,0 +1,302 @@
+//! A pool of connections shared between threads
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use LDAP;
+use ReadTimeout;
+use Result;
+
+/// Up to `max_size` connections, handed out to one user at a time
+///
+/// New connections are opened by the function given to `new`, which should bind them as well.
+/// Idle connections are checked with `is_alive_within` before they are handed out again, and
+/// replaced by a new one using that function if they died in the meantime or didn't answer in
+/// time.
+pub struct LdapPool<S: Read + Write = TcpStream>
+{
+    connect: Box<dyn Fn() -> Result<LDAP<S>> + Send + Sync>,
+    max_size: usize,
+    // How long an idle connection has to answer the liveness check
+    check_timeout: Duration,
+    state: Mutex<PoolState<S>>,
+    // Signalled whenever a connection is returned or closed
+    returned: Condvar,
+}
+
+struct PoolState<S: Read + Write>
+{
+    idle: Vec<LDAP<S>>,
+    // Connections idle or checked out
+    open: usize,
+}
+
+/// A connection checked out of an `LdapPool`, returned to it when dropped
+pub struct PooledConnection<'a, S: 'a + Read + Write>
+{
+    pool: &'a LdapPool<S>,
+    ldap: Option<LDAP<S>>,
+}
+
+impl<S: Read + Write> LdapPool<S>
+{
+    /// Create a pool of at most `max_size` connections opened by `connect`
+    ///
+    /// No connection is opened before the first `checkout`.
+    pub fn new<F>(max_size: usize, connect: F) -> LdapPool<S>
+        where F: Fn() -> Result<LDAP<S>> + Send + Sync + 'static
+    {
+        assert!(max_size > 0, "A pool needs room for at least one connection");
+
+        LdapPool
+        {
+            connect: Box::new(connect),
+            max_size: max_size,
+            check_timeout: Duration::from_secs(5),
+            state: Mutex::new(PoolState { idle: Vec::new(), open: 0 }),
+            returned: Condvar::new(),
+        }
+    }
+
+    /// Give up on an idle connection if it doesn't answer the liveness check within `timeout`
+    ///
+    /// Defaults to five seconds.
+    pub fn set_check_timeout(&mut self, timeout: Duration)
+    {
+        self.check_timeout = timeout;
+    }
+
+    /// Number of connections currently open, idle or checked out
+    pub fn open_connections(&self) -> usize
+    {
+        self.state.lock().unwrap().open
+    }
+
+    // Forget about a connection that is gone, making room for a new one
+    fn close(&self)
+    {
+        self.state.lock().unwrap().open -= 1;
+        self.returned.notify_one();
+    }
+}
+
+impl<S: Read + Write + ReadTimeout> LdapPool<S>
+{
+    /// Take a connection out of the pool, waiting for one to be returned if all are in use
+    ///
+    /// Fails only if a new connection had to be opened and that failed.
+    pub fn checkout<'a>(&'a self) -> Result<PooledConnection<'a, S>>
+    {
+        loop
+        {
+            let mut state = self.state.lock().unwrap();
+
+            if let Some(mut ldap) = state.idle.pop()
+            {
+                // Don't hold the lock while talking to the server
+                drop(state);
+
+                if ldap.is_alive_within(self.check_timeout)
+                {
+                    return Ok(PooledConnection { pool: self, ldap: Some(ldap) });
+                }
+
+                self.close();
+                continue;
+            }
+
+            if state.open < self.max_size
+            {
+                state.open += 1;
+                drop(state);
+
+                return match (self.connect)()
+                {
+                    Ok(ldap) => Ok(PooledConnection { pool: self, ldap: Some(ldap) }),
+                    Err(err) =>
+                    {
+                        self.close();
+                        Err(err)
+                    },
+                };
+            }
+
+            while state.idle.is_empty() && state.open >= self.max_size
+            {
+                state = self.returned.wait(state).unwrap();
+            }
+        }
+    }
+}
+
+impl<'a, S: Read + Write> PooledConnection<'a, S>
+{
+    /// Close the connection instead of returning it to the pool
+    ///
+    /// Useful after errors that leave the connection in an unknown state.
+    pub fn discard(mut self)
+    {
+        self.ldap = None;
+        self.pool.close();
+    }
+}
+
+impl<'a, S: Read + Write> Deref for PooledConnection<'a, S>
+{
+    type Target = LDAP<S>;
+
+    fn deref(&self) -> &LDAP<S>
+    {
+        self.ldap.as_ref().unwrap()
+    }
+}
+
+impl<'a, S: Read + Write> DerefMut for PooledConnection<'a, S>
+{
+    fn deref_mut(&mut self) -> &mut LDAP<S>
+    {
+        self.ldap.as_mut().unwrap()
+    }
+}
+
+impl<'a, S: Read + Write> Drop for PooledConnection<'a, S>
+{
+    fn drop(&mut self)
+    {
+        if let Some(ldap) = self.ldap.take()
+        {
+            self.pool.state.lock().unwrap().idle.push(ldap);
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use mock::MockStream;
+    use super::LdapPool;
+    use LDAP;
+
+    // A connection answering `count` WhoAmI requests, with message IDs 1 to `count`
+    fn whoami_server(count: u8) -> LDAP<MockStream>
+    {
+        let mut responses = Vec::new();
+        for msgid in 1..count + 1
+        {
+            responses.extend_from_slice(&[0x30, 0x0c, 0x02, 0x01, msgid, 0x78, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00]);
+        }
+
+        LDAP::from_stream(MockStream::new(responses))
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn concurrent_checkouts()
+    {
+        assert_send_sync::<LdapPool<MockStream>>();
+
+        let connects = Arc::new(AtomicUsize::new(0));
+        let pool = {
+            let connects = connects.clone();
+            Arc::new(LdapPool::new(3, move || {
+                connects.fetch_add(1, Ordering::SeqCst);
+                Ok(whoami_server(100))
+            }))
+        };
+        let in_use = Arc::new(AtomicUsize::new(0));
+
+        let threads: Vec<_> = (0..8).map(|_| {
+            let pool = pool.clone();
+            let in_use = in_use.clone();
+
+            thread::spawn(move || {
+                for _ in 0..10
+                {
+                    let _ldap = pool.checkout().unwrap();
+                    assert!(in_use.fetch_add(1, Ordering::SeqCst) < 3);
+                    thread::yield_now();
+                    in_use.fetch_sub(1, Ordering::SeqCst);
+                }
+            })
+        }).collect();
+
+        for thread in threads
+        {
+            thread.join().unwrap();
+        }
+
+        assert!(connects.load(Ordering::SeqCst) <= 3);
+        assert_eq!(pool.open_connections(), connects.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn dead_connections_are_replaced()
+    {
+        let connects = Arc::new(AtomicUsize::new(0));
+        let pool = {
+            let connects = connects.clone();
+            // The first connection dies right away, the second answers
+            LdapPool::new(1, move || {
+                let answers = if connects.fetch_add(1, Ordering::SeqCst) == 0 { 0 } else { 1 };
+                Ok(whoami_server(answers))
+            })
+        };
+
+        drop(pool.checkout().unwrap());
+        drop(pool.checkout().unwrap());
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+
+        // The second one was alive when it was checked out again
+        drop(pool.checkout().unwrap());
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.open_connections(), 1);
+
+        pool.checkout().unwrap().discard();
+        assert_eq!(pool.open_connections(), 0);
+    }
+
+    #[test]
+    fn silent_connections_are_replaced()
+    {
+        // Accepts connections, but never answers on them
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let peers: Vec<_> = listener.incoming().take(2).collect();
+            peers
+        });
+
+        let connects = Arc::new(AtomicUsize::new(0));
+        let mut pool = {
+            let connects = connects.clone();
+            LdapPool::new(1, move || {
+                connects.fetch_add(1, Ordering::SeqCst);
+                LDAP::connect(addr)
+            })
+        };
+        pool.set_check_timeout(Duration::from_millis(50));
+
+        drop(pool.checkout().unwrap());
+
+        // The check of the idle connection gets no answer, so a new one is opened
+        let start = Instant::now();
+        drop(pool.checkout().unwrap());
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(connects.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.open_connections(), 1);
+
+        drop(pool);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,1131 @@
+//! Search helper functions
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use protocol::ber::{self, common};
+use protocol::control::{Control, PagedResults, PAGED_RESULTS_OID, POST_READ_OID, PRE_READ_OID};
+use protocol::error::{LDAPError, LdapError};
+use protocol::filter::Filter;
+use protocol::result::{LDAPResult, ResultCode};
+use protocol::schema::{self, AttributeType, MatchingRule, ObjectClass, Schema};
+use protocol::search::{Scope, SearchRequestBuilder};
+use protocol::url::LdapUrl;
+
+use LDAP;
+use ReadTimeout;
+use Result;
+
+/// How many referrals in a row are followed before giving up, in case they lead in circles
+pub const MAX_REFERRAL_HOPS: usize = 10;
+
+/// Entry returned by a search
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchEntry
+{
+    /// DN of the entry
+    pub dn: String,
+    /// Every returned attribute description with its raw values, in the order the server sent
+    /// them. Attributes may come without values, e.g. when only types were requested.
+    pub attributes: Vec<(String, Vec<Vec<u8>>)>,
+}
+
+impl SearchEntry
+{
+    /// Values of the attribute `attr`, compared case insensitively as LDAP does
+    pub fn get(&self, attr: &str) -> Option<&Vec<Vec<u8>>>
+    {
+        self.attributes.iter()
+            .find(|&&(ref name, _)| name.eq_ignore_ascii_case(attr))
+            .map(|&(_, ref values)| values)
+    }
+
+    /// Raw values of the attribute `attr`, the way to get at binary attributes
+    pub fn get_bytes(&self, attr: &str) -> Option<&Vec<Vec<u8>>>
+    {
+        self.get(attr)
+    }
+
+    /// Values of the attribute `attr` as strings, for attributes known to be textual
+    ///
+    /// Values that aren't valid UTF-8 result in a `Protocol` error, and so do attributes
+    /// `is_binary` knows to hold bytes, even if their values happen to be valid UTF-8.
+    pub fn get_str(&self, attr: &str) -> Result<Option<Vec<String>>>
+    {
+        if self.is_binary(attr)
+        {
+            return Err(LDAPError::Protocol);
+        }
+
+        let values = match self.get(attr)
+        {
+            Some(values) => values,
+            None => return Ok(None),
+        };
+
+        let mut strings = Vec::with_capacity(values.len());
+        for value in values
+        {
+            strings.push(try!(String::from_utf8(value.clone()).map_err(|_| LDAPError::Protocol)));
+        }
+
+        Ok(Some(strings))
+    }
+
+    /// Whether `attr` holds binary values, judging by its name and options
+    ///
+    /// See `protocol::schema::is_known_binary`. `Schema::is_binary` knows better for
+    /// attributes defined in the server's schema.
+    pub fn is_binary(&self, attr: &str) -> bool
+    {
+        schema::is_known_binary(attr)
+    }
+}
+
+/// Everything a search returned
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SearchResult
+{
+    /// The entries found, in the order the server sent them
+    pub entries: Vec<SearchEntry>,
+    /// SearchResultReferences not followed, each with the URLs the rest of the search can be
+    /// continued at. The URLs of one reference are alternatives to each other.
+    pub references: Vec<Vec<String>>,
+}
+
+impl<S: Read + Write> LDAP<S>
+{
+    /// Search the directory
+    ///
+    /// Looks for entries matching `filter` in `scope` of `base_dn`, returning the attributes
+    /// listed in `attrs` for every one of them. An empty `attrs` returns all user attributes.
+    pub fn search(&mut self, base_dn: &str, scope: Scope, filter: Filter, attrs: &[&str], controls: &[Control]) -> Result<Vec<SearchEntry>>
+    {
+        let request = SearchRequestBuilder::new(base_dn).scope(scope).filter(filter).attributes(attrs);
+
+        self.search_with(&request, controls)
+    }
+
+    /// Search the directory with all parameters of the SearchRequest taken from `request`
+    pub fn search_with(&mut self, request: &SearchRequestBuilder, controls: &[Control]) -> Result<Vec<SearchEntry>>
+    {
+        self.search_with_references(request, controls).map(|result| result.entries)
+    }
+
+    /// Like `search_with`, but also returns the SearchResultReferences the server sent
+    ///
+    /// References that were followed because of `set_follow_referrals` aren't included,
+    /// the entries found there are.
+    pub fn search_with_references(&mut self, request: &SearchRequestBuilder, controls: &[Control]) -> Result<SearchResult>
+    {
+        self.search_hops(request, controls, 0, None)
+    }
+
+    /// Like `search_with`, but gives up once `deadline` passes
+    ///
+    /// The search is abandoned and fails with `Timeout` if its results haven't all arrived by
+    /// then, no matter how quickly the server sends each of them. Unlike the timeLimit of the
+    /// request the server knows nothing about the deadline.
+    ///
+    /// Reads are timed out at the deadline, and the read timeout of the connection is restored
+    /// afterwards. Should that interrupt a message, the connection can't be used any further.
+    pub fn search_with_deadline(&mut self, request: &SearchRequestBuilder, controls: &[Control], deadline: Instant)
+        -> Result<Vec<SearchEntry>>
+        where S: ReadTimeout
+    {
+        let previous = try!(self.stream.read_timeout());
+        let deadline = Deadline { at: deadline, read_timeout: previous, arm: S::set_read_timeout };
+
+        let result = self.search_hops(request, controls, 0, Some(&deadline));
+        try!(self.stream.set_read_timeout(previous));
+
+        result.map(|result| result.entries)
+    }
+
+    // `hops` counts the referrals followed to get to this connection
+    fn search_hops(&mut self, request: &SearchRequestBuilder, controls: &[Control], hops: usize, deadline: Option<&Deadline<S>>)
+        -> Result<SearchResult>
+    {
+        let follow = self.follow_referrals && hops < MAX_REFERRAL_HOPS;
+        let msgid = try!(self.send(try!(request.build()), controls));
+        let referral_deadline = deadline.map(|deadline| deadline.at);
+
+        let mut found = SearchResult::default();
+
+        loop
+        {
+            match deadline
+            {
+                // Results already received are used even when the deadline passed meanwhile
+                Some(deadline) if !self.pending.iter().any(|&(id, _)| id == msgid) =>
+                {
+                    let now = Instant::now();
+                    if now >= deadline.at
+                    {
+                        try!(self.abandon(msgid));
+                        return Err(LDAPError::Timeout);
+                    }
+
+                    let remaining = deadline.at - now;
+                    let timeout = deadline.read_timeout.map_or(remaining, |timeout| timeout.min(remaining));
+                    try!((deadline.arm)(&mut self.stream, Some(timeout)));
+                },
+                _ => {},
+            }
+
+            let protocol_op = match self.recv_response(msgid)
+            {
+                Err(LDAPError::Io(ref err)) if timed_out(err) && deadline.map_or(false, |deadline| Instant::now() >= deadline.at) =>
+                {
+                    try!(self.abandon(msgid));
+                    return Err(LDAPError::Timeout);
+                },
+                result => try!(result),
+            };
+
+            match protocol_op._type.class
+            {
+                // SearchResultEntry
+                common::Class::Application(4) => found.entries.push(try!(parse_search_entry(&protocol_op))),
+                // SearchResultDone
+                common::Class::Application(5) =>
+                {
+                    let (result, _) = try!(LDAPResult::from_tag(protocol_op));
+                    if result.result_code == ResultCode::referral && follow
+                    {
+                        if let Some(ref urls) = result.referral
+                        {
+                            found.append(try!(follow_referral(urls, request, false, controls, hops + 1, referral_deadline)));
+                            return Ok(found);
+                        }
+                    }
+                    if result.result_code != ResultCode::success
+                    {
+                        return Err(LDAPError::from(LdapError::from(result)));
+                    }
+
+                    return Ok(found);
+                },
+                // SearchResultReference, the rest of the results are found elsewhere
+                common::Class::Application(19) =>
+                {
+                    let urls = try!(parse_search_reference(&protocol_op));
+                    if follow
+                    {
+                        found.append(try!(follow_referral(&urls, request, true, controls, hops + 1, referral_deadline)));
+                    }
+                    else
+                    {
+                        found.references.push(urls);
+                    }
+                },
+                found => return Err(LDAPError::UnexpectedTag { expected: common::Class::Application(5), found: found }),
+            }
+        }
+    }
+
+    /// Search the directory in pages of `page_size` entries
+    ///
+    /// Works like `search`, but uses the paged results control to fetch all entries, even those
+    /// beyond a size limit the server imposes on single searches. A server that hands out the
+    /// same cookie twice or a page without entries under a cookie would keep this going
+    /// forever, so either fails with `Protocol`.
+    pub fn search_paged(&mut self, base_dn: &str, scope: Scope, filter: Filter, attrs: &[&str], page_size: i32) -> Result<Vec<SearchEntry>>
+    {
+        let mut entries = Vec::new();
+        let mut cookie = Vec::new();
+
+        loop
+        {
+            let control = PagedResults { size: page_size, cookie: cookie.clone() }.to_control();
+            let page = try!(self.search(base_dn, scope, filter.clone(), attrs, &[control]));
+            let empty = page.is_empty();
+            entries.extend(page);
+
+            // A server not supporting paging returns everything at once without the control
+            let next = match self.response_controls.iter().find(|c| c.oid == PAGED_RESULTS_OID)
+            {
+                Some(control) => try!(PagedResults::from_control(control)).cookie,
+                None => Vec::new(),
+            };
+
+            if next.is_empty()
+            {
+                return Ok(entries);
+            }
+
+            if empty || next == cookie
+            {
+                return Err(LDAPError::Protocol);
+            }
+
+            cookie = next;
+        }
+    }
+
+    /// The entry as it was before the last operation, returned for a `Control::pre_read`
+    ///
+    /// None if the server didn't return the control, e.g. because the operation failed.
+    pub fn pre_read_entry(&self) -> Result<Option<SearchEntry>>
+    {
+        self.read_entry(PRE_READ_OID)
+    }
+
+    /// The entry as it is after the last operation, returned for a `Control::post_read`
+    pub fn post_read_entry(&self) -> Result<Option<SearchEntry>>
+    {
+        self.read_entry(POST_READ_OID)
+    }
+
+    fn read_entry(&self, oid: &str) -> Result<Option<SearchEntry>>
+    {
+        match self.response_controls.iter().find(|c| c.oid == oid)
+        {
+            Some(control) => parse_read_entry(control).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Read the root DSE, the entry with the empty DN describing the server itself
+    ///
+    /// Its attributes like namingContexts, supportedControl, supportedSASLMechanisms,
+    /// supportedExtension and vendorName are operational, so servers only return those named in
+    /// `attrs`. Many servers accept `+` for all of them.
+    pub fn root_dse(&mut self, attrs: &[&str]) -> Result<SearchEntry>
+    {
+        let request = SearchRequestBuilder::new("")
+            .scope(Scope::BaseObject)
+            .filter(Filter::Present("objectClass".to_string()))
+            .attributes(attrs);
+
+        match try!(self.search_with(&request, &[])).into_iter().next()
+        {
+            Some(entry) => Ok(entry),
+            None => Err(LDAPError::from(io::Error::new(io::ErrorKind::NotFound, "Server did not return its root DSE"))),
+        }
+    }
+
+    /// OIDs of the controls the server announces in its root DSE
+    pub fn supported_controls(&mut self) -> Result<Vec<String>>
+    {
+        let entry = try!(self.root_dse(&["supportedControl"]));
+
+        Ok(try!(entry.get_str("supportedControl")).unwrap_or_default())
+    }
+
+    /// Read the schema from the subschema subentry the root DSE names
+    ///
+    /// Fails with a `SchemaParse` error if one of the definitions is malformed.
+    pub fn schema(&mut self) -> Result<Schema>
+    {
+        let dse = try!(self.root_dse(&["subschemaSubentry"]));
+        let dn = match try!(dse.get_str("subschemaSubentry")).and_then(|dns| dns.into_iter().next())
+        {
+            Some(dn) => dn,
+            None => return Err(LDAPError::from(io::Error::new(io::ErrorKind::NotFound, "Root DSE does not name a subschema subentry"))),
+        };
+
+        let request = SearchRequestBuilder::new(&dn)
+            .scope(Scope::BaseObject)
+            .filter(Filter::Equality { attr: "objectClass".to_string(), value: b"subschema".to_vec() })
+            .attributes(&["attributeTypes", "objectClasses", "matchingRules"]);
+        let entry = match try!(self.search_with(&request, &[])).into_iter().next()
+        {
+            Some(entry) => entry,
+            None => return Err(LDAPError::from(io::Error::new(io::ErrorKind::NotFound, "Server did not return the subschema subentry"))),
+        };
+
+        let mut schema = Schema::default();
+        for def in try!(entry.get_str("attributeTypes")).unwrap_or_default()
+        {
+            schema.attribute_types.push(try!(AttributeType::parse(&def)));
+        }
+        for def in try!(entry.get_str("objectClasses")).unwrap_or_default()
+        {
+            schema.object_classes.push(try!(ObjectClass::parse(&def)));
+        }
+        for def in try!(entry.get_str("matchingRules")).unwrap_or_default()
+        {
+            schema.matching_rules.push(try!(MatchingRule::parse(&def)));
+        }
+
+        Ok(schema)
+    }
+}
+
+/// Continue `request` at the first of `urls` that can be reached
+///
+/// The URLs of one referral are alternatives, so the others are only tried if one fails.
+fn follow_referral(urls: &[String], request: &SearchRequestBuilder, continuation: bool, controls: &[Control], hops: usize,
+                   deadline: Option<Instant>) -> Result<SearchResult>
+{
+    let mut last_err = None;
+
+    for url in urls
+    {
+        let result = LdapUrl::parse(url).and_then(|url| {
+            let request = request.referred(&url, continuation);
+            search_referred(&url, &request, controls, hops, deadline)
+        });
+
+        match result
+        {
+            Ok(entries) => return Ok(entries),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    // A referral has at least one URL
+    Err(last_err.unwrap_or(LDAPError::Protocol))
+}
+
+/// Connect anonymously to the server `url` names and search there
+fn search_referred(url: &LdapUrl, request: &SearchRequestBuilder, controls: &[Control], hops: usize, deadline: Option<Instant>)
+    -> Result<SearchResult>
+{
+    if url.host.is_empty()
+    {
+        return Err(LDAPError::from(io::Error::new(io::ErrorKind::InvalidInput, "Referral does not name a server")));
+    }
+
+    if url.ldaps
+    {
+        #[cfg(feature = "tls")]
+        {
+            let mut ld = try!(LDAP::connect_tls((&*url.host, url.port), &url.host));
+            ld.follow_referrals = true;
+            return ld.search_hops(request, controls, hops, referred_deadline(deadline).as_ref());
+        }
+
+        #[cfg(not(feature = "tls"))]
+        return Err(LDAPError::from(io::Error::new(io::ErrorKind::InvalidInput, "Following ldaps referrals needs the tls feature")));
+    }
+
+    let mut ld: LDAP<TcpStream> = try!(LDAP::connect((&*url.host, url.port)));
+    ld.follow_referrals = true;
+    ld.search_hops(request, controls, hops, referred_deadline(deadline).as_ref())
+}
+
+/// When a search has to be done by, and how to keep reads on its connection from taking longer
+struct Deadline<S>
+{
+    at: Instant,
+    // Read timeout set on the connection before the search, which still applies
+    read_timeout: Option<Duration>,
+    arm: fn(&mut S, Option<Duration>) -> io::Result<()>,
+}
+
+// A read timeout fails with either kind, depending on the platform
+fn timed_out(err: &io::Error) -> bool
+{
+    err.kind() == io::ErrorKind::WouldBlock || err.kind() == io::ErrorKind::TimedOut
+}
+
+// Referrals are followed over new connections, which have no read timeout of their own
+fn referred_deadline<S: ReadTimeout>(deadline: Option<Instant>) -> Option<Deadline<S>>
+{
+    deadline.map(|at| Deadline { at: at, read_timeout: None, arm: S::set_read_timeout })
+}
+
+impl SearchResult
+{
+    fn append(&mut self, other: SearchResult)
+    {
+        self.entries.extend(other.entries);
+        self.references.extend(other.references);
+    }
+}
+
+/// Parse a SearchResultReference into the URLs it refers to
+pub fn parse_search_reference(tag: &common::Tag) -> Result<Vec<String>>
+{
+    if tag._type.class != common::Class::Application(19)
+    {
+        return Err(LDAPError::UnexpectedTag { expected: common::Class::Application(19), found: tag._type.class });
+    }
+
+    let urls = try!(constructed(tag));
+    // SEQUENCE SIZE (1..MAX) OF uri
+    if urls.is_empty()
+    {
+        return Err(LDAPError::Protocol);
+    }
+
+    urls.iter().map(string).collect()
+}
+
+/// Parse the entry in the value of a pre-read or post-read response control
+pub fn parse_read_entry(control: &Control) -> Result<SearchEntry>
+{
+    match control.value
+    {
+        Some(ref value) => parse_search_entry(&try!(ber::decode(value))),
+        None => Err(LDAPError::Protocol),
+    }
+}
+
+/// Parse a SearchResultEntry into the DN and its attributes
+pub fn parse_search_entry(tag: &common::Tag) -> Result<SearchEntry>
+{
+    if tag._type.class != common::Class::Application(4)
+    {
+        return Err(LDAPError::UnexpectedTag { expected: common::Class::Application(4), found: tag._type.class });
+    }
+
+    let mut children = try!(constructed(tag)).iter();
+
+    let dn = match children.next()
+    {
+        Some(tag) => try!(string(tag)),
+        None => return Err(LDAPError::Protocol),
+    };
+
+    let list = match children.next()
+    {
+        Some(tag) => try!(constructed(tag)),
+        None => return Err(LDAPError::Protocol),
+    };
+
+    let mut attributes = Vec::with_capacity(list.len());
+
+    for attribute in list
+    {
+        let mut attribute = try!(constructed(attribute)).iter();
+
+        let (name, vals) = match (attribute.next(), attribute.next())
+        {
+            (Some(name), Some(vals)) => (try!(string(name)), try!(constructed(vals))),
+            _ => return Err(LDAPError::Protocol),
+        };
+
+        let mut values = Vec::with_capacity(vals.len());
+        for val in vals
+        {
+            values.push(try!(primitive(val)).to_vec());
+        }
+
+        attributes.push((name, values));
+    }
+
+    Ok(SearchEntry
+    {
+        dn: dn,
+        attributes: attributes,
+    })
+}
+
+fn constructed(tag: &common::Tag) -> Result<&[common::Tag]>
+{
+    match tag._value
+    {
+        common::Payload::Constructed(ref children) => Ok(children),
+        common::Payload::Primitive(_) => Err(LDAPError::Protocol),
+    }
+}
+
+fn primitive(tag: &common::Tag) -> Result<&[u8]>
+{
+    match tag._value
+    {
+        common::Payload::Primitive(ref bytes) => Ok(bytes),
+        common::Payload::Constructed(_) => Err(LDAPError::Protocol),
+    }
+}
+
+fn string(tag: &common::Tag) -> Result<String>
+{
+    String::from_utf8(try!(primitive(tag)).to_vec()).map_err(|_| LDAPError::Protocol)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::collections::VecDeque;
+    use std::io::{self, Cursor, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use protocol;
+    use protocol::ber::{self, common};
+    use protocol::control::{Control, PagedResults, POST_READ_OID};
+    use protocol::error::{LDAPError, LdapError};
+    use protocol::filter::Filter;
+    use protocol::result::ResultCode;
+    use protocol::search::{Scope, SearchRequestBuilder};
+
+    use mock::MockStream;
+    use modify::Modification;
+    use LDAP;
+    use ReadTimeout;
+
+    fn octet_string(value: &[u8]) -> common::Tag
+    {
+        let class = common::Class::Universal(common::UniversalTypes::OctetString);
+        common::construct(class, common::Payload::Primitive(value.to_vec()))
+    }
+
+    fn entry(dn: &str, attr: &str, values: &[&[u8]]) -> common::Tag
+    {
+        let vals = common::construct(common::Class::Universal(common::UniversalTypes::Set),
+            common::Payload::Constructed(values.iter().map(|v| octet_string(v)).collect()));
+        let partial = common::construct(common::Class::Universal(common::UniversalTypes::Sequence),
+            common::Payload::Constructed(vec![octet_string(attr.as_bytes()), vals]));
+        let list = common::construct(common::Class::Universal(common::UniversalTypes::Sequence),
+            common::Payload::Constructed(vec![partial]));
+
+        common::construct(common::Class::Application(4),
+            common::Payload::Constructed(vec![octet_string(dn.as_bytes()), list]))
+    }
+
+    fn done(code: u8) -> common::Tag
+    {
+        let result = common::construct(common::Class::Universal(common::UniversalTypes::Enumerated),
+            common::Payload::Primitive(vec![code]));
+
+        common::construct(common::Class::Application(5),
+            common::Payload::Constructed(vec![result, octet_string(b""), octet_string(b"")]))
+    }
+
+    fn referral(code: u8, url: &str) -> common::Tag
+    {
+        let result = common::construct(common::Class::Universal(common::UniversalTypes::Enumerated),
+            common::Payload::Primitive(vec![code]));
+        let urls = common::construct(common::Class::ContextSpecific(3),
+            common::Payload::Constructed(vec![octet_string(url.as_bytes())]));
+
+        common::construct(common::Class::Application(5),
+            common::Payload::Constructed(vec![result, octet_string(b""), octet_string(b""), urls]))
+    }
+
+    fn reference(url: &str) -> common::Tag
+    {
+        common::construct(common::Class::Application(19),
+            common::Payload::Constructed(vec![octet_string(url.as_bytes())]))
+    }
+
+    // Answer `connections` searches with the responses `respond` gives for the URL of the
+    // server, returning the base of each request
+    fn serve<F: FnOnce(&str) -> Vec<u8>>(connections: usize, respond: F) -> (String, thread::JoinHandle<Vec<String>>)
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ldap://{}", listener.local_addr().unwrap());
+        let responses = respond(&url);
+
+        let server = thread::spawn(move || {
+            (0..connections).map(|_| {
+                let (mut stream, _) = listener.accept().unwrap();
+                let request = ber::decode_from_reader(&mut stream).unwrap();
+                stream.write_all(&responses).unwrap();
+
+                let (_, op, _) = protocol::deconstruct_envelope(request).unwrap();
+                match op._value
+                {
+                    common::Payload::Constructed(ref children) => super::string(&children[0]).unwrap(),
+                    _ => panic!("Expected a SearchRequest"),
+                }
+            }).collect()
+        });
+
+        (url, server)
+    }
+
+    #[test]
+    fn search_request_layout()
+    {
+        let filter = Filter::Equality { attr: "cn".to_string(), value: b"alice".to_vec() };
+        let tag = SearchRequestBuilder::new("dc=example,dc=com").filter(filter).attributes(&["mail"]).build().unwrap();
+        let bytes = ber::encode(tag, 1).unwrap();
+
+        let expected = [
+            0x63, 0x37,
+            // baseObject
+            0x04, 0x11, 0x64, 0x63, 0x3d, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2c, 0x64, 0x63,
+            0x3d, 0x63, 0x6f, 0x6d,
+            // scope, derefAliases, sizeLimit, timeLimit, typesOnly
+            0x0a, 0x01, 0x02, 0x0a, 0x01, 0x00, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x01, 0x01, 0x00,
+            // filter
+            0xa3, 0x0b, 0x04, 0x02, 0x63, 0x6e, 0x04, 0x05, 0x61, 0x6c, 0x69, 0x63, 0x65,
+            // attributes
+            0x30, 0x06, 0x04, 0x04, 0x6d, 0x61, 0x69, 0x6c,
+        ];
+
+        assert!(bytes.ends_with(&expected));
+    }
+
+    #[test]
+    fn search_collects_entries()
+    {
+        let mut responses = Vec::new();
+        responses.extend(ber::encode(entry("cn=alice,dc=example,dc=com", "mail", &[b"alice@example.com"]), 1).unwrap());
+        responses.extend(ber::encode(entry("cn=bob,dc=example,dc=com", "mail", &[b"bob@example.com", b"b@example.com"]), 1).unwrap());
+        responses.extend(ber::encode(done(0), 1).unwrap());
+
+        let mut ld = LDAP::from_stream(MockStream::new(responses));
+        let filter = Filter::Equality { attr: "objectClass".to_string(), value: b"person".to_vec() };
+        let entries = ld.search("dc=example,dc=com", Scope::WholeSubtree, filter, &["mail"], &[]).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].dn, "cn=alice,dc=example,dc=com");
+        assert_eq!(entries[0].get("mail"), Some(&vec![b"alice@example.com".to_vec()]));
+        assert_eq!(entries[1].dn, "cn=bob,dc=example,dc=com");
+        assert_eq!(entries[1].get("mail"), Some(&vec![b"bob@example.com".to_vec(), b"b@example.com".to_vec()]));
+    }
+
+    #[test]
+    fn search_fails_on_error_result()
+    {
+        // noSuchObject
+        let responses = ber::encode(done(32), 1).unwrap();
+
+        let mut ld = LDAP::from_stream(MockStream::new(responses));
+        let filter = Filter::Equality { attr: "cn".to_string(), value: b"alice".to_vec() };
+
+        assert!(ld.search("dc=nowhere", Scope::BaseObject, filter, &[], &[]).is_err());
+    }
+
+    #[test]
+    fn search_paged_follows_cookie()
+    {
+        let page = |cookie: &[u8]| PagedResults { size: 0, cookie: cookie.to_vec() }.to_control();
+
+        let mut responses = Vec::new();
+        responses.extend(ber::encode(entry("cn=alice,dc=example,dc=com", "cn", &[b"alice"]), 1).unwrap());
+        responses.extend(ber::encode_with_controls(done(0), 1, &[page(b"page2")]).unwrap());
+        responses.extend(ber::encode(entry("cn=bob,dc=example,dc=com", "cn", &[b"bob"]), 2).unwrap());
+        responses.extend(ber::encode_with_controls(done(0), 2, &[page(b"")]).unwrap());
+
+        let mut ld = LDAP::from_stream(MockStream::new(responses));
+        let filter = Filter::Present("cn".to_string());
+        let entries = ld.search_paged("dc=example,dc=com", Scope::WholeSubtree, filter.clone(), &["cn"], 1).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].dn, "cn=alice,dc=example,dc=com");
+        assert_eq!(entries[1].dn, "cn=bob,dc=example,dc=com");
+
+        // The second request has to carry the cookie of the first page
+        let mut expected = Vec::new();
+        for (msgid, cookie) in vec![(1, &b""[..]), (2, &b"page2"[..])]
+        {
+            let request = SearchRequestBuilder::new("dc=example,dc=com").filter(filter.clone()).attributes(&["cn"]).build().unwrap();
+            let control = PagedResults { size: 1, cookie: cookie.to_vec() }.to_control();
+            expected.extend(ber::encode_with_controls(request, msgid, &[control]).unwrap());
+        }
+
+        assert_eq!(ld.stream.output, expected);
+    }
+
+    #[test]
+    fn search_paged_stops_on_a_stuck_cookie()
+    {
+        let page = |cookie: &[u8]| PagedResults { size: 0, cookie: cookie.to_vec() }.to_control();
+        let filter = Filter::Present("cn".to_string());
+
+        // The same cookie for the second page again
+        let mut responses = Vec::new();
+        for msgid in 1..3
+        {
+            responses.extend(ber::encode(entry("cn=alice,dc=example,dc=com", "cn", &[b"alice"]), msgid).unwrap());
+            responses.extend(ber::encode_with_controls(done(0), msgid, &[page(b"page2")]).unwrap());
+        }
+
+        let mut ld = LDAP::from_stream(MockStream::new(responses));
+        match ld.search_paged("dc=example,dc=com", Scope::WholeSubtree, filter.clone(), &["cn"], 1)
+        {
+            Err(LDAPError::Protocol) => {},
+            x => panic!("Expected Protocol, got {:?}", x),
+        }
+
+        // A page without entries that still promises more
+        let responses = ber::encode_with_controls(done(0), 1, &[page(b"page2")]).unwrap();
+
+        let mut ld = LDAP::from_stream(MockStream::new(responses));
+        match ld.search_paged("dc=example,dc=com", Scope::WholeSubtree, filter, &["cn"], 1)
+        {
+            Err(LDAPError::Protocol) => {},
+            x => panic!("Expected Protocol, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn parse_user_entry()
+    {
+        // SearchResultEntry with message ID 2, encoded by hand
+        let message = [
+            0x30, 0x81, 0xbb, 0x02, 0x01, 0x02, 0x64, 0x81, 0xb5, 0x04, 0x24, 0x75, 0x69, 0x64, 0x3d, 0x6a,
+            0x64, 0x6f, 0x65, 0x2c, 0x6f, 0x75, 0x3d, 0x70, 0x65, 0x6f, 0x70, 0x6c, 0x65, 0x2c, 0x64, 0x63,
+            0x3d, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2c, 0x64, 0x63, 0x3d, 0x63, 0x6f, 0x6d, 0x30,
+            0x81, 0x8c, 0x30, 0x2b, 0x04, 0x0b, 0x6f, 0x62, 0x6a, 0x65, 0x63, 0x74, 0x43, 0x6c, 0x61, 0x73,
+            0x73, 0x31, 0x1c, 0x04, 0x03, 0x74, 0x6f, 0x70, 0x04, 0x06, 0x70, 0x65, 0x72, 0x73, 0x6f, 0x6e,
+            0x04, 0x0d, 0x69, 0x6e, 0x65, 0x74, 0x4f, 0x72, 0x67, 0x50, 0x65, 0x72, 0x73, 0x6f, 0x6e, 0x30,
+            0x10, 0x04, 0x02, 0x63, 0x6e, 0x31, 0x0a, 0x04, 0x08, 0x4a, 0x6f, 0x68, 0x6e, 0x20, 0x44, 0x6f,
+            0x65, 0x30, 0x1a, 0x04, 0x04, 0x6d, 0x61, 0x69, 0x6c, 0x31, 0x12, 0x04, 0x10, 0x6a, 0x64, 0x6f,
+            0x65, 0x40, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2e, 0x63, 0x6f, 0x6d, 0x30, 0x20, 0x04,
+            0x16, 0x75, 0x73, 0x65, 0x72, 0x43, 0x65, 0x72, 0x74, 0x69, 0x66, 0x69, 0x63, 0x61, 0x74, 0x65,
+            0x3b, 0x62, 0x69, 0x6e, 0x61, 0x72, 0x79, 0x31, 0x06, 0x04, 0x04, 0x30, 0x82, 0x01, 0x0a, 0x30,
+            0x0d, 0x04, 0x09, 0x6a, 0x70, 0x65, 0x67, 0x50, 0x68, 0x6f, 0x74, 0x6f, 0x31, 0x00,
+        ];
+
+        let envelope = ber::decode(&message).unwrap();
+        let (_, op, _) = protocol::deconstruct_envelope(envelope).unwrap();
+        let entry = super::parse_search_entry(&op).unwrap();
+
+        assert_eq!(entry.dn, "uid=jdoe,ou=people,dc=example,dc=com");
+
+        let names: Vec<&str> = entry.attributes.iter().map(|&(ref name, _)| &name[..]).collect();
+        assert_eq!(names, vec!["objectClass", "cn", "mail", "userCertificate;binary", "jpegPhoto"]);
+
+        assert_eq!(entry.get_str("objectclass").unwrap(),
+                   Some(vec!["top".to_string(), "person".to_string(), "inetOrgPerson".to_string()]));
+        assert_eq!(entry.get_str("CN").unwrap(), Some(vec!["John Doe".to_string()]));
+        assert_eq!(entry.get("userCertificate;binary"), Some(&vec![vec![0x30, 0x82, 0x01, 0x0a]]));
+        // Present without values
+        assert_eq!(entry.get("jpegPhoto"), Some(&vec![]));
+        assert_eq!(entry.get_str("sn").unwrap(), None);
+        assert!(entry.get_str("userCertificate;binary").is_err());
+    }
+
+    #[test]
+    fn search_follows_referral()
+    {
+        let (url, server) = serve(1, |_| {
+            let mut responses = ber::encode(entry("cn=bob,ou=People,dc=example", "cn", &[b"bob"]), 1).unwrap();
+            responses.extend(ber::encode(done(0), 1).unwrap());
+            responses
+        });
+
+        let referred = ber::encode(referral(10, &format!("{}/ou=People,dc=example", url)), 1).unwrap();
+        let filter = Filter::Present("cn".to_string());
+
+        // Not followed by default
+        let mut ld = LDAP::from_stream(MockStream::new(referred.clone()));
+        match ld.search("dc=example", Scope::WholeSubtree, filter.clone(), &[], &[])
+        {
+            Err(LDAPError::Server(LdapError { result_code: ResultCode::referral, .. })) => {},
+            x => panic!("Expected a referral error, got {:?}", x),
+        }
+
+        let mut ld = LDAP::from_stream(MockStream::new(referred));
+        ld.set_follow_referrals(true);
+        let entries = ld.search("dc=example", Scope::WholeSubtree, filter, &[], &[]).unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].dn, "cn=bob,ou=People,dc=example");
+        assert_eq!(server.join().unwrap(), vec!["ou=People,dc=example".to_string()]);
+    }
+
+    #[test]
+    fn search_follows_continuation_references()
+    {
+        let (url, server) = serve(1, |_| {
+            let mut responses = ber::encode(entry("cn=bob,ou=Remote,dc=example", "cn", &[b"bob"]), 1).unwrap();
+            responses.extend(ber::encode(done(0), 1).unwrap());
+            responses
+        });
+
+        let mut responses = Vec::new();
+        responses.extend(ber::encode(entry("cn=alice,dc=example", "cn", &[b"alice"]), 1).unwrap());
+        responses.extend(ber::encode(reference(&format!("{}/ou=Remote,dc=example", url)), 1).unwrap());
+        responses.extend(ber::encode(done(0), 1).unwrap());
+
+        let mut ld = LDAP::from_stream(MockStream::new(responses));
+        ld.set_follow_referrals(true);
+        let entries = ld.search("dc=example", Scope::WholeSubtree, Filter::Present("cn".to_string()), &[], &[]).unwrap();
+
+        let dns: Vec<&str> = entries.iter().map(|e| &e.dn[..]).collect();
+        assert_eq!(dns, vec!["cn=alice,dc=example", "cn=bob,ou=Remote,dc=example"]);
+        assert_eq!(server.join().unwrap(), vec!["ou=Remote,dc=example".to_string()]);
+    }
+
+    #[test]
+    fn search_referral_hops_are_capped()
+    {
+        // A server referring back to itself
+        let (url, server) = serve(super::MAX_REFERRAL_HOPS, |url| ber::encode(referral(10, &format!("{}/dc=loop", url)), 1).unwrap());
+        let url = format!("{}/dc=loop", url);
+
+        let mut ld = LDAP::from_stream(MockStream::new(ber::encode(referral(10, &url), 1).unwrap()));
+        ld.set_follow_referrals(true);
+        match ld.search("dc=example", Scope::WholeSubtree, Filter::Present("cn".to_string()), &[], &[])
+        {
+            Err(LDAPError::Server(LdapError { result_code: ResultCode::referral, .. })) => {},
+            x => panic!("Expected a referral error, got {:?}", x),
+        }
+        assert_eq!(server.join().unwrap().len(), super::MAX_REFERRAL_HOPS);
+    }
+
+    #[test]
+    fn search_collects_references()
+    {
+        let mut responses = Vec::new();
+        responses.extend(ber::encode(entry("cn=alice,dc=example", "cn", &[b"alice"]), 1).unwrap());
+        responses.extend(ber::encode(reference("ldap://a.example/ou=A,dc=example"), 1).unwrap());
+        responses.extend(ber::encode(entry("cn=bob,dc=example", "cn", &[b"bob"]), 1).unwrap());
+        let both = common::construct(common::Class::Application(19), common::Payload::Constructed(vec![
+            octet_string(b"ldap://b1.example/ou=B,dc=example"), octet_string(b"ldap://b2.example/ou=B,dc=example")]));
+        responses.extend(ber::encode(both, 1).unwrap());
+        responses.extend(ber::encode(done(0), 1).unwrap());
+
+        let mut ld = LDAP::from_stream(MockStream::new(responses));
+        let request = SearchRequestBuilder::new("dc=example").filter(Filter::Present("cn".to_string()));
+        let result = ld.search_with_references(&request, &[]).unwrap();
+
+        let dns: Vec<&str> = result.entries.iter().map(|e| &e.dn[..]).collect();
+        assert_eq!(dns, vec!["cn=alice,dc=example", "cn=bob,dc=example"]);
+        assert_eq!(result.references, vec![
+            vec!["ldap://a.example/ou=A,dc=example".to_string()],
+            vec!["ldap://b1.example/ou=B,dc=example".to_string(), "ldap://b2.example/ou=B,dc=example".to_string()],
+        ]);
+
+        // An empty reference is malformed
+        let empty = common::construct(common::Class::Application(19), common::Payload::Constructed(vec![]));
+        assert!(super::parse_search_reference(&empty).is_err());
+    }
+
+    #[test]
+    fn root_dse_discovery()
+    {
+        // Root DSE entry with namingContexts, supportedControl and supportedLDAPVersion, encoded by hand
+        let responses = vec![
+            0x30, 0x81, 0x96, 0x02, 0x01, 0x01, 0x64, 0x81, 0x90, 0x04, 0x00, 0x30, 0x81, 0x8b, 0x30, 0x25,
+            0x04, 0x0e, 0x6e, 0x61, 0x6d, 0x69, 0x6e, 0x67, 0x43, 0x6f, 0x6e, 0x74, 0x65, 0x78, 0x74, 0x73,
+            0x31, 0x13, 0x04, 0x11, 0x64, 0x63, 0x3d, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2c, 0x64,
+            0x63, 0x3d, 0x63, 0x6f, 0x6d, 0x30, 0x45, 0x04, 0x10, 0x73, 0x75, 0x70, 0x70, 0x6f, 0x72, 0x74,
+            0x65, 0x64, 0x43, 0x6f, 0x6e, 0x74, 0x72, 0x6f, 0x6c, 0x31, 0x31, 0x04, 0x16, 0x31, 0x2e, 0x32,
+            0x2e, 0x38, 0x34, 0x30, 0x2e, 0x31, 0x31, 0x33, 0x35, 0x35, 0x36, 0x2e, 0x31, 0x2e, 0x34, 0x2e,
+            0x33, 0x31, 0x39, 0x04, 0x17, 0x32, 0x2e, 0x31, 0x36, 0x2e, 0x38, 0x34, 0x30, 0x2e, 0x31, 0x2e,
+            0x31, 0x31, 0x33, 0x37, 0x33, 0x30, 0x2e, 0x33, 0x2e, 0x34, 0x2e, 0x32, 0x30, 0x1b, 0x04, 0x14,
+            0x73, 0x75, 0x70, 0x70, 0x6f, 0x72, 0x74, 0x65, 0x64, 0x4c, 0x44, 0x41, 0x50, 0x56, 0x65, 0x72,
+            0x73, 0x69, 0x6f, 0x6e, 0x31, 0x03, 0x04, 0x01, 0x33,
+            0x30, 0x0c, 0x02, 0x01, 0x01, 0x65, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00,
+        ];
+
+        let mut ld = LDAP::from_stream(MockStream::new(responses.clone()));
+        let dse = ld.root_dse(&["namingContexts", "supportedControl", "supportedLDAPVersion"]).unwrap();
+
+        assert_eq!(dse.dn, "");
+        assert_eq!(dse.get_str("namingContexts").unwrap(), Some(vec!["dc=example,dc=com".to_string()]));
+        assert_eq!(dse.get_str("supportedLDAPVersion").unwrap(), Some(vec!["3".to_string()]));
+
+        // Base search of the empty DN for (objectClass=*)
+        let expected = [
+            0x63, 0x58, 0x04, 0x00, 0x0a, 0x01, 0x00, 0x0a, 0x01, 0x00, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00,
+            0x01, 0x01, 0x00, 0x87, 0x0b, 0x6f, 0x62, 0x6a, 0x65, 0x63, 0x74, 0x43, 0x6c, 0x61, 0x73, 0x73,
+        ];
+        assert_eq!(&ld.stream.output[5..5 + expected.len()], &expected[..]);
+
+        let mut ld = LDAP::from_stream(MockStream::new(responses));
+        assert_eq!(ld.supported_controls().unwrap(), vec!["1.2.840.113556.1.4.319".to_string(), "2.16.840.1.113730.3.4.2".to_string()]);
+
+        // A server hiding its root DSE
+        let mut ld = LDAP::from_stream(MockStream::new(ber::encode(done(0), 1).unwrap()));
+        assert!(ld.root_dse(&[]).is_err());
+    }
+
+    #[test]
+    fn schema_from_subentry()
+    {
+        let mut responses = Vec::new();
+        responses.extend(ber::encode(entry("", "subschemaSubentry", &[b"cn=Subschema"]), 1).unwrap());
+        responses.extend(ber::encode(done(0), 1).unwrap());
+        responses.extend(ber::encode(entry("cn=Subschema", "attributeTypes", &[
+            b"( 2.5.4.3 NAME ( 'cn' 'commonName' ) SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )",
+            b"( 0.9.2342.19200300.100.1.60 NAME 'jpegPhoto' SYNTAX 1.3.6.1.4.1.1466.115.121.1.28 )",
+        ]), 2).unwrap());
+        responses.extend(ber::encode(done(0), 2).unwrap());
+
+        let mut ld = LDAP::from_stream(MockStream::new(responses));
+        let schema = ld.schema().unwrap();
+
+        assert_eq!(schema.attribute_types.len(), 2);
+        assert!(schema.object_classes.is_empty());
+        assert!(schema.is_binary("jpegPhoto"));
+        assert!(!schema.is_binary("commonName"));
+    }
+
+    #[test]
+    fn textual_and_binary_values()
+    {
+        let entry = super::SearchEntry
+        {
+            dn: "cn=alice,dc=example".to_string(),
+            attributes: vec![
+                ("cn".to_string(), vec!["Jürgen".as_bytes().to_vec()]),
+                ("description".to_string(), vec![vec![0xff, 0xfe]]),
+                ("jpegPhoto".to_string(), vec![vec![0xff, 0xd8, 0xff, 0xe0]]),
+                ("objectGUID".to_string(), vec![b"0123456789abcdef".to_vec()]),
+            ],
+        };
+
+        assert_eq!(entry.get_str("cn").unwrap(), Some(vec!["Jürgen".to_string()]));
+        assert!(!entry.is_binary("cn"));
+        // Not UTF-8, although the attribute should be
+        assert!(entry.get_str("description").is_err());
+
+        assert!(entry.is_binary("jpegPhoto"));
+        assert_eq!(entry.get_bytes("jpegPhoto"), Some(&vec![vec![0xff, 0xd8, 0xff, 0xe0]]));
+        assert!(entry.get_str("jpegPhoto").is_err());
+        // Binary even if the bytes happen to be valid UTF-8
+        assert!(entry.get_str("objectGUID").is_err());
+    }
+
+    #[test]
+    fn post_read_entry_from_response_control()
+    {
+        let entry_value = ber::encode_tag(&entry("cn=alice,dc=example", "mail", &[b"alice@example.org"])).unwrap();
+        let control = Control { oid: POST_READ_OID.to_string(), criticality: false, value: Some(entry_value) };
+        // ModifyResponse: success, carrying the entry after the change
+        let response = {
+            let result = common::construct(common::Class::Universal(common::UniversalTypes::Enumerated),
+                common::Payload::Primitive(vec![0x00]));
+            common::construct(common::Class::Application(7),
+                common::Payload::Constructed(vec![result, octet_string(b""), octet_string(b"")]))
+        };
+
+        let mut ld = LDAP::from_stream(MockStream::new(ber::encode_with_controls(response, 1, &[control]).unwrap()));
+        let changes = vec![Modification::Replace("mail".to_string(), vec![b"alice@example.org".to_vec()])];
+        assert_eq!(ld.modify("cn=alice,dc=example", changes, &[Control::post_read(&["mail"])]).unwrap(), ResultCode::success);
+
+        let entry = ld.post_read_entry().unwrap().unwrap();
+        assert_eq!(entry.dn, "cn=alice,dc=example");
+        assert_eq!(entry.get_str("mail").unwrap(), Some(vec!["alice@example.org".to_string()]));
+        assert_eq!(ld.pre_read_entry().unwrap(), None);
+
+        // A value that isn't an entry
+        let broken = Control { oid: POST_READ_OID.to_string(), criticality: false, value: Some(vec![0x04, 0x00]) };
+        assert!(super::parse_read_entry(&broken).is_err());
+    }
+
+    // Stream handing out one message at a time, waiting `gap` before each
+    struct Drip
+    {
+        messages: VecDeque<Vec<u8>>,
+        current: Cursor<Vec<u8>>,
+        gap: Duration,
+        output: Vec<u8>,
+        read_timeout: Option<Duration>,
+    }
+
+    impl Read for Drip
+    {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+        {
+            if self.current.position() == self.current.get_ref().len() as u64
+            {
+                if let Some(timeout) = self.read_timeout.filter(|&timeout| timeout < self.gap)
+                {
+                    thread::sleep(timeout);
+                    return Err(io::Error::from(io::ErrorKind::WouldBlock));
+                }
+
+                match self.messages.pop_front()
+                {
+                    Some(message) => self.current = Cursor::new(message),
+                    None => return Ok(0),
+                }
+                thread::sleep(self.gap);
+            }
+
+            self.current.read(buf)
+        }
+    }
+
+    impl Write for Drip
+    {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize>
+        {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()>
+        {
+            Ok(())
+        }
+    }
+
+    impl ReadTimeout for Drip
+    {
+        fn read_timeout(&self) -> io::Result<Option<Duration>>
+        {
+            Ok(self.read_timeout)
+        }
+
+        fn set_read_timeout(&mut self, dur: Option<Duration>) -> io::Result<()>
+        {
+            self.read_timeout = dur;
+
+            Ok(())
+        }
+    }
+
+    fn drip(entries: usize, gap: Duration) -> Drip
+    {
+        let mut messages: VecDeque<_> = (0..entries)
+            .map(|i| ber::encode(entry(&format!("cn=user{},dc=example", i), "cn", &[b"user"]), 1).unwrap())
+            .collect();
+        messages.push_back(ber::encode(done(0), 1).unwrap());
+
+        Drip { messages: messages, current: Cursor::new(Vec::new()), gap: gap, output: Vec::new(), read_timeout: None }
+    }
+
+    #[test]
+    fn search_with_deadline_abandons_slow_searches()
+    {
+        let request = SearchRequestBuilder::new("dc=example").scope(Scope::WholeSubtree).filter(Filter::Present("cn".to_string()));
+
+        let mut ld = LDAP::from_stream(drip(3, Duration::from_millis(0)));
+        let deadline = Instant::now() + Duration::from_secs(60);
+        assert_eq!(ld.search_with_deadline(&request, &[], deadline).unwrap().len(), 3);
+
+        // Every entry arrives well within any read timeout, but all of them take too long
+        let mut ld = LDAP::from_stream(drip(100, Duration::from_millis(20)));
+        let deadline = Instant::now() + Duration::from_millis(100);
+        match ld.search_with_deadline(&request, &[], deadline)
+        {
+            Err(LDAPError::Timeout) => {},
+            x => panic!("Expected Timeout, got {:?}", x),
+        }
+        assert!(Instant::now() < deadline + Duration::from_millis(100));
+
+        // AbandonRequest for the search, message 1
+        assert!(ld.stream.output.ends_with(&[0x30, 0x06, 0x02, 0x01, 0x02, 0x50, 0x01, 0x01]));
+        assert!(ld.stream.messages.len() > 90);
+        assert_eq!(ld.stream.read_timeout, None);
+    }
+
+    #[test]
+    fn search_with_deadline_times_out_reads()
+    {
+        let request = SearchRequestBuilder::new("dc=example").scope(Scope::WholeSubtree).filter(Filter::Present("cn".to_string()));
+
+        // Nothing arrives before the deadline, the read timeout has to end the wait
+        let mut ld = LDAP::from_stream(drip(0, Duration::from_secs(30)));
+        ld.stream.read_timeout = Some(Duration::from_secs(60));
+        let deadline = Instant::now() + Duration::from_millis(50);
+        match ld.search_with_deadline(&request, &[], deadline)
+        {
+            Err(LDAPError::Timeout) => {},
+            x => panic!("Expected Timeout, got {:?}", x),
+        }
+        assert!(Instant::now() < deadline + Duration::from_secs(5));
+        assert_eq!(ld.stream.read_timeout, Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn search_with_deadline_keeps_results_received_in_time()
+    {
+        let request = SearchRequestBuilder::new("dc=example").scope(Scope::WholeSubtree).filter(Filter::Present("cn".to_string()));
+        let message = |op| ber::decode(&ber::encode(op, 1).unwrap()).unwrap();
+
+        // All results were read before the deadline passed, while waiting for another operation
+        let mut ld = LDAP::from_stream(drip(0, Duration::from_secs(30)));
+        ld.pending.push_back((1, message(entry("cn=user,dc=example", "cn", &[b"user"]))));
+        ld.pending.push_back((1, message(done(0))));
+
+        let entries = ld.search_with_deadline(&request, &[], Instant::now()).unwrap();
+        assert_eq!(entries.len(), 1);
+        // Only the SearchRequest went out, no AbandonRequest
+        assert_eq!(ld.stream.output, ber::encode(request.build().unwrap(), 1).unwrap());
+    }
+}
@@ -0,0 +1,290 @@
+//! Connections tunneled through a SOCKS5 proxy (RFC1928)
+//!
+//! Once the proxy established the connection to the LDAP server it just passes bytes along, so
+//! everything after the handshake works like on a direct connection.
+
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+
+use LDAP;
+use Result;
+
+const VERSION: u8 = 0x05;
+
+const NO_AUTH: u8 = 0x00;
+const USERNAME_PASSWORD: u8 = 0x02;
+const NO_ACCEPTABLE_METHOD: u8 = 0xFF;
+
+const CONNECT: u8 = 0x01;
+
+const IPV4: u8 = 0x01;
+const DOMAIN: u8 = 0x03;
+const IPV6: u8 = 0x04;
+
+impl LDAP<TcpStream>
+{
+    /// Connect to the LDAP-Server at `target`:`port` through the SOCKS5 proxy found at `proxy`
+    ///
+    /// `target` may be an IP address or a host name, which the proxy resolves. Only proxies
+    /// accepting clients without authentication can be used, see `connect_via_socks5_auth`
+    /// for the others.
+    pub fn connect_via_socks5<A: ToSocketAddrs>(proxy: A, target: &str, port: u16) -> Result<LDAP<TcpStream>>
+    {
+        let mut stream = try!(TcpStream::connect(proxy));
+        try!(handshake(&mut stream, target, port, None));
+
+        Ok(LDAP::from_stream(stream))
+    }
+
+    /// Like `connect_via_socks5`, but offers the proxy to log in with `username` and
+    /// `password` (RFC1929)
+    ///
+    /// The credentials are sent in plain text. The proxy may still let us in without them.
+    pub fn connect_via_socks5_auth<A: ToSocketAddrs>(proxy: A, target: &str, port: u16, username: &str, password: &str)
+        -> Result<LDAP<TcpStream>>
+    {
+        let mut stream = try!(TcpStream::connect(proxy));
+        try!(handshake(&mut stream, target, port, Some((username, password))));
+
+        Ok(LDAP::from_stream(stream))
+    }
+}
+
+/// Ask the proxy at the other end of `stream` to connect to `target`:`port`
+fn handshake<S: Read + Write>(stream: &mut S, target: &str, port: u16, auth: Option<(&str, &str)>) -> io::Result<()>
+{
+    let methods: &[u8] = if auth.is_some() { &[NO_AUTH, USERNAME_PASSWORD] } else { &[NO_AUTH] };
+
+    let mut greeting = vec![VERSION, methods.len() as u8];
+    greeting.extend_from_slice(methods);
+    try!(stream.write_all(&greeting));
+
+    let mut reply = [0; 2];
+    try!(stream.read_exact(&mut reply));
+    if reply[0] != VERSION
+    {
+        return Err(socks_error("Proxy does not speak SOCKS5"));
+    }
+
+    match (reply[1], auth)
+    {
+        (NO_AUTH, _) => {},
+        (USERNAME_PASSWORD, Some((username, password))) => try!(login(stream, username, password)),
+        (NO_ACCEPTABLE_METHOD, _) => return Err(socks_error("Proxy accepts none of the offered authentication methods")),
+        _ => return Err(socks_error("Proxy chose an authentication method that was not offered")),
+    }
+
+    let mut request = vec![VERSION, CONNECT, 0x00];
+    // IPv6 addresses may come in brackets like in URLs
+    match target.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>()
+    {
+        Ok(IpAddr::V4(addr)) =>
+        {
+            request.push(IPV4);
+            request.extend_from_slice(&addr.octets());
+        },
+        Ok(IpAddr::V6(addr)) =>
+        {
+            request.push(IPV6);
+            request.extend_from_slice(&addr.octets());
+        },
+        Err(_) =>
+        {
+            if target.is_empty() || target.len() > 255
+            {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Host name has to be 1 to 255 bytes long"));
+            }
+
+            request.push(DOMAIN);
+            request.push(target.len() as u8);
+            request.extend_from_slice(target.as_bytes());
+        },
+    }
+    request.push((port >> 8) as u8);
+    request.push(port as u8);
+    try!(stream.write_all(&request));
+    try!(stream.flush());
+
+    let mut reply = [0; 4];
+    try!(stream.read_exact(&mut reply));
+    if reply[0] != VERSION
+    {
+        return Err(socks_error("Proxy does not speak SOCKS5"));
+    }
+    if reply[1] != 0x00
+    {
+        return Err(socks_error(reply_message(reply[1])));
+    }
+
+    // The address the proxy connected from, which we have no use for
+    let addr_len = match reply[3]
+    {
+        IPV4 => 4,
+        IPV6 => 16,
+        DOMAIN =>
+        {
+            let mut len = [0; 1];
+            try!(stream.read_exact(&mut len));
+            len[0] as usize
+        },
+        _ => return Err(socks_error("Proxy replied with an unknown address type")),
+    };
+    let mut bound = vec![0; addr_len + 2];
+    try!(stream.read_exact(&mut bound));
+
+    Ok(())
+}
+
+/// Username/password authentication (RFC1929)
+fn login<S: Read + Write>(stream: &mut S, username: &str, password: &str) -> io::Result<()>
+{
+    if username.len() > 255 || password.len() > 255
+    {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Username and password may be at most 255 bytes long"));
+    }
+
+    let mut request = vec![0x01, username.len() as u8];
+    request.extend_from_slice(username.as_bytes());
+    request.push(password.len() as u8);
+    request.extend_from_slice(password.as_bytes());
+    try!(stream.write_all(&request));
+
+    let mut reply = [0; 2];
+    try!(stream.read_exact(&mut reply));
+    if reply[1] != 0x00
+    {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Proxy rejected username and password"));
+    }
+
+    Ok(())
+}
+
+fn reply_message(code: u8) -> &'static str
+{
+    match code
+    {
+        0x01 => "Proxy: general SOCKS server failure",
+        0x02 => "Proxy: connection not allowed by ruleset",
+        0x03 => "Proxy: network unreachable",
+        0x04 => "Proxy: host unreachable",
+        0x05 => "Proxy: connection refused",
+        0x06 => "Proxy: TTL expired",
+        0x07 => "Proxy: command not supported",
+        0x08 => "Proxy: address type not supported",
+        _ => "Proxy: unknown error",
+    }
+}
+
+fn socks_error(message: &'static str) -> io::Error
+{
+    io::Error::new(io::ErrorKind::Other, message)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::io::{self, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use mock::MockStream;
+    use LDAP;
+
+    #[test]
+    fn handshake_without_auth()
+    {
+        // Method choice, then a success reply with a bound IPv4 address
+        let mut stream = MockStream::new(vec![0x05, 0x00,
+                                              0x05, 0x00, 0x00, 0x01, 10, 0, 0, 1, 0x12, 0x34]);
+
+        super::handshake(&mut stream, "ldap.example.com", 389, None).unwrap();
+
+        let mut expected = vec![0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x03, 16];
+        expected.extend_from_slice(b"ldap.example.com");
+        expected.extend_from_slice(&[0x01, 0x85]);
+        assert_eq!(stream.output, expected);
+    }
+
+    #[test]
+    fn handshake_with_auth()
+    {
+        // Username/password chosen and accepted, bound address given as domain
+        let mut stream = MockStream::new(vec![0x05, 0x02, 0x01, 0x00,
+                                              0x05, 0x00, 0x00, 0x03, 0x01, b'p', 0x00, 0x50]);
+
+        super::handshake(&mut stream, "192.0.2.7", 636, Some(("bob", "secret"))).unwrap();
+
+        let mut expected = vec![0x05, 0x02, 0x00, 0x02, 0x01, 0x03];
+        expected.extend_from_slice(b"bob");
+        expected.push(0x06);
+        expected.extend_from_slice(b"secret");
+        expected.extend_from_slice(&[0x05, 0x01, 0x00, 0x01, 192, 0, 2, 7, 0x02, 0x7C]);
+        assert_eq!(stream.output, expected);
+        // Everything the proxy sent was consumed
+        assert_eq!(stream.input.position(), 12);
+    }
+
+    #[test]
+    fn handshake_ipv6_target()
+    {
+        let mut stream = MockStream::new(vec![0x05, 0x00,
+                                              0x05, 0x00, 0x00, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0]);
+
+        super::handshake(&mut stream, "[::1]", 389, None).unwrap();
+
+        assert_eq!(&stream.output[3..7], &[0x05, 0x01, 0x00, 0x04]);
+        assert_eq!(&stream.output[7..23], &[0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn handshake_errors()
+    {
+        let error = |input: Vec<u8>, auth| {
+            let mut stream = MockStream::new(input);
+            super::handshake(&mut stream, "ldap.example.com", 389, auth).unwrap_err()
+        };
+
+        // No acceptable method, rejected login, connection refused
+        assert_eq!(error(vec![0x05, 0xFF], None).kind(), io::ErrorKind::Other);
+        assert_eq!(error(vec![0x05, 0x02, 0x01, 0x01], Some(("bob", "wrong"))).kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(error(vec![0x05, 0x00, 0x05, 0x05, 0x00, 0x01], None).to_string(), "Proxy: connection refused");
+        // Username/password chosen although we didn't offer it
+        assert_eq!(error(vec![0x05, 0x02], None).kind(), io::ErrorKind::Other);
+        // Proxy hung up
+        assert_eq!(error(vec![0x05], None).kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn connect_via_socks5_tunnels()
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+
+            let mut greeting = [0; 3];
+            stream.read_exact(&mut greeting).unwrap();
+            assert_eq!(greeting, [0x05, 0x01, 0x00]);
+            stream.write_all(&[0x05, 0x00]).unwrap();
+
+            let mut request = [0; 10];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(request, [0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1, 0x01, 0x85]);
+            stream.write_all(&[0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0xC0, 0x00]).unwrap();
+
+            // Whatever comes next is LDAP
+            let mut ldap = Vec::new();
+            stream.read_to_end(&mut ldap).unwrap();
+            ldap
+        });
+
+        {
+            let mut ld = LDAP::connect_via_socks5(proxy, "127.0.0.1", 389).unwrap();
+            ld.unbind().unwrap();
+        }
+
+        // UnbindRequest as message 1
+        assert_eq!(server.join().unwrap(), vec![0x30, 0x05, 0x02, 0x01, 0x01, 0x42, 0x00]);
+    }
+}
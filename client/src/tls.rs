@@ -0,0 +1,219 @@
+//! TLS encrypted connections
+//!
+//! Only available with the `tls` feature. The TLS implementation of the platform is used
+//! through `native-tls`, so OpenSSL on Linux, Secure Transport on macOS and SChannel on Windows.
+
+use std::io;
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use native_tls::{HandshakeError, TlsConnector, TlsStream};
+
+use LDAP;
+use ReadTimeout;
+use Result;
+
+impl<S: ReadTimeout + io::Read + io::Write> ReadTimeout for TlsStream<S>
+{
+    fn read_timeout(&self) -> io::Result<Option<Duration>>
+    {
+        self.get_ref().read_timeout()
+    }
+
+    fn set_read_timeout(&mut self, dur: Option<Duration>) -> io::Result<()>
+    {
+        self.get_mut().set_read_timeout(dur)
+    }
+}
+
+impl LDAP<TlsStream<TcpStream>>
+{
+    /// Connect to the LDAPS-Server found at `addr`, usually on port 636
+    ///
+    /// The TLS handshake is done before any LDAP message is sent. The servers certificate has
+    /// to be valid for `domain` and signed by one of the systems trusted roots, otherwise the
+    /// connection fails. Use `connect_tls_with` to trust other roots.
+    pub fn connect_tls<A: ToSocketAddrs>(addr: A, domain: &str) -> Result<LDAP<TlsStream<TcpStream>>>
+    {
+        let connector = try!(TlsConnector::new().map_err(tls_error));
+
+        LDAP::connect_tls_with(addr, domain, &connector)
+    }
+
+    /// Like `connect_tls` but with a custom `connector`
+    ///
+    /// This allows trusting a private CA through `TlsConnectorBuilder::add_root_certificate`,
+    /// presenting a client certificate or, for testing only, disabling certificate verification.
+    pub fn connect_tls_with<A: ToSocketAddrs>(addr: A, domain: &str, connector: &TlsConnector)
+        -> Result<LDAP<TlsStream<TcpStream>>>
+    {
+        let stream = try!(TcpStream::connect(addr));
+        let stream = try!(handshake(connector, domain, stream));
+
+        Ok(LDAP::from_stream(stream))
+    }
+
+    /// Same as `LDAP::<TcpStream>::set_read_timeout` for the socket below the TLS layer
+    pub fn set_read_timeout(&mut self, dur: Option<Duration>) -> Result<()>
+    {
+        try!(self.stream.get_ref().set_read_timeout(dur));
+
+        Ok(())
+    }
+
+    /// Same as `LDAP::<TcpStream>::set_write_timeout` for the socket below the TLS layer
+    pub fn set_write_timeout(&mut self, dur: Option<Duration>) -> Result<()>
+    {
+        try!(self.stream.get_ref().set_write_timeout(dur));
+
+        Ok(())
+    }
+
+    /// Same as `LDAP::<TcpStream>::close`, ending the TLS session with a close_notify first
+    pub fn close(mut self) -> Result<()>
+    {
+        try!(self.unbind());
+        try!(self.stream.shutdown());
+        try!(self.stream.get_ref().shutdown(Shutdown::Both));
+
+        Ok(())
+    }
+}
+
+impl LDAP<TcpStream>
+{
+    /// Switch the connection over to TLS after a successful `start_tls`
+    ///
+    /// Certificates are verified the same way as for `connect_tls`. Message IDs keep counting
+    /// where the plaintext connection left off.
+    pub fn into_tls(self, domain: &str) -> Result<LDAP<TlsStream<TcpStream>>>
+    {
+        let connector = try!(TlsConnector::new().map_err(tls_error));
+
+        self.into_tls_with(domain, &connector)
+    }
+
+    /// Like `into_tls` but with a custom `connector`
+    pub fn into_tls_with(mut self, domain: &str, connector: &TlsConnector) -> Result<LDAP<TlsStream<TcpStream>>>
+    {
+        // Not even a failed handshake leaves the connection usable for plain LDAP
+        self.unbound = true;
+        let stream = try!(handshake(connector, domain, try!(self.stream.try_clone())));
+
+        Ok(self.continue_on(stream))
+    }
+}
+
+/// Run the TLS handshake on an established, blocking `stream`
+fn handshake(connector: &TlsConnector, domain: &str, stream: TcpStream) -> io::Result<TlsStream<TcpStream>>
+{
+    match connector.connect(domain, stream)
+    {
+        Ok(stream) => Ok(stream),
+        Err(HandshakeError::Failure(err)) => Err(tls_error(err)),
+        // Only happens on non-blocking sockets
+        Err(HandshakeError::WouldBlock(_)) =>
+            Err(io::Error::new(io::ErrorKind::WouldBlock, "TLS handshake would block")),
+    }
+}
+
+fn tls_error(err: ::native_tls::Error) -> io::Error
+{
+    io::Error::new(io::ErrorKind::Other, err)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use native_tls::{Certificate, Identity, TlsAcceptor, TlsConnector, TlsStream};
+
+    use protocol::error::LDAPError;
+
+    use LDAP;
+
+    // Self-signed for localhost, generated once with openssl and valid until 2126
+    const CERT: &'static [u8] = include_bytes!("../tests/tls/localhost.crt");
+    const KEY: &'static [u8] = include_bytes!("../tests/tls/localhost.key");
+
+    // Accept one connection on the returned port and hand the result of the handshake to `serve`
+    fn server<F>(serve: F) -> (u16, thread::JoinHandle<()>)
+        where F: FnOnce(Result<TlsStream<TcpStream>, ()>) + Send + 'static
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let handle = thread::spawn(move || {
+            let acceptor = TlsAcceptor::new(Identity::from_pkcs8(CERT, KEY).unwrap()).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+
+            serve(acceptor.accept(stream).map_err(|_| ()));
+        });
+
+        (port, handle)
+    }
+
+    #[test]
+    fn connect_tls_with_trusted_root()
+    {
+        let (port, handle) = server(|stream| {
+            let mut stream = stream.unwrap();
+
+            // WhoAmI request, answered anonymously
+            let mut request = [0; 32];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(&request[..9], &[0x30, 0x1e, 0x02, 0x01, 0x01, 0x77, 0x19, 0x80, 0x17]);
+            assert_eq!(&request[9..], b"1.3.6.1.4.1.4203.1.11.3");
+
+            stream.write_all(&[0x30, 0x0c, 0x02, 0x01, 0x01, 0x78, 0x07, 0x0a, 0x01, 0x00, 0x04, 0x00, 0x04, 0x00])
+                  .unwrap();
+        });
+
+        let connector = TlsConnector::builder()
+            .add_root_certificate(Certificate::from_pem(CERT).unwrap())
+            .build()
+            .unwrap();
+
+        let mut ld = LDAP::connect_tls_with(("127.0.0.1", port), "localhost", &connector).unwrap();
+        assert_eq!(ld.whoami().unwrap(), "");
+
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn connect_tls_rejects_untrusted_certificate()
+    {
+        let (port, handle) = server(|stream| assert!(stream.is_err()));
+
+        // The self-signed certificate isn't among the system roots
+        match LDAP::connect_tls(("127.0.0.1", port), "localhost")
+        {
+            Err(LDAPError::Io(_)) => {},
+            x => panic!("Expected the handshake to fail, got {:?}", x.map(|_| ())),
+        }
+
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn connect_tls_rejects_other_domain()
+    {
+        let (port, handle) = server(|stream| assert!(stream.is_err()));
+
+        let connector = TlsConnector::builder()
+            .add_root_certificate(Certificate::from_pem(CERT).unwrap())
+            .build()
+            .unwrap();
+
+        match LDAP::connect_tls_with(("127.0.0.1", port), "ldap.example.com", &connector)
+        {
+            Err(LDAPError::Io(_)) => {},
+            x => panic!("Expected the handshake to fail, got {:?}", x.map(|_| ())),
+        }
+
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,88 @@
+//! Compares `decode` and `decode_ref` on a large search result entry
+//!
+//! Run with `cargo bench`. Prints the number of allocations and the time taken per decode.
+
+extern crate ldap_protocol;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use ldap_protocol::ber::{self, common};
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8
+    {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout)
+    {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn octet_string(value: Vec<u8>) -> common::Tag
+{
+    common::construct(common::Class::Universal(common::UniversalTypes::OctetString),
+                      common::Payload::Primitive(value))
+}
+
+fn sequence(class: common::Class, children: Vec<common::Tag>) -> common::Tag
+{
+    common::construct(class, common::Payload::Constructed(children))
+}
+
+// SearchResultEntry with 50 attributes of 20 values each
+fn entry() -> Vec<u8>
+{
+    let attributes = (0..50).map(|a| {
+        let values = (0..20).map(|v| octet_string(format!("value {} of attribute {}", v, a).into_bytes())).collect();
+        let values = sequence(common::Class::Universal(common::UniversalTypes::Set), values);
+
+        sequence(common::Class::Universal(common::UniversalTypes::Sequence),
+                 vec![octet_string(format!("attribute{}", a).into_bytes()), values])
+    }).collect();
+
+    let entry = sequence(common::Class::Application(4), vec![
+        octet_string(b"cn=bench,dc=example,dc=com".to_vec()),
+        sequence(common::Class::Universal(common::UniversalTypes::Sequence), attributes),
+    ]);
+
+    ber::encode_tag(&entry).unwrap()
+}
+
+fn measure<F: FnMut()>(name: &str, mut f: F)
+{
+    const ROUNDS: u32 = 1000;
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS
+    {
+        f();
+    }
+    let elapsed = start.elapsed() / ROUNDS;
+
+    println!("{:<12} {:>6} allocations {:>10?} per decode", name, allocations, elapsed);
+}
+
+fn main()
+{
+    let bytes = entry();
+
+    measure("decode", || { ber::decode(&bytes).unwrap(); });
+    measure("decode_ref", || { ber::decode_ref(&bytes).unwrap(); });
+}
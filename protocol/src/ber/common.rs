@@ -1,7 +1,10 @@
+use std::hash::{Hash, Hasher};
+
 use super::Result;
 use super::error::ASN1Error;
+use prelude::*;
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 
 /// Enum containing all UniversalTypes' Numbers
 pub enum UniversalTypes
@@ -73,18 +76,18 @@ impl UniversalTypes
             29 => Ok(UniversalTypes::CharacterString),
             30 => Ok(UniversalTypes::BmpString),
             // BER uses 5 bits to encode the universal tags, and 31/0x1F/b11111 is used
-            // to signal to use the long form of encoding
-            // FIXME: This is a public function, better error handling!
-            _  => unreachable!(),
+            // to signal to use the long form of encoding. Neither that nor anything above
+            // it names a known universal type.
+            _  => Err(ASN1Error::InvalidASN1),
         }
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
 pub enum Class
 {
-    // TODO: Use BigInt instead of i64 to make encoding arbitrary sizes possbile?
-    // Much more TODO: Find out if even necessary for LDAP
+    // LDAP never gets anywhere near the 2^63 tag numbers an i64 can hold, decoding a tag
+    // with a larger number fails with ExtendedTagTooLong.
     Universal(UniversalTypes),
     Application(i64),
     ContextSpecific(i64),
@@ -97,6 +100,9 @@ impl Class
     {
         match class
         {
+            // Universal tags never use the long form, so anything that doesn't fit the
+            // 5 tag bits is invalid anyway. Don't let `as u8` truncate it into a valid one.
+            0 if number < 0 || number > 30 => Err(ASN1Error::InvalidASN1),
             0 => Ok(Class::Universal(try!(UniversalTypes::from_u8(number as u8)))),
             1 => Ok(Class::Application(number)),
             2 => Ok(Class::ContextSpecific(number)),
@@ -119,21 +125,21 @@ pub enum ClassNumber
 
 impl ClassNumber
 {
-    pub fn from_u8(v: u8) -> ClassNumber
+    pub fn from_u8(v: u8) -> Result<ClassNumber>
     {
         match v
         {
-            0 => ClassNumber::Universal,
-            1 => ClassNumber::Application,
-            2 => ClassNumber::ContextSpecific,
-            3 => ClassNumber::Private,
-            // FIXME: Better Error handling
-            _ => unreachable!(),
+            0 => Ok(ClassNumber::Universal),
+            1 => Ok(ClassNumber::Application),
+            2 => Ok(ClassNumber::ContextSpecific),
+            3 => Ok(ClassNumber::Private),
+            // The class only has two bits
+            _ => Err(ASN1Error::InvalidASN1),
         }
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum Structure
 {
     Primitive   = 0,
@@ -142,26 +148,26 @@ pub enum Structure
 
 impl Structure
 {
-    pub fn from_u8(v: u8) -> Structure
+    pub fn from_u8(v: u8) -> Result<Structure>
     {
         match v
         {
-            0 => Structure::Primitive,
-            1 => Structure::Constructed,
-            // FIXME: Better Error handling
-            _ => unreachable!(),
+            0 => Ok(Structure::Primitive),
+            1 => Ok(Structure::Constructed),
+            // The structure is a single bit
+            _ => Err(ASN1Error::InvalidASN1),
         }
     }
 }
 
-#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
 pub struct Type
 {
     pub class: Class,
     pub structure: Structure
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub enum Payload
 {
     Primitive(Vec<u8>),
@@ -170,6 +176,10 @@ pub enum Payload
 
 impl Payload
 {
+    /// Length of the encoded payload
+    ///
+    /// For constructed payloads this sums up the `size` of the children, so it is only correct
+    /// as long as those are up to date. Call `Tag::recompute_size` after modifying children.
     pub fn len(&self) -> u64
     {
         let mut l: u64 = 0;
@@ -191,7 +201,8 @@ impl Payload
 
 }
 
-#[derive(PartialEq, Eq, Debug)]
+// Equality and hashing only look at `_type` and `_value`, the lengths follow from those
+#[derive(Debug, Clone)]
 pub struct Tag
 {
     pub _type: Type,
@@ -203,6 +214,141 @@ pub struct Tag
     pub size: u64,
 }
 
+impl PartialEq for Tag
+{
+    fn eq(&self, other: &Tag) -> bool
+    {
+        self._type == other._type && self._value == other._value
+    }
+}
+
+impl Eq for Tag {}
+
+impl Hash for Tag
+{
+    fn hash<H: Hasher>(&self, state: &mut H)
+    {
+        self._type.hash(state);
+        self._value.hash(state);
+    }
+}
+
+impl Tag
+{
+    /// Recalculate `_length` and `size` of this tag and all its children from their payloads
+    pub fn recompute_size(&mut self)
+    {
+        if let Payload::Constructed(ref mut children) = self._value
+        {
+            for child in children.iter_mut()
+            {
+                child.recompute_size();
+            }
+        }
+
+        self._length = self._value.len();
+        self.size = calculate_len(&self._type, &self._length);
+    }
+
+    /// Render the tag tree indented one level per nesting depth, for debugging
+    ///
+    /// Every line shows the class and length of a tag. Primitive values follow in hex, and as
+    /// text as well if they are printable UTF-8.
+    pub fn pretty(&self) -> String
+    {
+        let mut out = String::new();
+        self.pretty_into(0, &mut out);
+
+        out
+    }
+
+    fn pretty_into(&self, depth: usize, out: &mut String)
+    {
+        for _ in 0..depth
+        {
+            out.push_str("  ");
+        }
+
+        match self._type.class
+        {
+            Class::Universal(t) => out.push_str(&format!("{:?}", t)),
+            Class::Application(n) => out.push_str(&format!("[APPLICATION {}]", n)),
+            Class::ContextSpecific(n) => out.push_str(&format!("[{}]", n)),
+            Class::Private(n) => out.push_str(&format!("[PRIVATE {}]", n)),
+        }
+        out.push_str(&format!(" (len {})", self._length));
+
+        match self._value
+        {
+            Payload::Primitive(ref bytes) =>
+            {
+                if !bytes.is_empty()
+                {
+                    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                    out.push_str(": ");
+                    out.push_str(&hex.join(" "));
+                }
+
+                match ::std::str::from_utf8(bytes)
+                {
+                    Ok(text) if !text.is_empty() && !text.chars().any(char::is_control) =>
+                        out.push_str(&format!(" {:?}", text)),
+                    _ => {},
+                }
+                out.push('\n');
+            },
+            Payload::Constructed(ref children) =>
+            {
+                out.push('\n');
+                for child in children
+                {
+                    child.pretty_into(depth + 1, out);
+                }
+            },
+        }
+    }
+}
+
+/// A decoded tag whose primitive payloads borrow from the buffer it was decoded from
+///
+/// Returned by `decode_ref`, which saves copying every value out of the input.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TagRef<'a>
+{
+    pub _type: Type,
+    pub _length: u64,
+    pub _value: PayloadRef<'a>,
+    pub size: u64,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PayloadRef<'a>
+{
+    Primitive(&'a [u8]),
+    Constructed(Vec<TagRef<'a>>),
+}
+
+impl<'a> TagRef<'a>
+{
+    /// Copy the tag and all its children into an owned `Tag`
+    pub fn to_owned(&self) -> Tag
+    {
+        let _value = match self._value
+        {
+            PayloadRef::Primitive(bytes) => Payload::Primitive(bytes.to_vec()),
+            PayloadRef::Constructed(ref children) => Payload::Constructed(children.iter().map(TagRef::to_owned).collect()),
+        };
+
+        Tag
+        {
+            _type: self._type,
+            _length: self._length,
+            _value: _value,
+            size: self.size,
+        }
+    }
+}
+
 pub fn construct(class: Class, payload: Payload) -> Tag
 {
     let tagtype = Type
@@ -239,9 +385,11 @@ pub fn calculate_len(tagtype: &Type, pllen: &u64) -> u64
         Class::Universal(_) => /* Universal is always exactly one byte */ 1,
         Class::Application(tag) | Class::ContextSpecific(tag) | Class::Private(tag) =>
         {
-            // In case of the other three we actually have to look at their content
+            // In case of the other three we actually have to look at their content.
+            // Anything above 30 uses the long form: one byte per 7 bits of the number
+            // following the identifier byte.
             let mut len = 1u64;
-            if tag > 127
+            if tag > 30
             {
                 let mut tag = tag;
                 while
@@ -280,3 +428,103 @@ pub fn calculate_len(tagtype: &Type, pllen: &u64) -> u64
 
     length
 }
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn equality_ignores_sizes()
+    {
+        let child = || construct(Class::Universal(UniversalTypes::Integer), Payload::Primitive(vec![0x05]));
+        let built = construct(Class::Universal(UniversalTypes::Sequence), Payload::Constructed(vec![child()]));
+
+        // Same content, but the sizes computed from a child that is out of date
+        let mut stale = child();
+        stale.size = 0;
+        let other = construct(Class::Universal(UniversalTypes::Sequence), Payload::Constructed(vec![stale]));
+        assert!(other.size != built.size);
+
+        assert_eq!(built, other);
+
+        let mut set = HashSet::new();
+        set.insert(built);
+        assert!(set.contains(&other));
+        assert!(!set.contains(&child()));
+    }
+
+    #[test]
+    fn universal_type_from_u8()
+    {
+        assert_eq!(UniversalTypes::from_u8(2).unwrap(), UniversalTypes::Integer);
+        assert_eq!(UniversalTypes::from_u8(30).unwrap(), UniversalTypes::BmpString);
+    }
+
+    #[test]
+    fn universal_type_from_u8_rejects_unknown()
+    {
+        assert!(UniversalTypes::from_u8(0x1F).is_err());
+        assert!(UniversalTypes::from_u8(0xFF).is_err());
+    }
+
+    #[test]
+    fn class_number_and_structure_from_u8()
+    {
+        assert!(ClassNumber::from_u8(3).is_ok());
+        assert!(ClassNumber::from_u8(4).is_err());
+
+        assert_eq!(Structure::from_u8(1).unwrap(), Structure::Constructed);
+        assert!(Structure::from_u8(2).is_err());
+    }
+
+    #[test]
+    fn recompute_size_after_mutating_child()
+    {
+        let inner = construct(Class::Universal(UniversalTypes::OctetString), Payload::Primitive(vec![0x61]));
+        let middle = construct(Class::Universal(UniversalTypes::Sequence), Payload::Constructed(vec![inner]));
+        let mut outer = construct(Class::Application(3), Payload::Constructed(vec![middle]));
+        assert_eq!(outer.size, 7);
+
+        if let Payload::Constructed(ref mut middle) = outer._value
+        {
+            if let Payload::Constructed(ref mut inner) = middle[0]._value
+            {
+                inner[0]._value = Payload::Primitive(vec![0x61; 200]);
+            }
+        }
+
+        // Sizes still describe the old one byte string
+        assert_eq!(outer.size, 7);
+
+        outer.recompute_size();
+
+        // 0x04 0x81 0xC8 + 200, wrapped in 0x30 0x81 0xCB and 0x63 0x81 0xCE
+        assert_eq!(outer._length, 206);
+        assert_eq!(outer.size, 209);
+        if let Payload::Constructed(ref middle) = outer._value
+        {
+            assert_eq!(middle[0]._length, 203);
+            assert_eq!(middle[0].size, 206);
+        }
+    }
+
+    #[test]
+    fn pretty_nested_sequence()
+    {
+        let id = construct(Class::Universal(UniversalTypes::Integer), Payload::Primitive(vec![0x05]));
+        let dn = construct(Class::Universal(UniversalTypes::OctetString), Payload::Primitive(b"cn=alice".to_vec()));
+        let cred = construct(Class::ContextSpecific(0), Payload::Primitive(vec![0x00, 0xFF]));
+        let op = construct(Class::Application(0), Payload::Constructed(vec![dn, cred]));
+        let message = construct(Class::Universal(UniversalTypes::Sequence), Payload::Constructed(vec![id, op]));
+
+        assert_eq!(message.pretty(), "\
+Sequence (len 19)
+  Integer (len 1): 05
+  [APPLICATION 0] (len 14)
+    OctetString (len 8): 63 6e 3d 61 6c 69 63 65 \"cn=alice\"
+    [0] (len 2): 00 ff
+");
+    }
+}
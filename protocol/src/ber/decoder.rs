@@ -1,65 +1,481 @@
 use ber;
 use ber::error::ASN1Error as Error;
-use ber::common::{self, Tag};
+use ber::common::{self, Tag, TagRef};
+use ber::types;
 
+#[cfg(feature = "std")]
 use std::io;
-use std::io::{Read, Take, Cursor};
+use std::{cmp, iter};
+#[cfg(feature = "std")]
+use std::io::Read;
 
-use byteorder::BigEndian;
+#[cfg(feature = "std")]
 use byteorder::ReadBytesExt;
 
+use prelude::*;
+
+/// Which encoding rules the decoder enforces
+#[derive(Clone, Copy)]
+struct Options
+{
+    /// Reject everything that is valid BER but not DER
+    der: bool,
+    /// How many constructed tags may be nested in each other
+    max_depth: usize,
+    /// How many bytes of content any tag may have
+    max_size: u64,
+}
+
+/// Nesting depth `decode` and `decode_der` allow
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
+/// How many bytes before and after the offending one a `ParseError` shows
+const CONTEXT_BYTES: usize = 8;
+
+/// Result of the slice decoder, errors coming with the offset into the input they were found at
+type Located<T> = ::std::result::Result<T, (usize, Error)>;
+
+/// What the decoder builds from the tags it reads, owning or borrowing their values
+trait Node<'a>: Sized
+{
+    type Payload;
+
+    fn primitive(bytes: &'a [u8]) -> Self::Payload;
+    fn constructed(children: Vec<Self>) -> Self::Payload;
+    fn new(_type: common::Type, _length: u64, _value: Self::Payload) -> Self;
+}
+
+impl<'a> Node<'a> for Tag
+{
+    type Payload = common::Payload;
+
+    fn primitive(bytes: &'a [u8]) -> common::Payload
+    {
+        // to_vec() copies
+        common::Payload::Primitive(bytes.to_vec())
+    }
+
+    fn constructed(children: Vec<Tag>) -> common::Payload
+    {
+        common::Payload::Constructed(children)
+    }
+
+    fn new(_type: common::Type, _length: u64, _value: common::Payload) -> Tag
+    {
+        Tag
+        {
+            size: common::calculate_len(&_type, &_length),
+            _type: _type,
+            _length: _length,
+            _value: _value,
+        }
+    }
+}
+
+impl<'a> Node<'a> for TagRef<'a>
+{
+    type Payload = common::PayloadRef<'a>;
+
+    fn primitive(bytes: &'a [u8]) -> common::PayloadRef<'a>
+    {
+        common::PayloadRef::Primitive(bytes)
+    }
+
+    fn constructed(children: Vec<TagRef<'a>>) -> common::PayloadRef<'a>
+    {
+        common::PayloadRef::Constructed(children)
+    }
+
+    fn new(_type: common::Type, _length: u64, _value: common::PayloadRef<'a>) -> TagRef<'a>
+    {
+        TagRef
+        {
+            size: common::calculate_len(&_type, &_length),
+            _type: _type,
+            _length: _length,
+            _value: _value,
+        }
+    }
+}
+
 pub fn decode(buf: &[u8]) -> ber::Result<common::Tag>
 {
-    let buffer = buf.clone();
-    let mut cursor = Cursor::new(buffer);
-    let _type = try!(read_type(&mut cursor));
-    let _length = try!(read_length(&mut cursor));
+    decode_with_len(buf).map(|(tag, _)| tag)
+}
+
+/// Decode the first tag of `buf`, also returning how many bytes of it the tag took up
+///
+/// Anything after those bytes is left alone, so the next tag can be decoded from there.
+pub fn decode_with_len(buf: &[u8]) -> ber::Result<(common::Tag, usize)>
+{
+    decode_consumed(buf, Options { der: false, max_depth: DEFAULT_MAX_DEPTH, max_size: u64::max_value() }, 0, 0)
+        .map_err(|(_, err)| err)
+}
+
+/// Read only the identifier and length octets at the start of `buf`
+///
+/// Returns how many bytes they take up and the content length they announce, `None` standing
+/// for indefinite length. If `buf` ends before the header does, the result is `Ok(None)`, so
+/// this can tell how much more of a message has to arrive before it can be decoded.
+pub fn decode_header(buf: &[u8]) -> ber::Result<Option<(usize, Option<u64>)>>
+{
+    let opts = Options { der: false, max_depth: DEFAULT_MAX_DEPTH, max_size: u64::max_value() };
+
+    let mut header = buf;
+    match read_type(&mut header).and_then(|_| read_length(&mut header, opts))
+    {
+        Ok(length) => Ok(Some((buf.len() - header.len(), length))),
+        Err(Error::UnexpectedEof) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Like `decode`, but failures are `ParseError`s telling where in `buf` decoding failed
+///
+/// Its context names the problem and shows the bytes around it, which helps a lot when
+/// looking into malformed responses.
+pub fn decode_with_context(buf: &[u8]) -> ber::Result<common::Tag>
+{
+    let opts = Options { der: false, max_depth: DEFAULT_MAX_DEPTH, max_size: u64::max_value() };
+
+    decode_consumed(buf, opts, 0, 0).map(|(tag, _)| tag).map_err(|(offset, err)| Error::ParseError
+    {
+        offset: offset,
+        context: format!("{} ({})", err.reason(), hex_window(buf, offset)),
+    })
+}
+
+/// Decode the first tag of `buf` without copying its values
+///
+/// Primitive payloads of the returned tag and its children are slices of `buf`. Use
+/// `TagRef::to_owned` where an owned `Tag` is needed.
+pub fn decode_ref<'a>(buf: &'a [u8]) -> ber::Result<TagRef<'a>>
+{
+    decode_consumed(buf, Options { der: false, max_depth: DEFAULT_MAX_DEPTH, max_size: u64::max_value() }, 0, 0)
+        .map(|(tag, _)| tag).map_err(|(_, err)| err)
+}
+
+/// Decode the tags following each other in `buf`
+///
+/// The iterator ends with the buffer or after the first error, which a partial tag at the end
+/// of the buffer results in as well.
+pub fn decode_all<'a>(buf: &'a [u8]) -> impl Iterator<Item = ber::Result<common::Tag>> + 'a
+{
+    let mut rest = Some(buf);
+
+    iter::from_fn(move || {
+        let buf = match rest
+        {
+            Some(buf) if !buf.is_empty() => buf,
+            _ => return None,
+        };
+
+        match decode_with_len(buf)
+        {
+            Ok((tag, len)) =>
+            {
+                rest = Some(&buf[len..]);
+                Some(Ok(tag))
+            },
+            Err(e) =>
+            {
+                rest = None;
+                Some(Err(e))
+            },
+        }
+    })
+}
+
+/// Decode a tag allowing at most `max_depth` constructed tags nested in each other
+///
+/// Deeper structures fail with `MaxDepthExceeded` rather than exhausting the stack.
+pub fn decode_with_depth(buf: &[u8], max_depth: usize) -> ber::Result<common::Tag>
+{
+    decode_consumed(buf, Options { der: false, max_depth: max_depth, max_size: u64::max_value() }, 0, 0)
+        .map(|(tag, _)| tag).map_err(|(_, err)| err)
+}
+
+/// Decode a tag of at most `max_total` bytes of content, including everything nested in it
+///
+/// Lengths claiming more fail with `SizeLimitExceeded` before anything is allocated for them.
+pub fn decode_bounded(buf: &[u8], max_total: usize) -> ber::Result<common::Tag>
+{
+    let opts = Options { der: false, max_depth: DEFAULT_MAX_DEPTH, max_size: max_total as u64 };
 
-    let curpos = cursor.position() as usize;
-    let endpos = curpos + _length as usize;
-    let subslice = &cursor.get_mut()[curpos..endpos];
-    let _value = try!(read_value(_type.structure, subslice));
+    decode_consumed(buf, opts, 0, 0).map(|(tag, _)| tag).map_err(|(_, err)| err)
+}
 
-    Ok(Tag
+/// Decode a tag, enforcing the Distinguished Encoding Rules
+///
+/// Next to everything `decode` checks this only accepts definite, minimally encoded lengths,
+/// minimally encoded INTEGER and ENUMERATED values and SETs whose elements are in
+/// ascending order (X.690 Section 10 and 11).
+pub fn decode_der(buf: &[u8]) -> ber::Result<common::Tag>
+{
+    decode_consumed(buf, Options { der: true, max_depth: DEFAULT_MAX_DEPTH, max_size: u64::max_value() }, 0, 0)
+        .map(|(tag, _)| tag).map_err(|(_, err)| err)
+}
+
+/// Decode a tag, also returning how many bytes of `buf` it took up
+///
+/// For definite lengths this is the encoded size of the tag. With indefinite lengths the
+/// header and the end-of-contents marker are counted as well. `depth` is the number of
+/// constructed tags enclosing this one and `offset` where `buf` starts in the whole input.
+fn decode_consumed<'a, T: Node<'a>>(buf: &'a [u8], opts: Options, depth: usize, offset: usize) -> Located<(T, usize)>
+{
+    let end = offset + buf.len();
+    // Running out of input is reported at its end, anything else at the start of the field
+    let at = |start: usize| move |err| match err
+    {
+        Error::UnexpectedEof => (end, err),
+        _ => (start, err),
+    };
+
+    let mut header = buf;
+    let _type = try!(read_type(&mut header).map_err(at(offset)));
+
+    if _type.structure == common::Structure::Constructed && depth >= opts.max_depth
+    {
+        return Err((offset, Error::MaxDepthExceeded));
+    }
+    let length_start = offset + buf.len() - header.len();
+    let length = try!(read_length(&mut header, opts).map_err(at(length_start)));
+
+    let curpos = buf.len() - header.len();
+
+    let (_length, _value, consumed) = match length
+    {
+        Some(_length) =>
+        {
+            // The value has to fit into what's left of the buffer
+            if _length > (buf.len() - curpos) as u64
+            {
+                return Err((end, Error::UnexpectedEof));
+            }
+
+            let endpos = curpos + _length as usize;
+            let subslice = &buf[curpos..endpos];
+            let _value = try!(read_value::<T>(&_type, subslice, opts, depth + 1, offset + curpos));
+
+            (_length, _value, endpos)
+        },
+        None =>
+        {
+            // Only constructed tags may use the indefinite form (X.690 8.1.3.2)
+            if _type.structure != common::Structure::Constructed
+            {
+                return Err((length_start, Error::IndefiniteLength));
+            }
+
+            let (tags, read_len) = try!(read_indefinite(&buf[curpos..], opts, depth + 1, offset + curpos));
+            if read_len as u64 > opts.max_size
+            {
+                return Err((length_start, Error::SizeLimitExceeded));
+            }
+
+            // The end-of-contents marker is not part of the content
+            (read_len as u64, T::constructed(tags), curpos + read_len + 2)
+        },
+    };
+
+    Ok((T::new(_type, _length, _value), consumed))
+}
+
+/// Decode a single tag from `reader`, reading no further than its last byte
+///
+/// Unlike `decode` this doesn't need the whole tag in memory up front, which makes it suitable
+/// for reading messages straight off a connection.
+#[cfg(feature = "std")]
+pub fn decode_from_reader<R: Read>(reader: &mut R) -> ber::Result<common::Tag>
+{
+    let opts = Options { der: false, max_depth: DEFAULT_MAX_DEPTH, max_size: u64::max_value() };
+
+    read_tag(reader, opts, 0).map(|(tag, _)| tag)
+}
+
+/// Like `decode_from_reader`, but refuses tags with more than `max_total` bytes of content
+///
+/// This keeps a peer from making us buffer an arbitrary amount of data for a single tag.
+#[cfg(feature = "std")]
+pub fn decode_from_reader_bounded<R: Read>(reader: &mut R, max_total: usize) -> ber::Result<common::Tag>
+{
+    let opts = Options { der: false, max_depth: DEFAULT_MAX_DEPTH, max_size: max_total as u64 };
+
+    read_tag(reader, opts, 0).map(|(tag, _)| tag)
+}
+
+/// Streaming counterpart of `decode_consumed`
+#[cfg(feature = "std")]
+fn read_tag<R: Read>(reader: &mut R, opts: Options, depth: usize) -> ber::Result<(common::Tag, usize)>
+{
+    let mut header = Vec::new();
+    let (_type, length) = {
+        // Keep track of the header bytes so we know how much was consumed
+        let mut tee = Tee { inner: &mut *reader, read: &mut header };
+        let _type = try!(read_type(&mut tee));
+
+        if _type.structure == common::Structure::Constructed && depth >= opts.max_depth
+        {
+            return Err(Error::MaxDepthExceeded);
+        }
+
+        (_type, try!(read_length(&mut tee, opts)))
+    };
+
+    let (_length, _value, consumed) = match length
+    {
+        Some(_length) =>
+        {
+            // Not allocating up front, so a bogus length can't make us reserve gigabytes
+            let mut content = Vec::new();
+            try!(reader.by_ref().take(_length).read_to_end(&mut content));
+            if (content.len() as u64) < _length
+            {
+                return Err(Error::UnexpectedEof);
+            }
+
+            let _value = try!(read_value::<Tag>(&_type, &content, opts, depth + 1, 0).map_err(|(_, err)| err));
+
+            (_length, _value, header.len() + content.len())
+        },
+        None =>
+        {
+            if _type.structure != common::Structure::Constructed
+            {
+                return Err(Error::IndefiniteLength);
+            }
+
+            let mut tags = Vec::new();
+            let mut read_len = 0;
+
+            loop
+            {
+                let (tag, child_len) = try!(read_tag(reader, opts, depth + 1));
+
+                // The end-of-contents marker is read like any other tag
+                if tag._type.class == common::Class::Universal(common::UniversalTypes::Eoc)
+                    && tag._length == 0
+                {
+                    break;
+                }
+
+                tags.push(tag);
+                read_len += child_len;
+                if read_len as u64 > opts.max_size
+                {
+                    return Err(Error::SizeLimitExceeded);
+                }
+            }
+
+            (read_len as u64, common::Payload::Constructed(tags), header.len() + read_len + 2)
+        },
+    };
+
+    Ok((Tag
     {
         size: common::calculate_len(&_type, &_length),
         _type: _type,
         _length: _length,
         _value: _value,
-    })
+    }, consumed))
 }
 
-fn read_type(cursor: &mut Cursor<&[u8]>) -> ber::Result<common::Type>
+/// Where `read_type` and `read_length` take the header bytes of a tag from
+trait Source
 {
-    let first_byte = try!(cursor.read_u8());
+    fn next_byte(&mut self) -> ber::Result<u8>;
+}
+
+impl<'a> Source for &'a [u8]
+{
+    fn next_byte(&mut self) -> ber::Result<u8>
+    {
+        match self.split_first()
+        {
+            Some((&byte, rest)) =>
+            {
+                *self = rest;
+                Ok(byte)
+            },
+            None => Err(Error::UnexpectedEof),
+        }
+    }
+}
+
+/// Reader copying everything read through it into `read`
+#[cfg(feature = "std")]
+struct Tee<'a, R: 'a>
+{
+    inner: &'a mut R,
+    read: &'a mut Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+impl<'a, R: Read> Source for Tee<'a, R>
+{
+    fn next_byte(&mut self) -> ber::Result<u8>
+    {
+        let byte = try!(self.inner.read_u8().map_err(read_error));
+        self.read.push(byte);
+
+        Ok(byte)
+    }
+}
+
+/// Decode children of an indefinite length tag up to the end-of-contents marker
+///
+/// Returns the children and the number of bytes they took up, not counting the marker.
+fn read_indefinite<'a, T: Node<'a>>(buf: &'a [u8], opts: Options, depth: usize, offset: usize) -> Located<(Vec<T>, usize)>
+{
+    let mut tags = Vec::new();
+    let mut pos = 0;
+
+    loop
+    {
+        let rest = &buf[pos..];
+
+        if rest.len() < 2
+        {
+            // Ran out of input before the end-of-contents marker
+            return Err((offset + buf.len(), Error::UnexpectedEof));
+        }
+
+        if rest[0] == 0x00 && rest[1] == 0x00
+        {
+            return Ok((tags, pos));
+        }
+
+        let (tag, read_len) = try!(decode_consumed(rest, opts, depth, offset + pos));
+        tags.push(tag);
+        pos += read_len;
+    }
+}
+
+fn read_type<S: Source>(reader: &mut S) -> ber::Result<common::Type>
+{
+    let first_byte = try!(reader.next_byte());
 
     let class = first_byte >> 6;
-    let structure = common::Structure::from_u8((first_byte & 0x20) >> 5);
+    let structure = try!(common::Structure::from_u8((first_byte & 0x20) >> 5));
     let number = first_byte & 0x1F;
 
     // Tags are using the extended form
     if number == 0x1F
     {
-        let mut tag = 0u32;
-
-        for count in 0..
+        // Collect the tag number bytes up to the one without continuation bit. Anything longer
+        // than ten bytes is too long for decode_tag_number anyway.
+        let mut bytes = Vec::new();
+        while
         {
-            let byte = try!(cursor.read_u8());
+            let byte = try!(reader.next_byte());
+            bytes.push(byte);
 
-            // The first bit does not count towards the final ID
-            let nbr = (byte & 0x7F) as u32;
+            byte & 0x80 != 0 && bytes.len() < 10
+        } {}
 
-            tag = tag << 7;
-            tag += nbr;
+        let (tag, _) = try!(decode_tag_number(&bytes));
 
-            // If the 8th bit is not set this byte is the last
-            if byte & 0x80 == 0
-            {
-                break;
-            }
-        }
-
-        let class = try!(common::Class::construct(class, tag as i64));
+        let class = try!(common::Class::construct(class, tag));
         Ok(common::Type {
             class: class,
             structure: structure
@@ -75,68 +491,245 @@ fn read_type(cursor: &mut Cursor<&[u8]>) -> ber::Result<common::Type>
     }
 }
 
-fn read_length(cursor: &mut Cursor<&[u8]>) -> ber::Result<u64>
+/// Decode the base-128 encoded tag number following an identifier octet of the form xxx11111.
+///
+/// Returns the tag number and the amount of bytes consumed from `bytes`.
+pub fn decode_tag_number(bytes: &[u8]) -> ber::Result<(i64, usize)>
 {
-    let first_byte = try!(cursor.read_u8());
+    // A leading 0x80 would only add leading zeroes, which BER forbids
+    if bytes.first() == Some(&0x80)
+    {
+        return Err(Error::InvalidASN1);
+    }
+
+    let mut tag = 0i64;
+
+    for (count, byte) in bytes.iter().enumerate()
+    {
+        // Shifting in another 7 bits would overflow the i64
+        if tag > (i64::max_value() >> 7)
+        {
+            return Err(Error::ExtendedTagTooLong);
+        }
+
+        // The first bit does not count towards the final ID
+        tag = (tag << 7) | (byte & 0x7F) as i64;
+
+        // If the 8th bit is not set this byte is the last
+        if byte & 0x80 == 0
+        {
+            return Ok((tag, count + 1));
+        }
+    }
+
+    // We ran out of bytes before the last tag byte
+    Err(Error::UnexpectedEof)
+}
+
+/// Running out of input is a truncated tag, other errors (e.g. timeouts) are passed on
+#[cfg(feature = "std")]
+fn read_error(err: io::Error) -> Error
+{
+    if err.kind() == io::ErrorKind::UnexpectedEof
+    {
+        Error::UnexpectedEof
+    }
+    else
+    {
+        Error::Io(err)
+    }
+}
+
+/// Read the length octets, `None` meaning indefinite length
+fn read_length<S: Source>(reader: &mut S, opts: Options) -> ber::Result<Option<u64>>
+{
+    let first_byte = try!(reader.next_byte());
 
     if first_byte == 0x80
     {
-        // Indefinite lenght. Not valid in LDAP
-        return Err(Error::IndefiniteLength);
+        if opts.der
+        {
+            return Err(Error::IndefiniteLengthInDer);
+        }
+
+        // Indefinite length, content ends with an end-of-contents marker
+        return Ok(None);
+    }
+
+    // 0xFF is reserved for future extensions (X.690 8.1.3.5)
+    if first_byte == 0xFF
+    {
+        return Err(Error::InvalidASN1);
     }
 
     // First bit is set. Either we're using indefinite length or the long form
     if first_byte > 0x80
     {
-        return Ok(try!(cursor.read_uint::<BigEndian>((first_byte & 0x7f) as usize)) as u64);
+        let count = (first_byte & 0x7f) as usize;
+
+        let mut length = 0u64;
+        for _ in 0..count
+        {
+            // Lengths beyond 64 bits would wrap around to something small instead
+            if length >> 56 != 0
+            {
+                return Err(Error::InvalidASN1);
+            }
+            length = length << 8 | try!(reader.next_byte()) as u64;
+
+            // The length only grows from here, so there's no point reading on
+            if length > opts.max_size
+            {
+                return Err(Error::SizeLimitExceeded);
+            }
+        }
+
+        // DER wants the short form where possible and no leading zero octets
+        let needed = (64 - length.leading_zeros() as usize + 7) / 8;
+        if opts.der && (length < 0x80 || count != needed)
+        {
+            return Err(Error::NonMinimalLength);
+        }
+
+        return Ok(Some(length));
     }
 
     // Using the short form
-    Ok(first_byte as u64)
+    if first_byte as u64 > opts.max_size
+    {
+        return Err(Error::SizeLimitExceeded);
+    }
+
+    Ok(Some(first_byte as u64))
 }
 
-fn read_value(s: common::Structure, buf: &[u8]) -> ber::Result<common::Payload>
+fn read_value<'a, T: Node<'a>>(t: &common::Type, buf: &'a [u8], opts: Options, depth: usize, offset: usize)
+    -> Located<T::Payload>
 {
-    match s
+    match t.structure
     {
         common::Structure::Primitive =>
         {
-            // to_vec() copies
-            Ok(common::Payload::Primitive(buf.to_vec()))
+            if opts.der
+            {
+                try!(check_der_primitive(&t.class, buf).map_err(|err| (offset, err)));
+            }
+
+            Ok(T::primitive(buf))
         },
         common::Structure::Constructed =>
         {
             // Parse
-            let mut tags = Vec::<common::Tag>::new();
+            let mut tags = Vec::<T>::new();
 
             // Constructed tags may be empty
             if buf.len() > 0
             {
                 let mut left = buf.len();
+                let mut previous: &[u8] = &[];
                 while
                 {
-                    let tag = try!(decode(buf));
-                    let read_len = common::calculate_len(&tag._type, &tag._length);
-                    tags.push(tag);
-                    left -= read_len as usize;
+                    // Each child starts right where the previous one ended
+                    let start = buf.len() - left;
+                    let (tag, read_len) = try!(decode_consumed(&buf[start..], opts, depth, offset + start));
+                    if read_len > left
+                    {
+                        return Err((offset + start, Error::InvalidASN1));
+                    }
+
+                    // DER sorts SET elements by their encoding (X.690 11.6)
+                    let encoding = &buf[start..start + read_len];
+                    if opts.der && t.class == common::Class::Universal(common::UniversalTypes::Set)
+                        && encoding < previous
+                    {
+                        return Err((offset + start, Error::UnsortedSet));
+                    }
+                    previous = encoding;
 
-                    println!("{}", left);
+                    tags.push(tag);
+                    left -= read_len;
 
                     // If this returns false the while loop ends
                     left > 0
                 } {}
             }
 
-            Ok(common::Payload::Constructed(tags))
+            Ok(T::constructed(tags))
         },
     }
 }
 
+/// Hex dump of the bytes around `offset` in `buf`, the one at `offset` in brackets
+///
+/// An offset at the end of `buf` is shown as empty brackets after the last byte.
+fn hex_window(buf: &[u8], offset: usize) -> String
+{
+    let start = offset.saturating_sub(CONTEXT_BYTES);
+    let end = cmp::min(offset + CONTEXT_BYTES + 1, buf.len());
+    let mut window = String::new();
+
+    if start > 0
+    {
+        window.push_str(".. ");
+    }
+    for pos in start..end
+    {
+        if pos == offset
+        {
+            window.push_str(&format!("[{:02x}] ", buf[pos]));
+        }
+        else
+        {
+            window.push_str(&format!("{:02x} ", buf[pos]));
+        }
+    }
+    if offset >= buf.len()
+    {
+        window.push_str("[] ");
+    }
+    else if end < buf.len()
+    {
+        window.push_str(".. ");
+    }
+
+    window.pop();
+    window
+}
+
+/// Check primitive values with a distinguished encoding
+fn check_der_primitive(class: &common::Class, buf: &[u8]) -> ber::Result<()>
+{
+    match *class
+    {
+        common::Class::Universal(common::UniversalTypes::Integer)
+        | common::Class::Universal(common::UniversalTypes::Enumerated) =>
+        {
+            if buf.is_empty()
+            {
+                return Err(Error::InvalidASN1);
+            }
+
+            // The first nine bits may not be all zeros or all ones (X.690 8.3.2)
+            if buf.len() > 1 && ((buf[0] == 0x00 && buf[1] & 0x80 == 0)
+                                 || (buf[0] == 0xFF && buf[1] & 0x80 != 0))
+            {
+                return Err(Error::NonMinimalInteger);
+            }
+
+            Ok(())
+        },
+        common::Class::Universal(common::UniversalTypes::Boolean) => types::decode_boolean_der(buf).map(|_| ()),
+        common::Class::Universal(common::UniversalTypes::BitString) => types::decode_bit_string_der(buf).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
 #[cfg(test)]
 mod tests
 {
     use super::*;
     use ber::common;
+    use ber::error::ASN1Error as Error;
     use std::io::Cursor;
 
     #[test]
@@ -182,14 +775,489 @@ mod tests
         })
     }
 
-    // #[test]
+    #[test]
+    fn decode_constructed_tag_with_multiple_children()
+    {
+        let bytestream = [0x30, 0x06, 0x02, 0x01, 0x05, 0x02, 0x01, 0x07];
+        let tag = super::decode(&bytestream).unwrap();
+
+        match tag._value
+        {
+            common::Payload::Constructed(ref children) =>
+            {
+                assert_eq!(children.len(), 2);
+                assert_eq!(children[0]._value, common::Payload::Primitive(vec![0x05]));
+                assert_eq!(children[1]._value, common::Payload::Primitive(vec![0x07]));
+            },
+            common::Payload::Primitive(_) => panic!("Expected a constructed tag"),
+        }
+    }
+
+    #[test]
+    fn decode_indefinite_length_tags()
+    {
+        // SEQUENCE { INTEGER 5, INTEGER 7 } terminated by end-of-contents
+        let bytestream = [0x30, 0x80, 0x02, 0x01, 0x05, 0x02, 0x01, 0x07, 0x00, 0x00];
+        let tag = super::decode(&bytestream).unwrap();
+
+        assert_eq!(tag._length, 6);
+        assert_eq!(tag.size, 8);
+
+        match tag._value
+        {
+            common::Payload::Constructed(ref children) =>
+            {
+                assert_eq!(children.len(), 2);
+                assert_eq!(children[0]._value, common::Payload::Primitive(vec![0x05]));
+                assert_eq!(children[1]._value, common::Payload::Primitive(vec![0x07]));
+            },
+            common::Payload::Primitive(_) => panic!("Expected a constructed tag"),
+        }
+    }
+
+    #[test]
+    fn decode_nested_indefinite_length_tags()
+    {
+        // SEQUENCE { SEQUENCE { INTEGER 5 }, INTEGER 7 }, both indefinite
+        let bytestream = [0x30, 0x80, 0x30, 0x80, 0x02, 0x01, 0x05, 0x00, 0x00, 0x02, 0x01, 0x07, 0x00, 0x00];
+        let tag = super::decode(&bytestream).unwrap();
+
+        match tag._value
+        {
+            common::Payload::Constructed(ref children) =>
+            {
+                assert_eq!(children.len(), 2);
+                assert_eq!(children[0]._length, 3);
+                assert_eq!(children[1]._value, common::Payload::Primitive(vec![0x07]));
+            },
+            common::Payload::Primitive(_) => panic!("Expected a constructed tag"),
+        }
+    }
+
+    #[test]
+    fn decode_indefinite_length_rejects_invalid()
+    {
+        // Primitive tags can't use the indefinite form
+        assert!(super::decode(&[0x04, 0x80, 0x61, 0x00, 0x00]).is_err());
+        // Missing end-of-contents
+        assert!(super::decode(&[0x30, 0x80, 0x02, 0x01, 0x05]).is_err());
+    }
+
+    #[test]
+    fn decode_rejects_reserved_and_misplaced_lengths()
+    {
+        // The reserved length byte, followed by junk that must not be read as 127 length bytes
+        let reserved = [0x04, 0xFF, 0x01, 0x02, 0x03];
+        match super::decode(&reserved)
+        {
+            Err(Error::InvalidASN1) => {},
+            x => panic!("Expected InvalidASN1, got {:?}", x),
+        }
+        match super::decode_from_reader(&mut &reserved[..])
+        {
+            Err(Error::InvalidASN1) => {},
+            x => panic!("Expected InvalidASN1, got {:?}", x),
+        }
+
+        // Indefinite length on an OCTET STRING
+        let indefinite = [0x04, 0x80, 0x61, 0x00, 0x00];
+        match super::decode(&indefinite)
+        {
+            Err(Error::IndefiniteLength) => {},
+            x => panic!("Expected IndefiniteLength, got {:?}", x),
+        }
+        match super::decode_from_reader(&mut &indefinite[..])
+        {
+            Err(Error::IndefiniteLength) => {},
+            x => panic!("Expected IndefiniteLength, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn decode_der_accepts_distinguished_encodings()
+    {
+        // SET { INTEGER 5, INTEGER 256 } with a long form length where one is needed
+        let bytestream = [0x31, 0x07, 0x02, 0x01, 0x05, 0x02, 0x02, 0x01, 0x00];
+        assert!(super::decode_der(&bytestream).is_ok());
+
+        let mut long = vec![0x04, 0x81, 0x80];
+        long.extend(vec![0x61; 0x80]);
+        assert!(super::decode_der(&long).is_ok());
+    }
+
+    #[test]
+    fn decode_der_rejects_ber_only_encodings()
+    {
+        // Nine length octets, eight of them leading zeros
+        let mut long = vec![0x04, 0x89, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80];
+        long.extend_from_slice(&[0x41; 0x80]);
+        match decode_der(&long)
+        {
+            Err(Error::NonMinimalLength) => {},
+            x => panic!("Expected NonMinimalLength, got {:?}", x),
+        }
+        match decode_der(&[0x04, 0x82, 0x00, 0x80])
+        {
+            Err(Error::NonMinimalLength) => {},
+            x => panic!("Expected NonMinimalLength, got {:?}", x),
+        }
+
+        match super::decode_der(&[0x30, 0x80, 0x02, 0x01, 0x05, 0x00, 0x00])
+        {
+            Err(Error::IndefiniteLengthInDer) => {},
+            x => panic!("Expected IndefiniteLengthInDer, got {:?}", x),
+        }
+
+        // Long form for a length below 128 and a leading zero length byte
+        for bytestream in &[&[0x04, 0x81, 0x01, 0x61][..], &[0x04, 0x82, 0x00, 0x01, 0x61][..]]
+        {
+            match super::decode_der(bytestream)
+            {
+                Err(Error::NonMinimalLength) => {},
+                x => panic!("Expected NonMinimalLength, got {:?}", x),
+            }
+        }
+
+        for bytestream in &[&[0x02, 0x02, 0x00, 0x05][..], &[0x0A, 0x02, 0xFF, 0xFF][..]]
+        {
+            match super::decode_der(bytestream)
+            {
+                Err(Error::NonMinimalInteger) => {},
+                x => panic!("Expected NonMinimalInteger, got {:?}", x),
+            }
+        }
+
+        match super::decode_der(&[0x31, 0x06, 0x02, 0x01, 0x07, 0x02, 0x01, 0x05])
+        {
+            Err(Error::UnsortedSet) => {},
+            x => panic!("Expected UnsortedSet, got {:?}", x),
+        }
+
+        match super::decode_der(&[0x03, 0x02, 0x04, 0xA1])
+        {
+            Err(Error::UnusedBitsSet) => {},
+            x => panic!("Expected UnusedBitsSet, got {:?}", x),
+        }
+
+        match super::decode_der(&[0x01, 0x01, 0x01])
+        {
+            Err(Error::NonCanonicalBoolean) => {},
+            x => panic!("Expected NonCanonicalBoolean, got {:?}", x),
+        }
+
+        // The lenient decoder still takes all of them
+        assert!(super::decode(&[0x04, 0x81, 0x01, 0x61]).is_ok());
+        assert!(super::decode(&[0x02, 0x02, 0x00, 0x05]).is_ok());
+        assert!(super::decode(&[0x31, 0x06, 0x02, 0x01, 0x07, 0x02, 0x01, 0x05]).is_ok());
+        assert!(super::decode(&[0x03, 0x02, 0x04, 0xA1]).is_ok());
+        assert!(super::decode(&[0x01, 0x01, 0x01]).is_ok());
+    }
+
+    #[test]
+    fn decode_rejects_truncated_input()
+    {
+        let truncated: [&[u8]; 7] = [
+            // Nothing at all
+            &[],
+            // Identifier without length
+            &[0x04],
+            // Extended tag number cut short
+            &[0x9F, 0x87],
+            // Long form length cut short
+            &[0x04, 0x82, 0x01],
+            // 200 byte value in a 10 byte buffer
+            &[0x04, 0x81, 0xC8, 0x61, 0x61, 0x61, 0x61, 0x61, 0x61, 0x61],
+            // Child claiming more than its parent holds
+            &[0x30, 0x03, 0x02, 0x05, 0x01],
+            // Indefinite length without end-of-contents
+            &[0x30, 0x80, 0x02, 0x01, 0x05, 0x00],
+        ];
+
+        for bytestream in truncated.iter()
+        {
+            match super::decode(bytestream)
+            {
+                Err(Error::UnexpectedEof) => {},
+                x => panic!("Expected UnexpectedEof for {:?}, got {:?}", bytestream, x),
+            }
+        }
+    }
+
+    #[test]
+    fn decode_with_len_back_to_back()
+    {
+        // INTEGER 5 and INTEGER 256
+        let bytestream = [0x02, 0x01, 0x05, 0x02, 0x02, 0x01, 0x00];
+
+        let (first, len) = super::decode_with_len(&bytestream).unwrap();
+        assert_eq!(len, 3);
+        assert_eq!(first._value, common::Payload::Primitive(vec![0x05]));
+
+        let (second, len) = super::decode_with_len(&bytestream[len..]).unwrap();
+        assert_eq!(len, 4);
+        assert_eq!(second._value, common::Payload::Primitive(vec![0x01, 0x00]));
+
+        // Indefinite lengths count their end-of-contents marker
+        let indefinite = [0x30, 0x80, 0x02, 0x01, 0x05, 0x00, 0x00, 0x02, 0x01, 0x06];
+        assert_eq!(super::decode_with_len(&indefinite).unwrap().1, 7);
+    }
+
+    #[test]
+    fn decode_all_iterates_messages()
+    {
+        // Three LDAPMessages with an empty [APPLICATION 2] each, message IDs 1 to 3
+        let bytestream = [
+            0x30, 0x05, 0x02, 0x01, 0x01, 0x42, 0x00,
+            0x30, 0x05, 0x02, 0x01, 0x02, 0x42, 0x00,
+            0x30, 0x05, 0x02, 0x01, 0x03, 0x42, 0x00,
+        ];
+
+        let tags: Vec<_> = super::decode_all(&bytestream).map(|tag| tag.unwrap()).collect();
+        assert_eq!(tags.len(), 3);
+        for (i, tag) in tags.iter().enumerate()
+        {
+            assert_eq!(*tag, super::decode(&bytestream[i * 7..(i + 1) * 7]).unwrap());
+        }
+
+        // A trailing partial message is the last item
+        let mut results = super::decode_all(&bytestream[..17]);
+        assert!(results.next().unwrap().is_ok());
+        assert!(results.next().unwrap().is_ok());
+        match results.next()
+        {
+            Some(Err(Error::UnexpectedEof)) => {},
+            x => panic!("Expected UnexpectedEof, got {:?}", x),
+        }
+        assert!(results.next().is_none());
+
+        assert!(super::decode_all(&[]).next().is_none());
+    }
+
+    #[test]
+    fn decode_header_needs_only_the_header()
+    {
+        assert_eq!(super::decode_header(&[]).unwrap(), None);
+        assert_eq!(super::decode_header(&[0x30]).unwrap(), None);
+        assert_eq!(super::decode_header(&[0x30, 0x05, 0x02]).unwrap(), Some((2, Some(5))));
+        assert_eq!(super::decode_header(&[0x30, 0x82, 0x01]).unwrap(), None);
+        assert_eq!(super::decode_header(&[0x30, 0x82, 0x01, 0x00]).unwrap(), Some((4, Some(256))));
+        assert_eq!(super::decode_header(&[0x7F, 0x87]).unwrap(), None);
+        assert_eq!(super::decode_header(&[0x7F, 0x87, 0x68, 0x01]).unwrap(), Some((4, Some(1))));
+        assert_eq!(super::decode_header(&[0x30, 0x80]).unwrap(), Some((2, None)));
+        assert!(super::decode_header(&[0x30, 0xFF]).is_err());
+    }
+
+    #[test]
+    fn decode_rejects_overflowing_lengths()
+    {
+        // Ten length octets for 2^72 + 5, which must not wrap around to 5
+        let overflow = [0x04, 0x8A, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x68, 0x65, 0x6c, 0x6c, 0x6f];
+        match super::decode(&overflow)
+        {
+            Err(Error::InvalidASN1) => {},
+            x => panic!("Expected InvalidASN1, got {:?}", x),
+        }
+        match super::decode_from_reader(&mut &overflow[..])
+        {
+            Err(Error::InvalidASN1) => {},
+            x => panic!("Expected InvalidASN1, got {:?}", x),
+        }
+
+        // Leading zero octets are allowed in BER, however many there are
+        let padded = [0x04, 0x8A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x68, 0x65, 0x6c, 0x6c, 0x6f];
+        assert_eq!(super::decode(&padded).unwrap()._value, common::Payload::Primitive(b"hello".to_vec()));
+
+        // DER gets the same guard, ahead of its own minimal length check
+        match super::decode_der(&overflow)
+        {
+            Err(Error::InvalidASN1) => {},
+            x => panic!("Expected InvalidASN1, got {:?}", x),
+        }
+
+        // The size limit applies to DER as well, ahead of the minimal length check
+        let opts = super::Options { der: true, max_depth: super::DEFAULT_MAX_DEPTH, max_size: 1024 };
+        match super::read_length(&mut &[0x8A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00][..], opts)
+        {
+            Err(Error::SizeLimitExceeded) => {},
+            x => panic!("Expected SizeLimitExceeded, got {:?}", x),
+        }
+        assert_eq!(super::read_length(&mut &[0x82, 0x04, 0x00][..], opts).unwrap(), Some(1024));
+    }
+
+    #[test]
+    fn decode_with_context_reports_offset()
+    {
+        let parse_error = |input: &[u8]| match decode_with_context(input)
+        {
+            Err(Error::ParseError { offset, context }) => (offset, context),
+            x => panic!("Expected ParseError, got {:?}", x),
+        };
+
+        // The second element of the SEQUENCE has the reserved length 0xFF
+        let input = [0x30, 0x06, 0x02, 0x01, 0x01, 0x04, 0xFF, 0x00];
+        assert_eq!(parse_error(&input), (6, "Invalid BER Structures detected. (30 06 02 01 01 04 [ff] 00)".to_string()));
+        assert_eq!(decode_with_context(&input).unwrap_err().to_string(),
+                   "Error: Malformed BER at byte 6: Invalid BER Structures detected. (30 06 02 01 01 04 [ff] 00)");
+        match decode(&input)
+        {
+            Err(Error::InvalidASN1) => {},
+            x => panic!("Expected InvalidASN1, got {:?}", x),
+        }
+
+        // Truncated input fails where it ends
+        assert_eq!(parse_error(&[0x30, 0x05, 0x02, 0x01]).1, "The input ended in the middle of a tag. (30 05 02 01 [])");
+
+        // Only the bytes near the offset are shown, here a BOOLEAN with indefinite length after four INTEGERs
+        let mut input = vec![0x30, 0x14];
+        for _ in 0..4
+        {
+            input.extend_from_slice(&[0x02, 0x01, 0x00]);
+        }
+        input.extend_from_slice(&[0x01, 0x80, 0x00, 0x00, 0x05, 0x00, 0x05, 0x00]);
+        assert_eq!(parse_error(&input), (15, "Indefinite Length is only valid for constructed tags. \
+                                              (.. 00 02 01 00 02 01 00 01 [80] 00 00 05 00 05 00)".to_string()));
+    }
+
+    #[test]
+    fn decode_bounded_rejects_huge_lengths()
+    {
+        // OCTET STRING claiming 4 GiB of content with only three bytes present
+        let huge = [0x04, 0x84, 0xFF, 0xFF, 0xFF, 0xFF, 0x61, 0x62, 0x63];
+        match super::decode_bounded(&huge, 1024)
+        {
+            Err(Error::SizeLimitExceeded) => {},
+            x => panic!("Expected SizeLimitExceeded, got {:?}", x),
+        }
+        match super::decode_from_reader_bounded(&mut &huge[..], 1024)
+        {
+            Err(Error::SizeLimitExceeded) => {},
+            x => panic!("Expected SizeLimitExceeded, got {:?}", x),
+        }
+
+        // The budget covers the nested content, even without a length up front
+        let nested = [0x30, 0x80, 0x04, 0x02, 0x61, 0x62, 0x04, 0x02, 0x63, 0x64, 0x00, 0x00];
+        assert!(super::decode_bounded(&nested, 8).is_ok());
+        match super::decode_bounded(&nested, 7)
+        {
+            Err(Error::SizeLimitExceeded) => {},
+            x => panic!("Expected SizeLimitExceeded, got {:?}", x),
+        }
+        match super::decode_from_reader_bounded(&mut &nested[..], 7)
+        {
+            Err(Error::SizeLimitExceeded) => {},
+            x => panic!("Expected SizeLimitExceeded, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn decode_enforces_max_depth()
+    {
+        // SEQUENCE { SEQUENCE { SEQUENCE { NULL } } }
+        let bytestream = [0x30, 0x06, 0x30, 0x04, 0x30, 0x02, 0x05, 0x00];
+
+        assert!(super::decode_with_depth(&bytestream, 3).is_ok());
+        match super::decode_with_depth(&bytestream, 2)
+        {
+            Err(Error::MaxDepthExceeded) => {},
+            x => panic!("Expected MaxDepthExceeded, got {:?}", x),
+        }
+
+        // Far deeper than the stack would take
+        let nesting = 100000;
+        let mut deep = Vec::with_capacity(nesting * 4);
+        for _ in 0..nesting
+        {
+            deep.extend(&[0x30, 0x80]);
+        }
+        for _ in 0..nesting
+        {
+            deep.extend(&[0x00, 0x00]);
+        }
+
+        match super::decode(&deep)
+        {
+            Err(Error::MaxDepthExceeded) => {},
+            x => panic!("Expected MaxDepthExceeded, got {:?}", x),
+        }
+    }
+
+    /// Reader handing out a single byte per call
+    struct OneByteReader<'a>
+    {
+        data: &'a [u8],
+    }
+
+    impl<'a> std::io::Read for OneByteReader<'a>
+    {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>
+        {
+            if self.data.is_empty() || buf.is_empty()
+            {
+                return Ok(0);
+            }
+
+            buf[0] = self.data[0];
+            self.data = &self.data[1..];
+
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn decode_from_reader_matches_decode()
+    {
+        let inputs: [&[u8]; 4] = [
+            &[0x30, 0x06, 0x02, 0x01, 0x05, 0x02, 0x01, 0x07],
+            &[0x9F, 0x87, 0x68, 0x06, 0x73, 0x65, 0x63, 0x6F, 0x6E, 0x64],
+            &[0x30, 0x80, 0x30, 0x80, 0x02, 0x01, 0x05, 0x00, 0x00, 0x02, 0x01, 0x07, 0x00, 0x00],
+            &[0x30, 0x81, 0x03, 0x02, 0x01, 0x05],
+        ];
+
+        for bytes in inputs.iter()
+        {
+            let expected = super::decode(bytes).unwrap();
+
+            let mut cursor = Cursor::new(bytes.to_vec());
+            assert_eq!(super::decode_from_reader(&mut cursor).unwrap(), expected);
+
+            let mut reader = OneByteReader { data: bytes };
+            assert_eq!(super::decode_from_reader(&mut reader).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn decode_from_reader_stops_after_the_tag()
+    {
+        let mut cursor = Cursor::new(vec![0x02, 0x01, 0x05, 0x02, 0x01, 0x07]);
+
+        let first = super::decode_from_reader(&mut cursor).unwrap();
+        assert_eq!(first._value, common::Payload::Primitive(vec![0x05]));
+        assert_eq!(cursor.position(), 3);
+
+        let second = super::decode_from_reader(&mut cursor).unwrap();
+        assert_eq!(second._value, common::Payload::Primitive(vec![0x07]));
+
+        match super::decode_from_reader(&mut cursor)
+        {
+            Err(Error::UnexpectedEof) => {},
+            x => panic!("Expected UnexpectedEof, got {:?}", x),
+        }
+
+        // Value cut short
+        let mut reader = OneByteReader { data: &[0x04, 0x05, 0x61, 0x62] };
+        match super::decode_from_reader(&mut reader)
+        {
+            Err(Error::UnexpectedEof) => {},
+            x => panic!("Expected UnexpectedEof, got {:?}", x),
+        }
+    }
+
+    #[test]
     fn decode_extended_type_tags()
     {
         let mut bytestream = [0x9F,0x87,0x68,0x06,0x73,0x65,0x63,0x6F,0x6E,0x64];
         let tag = super::decode(&mut bytestream).unwrap();
 
-        println!("{:?}", tag);
-
         assert!(tag == common::Tag {
             _type: common::Type {
                     class: common::Class::ContextSpecific(1000),
@@ -201,6 +1269,25 @@ mod tests
         });
     }
 
+    #[test]
+    fn decode_tag_numbers()
+    {
+        assert_eq!(super::decode_tag_number(&[0x1F]).unwrap(), (31, 1));
+        assert_eq!(super::decode_tag_number(&[0x87, 0x68, 0x06]).unwrap(), (1000, 2));
+        assert_eq!(super::decode_tag_number(&[0x81, 0x80, 0x00]).unwrap(), (16384, 3));
+    }
+
+    #[test]
+    fn decode_tag_number_rejects_invalid()
+    {
+        // Non-minimal: leading 0x80
+        assert!(super::decode_tag_number(&[0x80, 0x01]).is_err());
+        // Truncated: continuation bit set on the last byte
+        assert!(super::decode_tag_number(&[0x87]).is_err());
+        // Too large for an i64
+        assert!(super::decode_tag_number(&[0xFF; 10]).is_err());
+    }
+
     // #[test]
     fn decode_long_length_tags()
     {
@@ -305,4 +1392,42 @@ mod tests
             size: 261
         });
     }
+
+    #[test]
+    fn decode_ref_borrows_the_input()
+    {
+        // SEQUENCE { OCTET STRING "cn", [0] "alice" } followed by a byte that isn't part of it
+        let bytes = vec![0x30, 0x0B, 0x04, 0x02, 0x63, 0x6E, 0xA0, 0x05, 0x80, 0x03, 0x61, 0x6C, 0x69, 0xFF];
+
+        let tag = decode_ref(&bytes).unwrap();
+        let children = match tag._value
+        {
+            common::PayloadRef::Constructed(ref children) => children,
+            ref x => panic!("Expected a constructed payload, got {:?}", x),
+        };
+
+        match children[0]._value
+        {
+            common::PayloadRef::Primitive(value) =>
+            {
+                assert_eq!(value, b"cn");
+                assert_eq!(value.as_ptr(), bytes[4..].as_ptr());
+            },
+            ref x => panic!("Expected a primitive payload, got {:?}", x),
+        }
+
+        match children[1]._value
+        {
+            common::PayloadRef::Constructed(ref inner) => match inner[0]._value
+            {
+                common::PayloadRef::Primitive(value) => assert_eq!(value.as_ptr(), bytes[10..].as_ptr()),
+                ref x => panic!("Expected a primitive payload, got {:?}", x),
+            },
+            ref x => panic!("Expected a constructed payload, got {:?}", x),
+        }
+
+        let owned = tag.to_owned();
+        assert_eq!(owned, decode(&bytes).unwrap());
+        assert_eq!((owned._length, owned.size), (11, 13));
+    }
 }
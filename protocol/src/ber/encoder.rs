@@ -1,34 +1,135 @@
 use ber;
-use ber::error::ASN1Error as Error;
 
-use std::io;
+#[cfg(feature = "std")]
 use std::io::Write;
 
 use byteorder::BigEndian;
 use byteorder::ByteOrder;
-use byteorder::WriteBytesExt;
 
-use ber::common::{self, Tag};
+use ber::common;
+use ber::types::ASNType;
+use control::{self, Control};
+use prelude::*;
 
+/// Where the encoder puts the bytes it produces
+trait Sink
+{
+    fn put(&mut self, bytes: &[u8]) -> ber::Result<()>;
+
+    /// Make room for `additional` more bytes, if that means anything for the sink
+    fn reserve(&mut self, _additional: usize) {}
+}
+
+impl Sink for Vec<u8>
+{
+    fn put(&mut self, bytes: &[u8]) -> ber::Result<()>
+    {
+        self.extend_from_slice(bytes);
+
+        Ok(())
+    }
+
+    fn reserve(&mut self, additional: usize)
+    {
+        Vec::reserve(self, additional);
+    }
+}
+
+/// Passes everything straight on to an `io::Write`
+#[cfg(feature = "std")]
+struct Writer<'a, W: 'a>(&'a mut W);
+
+#[cfg(feature = "std")]
+impl<'a, W: Write> Sink for Writer<'a, W>
+{
+    fn put(&mut self, bytes: &[u8]) -> ber::Result<()>
+    {
+        try!(self.0.write_all(bytes));
+
+        Ok(())
+    }
+}
+
+/// Encode `tag` as protocolOp of an LDAPMessage with the given message ID
 pub fn encode(tag: common::Tag, msgid: i32) -> ber::Result<Vec<u8>>
 {
-    let mut msgidvec: Vec<u8> = Vec::with_capacity(4);
-    try!(msgidvec.write_i32::<BigEndian>(msgid));
-    let msgidbuf = common::Payload::Primitive(msgidvec);
-    let msgidtag = common::construct(common::Class::Universal(common::UniversalTypes::Integer), msgidbuf);
+    encode_with_controls(tag, msgid, &[])
+}
+
+/// Like `encode`, but attaches `controls` to the LDAPMessage
+pub fn encode_with_controls(tag: common::Tag, msgid: i32, controls: &[Control]) -> ber::Result<Vec<u8>>
+{
+    let mut buffer = Vec::new();
+    try!(encode_into_with_controls(&tag, msgid, controls, &mut buffer));
+
+    Ok(buffer)
+}
 
-    let pl = common::Payload::Constructed(vec![msgidtag, tag]);
-    let envelope = common::construct(common::Class::Universal(common::UniversalTypes::Sequence), pl);
+/// Encode `tag` as protocolOp of an LDAPMessage, appending it to `buf`
+///
+/// Whatever `buf` held before is left in place, so a buffer can be cleared and reused for many
+/// messages instead of allocating a new one for each.
+pub fn encode_into(tag: &common::Tag, msgid: i32, buf: &mut Vec<u8>) -> ber::Result<()>
+{
+    encode_into_with_controls(tag, msgid, &[], buf)
+}
 
-    let mut buffer: Vec<u8> = Vec::with_capacity(envelope.size as usize);
-    try!(write_type(envelope._type, &mut buffer));
-    try!(write_length(envelope._length, &mut buffer));
-    try!(write_value(&envelope._value, &mut buffer));
+/// Like `encode_into`, but attaches `controls` to the LDAPMessage
+pub fn encode_into_with_controls(tag: &common::Tag, msgid: i32, controls: &[Control], buf: &mut Vec<u8>) -> ber::Result<()>
+{
+    write_message(tag, msgid, controls, buf)
+}
+
+/// Encode `tag` as protocolOp of an LDAPMessage straight into `writer`
+///
+/// Writes the same bytes as `encode`, but only borrows `tag` and never holds the whole message
+/// in memory. Lengths are taken from the sizes the tags already carry.
+#[cfg(feature = "std")]
+pub fn encode_to_writer<W: Write>(tag: &common::Tag, msgid: i32, controls: &[Control], writer: &mut W) -> ber::Result<()>
+{
+    write_message(tag, msgid, controls, &mut Writer(writer))
+}
+
+// Write the LDAPMessage envelope around `tag` without building it as a Tag first
+fn write_message<S: Sink>(tag: &common::Tag, msgid: i32, controls: &[Control], writer: &mut S) -> ber::Result<()>
+{
+    let msgidtag = msgid.into_ber_universal();
+    let controls = control::build_controls(controls);
+    let controls_size = controls.as_ref().map(|c| c.size).unwrap_or(0);
+
+    let envelope_type = common::Type
+    {
+        class: common::Class::Universal(common::UniversalTypes::Sequence),
+        structure: common::Structure::Constructed,
+    };
+
+    let length = msgidtag.size + tag.size + controls_size;
+    writer.reserve(common::calculate_len(&envelope_type, &length) as usize);
+
+    try!(write_type(envelope_type, writer));
+    try!(write_length(length, writer));
+    try!(write(&msgidtag, writer));
+    try!(write(tag, writer));
+    if let Some(ref controls) = controls
+    {
+        try!(write(controls, writer));
+    }
+
+    Ok(())
+}
+
+/// Encode a single `tag` without wrapping it into an LDAPMessage
+///
+/// Used for values that carry BER themselves, like those of controls.
+pub fn encode_tag(tag: &common::Tag) -> ber::Result<Vec<u8>>
+{
+    let mut buffer: Vec<u8> = Vec::with_capacity(tag.size as usize);
+    try!(write(tag, &mut buffer));
 
     Ok(buffer)
 }
 
-fn write(tag: &common::Tag, mut w: &mut Write) -> ber::Result<()>
+fn write<S: Sink>(tag: &common::Tag, w: &mut S) -> ber::Result<()>
 {
     try!(write_type(tag._type, w));
     try!(write_length(tag._length, w));
@@ -37,7 +138,7 @@ fn write(tag: &common::Tag, mut w: &mut Write) -> ber::Result<()>
     Ok(())
 }
 
-fn write_type(tagtype: common::Type, mut w: &mut Write) -> ber::Result<()>
+fn write_type<S: Sink>(tagtype: common::Type, w: &mut S) -> ber::Result<()>
 {
     let class_number = match tagtype.class
     {
@@ -66,17 +167,7 @@ fn write_type(tagtype: common::Type, mut w: &mut Write) -> ber::Result<()>
                     if tag > 30
                     {
                         let mut tagbytes: Vec<u8> = Vec::new();
-
-                        let mut tag = tag;
-                        while tag > 0
-                        {
-                            let mut byte = (tag & 0x7F) as u8;
-
-                            tag >>= 7;
-
-                            tagbytes.push(byte);
-                        }
-
+                        encode_tag_number(tag, &mut tagbytes);
                         extended_tag = Some(tagbytes);
 
                         // This means we need to set the 5 tag bits to 11111, so 31 or 0x1F
@@ -84,42 +175,53 @@ fn write_type(tagtype: common::Type, mut w: &mut Write) -> ber::Result<()>
                     }
                     else
                     {
-                        extended_tag = None;
                         (tag as u8)
                     }
                 },
             }; // let type_byte
 
-    try!(w.write_u8(type_byte));
-
-    let mut written = 1;
+    try!(w.put(&[type_byte]));
 
-    if let Some(mut ext_bytes) = extended_tag
+    if let Some(ext_bytes) = extended_tag
     {
-        for _ in 0..ext_bytes.len()-1
-        {
-            let mut byte = ext_bytes.pop().unwrap();
+        try!(w.put(&ext_bytes));
+    }
 
-            // Set the first bit
-            byte |= 0x80;
+    Ok(())
+}
 
-            w.write_u8(byte);
-        }
+/// Append the base-128 encoding of a long-form tag number to `buf`.
+///
+/// Every byte but the last has its 8th bit set to signal that more bytes follow.
+pub fn encode_tag_number(number: i64, buf: &mut Vec<u8>)
+{
+    let start = buf.len();
 
-        let byte = ext_bytes.pop().unwrap();
-        w.write_u8(byte);
-    }
+    let mut number = number;
+    while
+    {
+        buf.push((number & 0x7F) as u8);
+        number >>= 7;
+        number > 0
+    } {}
 
-    Ok(())
+    // We pushed the least significant group first
+    buf[start..].reverse();
+
+    let last = buf.len() - 1;
+    for byte in &mut buf[start..last]
+    {
+        *byte |= 0x80;
+    }
 }
 
 // Yes I know you could overflow the length in theory. But, do you have 2^64 bytes of memory?
-fn write_length(mut length: u64, mut w: &mut Write) -> ber::Result<()>
+fn write_length<S: Sink>(length: u64, w: &mut S) -> ber::Result<()>
 {
     // Short form
     if length < 128
     {
-        try!(w.write_u8(length as u8));
+        try!(w.put(&[length as u8]));
 
         Ok(())
     }
@@ -131,20 +233,23 @@ fn write_length(mut length: u64, mut w: &mut Write) -> ber::Result<()>
         while {count += 1; len >>= 8; len > 0 }{}
 
 
-        try!(w.write_u8(count | 0x80));
-        w.write_uint::<BigEndian>(length, count as usize);
+        let mut bytes = [0u8; 8];
+        BigEndian::write_u64(&mut bytes, length);
+
+        try!(w.put(&[count | 0x80]));
+        try!(w.put(&bytes[8 - count as usize..]));
 
         Ok(())
     }
 }
 
-fn write_value(payload: &common::Payload, mut w: &mut Write) -> ber::Result<()>
+fn write_value<S: Sink>(payload: &common::Payload, w: &mut S) -> ber::Result<()>
 {
     match *payload
     {
         common::Payload::Primitive(ref value) =>
         {
-            try!(w.write_all(value));
+            try!(w.put(value));
             Ok(())
         },
         common::Payload::Constructed(ref tags) =>
@@ -162,12 +267,94 @@ fn write_value(payload: &common::Payload, mut w: &mut Write) -> ber::Result<()>
 mod test
 {
     use super::*;
+    use ber;
     use ber::common;
     use std::io::Cursor;
 
     use byteorder::WriteBytesExt;
     use byteorder::BigEndian;
 
+    #[test]
+    fn encode_to_writer_matches_encode()
+    {
+        let make = || {
+            let name = {
+                let class = common::Class::Universal(common::UniversalTypes::OctetString);
+                let pl = common::Payload::Primitive(vec![0x61; 300]);
+
+                common::construct(class, pl)
+            };
+
+            let class = common::Class::Application(3);
+            let pl = common::Payload::Constructed(vec![name]);
+
+            common::construct(class, pl)
+        };
+
+        for &msgid in &[1, 127, 128, 70000]
+        {
+            let mut buf = Vec::<u8>::new();
+            super::encode_to_writer(&make(), msgid, &[], &mut buf).unwrap();
+
+            assert_eq!(buf, super::encode(make(), msgid).unwrap());
+        }
+
+        let controls = [Control { oid: "1.2.3.4".to_string(), criticality: true, value: Some(vec![0x05, 0x00]) }];
+        let mut buf = Vec::<u8>::new();
+        super::encode_to_writer(&make(), 2, &controls, &mut buf).unwrap();
+
+        assert_eq!(buf, super::encode_with_controls(make(), 2, &controls).unwrap());
+    }
+
+    #[test]
+    fn encode_into_appends()
+    {
+        let make = || {
+            let class = common::Class::Application(2);
+            let pl = common::Payload::Primitive(Vec::new());
+
+            common::construct(class, pl)
+        };
+
+        let mut buf = vec![0xDE, 0xAD];
+        super::encode_into(&make(), 1, &mut buf).unwrap();
+        super::encode_into(&make(), 2, &mut buf).unwrap();
+
+        let mut expected = vec![0xDE, 0xAD];
+        expected.extend(super::encode(make(), 1).unwrap());
+        expected.extend(super::encode(make(), 2).unwrap());
+        assert_eq!(buf, expected);
+        assert_eq!(&buf[2..9], &[0x30, 0x05, 0x02, 0x01, 0x01, 0x42, 0x00]);
+
+        // A cleared buffer is reused without growing
+        let capacity = buf.capacity();
+        buf.clear();
+        super::encode_into(&make(), 3, &mut buf).unwrap();
+        assert_eq!(buf, super::encode(make(), 3).unwrap());
+        assert_eq!(buf.capacity(), capacity);
+    }
+
+    #[test]
+    fn encode_allocates_exact_size()
+    {
+        // An AddRequest with a 1 MB value
+        let value = {
+            let class = common::Class::Universal(common::UniversalTypes::OctetString);
+            let pl = common::Payload::Primitive(vec![0x2A; 1 << 20]);
+
+            common::construct(class, pl)
+        };
+        let tag = common::construct(common::Class::Application(8), common::Payload::Constructed(vec![value]));
+
+        // Growing the buffer would have left spare capacity behind
+        let buf = super::encode_tag(&tag).unwrap();
+        assert_eq!(buf.capacity(), buf.len());
+
+        let buf = super::encode(tag, 3).unwrap();
+        assert_eq!(buf.capacity(), buf.len());
+        assert_eq!(buf.len(), (1 << 20) + 5 + 5 + 3 + 5);
+    }
+
     #[test]
     fn encode_simple_tag()
     {
@@ -237,6 +424,82 @@ mod test
         assert!(buf == vec![0x9F,0x87,0x68,0x06,0x73,0x65,0x63,0x6F,0x6E,0x64]);
     }
 
+    #[test]
+    fn encode_tag_numbers()
+    {
+        let mut buf = Vec::<u8>::new();
+        super::encode_tag_number(31, &mut buf);
+        assert_eq!(buf, vec![0x1F]);
+
+        let mut buf = Vec::<u8>::new();
+        super::encode_tag_number(16384, &mut buf);
+        assert_eq!(buf, vec![0x81, 0x80, 0x00]);
+    }
+
+    #[test]
+    fn roundtrip_extended_type_tags()
+    {
+        for &number in &[31, 500, 16384]
+        {
+            let tag = {
+                let class = common::Class::ContextSpecific(number);
+                let pl = common::Payload::Primitive(vec![0x42]);
+
+                common::construct(class, pl)
+            };
+
+            let mut buf = Vec::<u8>::new();
+            super::write(&tag, &mut buf).unwrap();
+
+            assert_eq!(buf.len() as u64, tag.size);
+            assert_eq!(buf[0], 0x9F);
+
+            let decoded = ber::decode(&buf).unwrap();
+            assert_eq!(decoded, tag);
+        }
+    }
+
+    #[test]
+    fn encode_length_form_boundary()
+    {
+        let octets = |len: usize| {
+            let class = common::Class::Universal(common::UniversalTypes::OctetString);
+            common::construct(class, common::Payload::Primitive(vec![0x61; len]))
+        };
+
+        // 127 bytes still fit the short form: 0x04 0x7F followed by the payload
+        let tag = octets(127);
+        let buf = ber::encode_tag(&tag).unwrap();
+        assert_eq!(&buf[..2], &[0x04, 0x7F]);
+        assert_eq!(buf.len(), 2 + 127);
+        assert_eq!(tag.size, buf.len() as u64);
+
+        // 128 bytes need the long form with one length byte: 0x04 0x81 0x80
+        let tag = octets(128);
+        let buf = ber::encode_tag(&tag).unwrap();
+        assert_eq!(&buf[..3], &[0x04, 0x81, 0x80]);
+        assert_eq!(buf.len(), 3 + 128);
+        assert_eq!(tag.size, buf.len() as u64);
+
+        // 255 and 256 bytes are the same boundary for the long form: 0x81 0xFF and 0x82 0x01 0x00
+        let tag = octets(255);
+        let buf = ber::encode_tag(&tag).unwrap();
+        assert_eq!(&buf[..3], &[0x04, 0x81, 0xFF]);
+        assert_eq!(tag.size, buf.len() as u64);
+
+        let tag = octets(256);
+        let buf = ber::encode_tag(&tag).unwrap();
+        assert_eq!(&buf[..4], &[0x04, 0x82, 0x01, 0x00]);
+        assert_eq!(tag.size, buf.len() as u64);
+
+        // And the decoder agrees
+        for &len in &[127, 128, 255, 256]
+        {
+            let decoded = ber::decode(&ber::encode_tag(&octets(len)).unwrap()).unwrap();
+            assert_eq!(decoded, octets(len));
+        }
+    }
+
     #[test]
     fn encode_long_length_tags()
     {
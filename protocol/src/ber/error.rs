@@ -1,6 +1,9 @@
-use std::convert::From;
-use std::{error, fmt, io, string};
-use byteorder;
+use std::{error, fmt};
+#[cfg(feature = "std")]
+use std::io;
+
+use ber::common::Class;
+use prelude::*;
 
 pub enum ASN1Error
 {
@@ -8,6 +11,29 @@ pub enum ASN1Error
     InvalidLenght,
     InvalidASN1,
     ExtendedTagTooLong,
+    IndefiniteLengthInDer,
+    NonMinimalLength,
+    NonMinimalInteger,
+    UnsortedSet,
+    UnusedBitsSet,
+    NonCanonicalBoolean,
+    UnexpectedEof,
+    MaxDepthExceeded,
+    SizeLimitExceeded,
+    InvalidTime(&'static str),
+    /// Decoding failed at byte `offset` of the input, for the reason and around the bytes in `context`
+    ParseError
+    {
+        offset: usize,
+        context: String,
+    },
+    /// The tag is of a different type than the value is decoded as
+    UnexpectedType
+    {
+        expected: Class,
+        found: Class,
+    },
+    #[cfg(feature = "std")]
     Io(io::Error),
 }
 
@@ -23,30 +49,70 @@ impl fmt::Debug for ASN1Error
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
     {
-        write!(f, "Error: {}", error::Error::description(self))
+        match *self
+        {
+            ASN1Error::UnexpectedType { expected, found } => write!(f, "Error: Expected a {:?} tag but got {:?}", expected, found),
+            ASN1Error::ParseError { offset, ref context } => write!(f, "Error: Malformed BER at byte {}: {}", offset, context),
+            _ => write!(f, "Error: {}", error::Error::description(self)),
+        }
     }
 }
 
-impl error::Error for ASN1Error
+impl ASN1Error
 {
-    fn description(&self) -> &str
+    // What `description` returns, without going through the deprecated trait method
+    pub(crate) fn reason(&self) -> &str
     {
         match *self
         {
             ASN1Error::InvalidASN1 =>
                 "Invalid BER Structures detected.",
             ASN1Error::IndefiniteLength =>
-                "Indefinite Length is not valid for LDAP. (RFC 2551 Section 5.1)",
+                "Indefinite Length is only valid for constructed tags.",
             ASN1Error::InvalidLenght =>
                 "The long encoding form for length bytes is not valid for Universal tags.",
             ASN1Error::ExtendedTagTooLong =>
-                "Rust-LDAP currently only handles extended tags up to 2^64. If you hit this case *please* open an issue.",
+                "Rust-LDAP currently only handles extended tags up to 2^63. If you hit this case *please* open an issue.",
+            ASN1Error::IndefiniteLengthInDer =>
+                "Indefinite Length is not valid in DER.",
+            ASN1Error::NonMinimalLength =>
+                "DER requires lengths to be encoded in as few bytes as possible.",
+            ASN1Error::NonMinimalInteger =>
+                "DER requires integers to be encoded in as few bytes as possible.",
+            ASN1Error::UnsortedSet =>
+                "DER requires the elements of a SET to be sorted by their encoding.",
+            ASN1Error::UnusedBitsSet =>
+                "DER requires the unused bits of a BIT STRING to be zero.",
+            ASN1Error::NonCanonicalBoolean =>
+                "DER requires TRUE to be encoded as 0xFF.",
+            ASN1Error::UnexpectedEof =>
+                "The input ended in the middle of a tag.",
+            ASN1Error::MaxDepthExceeded =>
+                "Constructed tags are nested deeper than allowed.",
+            ASN1Error::SizeLimitExceeded =>
+                "The tag is larger than allowed.",
+            ASN1Error::InvalidTime(reason) =>
+                reason,
+            ASN1Error::UnexpectedType { .. } =>
+                "The tag is not of the expected type.",
+            ASN1Error::ParseError { .. } =>
+                "Decoding failed at the given offset.",
+            #[cfg(feature = "std")]
             ASN1Error::Io(ref x) =>
                 error::Error::description(x),
         }
     }
 }
 
+impl error::Error for ASN1Error
+{
+    fn description(&self) -> &str
+    {
+        self.reason()
+    }
+}
+
+#[cfg(feature = "std")]
 impl From<io::Error> for ASN1Error
 {
     fn from(err: io::Error) -> ASN1Error
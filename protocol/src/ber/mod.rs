@@ -6,8 +6,14 @@ pub mod common;
 mod encoder;
 mod decoder;
 pub mod types;
+#[cfg(feature = "chrono")]
+pub mod time;
 
-pub use self::encoder::encode;
-pub use self::decoder::decode;
+pub use self::encoder::{encode, encode_with_controls, encode_into, encode_into_with_controls, encode_tag, encode_tag_number};
+#[cfg(feature = "std")]
+pub use self::encoder::encode_to_writer;
+pub use self::decoder::{decode, decode_all, decode_bounded, decode_der, decode_header, decode_ref, decode_with_context, decode_with_depth, decode_with_len, decode_tag_number, DEFAULT_MAX_DEPTH};
+#[cfg(feature = "std")]
+pub use self::decoder::{decode_from_reader, decode_from_reader_bounded};
 
 pub type Result<T> = std::result::Result<T, error::ASN1Error>;
@@ -0,0 +1,317 @@
+//! Parsing of the ASN.1 time types into chrono's `DateTime`
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+
+use ber;
+use ber::common::{self, Tag};
+use ber::error::ASN1Error;
+
+/// Decode a UTCTime or GeneralizedTime tag, depending on its universal type
+pub fn decode_time(tag: &Tag) -> ber::Result<DateTime<Utc>>
+{
+    let bytes = match tag._value
+    {
+        common::Payload::Primitive(ref bytes) => bytes,
+        common::Payload::Constructed(_) => return Err(ASN1Error::InvalidASN1),
+    };
+
+    match tag._type.class
+    {
+        common::Class::Universal(common::UniversalTypes::UtcTime) => parse_utc_time(bytes),
+        common::Class::Universal(common::UniversalTypes::GeneralizedTime) => parse_generalized_time(bytes),
+        _ => Err(ASN1Error::InvalidASN1),
+    }
+}
+
+/// Parse a GeneralizedTime value like `20240115093000Z`
+///
+/// Minutes and seconds may be left out, and the last given component may carry a fraction
+/// separated by `.` or `,`. The value has to end in `Z` or a `+hh[mm]` / `-hh[mm]` offset from
+/// UTC as LDAP requires (RFC4517 3.3.13), local times are rejected.
+pub fn parse_generalized_time(bytes: &[u8]) -> ber::Result<DateTime<Utc>>
+{
+    let mut parser = Parser { input: bytes, pos: 0 };
+
+    let year = try!(parser.digits(4, "Expected a four digit year"));
+    let month = try!(parser.digits(2, "Expected a two digit month"));
+    let day = try!(parser.digits(2, "Expected a two digit day"));
+    let hour = try!(parser.digits(2, "Expected a two digit hour"));
+
+    // A fraction applies to the last component given
+    let mut unit = 3_600_000_000_000u64;
+    let mut minute = 0;
+    let mut second = 0;
+    if parser.peek_digit()
+    {
+        minute = try!(parser.digits(2, "Expected a two digit minute"));
+        unit /= 60;
+
+        if parser.peek_digit()
+        {
+            second = try!(parser.digits(2, "Expected a two digit second"));
+            unit /= 60;
+        }
+    }
+
+    let fraction = match parser.peek()
+    {
+        Some(b'.') | Some(b',') =>
+        {
+            parser.pos += 1;
+            try!(parser.fraction(unit))
+        },
+        _ => 0,
+    };
+
+    let offset = try!(parser.offset(true));
+
+    let datetime = try!(combine(year, month, day, hour, minute, second));
+    Ok(datetime + Duration::nanoseconds(fraction as i64) - Duration::minutes(offset))
+}
+
+/// Parse a UTCTime value like `240115093000Z`
+///
+/// Two digit years below 50 are taken as 20xx, the others as 19xx (RFC5280 4.1.2.5.1). Seconds
+/// are optional, and the value has to end in `Z` or a `+hhmm` / `-hhmm` offset from UTC.
+pub fn parse_utc_time(bytes: &[u8]) -> ber::Result<DateTime<Utc>>
+{
+    let mut parser = Parser { input: bytes, pos: 0 };
+
+    let year = try!(parser.digits(2, "Expected a two digit year"));
+    let month = try!(parser.digits(2, "Expected a two digit month"));
+    let day = try!(parser.digits(2, "Expected a two digit day"));
+    let hour = try!(parser.digits(2, "Expected a two digit hour"));
+    let minute = try!(parser.digits(2, "Expected a two digit minute"));
+    let second = if parser.peek_digit()
+    {
+        try!(parser.digits(2, "Expected a two digit second"))
+    }
+    else
+    {
+        0
+    };
+
+    let offset = try!(parser.offset(false));
+
+    let year = if year < 50 { 2000 + year } else { 1900 + year };
+    let datetime = try!(combine(year, month, day, hour, minute, second));
+    Ok(datetime - Duration::minutes(offset))
+}
+
+// Build the DateTime, treating a 60th second as the leap second it is
+fn combine(year: u32, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> ber::Result<DateTime<Utc>>
+{
+    let date = match NaiveDate::from_ymd_opt(year as i32, month, day)
+    {
+        Some(date) => date,
+        None => return Err(ASN1Error::InvalidTime("Date does not exist")),
+    };
+
+    let time = if second == 60
+    {
+        NaiveTime::from_hms_nano_opt(hour, minute, 59, 1_000_000_000)
+    }
+    else
+    {
+        NaiveTime::from_hms_opt(hour, minute, second)
+    };
+
+    match time
+    {
+        Some(time) => Ok(DateTime::from_naive_utc_and_offset(date.and_time(time), Utc)),
+        None => Err(ASN1Error::InvalidTime("Time of day out of range")),
+    }
+}
+
+struct Parser<'a>
+{
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a>
+{
+    fn peek(&self) -> Option<u8>
+    {
+        self.input.get(self.pos).cloned()
+    }
+
+    fn peek_digit(&self) -> bool
+    {
+        match self.peek()
+        {
+            Some(b'0'..=b'9') => true,
+            _ => false,
+        }
+    }
+
+    fn digits(&mut self, count: usize, reason: &'static str) -> ber::Result<u32>
+    {
+        let mut value = 0;
+
+        for _ in 0..count
+        {
+            match self.peek()
+            {
+                Some(digit @ b'0'..=b'9') => value = value * 10 + (digit - b'0') as u32,
+                _ => return Err(ASN1Error::InvalidTime(reason)),
+            }
+            self.pos += 1;
+        }
+
+        Ok(value)
+    }
+
+    // The fraction of `unit` nanoseconds given by the digits following the separator
+    fn fraction(&mut self, unit: u64) -> ber::Result<u64>
+    {
+        let mut numerator = 0u128;
+        let mut denominator = 1u128;
+
+        if !self.peek_digit()
+        {
+            return Err(ASN1Error::InvalidTime("Expected digits after the decimal separator"));
+        }
+
+        while self.peek_digit()
+        {
+            // Digits beyond nanosecond precision of an hour don't change the result
+            if denominator < 1_000_000_000_000_000
+            {
+                numerator = numerator * 10 + (self.input[self.pos] - b'0') as u128;
+                denominator *= 10;
+            }
+            self.pos += 1;
+        }
+
+        Ok((numerator * unit as u128 / denominator) as u64)
+    }
+
+    // Offset from UTC in minutes, `Z` being 0. Minutes of the offset are optional if
+    // `short_offset` is set.
+    fn offset(&mut self, short_offset: bool) -> ber::Result<i64>
+    {
+        let sign = match self.peek()
+        {
+            Some(b'Z') => { self.pos += 1; 0 },
+            Some(b'+') => { self.pos += 1; 1 },
+            Some(b'-') => { self.pos += 1; -1 },
+            _ => return Err(ASN1Error::InvalidTime("Expected 'Z' or an offset from UTC")),
+        };
+
+        let mut minutes = 0;
+        if sign != 0
+        {
+            let hours = try!(self.digits(2, "Expected a two digit offset hour"));
+            let mins = if short_offset && self.peek().is_none()
+            {
+                0
+            }
+            else
+            {
+                try!(self.digits(2, "Expected two digit offset minutes"))
+            };
+
+            if hours > 23 || mins > 59
+            {
+                return Err(ASN1Error::InvalidTime("Offset from UTC out of range"));
+            }
+
+            minutes = sign * (hours * 60 + mins) as i64;
+        }
+
+        if self.pos != self.input.len()
+        {
+            return Err(ASN1Error::InvalidTime("Trailing characters after the time zone"));
+        }
+
+        Ok(minutes)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use chrono::{TimeZone, Timelike, Utc};
+
+    use ber::common;
+    use ber::error::ASN1Error;
+    use super::{decode_time, parse_generalized_time, parse_utc_time};
+
+    #[test]
+    fn generalized_time_utc()
+    {
+        assert_eq!(parse_generalized_time(b"20240115093000Z").unwrap(), Utc.with_ymd_and_hms(2024, 1, 15, 9, 30, 0).unwrap());
+        // Minutes and seconds are optional
+        assert_eq!(parse_generalized_time(b"2024011509Z").unwrap(), Utc.with_ymd_and_hms(2024, 1, 15, 9, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn generalized_time_offset()
+    {
+        assert_eq!(parse_generalized_time(b"20240115093000+0130").unwrap(), Utc.with_ymd_and_hms(2024, 1, 15, 8, 0, 0).unwrap());
+        assert_eq!(parse_generalized_time(b"20231231233000-05").unwrap(), Utc.with_ymd_and_hms(2024, 1, 1, 4, 30, 0).unwrap());
+    }
+
+    #[test]
+    fn generalized_time_fractions()
+    {
+        let time = parse_generalized_time(b"20240115093000.123Z").unwrap();
+        assert_eq!(time.nanosecond(), 123_000_000);
+
+        // A fraction of the minute or the hour
+        assert_eq!(parse_generalized_time(b"202401150930,5Z").unwrap(), Utc.with_ymd_and_hms(2024, 1, 15, 9, 30, 30).unwrap());
+        assert_eq!(parse_generalized_time(b"2024011509.25Z").unwrap(), Utc.with_ymd_and_hms(2024, 1, 15, 9, 15, 0).unwrap());
+    }
+
+    #[test]
+    fn generalized_time_rejects_malformed()
+    {
+        for input in &[&b""[..], b"2024", b"20240115093000", b"20240115093000.Z", b"20240230093000Z",
+                       b"20240115253000Z", b"20240115093000+2400", b"20240115093000Zjunk", b"2024-01-15T09:30:00Z"]
+        {
+            match parse_generalized_time(input)
+            {
+                Err(ASN1Error::InvalidTime(_)) => {},
+                x => panic!("Expected InvalidTime for {:?}, got {:?}", input, x),
+            }
+        }
+    }
+
+    #[test]
+    fn utc_time_pivot()
+    {
+        assert_eq!(parse_utc_time(b"491231235959Z").unwrap(), Utc.with_ymd_and_hms(2049, 12, 31, 23, 59, 59).unwrap());
+        assert_eq!(parse_utc_time(b"500101000000Z").unwrap(), Utc.with_ymd_and_hms(1950, 1, 1, 0, 0, 0).unwrap());
+        assert_eq!(parse_utc_time(b"000101000000Z").unwrap(), Utc.with_ymd_and_hms(2000, 1, 1, 0, 0, 0).unwrap());
+        assert_eq!(parse_utc_time(b"991231235959Z").unwrap(), Utc.with_ymd_and_hms(1999, 12, 31, 23, 59, 59).unwrap());
+    }
+
+    #[test]
+    fn utc_time_variants()
+    {
+        // Without seconds and with an offset
+        assert_eq!(parse_utc_time(b"2401150930Z").unwrap(), Utc.with_ymd_and_hms(2024, 1, 15, 9, 30, 0).unwrap());
+        assert_eq!(parse_utc_time(b"240115093000-0200").unwrap(), Utc.with_ymd_and_hms(2024, 1, 15, 11, 30, 0).unwrap());
+
+        // UTCTime has neither fractions nor short offsets
+        assert!(parse_utc_time(b"240115093000.5Z").is_err());
+        assert!(parse_utc_time(b"240115093000+02").is_err());
+    }
+
+    #[test]
+    fn decode_time_dispatches_on_type()
+    {
+        let utc = common::construct(common::Class::Universal(common::UniversalTypes::UtcTime),
+                                    common::Payload::Primitive(b"240115093000Z".to_vec()));
+        let generalized = common::construct(common::Class::Universal(common::UniversalTypes::GeneralizedTime),
+                                            common::Payload::Primitive(b"20240115093000Z".to_vec()));
+        let octets = common::construct(common::Class::Universal(common::UniversalTypes::OctetString),
+                                       common::Payload::Primitive(b"20240115093000Z".to_vec()));
+
+        let expected = Utc.with_ymd_and_hms(2024, 1, 15, 9, 30, 0).unwrap();
+        assert_eq!(decode_time(&utc).unwrap(), expected);
+        assert_eq!(decode_time(&generalized).unwrap(), expected);
+        assert!(decode_time(&octets).is_err());
+    }
+}
@@ -1,7 +1,7 @@
-use std::io::Cursor;
-
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use ber::{common};
+use byteorder::{BigEndian, ByteOrder};
+use ber::{self, common};
+use ber::error::ASN1Error;
+use prelude::*;
 
 pub trait ASNType
 {
@@ -15,6 +15,395 @@ pub trait ASNType
     fn from_tag(tag: &mut common::Tag) -> Option<Self> where Self: Sized;
 }
 
+/// Types that can be encoded as a tag, the way `serde::Serialize` works for serde
+///
+/// Implement it for your own types by building on the implementations for the scalar types,
+/// e.g. with `sequence` for structs.
+pub trait ToBer
+{
+    fn to_ber(&self) -> common::Tag;
+}
+
+/// Types that can be decoded from a tag, the counterpart of `ToBer`
+///
+/// Tags of another type than the one the value is encoded as are rejected with
+/// `UnexpectedType`.
+pub trait FromBer: Sized
+{
+    fn from_ber(tag: &common::Tag) -> ber::Result<Self>;
+}
+
+/// Decode the big-endian two's-complement payload of an INTEGER.
+///
+/// The payload must be minimal, i.e. the first nine bits may not all be the same.
+pub fn decode_integer(bytes: &[u8]) -> ber::Result<i64>
+{
+    if bytes.is_empty() || bytes.len() > 8
+    {
+        return Err(ASN1Error::InvalidASN1);
+    }
+
+    if bytes.len() > 1
+    {
+        // A leading 0x00 is only needed to clear the sign bit, a leading 0xFF to set it
+        if (bytes[0] == 0x00 && bytes[1] & 0x80 == 0) || (bytes[0] == 0xFF && bytes[1] & 0x80 != 0)
+        {
+            return Err(ASN1Error::InvalidASN1);
+        }
+    }
+
+    // Start with all ones for negative numbers so the sign is extended
+    let init = if bytes[0] & 0x80 != 0 { -1i64 } else { 0i64 };
+
+    Ok(bytes.iter().fold(init, |acc, &b| (acc << 8) | b as i64))
+}
+
+/// Encode `value` as the minimal big-endian two's-complement payload of an INTEGER.
+pub fn encode_integer(value: i64) -> Vec<u8>
+{
+    let mut bytes: Vec<u8> = (0..8).rev().map(|i| (value >> (i * 8)) as u8).collect();
+
+    // Drop leading bytes that only repeat the sign of the following one
+    let mut start = 0;
+    while start < 7 && ((bytes[start] == 0x00 && bytes[start + 1] & 0x80 == 0) ||
+                        (bytes[start] == 0xFF && bytes[start + 1] & 0x80 != 0))
+    {
+        start += 1;
+    }
+    bytes.drain(..start);
+
+    bytes
+}
+
+/// Encode the arcs of an OBJECT IDENTIFIER into its content octets.
+///
+/// The first two arcs share one subidentifier (`40 * arc1 + arc2`), every subidentifier is
+/// written base-128 with the continuation bit set on all but its last byte.
+pub fn encode_oid(arcs: &[u64]) -> ber::Result<Vec<u8>>
+{
+    if arcs.len() < 2 || arcs[0] > 2 || (arcs[0] < 2 && arcs[1] >= 40)
+    {
+        return Err(ASN1Error::InvalidASN1);
+    }
+
+    let first = match (arcs[0] * 40).checked_add(arcs[1])
+    {
+        Some(first) => first,
+        None => return Err(ASN1Error::InvalidASN1),
+    };
+
+    let mut bytes = Vec::new();
+    encode_subidentifier(first, &mut bytes);
+    for &arc in &arcs[2..]
+    {
+        encode_subidentifier(arc, &mut bytes);
+    }
+
+    Ok(bytes)
+}
+
+fn encode_subidentifier(value: u64, buf: &mut Vec<u8>)
+{
+    let start = buf.len();
+
+    let mut value = value;
+    while
+    {
+        buf.push((value & 0x7F) as u8);
+        value >>= 7;
+        value > 0
+    } {}
+
+    // We pushed the least significant group first
+    buf[start..].reverse();
+
+    let last = buf.len() - 1;
+    for byte in &mut buf[start..last]
+    {
+        *byte |= 0x80;
+    }
+}
+
+/// Decode the content octets of an OBJECT IDENTIFIER into its arcs.
+///
+/// Subidentifiers have to be minimal, i.e. not start with 0x80, and the last one may not have
+/// its continuation bit set.
+pub fn decode_oid(bytes: &[u8]) -> ber::Result<Vec<u64>>
+{
+    let mut arcs = Vec::new();
+    let mut pos = 0;
+
+    while pos < bytes.len()
+    {
+        let (value, consumed) = try!(decode_subidentifier(&bytes[pos..]));
+        pos += consumed;
+
+        if arcs.is_empty()
+        {
+            // Undo the combination of the first two arcs
+            match value
+            {
+                0..=39 => arcs.extend_from_slice(&[0, value]),
+                40..=79 => arcs.extend_from_slice(&[1, value - 40]),
+                _ => arcs.extend_from_slice(&[2, value - 80]),
+            }
+        }
+        else
+        {
+            arcs.push(value);
+        }
+    }
+
+    if arcs.is_empty()
+    {
+        return Err(ASN1Error::InvalidASN1);
+    }
+
+    Ok(arcs)
+}
+
+fn decode_subidentifier(bytes: &[u8]) -> ber::Result<(u64, usize)>
+{
+    if bytes.first() == Some(&0x80)
+    {
+        return Err(ASN1Error::InvalidASN1);
+    }
+
+    let mut value = 0u64;
+
+    for (count, &byte) in bytes.iter().enumerate()
+    {
+        // Another 7 bits would overflow
+        if value >> 57 != 0
+        {
+            return Err(ASN1Error::InvalidASN1);
+        }
+
+        value = (value << 7) | (byte & 0x7F) as u64;
+
+        if byte & 0x80 == 0
+        {
+            return Ok((value, count + 1));
+        }
+    }
+
+    // Ran out of bytes before the last one of the subidentifier
+    Err(ASN1Error::InvalidASN1)
+}
+
+/// Render OID arcs in dotted notation, e.g. `2.5.4.3`
+pub fn oid_to_string(arcs: &[u64]) -> String
+{
+    arcs.iter().map(|arc| arc.to_string()).collect::<Vec<_>>().join(".")
+}
+
+/// Encode `value` as the content octet of a BOOLEAN, 0xFF for true as DER requires.
+pub fn encode_boolean(value: bool) -> Vec<u8>
+{
+    vec![if value { 0xFF } else { 0x00 }]
+}
+
+/// Decode the content octet of a BOOLEAN, any nonzero value being true.
+pub fn decode_boolean(bytes: &[u8]) -> ber::Result<bool>
+{
+    match bytes
+    {
+        [byte] => Ok(*byte != 0x00),
+        _ => Err(ASN1Error::InvalidASN1),
+    }
+}
+
+/// Like `decode_boolean`, but only accepts 0xFF for true as DER does.
+pub fn decode_boolean_der(bytes: &[u8]) -> ber::Result<bool>
+{
+    match try!(decode_boolean(bytes))
+    {
+        true if bytes[0] != 0xFF => Err(ASN1Error::NonCanonicalBoolean),
+        value => Ok(value),
+    }
+}
+
+/// Encode `bits` as the content octets of a BIT STRING.
+///
+/// The first octet counts the unused bits in the last one, which are left zero.
+pub fn encode_bit_string(bits: &[bool]) -> Vec<u8>
+{
+    let mut bytes = vec![((8 - bits.len() % 8) % 8) as u8];
+
+    for chunk in bits.chunks(8)
+    {
+        bytes.push(chunk.iter().enumerate().fold(0, |acc, (i, &bit)| if bit { acc | 0x80 >> i } else { acc }));
+    }
+
+    bytes
+}
+
+/// Decode the content octets of a BIT STRING.
+///
+/// The leading unused bits count has to be at most 7, and 0 for the empty bit string.
+pub fn decode_bit_string(bytes: &[u8]) -> ber::Result<Vec<bool>>
+{
+    let unused = match bytes.first()
+    {
+        Some(&unused) if unused <= 7 && (unused == 0 || bytes.len() > 1) => unused as usize,
+        _ => return Err(ASN1Error::InvalidASN1),
+    };
+
+    let mut bits = Vec::with_capacity((bytes.len() - 1) * 8);
+    for byte in &bytes[1..]
+    {
+        for i in 0..8
+        {
+            bits.push(byte & 0x80 >> i != 0);
+        }
+    }
+
+    let len = bits.len() - unused;
+    bits.truncate(len);
+
+    Ok(bits)
+}
+
+/// Like `decode_bit_string`, but also requires the unused bits to be zero as DER does.
+pub fn decode_bit_string_der(bytes: &[u8]) -> ber::Result<Vec<bool>>
+{
+    let bits = try!(decode_bit_string(bytes));
+
+    let unused = bytes[0];
+    if unused > 0 && bytes[bytes.len() - 1] & ((1 << unused) - 1) != 0
+    {
+        return Err(ASN1Error::UnusedBitsSet);
+    }
+
+    Ok(bits)
+}
+
+/// Wrap `value` in a primitive universal OCTET STRING tag.
+pub fn octet_string(value: &[u8]) -> common::Tag
+{
+    let class = common::Class::Universal(common::UniversalTypes::OctetString);
+    let pl = common::Payload::Primitive(value.to_vec());
+
+    common::construct(class, pl)
+}
+
+/// The contents of a primitive universal OCTET STRING tag.
+pub fn decode_octet_string(tag: &common::Tag) -> ber::Result<&[u8]>
+{
+    decode_octet_string_as(tag, common::Class::Universal(common::UniversalTypes::OctetString))
+}
+
+/// Like `decode_octet_string`, for OCTET STRINGs implicitly tagged as `class`.
+///
+/// LDAP does this a lot, e.g. with the `[0]` password of a simple bind. A tag of another class
+/// is rejected with `UnexpectedType`, a constructed one with `InvalidASN1`.
+pub fn decode_octet_string_as(tag: &common::Tag, class: common::Class) -> ber::Result<&[u8]>
+{
+    if tag._type.class != class
+    {
+        return Err(ASN1Error::UnexpectedType { expected: class, found: tag._type.class });
+    }
+
+    match tag._value
+    {
+        common::Payload::Primitive(ref bytes) => Ok(bytes),
+        common::Payload::Constructed(_) => Err(ASN1Error::InvalidASN1),
+    }
+}
+
+/// Wrap `children` in a constructed universal SEQUENCE tag.
+pub fn sequence(children: Vec<common::Tag>) -> common::Tag
+{
+    let class = common::Class::Universal(common::UniversalTypes::Sequence);
+    let pl = common::Payload::Constructed(children);
+
+    common::construct(class, pl)
+}
+
+/// Wrap `children` in a constructed universal SET tag.
+///
+/// The children are kept in the given order, which DER requires to be sorted.
+pub fn set(children: Vec<common::Tag>) -> common::Tag
+{
+    let class = common::Class::Universal(common::UniversalTypes::Set);
+    let pl = common::Payload::Constructed(children);
+
+    common::construct(class, pl)
+}
+
+/// A universal NULL tag, encoded as `05 00`.
+pub fn null() -> common::Tag
+{
+    let class = common::Class::Universal(common::UniversalTypes::Null);
+    let pl = common::Payload::Primitive(Vec::new());
+
+    common::construct(class, pl)
+}
+
+/// Check that `tag` is a universal NULL.
+///
+/// NULL has no contents, so any are rejected with `InvalidASN1`.
+pub fn decode_null(tag: &common::Tag) -> ber::Result<()>
+{
+    if !try!(universal_primitive(tag, common::UniversalTypes::Null)).is_empty()
+    {
+        return Err(ASN1Error::InvalidASN1);
+    }
+
+    Ok(())
+}
+
+/// Wrap `value` in a primitive universal ENUMERATED tag.
+///
+/// The encoding is that of an INTEGER, only the tag differs.
+pub fn encode_enumerated(value: i64) -> common::Tag
+{
+    let class = common::Class::Universal(common::UniversalTypes::Enumerated);
+    let pl = common::Payload::Primitive(encode_integer(value));
+
+    common::construct(class, pl)
+}
+
+/// The value of a primitive universal ENUMERATED tag.
+///
+/// Other tags, INTEGERs included, are rejected with `UnexpectedType`.
+pub fn decode_enumerated(tag: &common::Tag) -> ber::Result<i64>
+{
+    decode_integer(try!(universal_primitive(tag, common::UniversalTypes::Enumerated)))
+}
+
+/// Wrap each of `items` into a SEQUENCE OF them.
+pub fn sequence_of<T: ToBer>(items: &[T]) -> common::Tag
+{
+    sequence(items.iter().map(ToBer::to_ber).collect())
+}
+
+/// Decode each element of a SEQUENCE OF tag.
+pub fn decode_sequence_of<T: FromBer>(tag: &common::Tag) -> ber::Result<Vec<T>>
+{
+    match tag._value
+    {
+        common::Payload::Constructed(ref children) if tag._type.class == common::Class::Universal(common::UniversalTypes::Sequence) =>
+            children.iter().map(T::from_ber).collect(),
+        _ => Err(unexpected(tag, common::UniversalTypes::Sequence)),
+    }
+}
+
+// The contents of `tag` if it is a primitive of the universal type `expected`
+fn universal_primitive(tag: &common::Tag, expected: common::UniversalTypes) -> ber::Result<&[u8]>
+{
+    match tag._value
+    {
+        common::Payload::Primitive(ref bytes) if tag._type.class == common::Class::Universal(expected) => Ok(bytes),
+        _ => Err(unexpected(tag, expected)),
+    }
+}
+
+fn unexpected(tag: &common::Tag, expected: common::UniversalTypes) -> ASN1Error
+{
+    ASN1Error::UnexpectedType { expected: common::Class::Universal(expected), found: tag._type.class }
+}
+
 impl ASNType for i32
 {
     fn into_ber_universal(self) -> common::Tag
@@ -24,11 +413,7 @@ impl ASNType for i32
 
     fn into_ber_typed(self, class: common::Class) -> common::Tag
     {
-        // It's a u32, we know it's always 4 bytes
-        let mut payload: Vec<u8> = Vec::with_capacity(4);
-        payload.write_i32::<BigEndian>(self);
-
-        let pl = common::Payload::Primitive(payload);
+        let pl = common::Payload::Primitive(encode_integer(self as i64));
 
         common::construct(class, pl)
     }
@@ -39,14 +424,9 @@ impl ASNType for i32
         {
             common::Payload::Primitive(ref vec) =>
             {
-                if vec.len() <= 4
+                if !vec.is_empty() && vec.len() <= 4
                 {
-                    let mut rdr = Cursor::new(vec);
-                    match rdr.read_int::<BigEndian>(vec.len()).ok()
-                    {
-                        None => None,
-                        Some(i) => Some(i as i32),
-                    }
+                    Some(BigEndian::read_int(vec, vec.len()) as i32)
                 }
                 else
                 {
@@ -67,11 +447,7 @@ impl ASNType for i64
 
     fn into_ber_typed(self, class: common::Class) -> common::Tag
     {
-        // It's a u32, we know it's always 4 bytes
-        let mut payload: Vec<u8> = Vec::with_capacity(8);
-        payload.write_i64::<BigEndian>(self);
-
-        let pl = common::Payload::Primitive(payload);
+        let pl = common::Payload::Primitive(encode_integer(self as i64));
 
         common::construct(class, pl)
     }
@@ -82,10 +458,9 @@ impl ASNType for i64
         {
             common::Payload::Primitive(ref vec) =>
             {
-                if vec.len() <= 8
+                if !vec.is_empty() && vec.len() <= 8
                 {
-                    let mut rdr = Cursor::new(vec);
-                    rdr.read_int::<BigEndian>(vec.len()).ok()
+                    Some(BigEndian::read_int(vec, vec.len()))
                 }
                 else
                 {
@@ -115,3 +490,488 @@ impl ASNType for Vec<common::Tag>
         None
     }
 }
+
+impl ToBer for bool
+{
+    /// BOOLEAN
+    fn to_ber(&self) -> common::Tag
+    {
+        let class = common::Class::Universal(common::UniversalTypes::Boolean);
+        common::construct(class, common::Payload::Primitive(encode_boolean(*self)))
+    }
+}
+
+impl ToBer for i32
+{
+    /// INTEGER
+    fn to_ber(&self) -> common::Tag
+    {
+        (*self as i64).to_ber()
+    }
+}
+
+impl ToBer for i64
+{
+    /// INTEGER
+    fn to_ber(&self) -> common::Tag
+    {
+        let class = common::Class::Universal(common::UniversalTypes::Integer);
+        common::construct(class, common::Payload::Primitive(encode_integer(*self)))
+    }
+}
+
+impl ToBer for str
+{
+    /// OCTET STRING, which LDAPString is (RFC4511 4.1.2)
+    fn to_ber(&self) -> common::Tag
+    {
+        octet_string(self.as_bytes())
+    }
+}
+
+impl ToBer for String
+{
+    /// OCTET STRING
+    fn to_ber(&self) -> common::Tag
+    {
+        octet_string(self.as_bytes())
+    }
+}
+
+impl ToBer for [u8]
+{
+    /// OCTET STRING
+    fn to_ber(&self) -> common::Tag
+    {
+        octet_string(self)
+    }
+}
+
+impl ToBer for Vec<u8>
+{
+    /// OCTET STRING
+    fn to_ber(&self) -> common::Tag
+    {
+        octet_string(self)
+    }
+}
+
+impl<'a, T: ToBer + ?Sized> ToBer for &'a T
+{
+    fn to_ber(&self) -> common::Tag
+    {
+        (**self).to_ber()
+    }
+}
+
+impl FromBer for bool
+{
+    /// BOOLEAN
+    fn from_ber(tag: &common::Tag) -> ber::Result<bool>
+    {
+        decode_boolean(try!(universal_primitive(tag, common::UniversalTypes::Boolean)))
+    }
+}
+
+impl FromBer for i32
+{
+    /// INTEGER, failing with `InvalidASN1` for values out of range
+    fn from_ber(tag: &common::Tag) -> ber::Result<i32>
+    {
+        let value = try!(i64::from_ber(tag));
+        if value < i32::min_value() as i64 || value > i32::max_value() as i64
+        {
+            return Err(ASN1Error::InvalidASN1);
+        }
+
+        Ok(value as i32)
+    }
+}
+
+impl FromBer for i64
+{
+    /// INTEGER
+    fn from_ber(tag: &common::Tag) -> ber::Result<i64>
+    {
+        decode_integer(try!(universal_primitive(tag, common::UniversalTypes::Integer)))
+    }
+}
+
+impl FromBer for Vec<u8>
+{
+    /// OCTET STRING
+    fn from_ber(tag: &common::Tag) -> ber::Result<Vec<u8>>
+    {
+        Ok(try!(decode_octet_string(tag).map_err(|_| unexpected(tag, common::UniversalTypes::OctetString))).to_vec())
+    }
+}
+
+impl FromBer for String
+{
+    /// OCTET STRING holding UTF-8, failing with `InvalidASN1` for other contents
+    fn from_ber(tag: &common::Tag) -> ber::Result<String>
+    {
+        String::from_utf8(try!(Vec::from_ber(tag))).map_err(|_| ASN1Error::InvalidASN1)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{decode_integer, encode_integer, decode_oid, encode_oid, oid_to_string};
+    use super::{decode_bit_string, decode_bit_string_der, encode_bit_string};
+    use super::{decode_boolean, decode_boolean_der, encode_boolean};
+    use super::{decode_octet_string, decode_octet_string_as, octet_string, sequence, sequence_of, set};
+    use super::{decode_enumerated, encode_enumerated, decode_null, null, decode_sequence_of, FromBer, ToBer};
+    use ber::{self, common};
+    use ber::error::ASN1Error;
+
+    #[test]
+    fn octet_strings()
+    {
+        let empty = octet_string(b"");
+        assert_eq!(ber::encode_tag(&empty).unwrap(), vec![0x04, 0x00]);
+        assert_eq!(decode_octet_string(&empty).unwrap(), b"");
+
+        // Any bytes, UTF-8 or not
+        let binary = octet_string(&[0xff, 0x00, 0xc3]);
+        assert_eq!(ber::encode_tag(&binary).unwrap(), vec![0x04, 0x03, 0xff, 0x00, 0xc3]);
+        assert_eq!(decode_octet_string(&binary).unwrap(), &[0xff, 0x00, 0xc3]);
+    }
+
+    #[test]
+    fn octet_string_classes()
+    {
+        let password = common::construct(common::Class::ContextSpecific(0), common::Payload::Primitive(b"secret".to_vec()));
+
+        assert_eq!(decode_octet_string_as(&password, common::Class::ContextSpecific(0)).unwrap(), b"secret");
+        match decode_octet_string(&password)
+        {
+            Err(ASN1Error::UnexpectedType { expected: common::Class::Universal(common::UniversalTypes::OctetString),
+                                            found: common::Class::ContextSpecific(0) }) => {},
+            x => panic!("Expected UnexpectedType, got {:?}", x),
+        }
+        match decode_octet_string_as(&octet_string(b"secret"), common::Class::ContextSpecific(0))
+        {
+            Err(ASN1Error::UnexpectedType { expected: common::Class::ContextSpecific(0), .. }) => {},
+            x => panic!("Expected UnexpectedType, got {:?}", x),
+        }
+
+        let constructed = common::construct(common::Class::Universal(common::UniversalTypes::OctetString),
+                                            common::Payload::Constructed(vec![octet_string(b"a")]));
+        match decode_octet_string(&constructed)
+        {
+            Err(ASN1Error::InvalidASN1) => {},
+            x => panic!("Expected InvalidASN1, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn nulls()
+    {
+        assert_eq!(ber::encode_tag(&null()).unwrap(), vec![0x05, 0x00]);
+        assert!(decode_null(&ber::decode(&[0x05, 0x00]).unwrap()).is_ok());
+
+        match decode_null(&ber::decode(&[0x05, 0x01, 0x00]).unwrap())
+        {
+            Err(ASN1Error::InvalidASN1) => {},
+            x => panic!("Expected InvalidASN1, got {:?}", x),
+        }
+        match decode_null(&octet_string(b""))
+        {
+            Err(ASN1Error::UnexpectedType { .. }) => {},
+            x => panic!("Expected UnexpectedType, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn enumerated()
+    {
+        let tag = encode_enumerated(2);
+        assert_eq!(ber::encode_tag(&tag).unwrap(), vec![0x0a, 0x01, 0x02]);
+        assert_eq!(decode_enumerated(&tag).unwrap(), 2);
+        assert_eq!(decode_enumerated(&encode_enumerated(300)).unwrap(), 300);
+
+        // Same encoding, different tag
+        match decode_enumerated(&2i64.to_ber())
+        {
+            Err(ASN1Error::UnexpectedType { expected: common::Class::Universal(common::UniversalTypes::Enumerated),
+                                            found: common::Class::Universal(common::UniversalTypes::Integer) }) => {},
+            x => panic!("Expected UnexpectedType, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn sequence_and_set()
+    {
+        // SEQUENCE { SET { "a", "bc" }, "" }
+        let tag = sequence(vec![set(vec![octet_string(b"a"), octet_string(b"bc")]), octet_string(b"")]);
+
+        assert_eq!(tag._type.class, common::Class::Universal(common::UniversalTypes::Sequence));
+        assert_eq!(tag._type.structure, common::Structure::Constructed);
+        assert_eq!(tag._length, 11);
+        assert_eq!(tag.size, 13);
+
+        let inner = match tag._value
+        {
+            common::Payload::Constructed(ref children) => &children[0],
+            common::Payload::Primitive(_) => panic!("Expected a constructed sequence"),
+        };
+        assert_eq!(inner._type.class, common::Class::Universal(common::UniversalTypes::Set));
+        assert_eq!(inner._length, 7);
+        assert_eq!(inner.size, 9);
+
+        let bytes = ber::encode_tag(&tag).unwrap();
+        assert_eq!(bytes, vec![0x30, 0x0b, 0x31, 0x07, 0x04, 0x01, 0x61, 0x04, 0x02, 0x62, 0x63, 0x04, 0x00]);
+        assert_eq!(bytes.len() as u64, tag.size);
+    }
+
+    #[test]
+    fn decode_integers()
+    {
+        assert_eq!(decode_integer(&[0x00]).unwrap(), 0);
+        assert_eq!(decode_integer(&[0x7F]).unwrap(), 127);
+        assert_eq!(decode_integer(&[0x00, 0x80]).unwrap(), 128);
+        assert_eq!(decode_integer(&[0xFF]).unwrap(), -1);
+        assert_eq!(decode_integer(&[0x80]).unwrap(), -128);
+        assert_eq!(decode_integer(&[0xFF, 0x7F]).unwrap(), -129);
+        assert_eq!(decode_integer(&[0x01, 0x2C]).unwrap(), 300);
+        assert_eq!(decode_integer(&[0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]).unwrap(), i64::max_value());
+        assert_eq!(decode_integer(&[0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]).unwrap(), i64::min_value());
+    }
+
+    #[test]
+    fn decode_integer_rejects_invalid()
+    {
+        assert!(decode_integer(&[]).is_err());
+        // Non-minimal
+        assert!(decode_integer(&[0x00, 0x7F]).is_err());
+        assert!(decode_integer(&[0xFF, 0x80]).is_err());
+        // Doesn't fit an i64
+        assert!(decode_integer(&[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]).is_err());
+    }
+
+    #[test]
+    fn encode_integers()
+    {
+        assert_eq!(encode_integer(0), vec![0x00]);
+        assert_eq!(encode_integer(127), vec![0x7F]);
+        assert_eq!(encode_integer(128), vec![0x00, 0x80]);
+        assert_eq!(encode_integer(-1), vec![0xFF]);
+        assert_eq!(encode_integer(-128), vec![0x80]);
+        assert_eq!(encode_integer(-129), vec![0xFF, 0x7F]);
+        assert_eq!(encode_integer(300), vec![0x01, 0x2C]);
+    }
+
+    #[test]
+    fn roundtrip_integers()
+    {
+        let mut values = vec![i64::min_value(), i64::min_value() + 1, i64::max_value(), i64::max_value() - 1];
+        for shift in 0..63
+        {
+            let v = 1i64 << shift;
+            values.extend_from_slice(&[v, v - 1, v + 1, -v, -v - 1, -v + 1]);
+        }
+
+        for value in values
+        {
+            let encoded = encode_integer(value);
+            assert_eq!(decode_integer(&encoded).unwrap(), value, "{:?}", encoded);
+        }
+    }
+
+    #[test]
+    fn encode_oids()
+    {
+        // StartTLS
+        assert_eq!(encode_oid(&[1, 3, 6, 1, 4, 1, 1466, 20037]).unwrap(),
+                   vec![0x2B, 0x06, 0x01, 0x04, 0x01, 0x8B, 0x3A, 0x81, 0x9C, 0x45]);
+        // cn
+        assert_eq!(encode_oid(&[2, 5, 4, 3]).unwrap(), vec![0x55, 0x04, 0x03]);
+        // Arcs below joint-iso-itu-t may exceed 39
+        assert_eq!(encode_oid(&[2, 999, 3]).unwrap(), vec![0x88, 0x37, 0x03]);
+    }
+
+    #[test]
+    fn encode_oid_rejects_invalid()
+    {
+        assert!(encode_oid(&[]).is_err());
+        assert!(encode_oid(&[1]).is_err());
+        assert!(encode_oid(&[3, 1]).is_err());
+        assert!(encode_oid(&[1, 40]).is_err());
+        assert!(encode_oid(&[2, u64::max_value()]).is_err());
+    }
+
+    #[test]
+    fn decode_oid_rejects_invalid()
+    {
+        assert!(decode_oid(&[]).is_err());
+        // Non-minimal subidentifier
+        assert!(decode_oid(&[0x2B, 0x80, 0x01]).is_err());
+        // Truncated continuation
+        assert!(decode_oid(&[0x2B, 0x06, 0x81]).is_err());
+        // Doesn't fit an u64
+        assert!(decode_oid(&[0x2B, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F]).is_err());
+    }
+
+    #[test]
+    fn roundtrip_oids()
+    {
+        let oids: [&[u64]; 6] = [
+            // StartTLS
+            &[1, 3, 6, 1, 4, 1, 1466, 20037],
+            // Who am I?
+            &[1, 3, 6, 1, 4, 1, 4203, 1, 11, 3],
+            // Paged results control
+            &[1, 2, 840, 113556, 1, 4, 319],
+            // cn
+            &[2, 5, 4, 3],
+            &[0, 39],
+            &[2, 999, u64::max_value()],
+        ];
+
+        for arcs in oids.iter()
+        {
+            let encoded = encode_oid(arcs).unwrap();
+            assert_eq!(&decode_oid(&encoded).unwrap()[..], *arcs);
+        }
+
+        assert_eq!(oid_to_string(&[1, 3, 6, 1, 4, 1, 1466, 20037]), "1.3.6.1.4.1.1466.20037");
+    }
+
+    #[test]
+    fn roundtrip_bit_strings()
+    {
+        let cases: [(&[bool], &[u8]); 4] = [
+            (&[], &[0x00]),
+            (&[true], &[0x07, 0x80]),
+            (&[true, false, true, true, false, false, false, true], &[0x00, 0xB1]),
+            (&[false, true, true, false, true, true, true, false, true, false], &[0x06, 0x6E, 0x80]),
+        ];
+
+        for &(bits, bytes) in cases.iter()
+        {
+            assert_eq!(&encode_bit_string(bits)[..], bytes);
+            assert_eq!(&decode_bit_string(bytes).unwrap()[..], bits);
+            assert_eq!(&decode_bit_string_der(bytes).unwrap()[..], bits);
+        }
+    }
+
+    #[test]
+    fn decode_bit_string_rejects_invalid()
+    {
+        // Missing prefix, too many unused bits, unused bits without content
+        for bytes in [&[][..], &[0x08, 0x00], &[0x03]].iter()
+        {
+            assert!(decode_bit_string(bytes).is_err());
+        }
+
+        // Unused bits are set, which only DER forbids
+        assert_eq!(decode_bit_string(&[0x04, 0xA1]).unwrap(), vec![true, false, true, false]);
+        match decode_bit_string_der(&[0x04, 0xA1])
+        {
+            Err(ASN1Error::UnusedBitsSet) => {},
+            x => panic!("Expected UnusedBitsSet, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn booleans()
+    {
+        assert_eq!(encode_boolean(true), vec![0xFF]);
+        assert_eq!(encode_boolean(false), vec![0x00]);
+
+        assert_eq!(decode_boolean(&[0x00]).unwrap(), false);
+        assert_eq!(decode_boolean(&[0xFF]).unwrap(), true);
+        assert_eq!(decode_boolean(&[0x01]).unwrap(), true);
+        assert_eq!(decode_boolean_der(&[0x00]).unwrap(), false);
+        assert_eq!(decode_boolean_der(&[0xFF]).unwrap(), true);
+
+        match decode_boolean_der(&[0x01])
+        {
+            Err(ASN1Error::NonCanonicalBoolean) => {},
+            x => panic!("Expected NonCanonicalBoolean, got {:?}", x),
+        }
+
+        assert!(decode_boolean(&[]).is_err());
+        assert!(decode_boolean(&[0xFF, 0xFF]).is_err());
+    }
+
+    #[test]
+    fn scalars_to_ber()
+    {
+        assert_eq!(ber::encode_tag(&true.to_ber()).unwrap(), vec![0x01, 0x01, 0xff]);
+        assert_eq!(ber::encode_tag(&(-129i64).to_ber()).unwrap(), vec![0x02, 0x02, 0xff, 0x7f]);
+        assert_eq!(ber::encode_tag(&128i32.to_ber()).unwrap(), vec![0x02, 0x02, 0x00, 0x80]);
+        assert_eq!(ber::encode_tag(&"cn".to_ber()).unwrap(), vec![0x04, 0x02, 0x63, 0x6e]);
+        assert_eq!("cn".to_string().to_ber(), "cn".to_ber());
+        assert_eq!(vec![0xffu8].to_ber(), octet_string(&[0xff]));
+        assert_eq!(ber::encode_tag(&sequence_of(&["a", "b"])).unwrap(), vec![0x30, 0x06, 0x04, 0x01, 0x61, 0x04, 0x01, 0x62]);
+    }
+
+    #[test]
+    fn custom_to_ber()
+    {
+        // Person ::= SEQUENCE { name OCTET STRING, age INTEGER, admin [0] BOOLEAN }
+        struct Person
+        {
+            name: String,
+            age: i64,
+            admin: bool,
+        }
+
+        impl ToBer for Person
+        {
+            fn to_ber(&self) -> common::Tag
+            {
+                let mut admin = self.admin.to_ber();
+                admin._type.class = common::Class::ContextSpecific(0);
+
+                sequence(vec![self.name.to_ber(), self.age.to_ber(), admin])
+            }
+        }
+
+        let people = [Person { name: "alice".to_string(), age: 30, admin: true }];
+        let bytes = ber::encode_tag(&sequence_of(&people)).unwrap();
+
+        assert_eq!(bytes, vec![0x30, 0x0f, 0x30, 0x0d, 0x04, 0x05, 0x61, 0x6c, 0x69, 0x63, 0x65,
+                               0x02, 0x01, 0x1e, 0x80, 0x01, 0xff]);
+    }
+
+    #[test]
+    fn scalars_from_ber()
+    {
+        assert_eq!(bool::from_ber(&true.to_ber()).unwrap(), true);
+        assert_eq!(i64::from_ber(&(-129i64).to_ber()).unwrap(), -129);
+        assert_eq!(i32::from_ber(&70000i64.to_ber()).unwrap(), 70000);
+        assert_eq!(Vec::<u8>::from_ber(&octet_string(&[0xff, 0x00])).unwrap(), vec![0xff, 0x00]);
+        assert_eq!(String::from_ber(&"cn".to_ber()).unwrap(), "cn");
+        assert_eq!(decode_sequence_of::<String>(&sequence_of(&["a", "b"])).unwrap(), vec!["a".to_string(), "b".to_string()]);
+
+        // Valid tags whose value doesn't fit
+        assert!(i32::from_ber(&(1i64 << 40).to_ber()).is_err());
+        assert!(String::from_ber(&octet_string(&[0xff])).is_err());
+    }
+
+    #[test]
+    fn from_ber_rejects_other_types()
+    {
+        let expect_mismatch = |result: Result<(), ASN1Error>, expected, found| match result
+        {
+            Err(ASN1Error::UnexpectedType { expected: e, found: f }) =>
+                assert_eq!((e, f), (common::Class::Universal(expected), found)),
+            x => panic!("Expected UnexpectedType, got {:?}", x),
+        };
+
+        expect_mismatch(i64::from_ber(&true.to_ber()).map(|_| ()),
+                        common::UniversalTypes::Integer, common::Class::Universal(common::UniversalTypes::Boolean));
+        expect_mismatch(bool::from_ber(&"yes".to_ber()).map(|_| ()),
+                        common::UniversalTypes::Boolean, common::Class::Universal(common::UniversalTypes::OctetString));
+
+        // Implicitly tagged values aren't taken for the universal type
+        let mut tagged = "cn".to_ber();
+        tagged._type.class = common::Class::ContextSpecific(0);
+        expect_mismatch(String::from_ber(&tagged).map(|_| ()), common::UniversalTypes::OctetString, common::Class::ContextSpecific(0));
+        expect_mismatch(decode_sequence_of::<i64>(&tagged).map(|_| ()), common::UniversalTypes::Sequence, common::Class::ContextSpecific(0));
+    }
+}
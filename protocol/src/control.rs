@@ -0,0 +1,580 @@
+//! LDAP Controls as attached to LDAPMessages (RFC 4511, section 4.1.11)
+
+use ber::{self, common};
+use ber::common::Tag;
+use ber::types::{self, ASNType};
+use error::LDAPError;
+use filter::Filter;
+use result::{string_from_tag, ResultCode};
+use Result;
+use prelude::*;
+
+/// OID of the simple paged results control (RFC 2696)
+pub const PAGED_RESULTS_OID: &'static str = "1.2.840.113556.1.4.319";
+/// OID of the server side sort request control (RFC 2891)
+pub const SORT_REQUEST_OID: &'static str = "1.2.840.113556.1.4.473";
+/// OID of the server side sort response control (RFC 2891)
+pub const SORT_RESPONSE_OID: &'static str = "1.2.840.113556.1.4.474";
+/// OID of the assertion control (RFC 4528)
+pub const ASSERTION_OID: &'static str = "1.3.6.1.4.1.4203.1.11.4";
+/// OID of the ManageDsaIT control (RFC 3296)
+pub const MANAGE_DSA_IT_OID: &'static str = "2.16.840.1.113730.3.4.2";
+/// OID of the proxied authorization control (RFC 4370)
+pub const PROXY_AUTH_OID: &'static str = "2.16.840.1.113730.3.4.18";
+/// OID of the pre-read control (RFC 4527)
+pub const PRE_READ_OID: &'static str = "1.3.6.1.1.13.1";
+/// OID of the post-read control (RFC 4527)
+pub const POST_READ_OID: &'static str = "1.3.6.1.1.13.2";
+
+/// A control extending a request or response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Control
+{
+    /// Dotted OID identifying the control
+    pub oid: String,
+    /// Whether the server has to refuse the operation if it doesn't support the control
+    pub criticality: bool,
+    /// Control specific value, usually BER encoded
+    pub value: Option<Vec<u8>>,
+}
+
+impl Control
+{
+    /// Encode the control as a Control SEQUENCE
+    pub fn to_tag(&self) -> Tag
+    {
+        let mut components = Vec::with_capacity(3);
+
+        components.push({
+            let class = common::Class::Universal(common::UniversalTypes::OctetString);
+            let pl = common::Payload::Primitive(self.oid.as_bytes().to_vec());
+
+            common::construct(class, pl)
+        });
+
+        // criticality is DEFAULT FALSE so it is only sent when set
+        if self.criticality
+        {
+            let class = common::Class::Universal(common::UniversalTypes::Boolean);
+            let pl = common::Payload::Primitive(types::encode_boolean(true));
+
+            components.push(common::construct(class, pl));
+        }
+
+        if let Some(ref value) = self.value
+        {
+            let class = common::Class::Universal(common::UniversalTypes::OctetString);
+            let pl = common::Payload::Primitive(value.clone());
+
+            components.push(common::construct(class, pl));
+        }
+
+        let class = common::Class::Universal(common::UniversalTypes::Sequence);
+        let pl = common::Payload::Constructed(components);
+
+        common::construct(class, pl)
+    }
+
+    /// Build a sort request control ordering search results by `keys`, most significant first
+    pub fn sort(keys: &[SortKey]) -> Control
+    {
+        let keys = keys.iter().map(|key| {
+            let mut components = vec![{
+                let class = common::Class::Universal(common::UniversalTypes::OctetString);
+                let pl = common::Payload::Primitive(key.attribute.as_bytes().to_vec());
+
+                common::construct(class, pl)
+            }];
+
+            if let Some(ref rule) = key.ordering_rule
+            {
+                let class = common::Class::ContextSpecific(0);
+                let pl = common::Payload::Primitive(rule.as_bytes().to_vec());
+
+                components.push(common::construct(class, pl));
+            }
+
+            // reverseOrder is DEFAULT FALSE as well
+            if key.reverse
+            {
+                let class = common::Class::ContextSpecific(1);
+                let pl = common::Payload::Primitive(types::encode_boolean(true));
+
+                components.push(common::construct(class, pl));
+            }
+
+            let class = common::Class::Universal(common::UniversalTypes::Sequence);
+            let pl = common::Payload::Constructed(components);
+
+            common::construct(class, pl)
+        }).collect();
+
+        let value = {
+            let class = common::Class::Universal(common::UniversalTypes::Sequence);
+            let pl = common::Payload::Constructed(keys);
+
+            common::construct(class, pl)
+        };
+
+        Control
+        {
+            oid: SORT_REQUEST_OID.to_string(),
+            criticality: false,
+            value: Some(ber::encode_tag(&value).expect("Encoding into a Vec can't fail")),
+        }
+    }
+
+    /// Build an assertion control, letting the operation only proceed if `filter` matches the
+    /// target entry
+    ///
+    /// Works with compare, delete, modify, modifyDN and search operations. If the filter
+    /// doesn't match, the server fails the operation with `assertionFailed`. The control is
+    /// critical, as a server ignoring it would skip the check.
+    pub fn assertion(filter: Filter) -> Control
+    {
+        Control
+        {
+            oid: ASSERTION_OID.to_string(),
+            criticality: true,
+            value: Some(ber::encode_tag(&filter.encode()).expect("Encoding into a Vec can't fail")),
+        }
+    }
+
+    /// Build a ManageDsaIT control, making the server treat referral objects and other special
+    /// entries like normal ones instead of returning referrals for them
+    pub fn manage_dsa_it() -> Control
+    {
+        Control
+        {
+            oid: MANAGE_DSA_IT_OID.to_string(),
+            criticality: true,
+            value: None,
+        }
+    }
+
+    /// Build a proxied authorization control, performing the operation as `authz_id`
+    ///
+    /// `authz_id` is `dn:` followed by a DN or `u:` followed by a user name, or empty for the
+    /// anonymous identity. Unlike most control values it is sent as is, not BER encoded. The
+    /// server fails the operation with `authorizationDenied` if the bound identity may not act
+    /// on behalf of `authz_id`.
+    pub fn proxy_auth(authz_id: &str) -> Control
+    {
+        Control
+        {
+            oid: PROXY_AUTH_OID.to_string(),
+            criticality: true,
+            value: Some(authz_id.as_bytes().to_vec()),
+        }
+    }
+
+    /// Build a pre-read control, asking for the `attrs` of the entry as they were before a
+    /// modify, delete or modifyDN
+    ///
+    /// The server returns the entry as SearchResultEntry in the value of a response control
+    /// with the same OID. An empty `attrs` returns all user attributes.
+    pub fn pre_read(attrs: &[&str]) -> Control
+    {
+        read_entry(PRE_READ_OID, attrs)
+    }
+
+    /// Like `pre_read`, but returns the entry as it is after an add, modify or modifyDN
+    pub fn post_read(attrs: &[&str]) -> Control
+    {
+        read_entry(POST_READ_OID, attrs)
+    }
+
+    /// Parse a Control SEQUENCE
+    pub fn from_tag(tag: Tag) -> Result<Control>
+    {
+        let components = match tag._value
+        {
+            common::Payload::Constructed(components) => components,
+            common::Payload::Primitive(_) => return Err(LDAPError::Protocol),
+        };
+
+        let mut components = components.into_iter().peekable();
+
+        let oid = match components.next()
+        {
+            Some(tag) => try!(string_from_tag(tag)),
+            None => return Err(LDAPError::Protocol),
+        };
+
+        let has_criticality = components.peek()
+            .map(|t| t._type.class == common::Class::Universal(common::UniversalTypes::Boolean))
+            .unwrap_or(false);
+        let criticality = if has_criticality
+        {
+            match components.next().unwrap()._value
+            {
+                common::Payload::Primitive(ref bytes) => try!(types::decode_boolean(bytes)),
+                common::Payload::Constructed(_) => return Err(LDAPError::Protocol),
+            }
+        }
+        else
+        {
+            false
+        };
+
+        let value = match components.next()
+        {
+            Some(Tag { _value: common::Payload::Primitive(bytes), .. }) => Some(bytes),
+            Some(_) => return Err(LDAPError::Protocol),
+            None => None,
+        };
+
+        if components.next().is_some()
+        {
+            return Err(LDAPError::Protocol);
+        }
+
+        Ok(Control
+        {
+            oid: oid,
+            criticality: criticality,
+            value: value,
+        })
+    }
+}
+
+// The value of both read entry controls is an AttributeSelection
+fn read_entry(oid: &str, attrs: &[&str]) -> Control
+{
+    Control
+    {
+        oid: oid.to_string(),
+        criticality: false,
+        value: Some(ber::encode_tag(&types::sequence_of(attrs)).expect("Encoding into a Vec can't fail")),
+    }
+}
+
+/// Value of the simple paged results control
+///
+/// Requests carry the wanted page `size` and the `cookie` of the previous page, empty for the
+/// first one. The server answers with an estimate of the total result count as `size` and the
+/// cookie for the next page, empty once all results were returned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PagedResults
+{
+    /// Page size in a request, estimated total result count in a response
+    pub size: i32,
+    /// Opaque server cookie
+    pub cookie: Vec<u8>,
+}
+
+impl PagedResults
+{
+    /// Build the request control asking for the page following `cookie`
+    pub fn to_control(&self) -> Control
+    {
+        let sizetag = self.size.into_ber_universal();
+
+        let cookietag = {
+            let class = common::Class::Universal(common::UniversalTypes::OctetString);
+            let pl = common::Payload::Primitive(self.cookie.clone());
+
+            common::construct(class, pl)
+        };
+
+        let value = {
+            let class = common::Class::Universal(common::UniversalTypes::Sequence);
+            let pl = common::Payload::Constructed(vec![sizetag, cookietag]);
+
+            common::construct(class, pl)
+        };
+
+        Control
+        {
+            oid: PAGED_RESULTS_OID.to_string(),
+            criticality: false,
+            value: Some(ber::encode_tag(&value).expect("Encoding into a Vec can't fail")),
+        }
+    }
+
+    /// Parse the value of a paged results control as returned on SearchResultDone
+    pub fn from_control(control: &Control) -> Result<PagedResults>
+    {
+        let value = match control.value
+        {
+            Some(ref value) if control.oid == PAGED_RESULTS_OID => value,
+            _ => return Err(LDAPError::Protocol),
+        };
+
+        let mut components = match try!(ber::decode(value))._value
+        {
+            common::Payload::Constructed(components) if components.len() == 2 => components,
+            _ => return Err(LDAPError::Protocol),
+        };
+
+        let cookie = match components.pop().unwrap()._value
+        {
+            common::Payload::Primitive(cookie) => cookie,
+            common::Payload::Constructed(_) => return Err(LDAPError::Protocol),
+        };
+        let size = match i32::from_tag(&mut components.pop().unwrap())
+        {
+            Some(size) => size,
+            None => return Err(LDAPError::Protocol),
+        };
+
+        Ok(PagedResults
+        {
+            size: size,
+            cookie: cookie,
+        })
+    }
+}
+
+/// A single key of a sort request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortKey
+{
+    /// Attribute to sort by
+    pub attribute: String,
+    /// Matching rule to order with instead of the attribute's default ordering
+    pub ordering_rule: Option<String>,
+    /// Sort descending instead of ascending
+    pub reverse: bool,
+}
+
+/// Outcome of a sort request as reported by the sort response control
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SortResult
+{
+    /// `success` if the results were sorted
+    pub result_code: ResultCode,
+    /// Attribute that caused the sort to fail, if the server named it
+    pub attribute: Option<String>,
+}
+
+impl SortResult
+{
+    /// Parse the value of a sort response control
+    pub fn from_control(control: &Control) -> Result<SortResult>
+    {
+        let value = match control.value
+        {
+            Some(ref value) if control.oid == SORT_RESPONSE_OID => value,
+            _ => return Err(LDAPError::Protocol),
+        };
+
+        let components = match try!(ber::decode(value))._value
+        {
+            common::Payload::Constructed(components) => components,
+            common::Payload::Primitive(_) => return Err(LDAPError::Protocol),
+        };
+
+        let mut components = components.into_iter();
+
+        let result_code = match components.next().as_mut().and_then(i64::from_tag)
+        {
+            Some(code) => ResultCode::from_i64(code),
+            None => return Err(LDAPError::Protocol),
+        };
+
+        let attribute = match components.next()
+        {
+            Some(tag) =>
+            {
+                if tag._type.class != common::Class::ContextSpecific(0)
+                {
+                    return Err(LDAPError::Protocol);
+                }
+
+                Some(try!(string_from_tag(tag)))
+            },
+            None => None,
+        };
+
+        Ok(SortResult
+        {
+            result_code: result_code,
+            attribute: attribute,
+        })
+    }
+}
+
+/// Build the [0] Controls element of an LDAPMessage, `None` if there are no controls
+pub fn build_controls(controls: &[Control]) -> Option<Tag>
+{
+    if controls.is_empty()
+    {
+        return None;
+    }
+
+    let class = common::Class::ContextSpecific(0);
+    let pl = common::Payload::Constructed(controls.iter().map(Control::to_tag).collect());
+
+    Some(common::construct(class, pl))
+}
+
+/// Parse the [0] Controls element of an LDAPMessage as returned by `deconstruct_envelope`
+pub fn parse_controls(controls: Option<Tag>) -> Result<Vec<Control>>
+{
+    let tag = match controls
+    {
+        Some(tag) => tag,
+        None => return Ok(Vec::new()),
+    };
+
+    if tag._type.class != common::Class::ContextSpecific(0)
+    {
+        return Err(LDAPError::Protocol);
+    }
+
+    match tag._value
+    {
+        common::Payload::Constructed(controls) => controls.into_iter().map(Control::from_tag).collect(),
+        common::Payload::Primitive(_) => Err(LDAPError::Protocol),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{build_controls, parse_controls, Control, PagedResults, SortKey, SortResult, PAGED_RESULTS_OID, SORT_REQUEST_OID, SORT_RESPONSE_OID};
+    use super::{ASSERTION_OID, MANAGE_DSA_IT_OID, POST_READ_OID, PRE_READ_OID, PROXY_AUTH_OID};
+    use filter::Filter;
+    use result::ResultCode;
+    use ber::{self, common};
+
+    #[test]
+    fn roundtrip_control()
+    {
+        let control = Control
+        {
+            oid: "1.3.6.1.4.1.99999.1".to_string(),
+            criticality: true,
+            value: Some(vec![0x30, 0x03, 0x02, 0x01, 0x2A]),
+        };
+
+        let op = {
+            let class = common::Class::Application(10);
+            let pl = common::Payload::Primitive(b"dc=example".to_vec());
+
+            common::construct(class, pl)
+        };
+
+        let bytes = ber::encode_with_controls(op, 3, &[control.clone()]).unwrap();
+        let (msgid, _, controls) = ::deconstruct_envelope(ber::decode(&bytes).unwrap()).unwrap();
+
+        assert_eq!(msgid, 3);
+        assert_eq!(parse_controls(controls).unwrap(), vec![control]);
+    }
+
+    #[test]
+    fn criticality_defaults_to_false()
+    {
+        let control = Control { oid: "1.2.3".to_string(), criticality: false, value: None };
+
+        // [0] { SEQUENCE { "1.2.3" } } without BOOLEAN or value
+        let bytes = ber::encode(build_controls(&[control.clone()]).unwrap(), 1).unwrap();
+        assert!(bytes.ends_with(&[0xA0, 0x09, 0x30, 0x07, 0x04, 0x05, 0x31, 0x2E, 0x32, 0x2E, 0x33]));
+
+        assert_eq!(parse_controls(build_controls(&[control.clone()])).unwrap(), vec![control]);
+        assert!(build_controls(&[]).is_none());
+    }
+
+    #[test]
+    fn paged_results_value()
+    {
+        let control = PagedResults { size: 500, cookie: Vec::new() }.to_control();
+
+        assert_eq!(control.oid, PAGED_RESULTS_OID);
+        assert_eq!(control.value, Some(vec![0x30, 0x06, 0x02, 0x02, 0x01, 0xF4, 0x04, 0x00]));
+
+        let response = PagedResults { size: 1234, cookie: b"next".to_vec() };
+        assert_eq!(PagedResults::from_control(&response.to_control()).unwrap(), response);
+
+        let other = Control { oid: "1.2.3".to_string(), criticality: false, value: control.value.clone() };
+        assert!(PagedResults::from_control(&other).is_err());
+    }
+
+    #[test]
+    fn assertion_value()
+    {
+        let control = Control::assertion(Filter::parse("(entryCSN=x)").unwrap());
+
+        assert_eq!(control.oid, ASSERTION_OID);
+        assert!(control.criticality);
+        // equalityMatch [3] { "entryCSN", "x" }
+        assert_eq!(control.value, Some(vec![
+            0xa3, 0x0d, 0x04, 0x08, 0x65, 0x6e, 0x74, 0x72, 0x79, 0x43, 0x53, 0x4e, 0x04, 0x01, 0x78,
+        ]));
+        assert_eq!(Filter::decode(&ber::decode(control.value.as_ref().unwrap()).unwrap()).unwrap(),
+                   Filter::parse("(entryCSN=x)").unwrap());
+    }
+
+    #[test]
+    fn manage_dsa_it_has_no_value()
+    {
+        let control = Control::manage_dsa_it();
+        assert_eq!(control.oid, MANAGE_DSA_IT_OID);
+
+        // SEQUENCE { controlType, criticality TRUE } without any controlValue
+        let mut expected = vec![0x30, 0x1c, 0x04, 0x17];
+        expected.extend_from_slice(b"2.16.840.1.113730.3.4.2");
+        expected.extend_from_slice(&[0x01, 0x01, 0xff]);
+        assert_eq!(ber::encode_tag(&control.to_tag()).unwrap(), expected);
+    }
+
+    #[test]
+    fn proxy_auth_value()
+    {
+        let control = Control::proxy_auth("dn:uid=alice,ou=people,dc=example");
+
+        assert_eq!(control.oid, PROXY_AUTH_OID);
+        assert!(control.criticality);
+        assert_eq!(control.value, Some(b"dn:uid=alice,ou=people,dc=example".to_vec()));
+
+        // The authzId is the plain content of controlValue
+        let mut expected = vec![0x01, 0x01, 0xff, 0x04, 0x21];
+        expected.extend_from_slice(b"dn:uid=alice,ou=people,dc=example");
+        assert!(ber::encode_tag(&control.to_tag()).unwrap().ends_with(&expected));
+    }
+
+    #[test]
+    fn read_entry_values()
+    {
+        let control = Control::pre_read(&["cn", "mail"]);
+        assert_eq!(control.oid, PRE_READ_OID);
+        assert_eq!(control.value, Some(vec![0x30, 0x0a, 0x04, 0x02, 0x63, 0x6e, 0x04, 0x04, 0x6d, 0x61, 0x69, 0x6c]));
+
+        let control = Control::post_read(&[]);
+        assert_eq!(control.oid, POST_READ_OID);
+        assert_eq!(control.value, Some(vec![0x30, 0x00]));
+    }
+
+    #[test]
+    fn sort_request_with_two_keys()
+    {
+        let keys = [
+            SortKey { attribute: "sn".to_string(), ordering_rule: None, reverse: false },
+            SortKey { attribute: "givenName".to_string(), ordering_rule: None, reverse: true },
+        ];
+        let control = Control::sort(&keys);
+
+        assert_eq!(control.oid, SORT_REQUEST_OID);
+        assert_eq!(control.value, Some(vec![
+            0x30, 0x16, 0x30, 0x04, 0x04, 0x02, 0x73, 0x6e, 0x30, 0x0e, 0x04, 0x09, 0x67, 0x69, 0x76, 0x65,
+            0x6e, 0x4e, 0x61, 0x6d, 0x65, 0x81, 0x01, 0xff,
+        ]));
+    }
+
+    #[test]
+    fn parse_sort_response()
+    {
+        let control = Control
+        {
+            oid: SORT_RESPONSE_OID.to_string(),
+            criticality: false,
+            // success, naming sn
+            value: Some(vec![0x30, 0x07, 0x0a, 0x01, 0x00, 0x80, 0x02, 0x73, 0x6e]),
+        };
+
+        let result = SortResult::from_control(&control).unwrap();
+        assert_eq!(result.result_code, ResultCode::success);
+        assert_eq!(result.attribute, Some("sn".to_string()));
+    }
+}
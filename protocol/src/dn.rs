@@ -0,0 +1,366 @@
+//! Distinguished names and their string representation (RFC4514)
+
+use std::fmt;
+
+use ber;
+use ber::common;
+use error::LDAPError;
+use Result;
+use prelude::*;
+
+/// A distinguished name, its RDNs ordered from the entry itself up to the root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dn
+{
+    /// The relative distinguished names, `cn=alice` first for `cn=alice,dc=example`
+    pub rdns: Vec<Rdn>,
+}
+
+/// A relative distinguished name, usually a single `attribute=value` pair
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rdn
+{
+    /// The attribute type and value pairs, more than one for multi-valued RDNs like `cn=a+sn=b`
+    pub components: Vec<(String, String)>,
+}
+
+impl Dn
+{
+    /// Parse the string representation of a DN
+    ///
+    /// Values may contain `\` escapes of special characters or hex pairs, and a value starting
+    /// with `#` is taken as hex encoded BER. Spaces around attribute types, `=` and the
+    /// separators are ignored unless escaped. The empty string is the empty DN of the root.
+    pub fn parse(input: &str) -> Result<Dn>
+    {
+        let mut parser = Parser { input: input.as_bytes(), pos: 0 };
+        let mut rdns = Vec::new();
+
+        parser.skip_whitespace();
+        if parser.peek().is_none()
+        {
+            return Ok(Dn { rdns: rdns });
+        }
+
+        loop
+        {
+            rdns.push(try!(parser.rdn()));
+
+            match parser.peek()
+            {
+                Some(b',') => parser.pos += 1,
+                None => break,
+                _ => return Err(parser.error("Expected ',' between RDNs")),
+            }
+        }
+
+        Ok(Dn { rdns: rdns })
+    }
+}
+
+impl fmt::Display for Dn
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        for (i, rdn) in self.rdns.iter().enumerate()
+        {
+            if i > 0
+            {
+                try!(f.write_str(","));
+            }
+            try!(write!(f, "{}", rdn));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Rdn
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        for (i, &(ref attr, ref value)) in self.components.iter().enumerate()
+        {
+            if i > 0
+            {
+                try!(f.write_str("+"));
+            }
+            try!(write!(f, "{}={}", attr, escape_value(value)));
+        }
+
+        Ok(())
+    }
+}
+
+/// Escape `value` for use as attribute value in a DN string
+pub fn escape_value(value: &str) -> String
+{
+    let mut escaped = String::with_capacity(value.len());
+    let last = value.chars().count().saturating_sub(1);
+
+    for (i, c) in value.chars().enumerate()
+    {
+        match c
+        {
+            '"' | '+' | ',' | ';' | '<' | '>' | '\\' => { escaped.push('\\'); escaped.push(c); },
+            '\0' => escaped.push_str("\\00"),
+            ' ' if i == 0 || i == last => escaped.push_str("\\ "),
+            '#' if i == 0 => escaped.push_str("\\#"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+// Recursive descent parser for RFC4514 string DNs
+struct Parser<'a>
+{
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a>
+{
+    fn error(&self, reason: &'static str) -> LDAPError
+    {
+        LDAPError::DnParse { offset: self.pos, reason: reason }
+    }
+
+    fn peek(&self) -> Option<u8>
+    {
+        self.input.get(self.pos).cloned()
+    }
+
+    fn skip_whitespace(&mut self)
+    {
+        while self.peek() == Some(b' ')
+        {
+            self.pos += 1;
+        }
+    }
+
+    // relativeDistinguishedName = attributeTypeAndValue *( PLUS attributeTypeAndValue )
+    fn rdn(&mut self) -> Result<Rdn>
+    {
+        let mut components = Vec::new();
+
+        loop
+        {
+            self.skip_whitespace();
+            let attr = try!(self.attr());
+
+            self.skip_whitespace();
+            if self.peek() != Some(b'=')
+            {
+                return Err(self.error("Expected '=' after attribute type"));
+            }
+            self.pos += 1;
+            self.skip_whitespace();
+
+            components.push((attr, try!(self.value())));
+
+            if self.peek() == Some(b'+')
+            {
+                self.pos += 1;
+            }
+            else
+            {
+                return Ok(Rdn { components: components });
+            }
+        }
+    }
+
+    // attributeType = descr / numericoid
+    fn attr(&mut self) -> Result<String>
+    {
+        let start = self.pos;
+
+        while let Some(byte) = self.peek()
+        {
+            match byte
+            {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' => self.pos += 1,
+                _ => break,
+            }
+        }
+
+        if start == self.pos
+        {
+            return Err(self.error("Expected attribute type"));
+        }
+
+        // Only ASCII was accepted above
+        Ok(String::from_utf8(self.input[start..self.pos].to_vec()).unwrap())
+    }
+
+    // attributeValue = string / hexstring
+    fn value(&mut self) -> Result<String>
+    {
+        if self.peek() == Some(b'#')
+        {
+            return self.hexstring();
+        }
+
+        let start = self.pos;
+        let mut value = Vec::new();
+        // Length of value up to the last escaped or non-space character
+        let mut keep = 0;
+
+        while let Some(byte) = self.peek()
+        {
+            match byte
+            {
+                b',' | b'+' => break,
+                b'"' | b';' | b'<' | b'>' | b'\0' => return Err(self.error("Unescaped special character in value")),
+                b'\\' =>
+                {
+                    self.pos += 1;
+                    match self.peek()
+                    {
+                        Some(c @ b' ') | Some(c @ b'"') | Some(c @ b'#') | Some(c @ b'+') | Some(c @ b',') |
+                        Some(c @ b';') | Some(c @ b'<') | Some(c @ b'=') | Some(c @ b'>') | Some(c @ b'\\') =>
+                        {
+                            value.push(c);
+                            self.pos += 1;
+                        },
+                        _ =>
+                        {
+                            let escaped = match (self.input.get(self.pos).and_then(hex), self.input.get(self.pos + 1).and_then(hex))
+                            {
+                                (Some(high), Some(low)) => high << 4 | low,
+                                _ => return Err(self.error("Expected special character or two hex digits after '\\'")),
+                            };
+
+                            value.push(escaped);
+                            self.pos += 2;
+                        },
+                    }
+                    keep = value.len();
+                },
+                _ =>
+                {
+                    value.push(byte);
+                    self.pos += 1;
+                    if byte != b' '
+                    {
+                        keep = value.len();
+                    }
+                },
+            }
+        }
+
+        value.truncate(keep);
+
+        String::from_utf8(value).map_err(|_| LDAPError::DnParse { offset: start, reason: "Value is not valid UTF-8" })
+    }
+
+    // hexstring = SHARP 1*hexpair, the BER encoding of the value
+    fn hexstring(&mut self) -> Result<String>
+    {
+        self.pos += 1;
+        let start = self.pos;
+        let mut encoded = Vec::new();
+
+        while let (Some(high), Some(low)) = (self.input.get(self.pos).and_then(hex), self.input.get(self.pos + 1).and_then(hex))
+        {
+            encoded.push(high << 4 | low);
+            self.pos += 2;
+        }
+
+        let end = self.pos;
+        self.skip_whitespace();
+        match self.peek()
+        {
+            None | Some(b',') | Some(b'+') if !encoded.is_empty() => {},
+            _ => return Err(self.error("Expected hex pairs after '#'")),
+        }
+
+        let error = LDAPError::DnParse { offset: start, reason: "Hex value is not a BER encoded string" };
+        match ber::decode(&encoded)
+        {
+            Ok(common::Tag { _value: common::Payload::Primitive(bytes), size, .. }) if size as usize == (end - start) / 2 =>
+                String::from_utf8(bytes).map_err(|_| error),
+            _ => Err(error),
+        }
+    }
+}
+
+fn hex(byte: &u8) -> Option<u8>
+{
+    match *byte
+    {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{Dn, Rdn};
+    use error::LDAPError;
+
+    fn rdn(components: &[(&str, &str)]) -> Rdn
+    {
+        Rdn { components: components.iter().map(|&(a, v)| (a.to_string(), v.to_string())).collect() }
+    }
+
+    #[test]
+    fn parse_simple_dn()
+    {
+        let dn = Dn::parse("cn=alice,dc=example, dc=com").unwrap();
+
+        assert_eq!(dn.rdns, vec![rdn(&[("cn", "alice")]), rdn(&[("dc", "example")]), rdn(&[("dc", "com")])]);
+        assert_eq!(dn.to_string(), "cn=alice,dc=example,dc=com");
+        assert_eq!(Dn::parse("").unwrap().rdns, vec![]);
+    }
+
+    #[test]
+    fn parse_multi_valued_rdn()
+    {
+        let dn = Dn::parse("cn=a+sn=b,dc=example").unwrap();
+
+        assert_eq!(dn.rdns, vec![rdn(&[("cn", "a"), ("sn", "b")]), rdn(&[("dc", "example")])]);
+        assert_eq!(dn.to_string(), "cn=a+sn=b,dc=example");
+    }
+
+    #[test]
+    fn parse_escaped_values()
+    {
+        let dn = Dn::parse(r"cn=Doe\, John,ou=R\26D,o=\22Quoted\22\2B\3Cx\3E").unwrap();
+
+        assert_eq!(dn.rdns, vec![rdn(&[("cn", "Doe, John")]), rdn(&[("ou", "R&D")]), rdn(&[("o", "\"Quoted\"+<x>")])]);
+        assert_eq!(dn.to_string(), r#"cn=Doe\, John,ou=R&D,o=\"Quoted\"\+\<x\>"#);
+
+        // UTF-8 given as hex pairs
+        assert_eq!(Dn::parse(r"cn=J\C3\BCrgen").unwrap().rdns, vec![rdn(&[("cn", "Jürgen")])]);
+        // BER encoded value
+        assert_eq!(Dn::parse("cn=#04024869").unwrap().rdns, vec![rdn(&[("cn", "Hi")])]);
+    }
+
+    #[test]
+    fn display_escapes_leading_and_trailing()
+    {
+        let dn = Dn { rdns: vec![rdn(&[("cn", "#lead"), ("sn", " both ")]), rdn(&[("o", "a;b\\c")])] };
+
+        assert_eq!(dn.to_string(), r"cn=\#lead+sn=\ both\ ,o=a\;b\\c");
+        assert_eq!(Dn::parse(&dn.to_string()).unwrap(), dn);
+    }
+
+    #[test]
+    fn parse_errors_report_offset()
+    {
+        // Trailing comma, missing '=', unescaped special, bad escape, odd BER value
+        for &(input, at) in &[("cn=a,", 5), ("cn", 2), ("cn=a<b", 4), (r"cn=a\zz", 5), ("cn=#0402", 4)]
+        {
+            match Dn::parse(input)
+            {
+                Err(LDAPError::DnParse { offset, .. }) => assert_eq!(offset, at, "{}", input),
+                x => panic!("Expected DnParse for {}, got {:?}", input, x),
+            }
+        }
+    }
+}
@@ -1,13 +1,115 @@
 use std::convert::From;
-use std::{error, io, fmt};
+use std::{error, fmt};
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::net::SocketAddr;
 
+use ber::common::Class;
 use ber::error::ASN1Error;
+use result::{LDAPResult, ResultCode};
+use prelude::*;
+
+/// A resultCode other than success, as sent by the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LdapError
+{
+    /// resultCode of the failed operation
+    pub result_code: ResultCode,
+    /// The part of the DN that does exist, for noSuchObject and similar codes
+    pub matched_dn: String,
+    /// Human readable explanation of the server, may be empty
+    pub diagnostic_message: String,
+}
+
+impl fmt::Display for LdapError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        try!(write!(f, "{:?}", self.result_code));
+        if !self.diagnostic_message.is_empty()
+        {
+            try!(write!(f, ": {}", self.diagnostic_message));
+        }
+        if !self.matched_dn.is_empty()
+        {
+            try!(write!(f, " (matched {})", self.matched_dn));
+        }
+
+        Ok(())
+    }
+}
+
+impl From<LDAPResult> for LdapError
+{
+    fn from(result: LDAPResult) -> LdapError
+    {
+        LdapError
+        {
+            result_code: result.result_code,
+            matched_dn: result.matched_dn,
+            diagnostic_message: result.diagnostic_message,
+        }
+    }
+}
 
 pub enum LDAPError
 {
     ASN1(ASN1Error),
+    #[cfg(feature = "std")]
     Io(io::Error),
+    /// None of the addresses accepted a connection, each given with the reason it failed
+    #[cfg(feature = "std")]
+    Connect(Vec<(SocketAddr, io::Error)>),
     Protocol,
+    /// The server answered with a different protocolOp than the request calls for
+    UnexpectedTag
+    {
+        expected: Class,
+        found: Class,
+    },
+    /// Malformed string search filter, with the byte offset the problem was found at
+    FilterParse
+    {
+        offset: usize,
+        reason: &'static str,
+    },
+    /// Malformed string DN, with the byte offset the problem was found at
+    DnParse
+    {
+        offset: usize,
+        reason: &'static str,
+    },
+    /// Malformed LDAP URL, with the byte offset the problem was found at
+    UrlParse
+    {
+        offset: usize,
+        reason: &'static str,
+    },
+    /// Malformed schema definition, with the byte offset the problem was found at
+    SchemaParse
+    {
+        offset: usize,
+        reason: &'static str,
+    },
+    /// An attribute was given without any values, which LDAP doesn't allow here
+    EmptyAttribute(String),
+    /// An entry breaks the rules of its object classes, found by checking it against the schema
+    ContentRule
+    {
+        attr: String,
+        reason: &'static str,
+    },
+    /// A SearchRequest was built without a filter
+    MissingFilter,
+    /// The message ID was never assigned on this connection
+    UnknownMessageId(i32),
+    /// The operation didn't finish before its deadline and was abandoned
+    Timeout,
+    /// The server refused the operation
+    Server(LdapError),
+    /// The server refused a bind with this protocol version, e.g. because it only speaks LDAPv2
+    UnsupportedVersion(u8),
     Other,
 }
 
@@ -23,7 +125,33 @@ impl fmt::Debug for LDAPError
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
     {
-        write!(f, "Error: {}", error::Error::description(self))
+        match *self
+        {
+            LDAPError::FilterParse { offset, reason } => write!(f, "Error: {} at byte {}", reason, offset),
+            LDAPError::DnParse { offset, reason } => write!(f, "Error: {} at byte {} of DN", reason, offset),
+            LDAPError::UrlParse { offset, reason } => write!(f, "Error: {} at byte {} of URL", reason, offset),
+            LDAPError::SchemaParse { offset, reason } => write!(f, "Error: {} at byte {} of schema definition", reason, offset),
+            LDAPError::UnexpectedTag { expected, found } =>
+                write!(f, "Error: Expected a {:?} response but got {:?}", expected, found),
+            LDAPError::EmptyAttribute(ref attr) => write!(f, "Error: Attribute {} has no values", attr),
+            LDAPError::ContentRule { ref attr, reason } => write!(f, "Error: {}: {}", reason, attr),
+            LDAPError::UnknownMessageId(id) => write!(f, "Error: Message ID {} was never sent", id),
+            LDAPError::Timeout => write!(f, "Error: Operation did not finish before its deadline"),
+            LDAPError::UnsupportedVersion(version) => write!(f, "Error: Server does not speak LDAPv{}", version),
+            LDAPError::Server(ref err) => write!(f, "Error: {}", err),
+            #[cfg(feature = "std")]
+            LDAPError::Connect(ref failed) =>
+            {
+                try!(write!(f, "Error: Could not connect to any address"));
+                for (i, &(ref addr, ref err)) in failed.iter().enumerate()
+                {
+                    try!(write!(f, "{} {}: {}", if i == 0 { ":" } else { "," }, addr, err));
+                }
+
+                Ok(())
+            },
+            _ => write!(f, "Error: {}", error::Error::description(self)),
+        }
     }
 }
 
@@ -34,13 +162,29 @@ impl error::Error for LDAPError
         match *self
         {
             LDAPError::ASN1(ref x) => error::Error::description(x),
+            #[cfg(feature = "std")]
             LDAPError::Io(ref x) => error::Error::description(x),
+            #[cfg(feature = "std")]
+            LDAPError::Connect(_) => "Could not connect to any address",
             LDAPError::Protocol => "Received ASN1 structure is not valid RFC4511",
+            LDAPError::UnexpectedTag { .. } => "Received a response not matching the request",
+            LDAPError::FilterParse { reason, .. } => reason,
+            LDAPError::DnParse { reason, .. } => reason,
+            LDAPError::UrlParse { reason, .. } => reason,
+            LDAPError::SchemaParse { reason, .. } => reason,
+            LDAPError::EmptyAttribute(_) => "Attribute has no values",
+            LDAPError::ContentRule { reason, .. } => reason,
+            LDAPError::MissingFilter => "Search request has no filter",
+            LDAPError::UnknownMessageId(_) => "Message ID was never sent",
+            LDAPError::Timeout => "Operation did not finish before its deadline",
+            LDAPError::Server(_) => "The server refused the operation",
+            LDAPError::UnsupportedVersion(_) => "Server does not speak the requested protocol version",
             LDAPError::Other => "Error occured",
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl From<io::Error> for LDAPError
 {
     fn from(err: io::Error) -> LDAPError
@@ -49,12 +193,21 @@ impl From<io::Error> for LDAPError
     }
 }
 
+impl From<LdapError> for LDAPError
+{
+    fn from(err: LdapError) -> LDAPError
+    {
+        LDAPError::Server(err)
+    }
+}
+
 impl From<ASN1Error> for LDAPError
 {
     fn from(err: ASN1Error) -> LDAPError
     {
         match err
         {
+            #[cfg(feature = "std")]
             ASN1Error::Io(e) => LDAPError::from(e),
             _ => LDAPError::ASN1(err),
         }
@@ -0,0 +1,926 @@
+//! Search filters (RFC4511 Section 4.5.1.7)
+
+use ber;
+use ber::common::{self, Tag};
+use ber::types;
+use error::LDAPError;
+use Result;
+use prelude::*;
+
+/// Filter of a SearchRequest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter
+{
+    /// `(&(..)(..))`, matches if all filters match
+    And(Vec<Filter>),
+    /// `(|(..)(..))`, matches if any filter matches
+    Or(Vec<Filter>),
+    /// `(!(..))`, matches if the filter doesn't
+    Not(Box<Filter>),
+    /// `(attr=value)`
+    Equality
+    {
+        attr: String,
+        value: Vec<u8>,
+    },
+    /// `(attr=initial*any*final)`
+    Substrings
+    {
+        attr: String,
+        initial: Option<Vec<u8>>,
+        any: Vec<Vec<u8>>,
+        final_: Option<Vec<u8>>,
+    },
+    /// `(attr>=value)`
+    GreaterOrEqual
+    {
+        attr: String,
+        value: Vec<u8>,
+    },
+    /// `(attr<=value)`
+    LessOrEqual
+    {
+        attr: String,
+        value: Vec<u8>,
+    },
+    /// `(attr=*)`
+    Present(String),
+    /// `(attr~=value)`
+    ApproxMatch
+    {
+        attr: String,
+        value: Vec<u8>,
+    },
+    /// `(attr:dn:rule:=value)`, where at least one of attr and rule is given
+    ExtensibleMatch
+    {
+        matching_rule: Option<String>,
+        attr: Option<String>,
+        value: Vec<u8>,
+        /// Also match against the attributes of the entries DN
+        dn_attributes: bool,
+    },
+}
+
+impl Filter
+{
+    /// Encode the filter as its context-specific CHOICE tag
+    pub fn encode(&self) -> Tag
+    {
+        match *self
+        {
+            Filter::And(ref filters) => set(0, filters),
+            Filter::Or(ref filters) => set(1, filters),
+            Filter::Not(ref filter) =>
+            {
+                let class = common::Class::ContextSpecific(2);
+                let pl = common::Payload::Constructed(vec![filter.encode()]);
+
+                common::construct(class, pl)
+            },
+            Filter::Equality { ref attr, ref value } => ava(3, attr, value),
+            Filter::Substrings { ref attr, ref initial, ref any, ref final_ } =>
+            {
+                let mut substrings = Vec::new();
+                if let Some(ref initial) = *initial
+                {
+                    substrings.push(context_string(0, initial));
+                }
+                for any in any
+                {
+                    substrings.push(context_string(1, any));
+                }
+                if let Some(ref final_) = *final_
+                {
+                    substrings.push(context_string(2, final_));
+                }
+
+                let substrings = {
+                    let class = common::Class::Universal(common::UniversalTypes::Sequence);
+                    let pl = common::Payload::Constructed(substrings);
+
+                    common::construct(class, pl)
+                };
+
+                let class = common::Class::ContextSpecific(4);
+                let pl = common::Payload::Constructed(vec![types::octet_string(attr.as_bytes()), substrings]);
+
+                common::construct(class, pl)
+            },
+            Filter::GreaterOrEqual { ref attr, ref value } => ava(5, attr, value),
+            Filter::LessOrEqual { ref attr, ref value } => ava(6, attr, value),
+            Filter::Present(ref attr) => context_string(7, attr.as_bytes()),
+            Filter::ApproxMatch { ref attr, ref value } => ava(8, attr, value),
+            Filter::ExtensibleMatch { ref matching_rule, ref attr, ref value, dn_attributes } =>
+            {
+                let mut children = Vec::new();
+                if let Some(ref matching_rule) = *matching_rule
+                {
+                    children.push(context_string(1, matching_rule.as_bytes()));
+                }
+                if let Some(ref attr) = *attr
+                {
+                    children.push(context_string(2, attr.as_bytes()));
+                }
+                children.push(context_string(3, value));
+                // dnAttributes is DEFAULT FALSE
+                if dn_attributes
+                {
+                    children.push(context_string(4, &types::encode_boolean(true)));
+                }
+
+                let class = common::Class::ContextSpecific(9);
+                let pl = common::Payload::Constructed(children);
+
+                common::construct(class, pl)
+            },
+        }
+    }
+
+    /// Parse a string representation of a filter as defined by RFC4515
+    ///
+    /// For example `(&(objectClass=user)(|(cn=bob)(cn=alice)))`. Assertion values may contain
+    /// `\XX` hex escapes, which is the only way to match a literal `*`, `(`, `)` or `\`.
+    /// Whitespace between the filters of `&` and `|` lists is ignored. Filters can't be nested
+    /// deeper than the decoder accepts, `ber::DEFAULT_MAX_DEPTH`.
+    pub fn parse(input: &str) -> Result<Filter>
+    {
+        let mut parser = Parser { input: input.as_bytes(), pos: 0, depth: 0 };
+
+        let filter = try!(parser.filter());
+        parser.skip_whitespace();
+
+        if parser.pos != parser.input.len()
+        {
+            return Err(parser.error("Trailing characters after filter"));
+        }
+
+        Ok(filter)
+    }
+
+    /// Decode a filter from its context-specific CHOICE tag
+    pub fn decode(tag: &Tag) -> Result<Filter>
+    {
+        let choice = match tag._type.class
+        {
+            common::Class::ContextSpecific(choice) => choice,
+            _ => return Err(LDAPError::Protocol),
+        };
+
+        match (choice, &tag._value)
+        {
+            (0, &common::Payload::Constructed(ref filters)) => Ok(Filter::And(try!(decode_all(filters)))),
+            (1, &common::Payload::Constructed(ref filters)) => Ok(Filter::Or(try!(decode_all(filters)))),
+            (2, &common::Payload::Constructed(ref filters)) if filters.len() == 1 =>
+                Ok(Filter::Not(Box::new(try!(Filter::decode(&filters[0]))))),
+            (3, &common::Payload::Constructed(ref ava)) =>
+            {
+                let (attr, value) = try!(decode_ava(ava));
+                Ok(Filter::Equality { attr: attr, value: value })
+            },
+            (4, &common::Payload::Constructed(ref children)) if children.len() == 2 =>
+            {
+                let attr = try!(string(&children[0]));
+                let substrings = match children[1]._value
+                {
+                    common::Payload::Constructed(ref substrings) if !substrings.is_empty() => substrings,
+                    _ => return Err(LDAPError::Protocol),
+                };
+
+                let mut initial = None;
+                let mut any = Vec::new();
+                let mut final_ = None;
+
+                for substring in substrings
+                {
+                    let value = try!(bytes(substring));
+                    match substring._type.class
+                    {
+                        common::Class::ContextSpecific(0) => initial = Some(value),
+                        common::Class::ContextSpecific(1) => any.push(value),
+                        common::Class::ContextSpecific(2) => final_ = Some(value),
+                        _ => return Err(LDAPError::Protocol),
+                    }
+                }
+
+                Ok(Filter::Substrings { attr: attr, initial: initial, any: any, final_: final_ })
+            },
+            (5, &common::Payload::Constructed(ref ava)) =>
+            {
+                let (attr, value) = try!(decode_ava(ava));
+                Ok(Filter::GreaterOrEqual { attr: attr, value: value })
+            },
+            (6, &common::Payload::Constructed(ref ava)) =>
+            {
+                let (attr, value) = try!(decode_ava(ava));
+                Ok(Filter::LessOrEqual { attr: attr, value: value })
+            },
+            (7, &common::Payload::Primitive(_)) => Ok(Filter::Present(try!(string(tag)))),
+            (8, &common::Payload::Constructed(ref ava)) =>
+            {
+                let (attr, value) = try!(decode_ava(ava));
+                Ok(Filter::ApproxMatch { attr: attr, value: value })
+            },
+            (9, &common::Payload::Constructed(ref children)) =>
+            {
+                let mut matching_rule = None;
+                let mut attr = None;
+                let mut value = None;
+                let mut dn_attributes = false;
+
+                for child in children
+                {
+                    match child._type.class
+                    {
+                        common::Class::ContextSpecific(1) => matching_rule = Some(try!(string(child))),
+                        common::Class::ContextSpecific(2) => attr = Some(try!(string(child))),
+                        common::Class::ContextSpecific(3) => value = Some(try!(bytes(child))),
+                        common::Class::ContextSpecific(4) => dn_attributes = try!(types::decode_boolean(&try!(bytes(child)))),
+                        _ => return Err(LDAPError::Protocol),
+                    }
+                }
+
+                match value
+                {
+                    Some(value) => Ok(Filter::ExtensibleMatch
+                    {
+                        matching_rule: matching_rule,
+                        attr: attr,
+                        value: value,
+                        dn_attributes: dn_attributes,
+                    }),
+                    None => Err(LDAPError::Protocol),
+                }
+            },
+            _ => Err(LDAPError::Protocol),
+        }
+    }
+}
+
+// Recursive descent parser for RFC4515 string filters
+struct Parser<'a>
+{
+    input: &'a [u8],
+    pos: usize,
+    // Filters currently open, bounded so nesting can't overflow the stack
+    depth: usize,
+}
+
+impl<'a> Parser<'a>
+{
+    fn error(&self, reason: &'static str) -> LDAPError
+    {
+        LDAPError::FilterParse { offset: self.pos, reason: reason }
+    }
+
+    fn peek(&self) -> Option<u8>
+    {
+        self.input.get(self.pos).cloned()
+    }
+
+    fn expect(&mut self, byte: u8, reason: &'static str) -> Result<()>
+    {
+        if self.peek() == Some(byte)
+        {
+            self.pos += 1;
+            Ok(())
+        }
+        else
+        {
+            Err(self.error(reason))
+        }
+    }
+
+    fn skip_whitespace(&mut self)
+    {
+        while self.peek() == Some(b' ')
+        {
+            self.pos += 1;
+        }
+    }
+
+    // filter = "(" filtercomp ")"
+    fn filter(&mut self) -> Result<Filter>
+    {
+        if self.depth == ber::DEFAULT_MAX_DEPTH
+        {
+            return Err(self.error("Filter nested too deeply"));
+        }
+
+        try!(self.expect(b'(', "Expected '('"));
+        self.depth += 1;
+
+        let filter = match self.peek()
+        {
+            Some(b'&') => { self.pos += 1; Filter::And(try!(self.filterlist())) },
+            Some(b'|') => { self.pos += 1; Filter::Or(try!(self.filterlist())) },
+            Some(b'!') =>
+            {
+                self.pos += 1;
+                self.skip_whitespace();
+                Filter::Not(Box::new(try!(self.filter())))
+            },
+            _ => try!(self.item()),
+        };
+
+        self.skip_whitespace();
+        try!(self.expect(b')', "Expected ')'"));
+        self.depth -= 1;
+
+        Ok(filter)
+    }
+
+    // filterlist = 1*filter
+    fn filterlist(&mut self) -> Result<Vec<Filter>>
+    {
+        let mut filters = Vec::new();
+
+        self.skip_whitespace();
+        while self.peek() == Some(b'(')
+        {
+            filters.push(try!(self.filter()));
+            self.skip_whitespace();
+        }
+
+        if filters.is_empty()
+        {
+            return Err(self.error("Expected at least one filter in list"));
+        }
+
+        Ok(filters)
+    }
+
+    // item = simple / present / substring / extensible
+    fn item(&mut self) -> Result<Filter>
+    {
+        // Only an extensible match may leave out the attribute
+        if self.peek() == Some(b':')
+        {
+            return self.extensible(None);
+        }
+
+        let attr = try!(self.attr());
+
+        let filtertype = match self.peek()
+        {
+            Some(b'=') => { self.pos += 1; b'=' },
+            Some(b':') => return self.extensible(Some(attr)),
+            Some(t @ b'~') | Some(t @ b'>') | Some(t @ b'<') =>
+            {
+                self.pos += 1;
+                try!(self.expect(b'=', "Expected '=' after filter type"));
+                t
+            },
+            _ => return Err(self.error("Expected filter type")),
+        };
+
+        let start = self.pos;
+        let parts = try!(self.value());
+
+        if filtertype != b'='
+        {
+            if parts.len() > 1
+            {
+                self.pos = start;
+                return Err(self.error("Wildcards are only allowed in equality filters"));
+            }
+
+            let value = parts.into_iter().next().unwrap();
+            return Ok(match filtertype
+            {
+                b'~' => Filter::ApproxMatch { attr: attr, value: value },
+                b'>' => Filter::GreaterOrEqual { attr: attr, value: value },
+                _ => Filter::LessOrEqual { attr: attr, value: value },
+            });
+        }
+
+        match parts.len()
+        {
+            1 => Ok(Filter::Equality { attr: attr, value: parts.into_iter().next().unwrap() }),
+            2 if parts[0].is_empty() && parts[1].is_empty() => Ok(Filter::Present(attr)),
+            // SubstringFilter requires SIZE (1..MAX), so "(cn=**)" has nothing to send
+            _ if parts.iter().all(|part| part.is_empty()) =>
+            {
+                self.pos = start;
+                Err(self.error("Substring filters need at least one substring"))
+            },
+            _ =>
+            {
+                let last = parts.len() - 1;
+                let mut initial = None;
+                let mut any = Vec::new();
+                let mut final_ = None;
+
+                for (i, part) in parts.into_iter().enumerate()
+                {
+                    if part.is_empty()
+                    {
+                        continue;
+                    }
+
+                    if i == 0
+                    {
+                        initial = Some(part);
+                    }
+                    else if i == last
+                    {
+                        final_ = Some(part);
+                    }
+                    else
+                    {
+                        any.push(part);
+                    }
+                }
+
+                Ok(Filter::Substrings { attr: attr, initial: initial, any: any, final_: final_ })
+            },
+        }
+    }
+
+    // extensible = ( attr [":dn"] [":" matchingrule] ":=" assertionvalue )
+    //              / ( [":dn"] ":" matchingrule ":=" assertionvalue )
+    fn extensible(&mut self, attr: Option<String>) -> Result<Filter>
+    {
+        let mut dn_attributes = false;
+        let mut matching_rule = None;
+
+        while matching_rule.is_none()
+        {
+            try!(self.expect(b':', "Expected ':' in extensible match"));
+            if self.peek() == Some(b'=')
+            {
+                break;
+            }
+
+            let start = self.pos;
+            let name = try!(self.attr());
+            if !dn_attributes && name.eq_ignore_ascii_case("dn")
+            {
+                dn_attributes = true;
+            }
+            else
+            {
+                matching_rule = Some(name);
+                if self.peek() != Some(b':')
+                {
+                    self.pos = start;
+                    return Err(self.error("Expected ':=' after matching rule"));
+                }
+                self.pos += 1;
+            }
+        }
+
+        if attr.is_none() && matching_rule.is_none()
+        {
+            return Err(self.error("Extensible match needs an attribute or matching rule"));
+        }
+        try!(self.expect(b'=', "Expected ':=' in extensible match"));
+
+        let start = self.pos;
+        let mut parts = try!(self.value());
+        if parts.len() > 1
+        {
+            self.pos = start;
+            return Err(self.error("Wildcards are only allowed in equality filters"));
+        }
+
+        Ok(Filter::ExtensibleMatch
+        {
+            matching_rule: matching_rule,
+            attr: attr,
+            value: parts.pop().unwrap(),
+            dn_attributes: dn_attributes,
+        })
+    }
+
+    // attr = AttributeDescription, an OID or name plus options
+    fn attr(&mut self) -> Result<String>
+    {
+        let start = self.pos;
+
+        while let Some(byte) = self.peek()
+        {
+            match byte
+            {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b';' | b'.' => self.pos += 1,
+                _ => break,
+            }
+        }
+
+        if start == self.pos
+        {
+            return Err(self.error("Expected attribute description"));
+        }
+
+        // Only ASCII was accepted above
+        Ok(String::from_utf8(self.input[start..self.pos].to_vec()).unwrap())
+    }
+
+    // The assertion value, split on the unescaped '*' a substring filter uses
+    fn value(&mut self) -> Result<Vec<Vec<u8>>>
+    {
+        let mut parts = vec![Vec::new()];
+
+        while let Some(byte) = self.peek()
+        {
+            match byte
+            {
+                b')' => break,
+                b'(' | b'\0' => return Err(self.error("Unescaped special character in value")),
+                b'*' =>
+                {
+                    self.pos += 1;
+                    parts.push(Vec::new());
+                },
+                b'\\' =>
+                {
+                    let escaped = match (self.input.get(self.pos + 1).and_then(hex), self.input.get(self.pos + 2).and_then(hex))
+                    {
+                        (Some(high), Some(low)) => high << 4 | low,
+                        _ => return Err(self.error("Expected two hex digits after '\\'")),
+                    };
+
+                    parts.last_mut().unwrap().push(escaped);
+                    self.pos += 3;
+                },
+                _ =>
+                {
+                    parts.last_mut().unwrap().push(byte);
+                    self.pos += 1;
+                },
+            }
+        }
+
+        Ok(parts)
+    }
+}
+
+fn hex(byte: &u8) -> Option<u8>
+{
+    match *byte
+    {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn decode_all(tags: &[Tag]) -> Result<Vec<Filter>>
+{
+    let mut filters = Vec::with_capacity(tags.len());
+    for tag in tags
+    {
+        filters.push(try!(Filter::decode(tag)));
+    }
+
+    Ok(filters)
+}
+
+fn decode_ava(tags: &[Tag]) -> Result<(String, Vec<u8>)>
+{
+    if tags.len() != 2
+    {
+        return Err(LDAPError::Protocol);
+    }
+
+    Ok((try!(string(&tags[0])), try!(bytes(&tags[1]))))
+}
+
+fn bytes(tag: &Tag) -> Result<Vec<u8>>
+{
+    match tag._value
+    {
+        common::Payload::Primitive(ref bytes) => Ok(bytes.clone()),
+        common::Payload::Constructed(_) => Err(LDAPError::Protocol),
+    }
+}
+
+fn string(tag: &Tag) -> Result<String>
+{
+    String::from_utf8(try!(bytes(tag))).map_err(|_| LDAPError::Protocol)
+}
+
+// SET OF Filter, implicitly tagged as the given filter choice
+fn set(choice: i64, filters: &[Filter]) -> Tag
+{
+    let class = common::Class::ContextSpecific(choice);
+    let pl = common::Payload::Constructed(filters.iter().map(Filter::encode).collect());
+
+    common::construct(class, pl)
+}
+
+/// Encode an AttributeValueAssertion, the SEQUENCE of attributeDesc and assertionValue
+///
+/// The value is taken as is, so binary values need no escaping.
+pub fn encode_ava(attr: &str, value: &[u8]) -> Tag
+{
+    types::sequence(vec![types::octet_string(attr.as_bytes()), types::octet_string(value)])
+}
+
+// AttributeValueAssertion, implicitly tagged as the given filter choice
+fn ava(choice: i64, attr: &str, value: &[u8]) -> Tag
+{
+    let class = common::Class::ContextSpecific(choice);
+    let pl = encode_ava(attr, value)._value;
+
+    common::construct(class, pl)
+}
+
+fn context_string(number: i64, value: &[u8]) -> Tag
+{
+    let class = common::Class::ContextSpecific(number);
+    let pl = common::Payload::Primitive(value.to_vec());
+
+    common::construct(class, pl)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{encode_ava, Filter};
+
+    use ber;
+    use error::LDAPError;
+
+    #[test]
+    fn encode_equality()
+    {
+        let filter = Filter::Equality { attr: "cn".to_string(), value: b"alice".to_vec() };
+
+        let tag = filter.encode();
+        let bytes = ber::encode(tag, 1).unwrap();
+
+        assert!(bytes.ends_with(&[0xa3, 0x0b, 0x04, 0x02, 0x63, 0x6e, 0x04, 0x05, 0x61, 0x6c, 0x69, 0x63, 0x65]));
+    }
+
+    #[test]
+    fn encode_ava_layout()
+    {
+        let bytes = ber::encode_tag(&encode_ava("uid", b"bob")).unwrap();
+
+        assert_eq!(bytes, vec![0x30, 0x0a, 0x04, 0x03, 0x75, 0x69, 0x64, 0x04, 0x03, 0x62, 0x6f, 0x62]);
+    }
+
+    #[test]
+    fn encode_present()
+    {
+        let tag = Filter::Present("cn".to_string()).encode();
+        let bytes = ber::encode(tag, 1).unwrap();
+
+        assert!(bytes.ends_with(&[0x87, 0x02, 0x63, 0x6e]));
+    }
+
+    #[test]
+    fn encode_substrings()
+    {
+        // (cn=a*b*c*d), the substrings in order with initial [0], any [1] and final [2]
+        let filter = Filter::parse("(cn=a*b*c*d)").unwrap();
+        assert_eq!(filter, Filter::Substrings
+        {
+            attr: "cn".to_string(),
+            initial: Some(b"a".to_vec()),
+            any: vec![b"b".to_vec(), b"c".to_vec()],
+            final_: Some(b"d".to_vec()),
+        });
+
+        let bytes = ber::encode(filter.encode(), 1).unwrap();
+        assert!(bytes.ends_with(&[
+            0xa4, 0x12, 0x04, 0x02, 0x63, 0x6e, 0x30, 0x0c, 0x80, 0x01, 0x61, 0x81, 0x01, 0x62, 0x81, 0x01,
+            0x63, 0x82, 0x01, 0x64,
+        ]));
+
+        // (cn=*b*) has neither initial nor final
+        let bytes = ber::encode(Filter::parse("(cn=*b*)").unwrap().encode(), 1).unwrap();
+        assert!(bytes.ends_with(&[0xa4, 0x09, 0x04, 0x02, 0x63, 0x6e, 0x30, 0x03, 0x81, 0x01, 0x62]));
+    }
+
+    #[test]
+    fn roundtrip_nested_and()
+    {
+        // (&(objectClass=person)(cn=alice))
+        let filter = Filter::And(vec![
+            Filter::Equality { attr: "objectClass".to_string(), value: b"person".to_vec() },
+            Filter::Equality { attr: "cn".to_string(), value: b"alice".to_vec() },
+        ]);
+
+        let bytes = ber::encode(filter.encode(), 1).unwrap();
+        assert!(bytes.ends_with(&[
+            0xa0, 0x24,
+            0xa3, 0x15, 0x04, 0x0b, 0x6f, 0x62, 0x6a, 0x65, 0x63, 0x74, 0x43, 0x6c, 0x61, 0x73, 0x73,
+            0x04, 0x06, 0x70, 0x65, 0x72, 0x73, 0x6f, 0x6e,
+            0xa3, 0x0b, 0x04, 0x02, 0x63, 0x6e, 0x04, 0x05, 0x61, 0x6c, 0x69, 0x63, 0x65,
+        ]));
+
+        assert_eq!(Filter::decode(&filter.encode()).unwrap(), filter);
+    }
+
+    #[test]
+    fn roundtrip_all_choices()
+    {
+        let filter = Filter::Or(vec![
+            Filter::Not(Box::new(Filter::Present("mail".to_string()))),
+            Filter::Substrings
+            {
+                attr: "cn".to_string(),
+                initial: Some(b"a".to_vec()),
+                any: vec![b"b".to_vec(), b"c".to_vec()],
+                final_: Some(b"d".to_vec()),
+            },
+            Filter::GreaterOrEqual { attr: "uidNumber".to_string(), value: b"1000".to_vec() },
+            Filter::LessOrEqual { attr: "uidNumber".to_string(), value: b"2000".to_vec() },
+            Filter::ApproxMatch { attr: "sn".to_string(), value: b"smith".to_vec() },
+        ]);
+
+        // Through the wire format and back
+        let bytes = ber::encode(filter.encode(), 1).unwrap();
+        let envelope = ber::decode(&bytes).unwrap();
+        let tag = match envelope._value
+        {
+            ber::common::Payload::Constructed(mut children) => children.pop().unwrap(),
+            ber::common::Payload::Primitive(_) => panic!("Expected an envelope"),
+        };
+
+        assert_eq!(Filter::decode(&tag).unwrap(), filter);
+    }
+
+    #[test]
+    fn decode_rejects_unknown_choice()
+    {
+        let tag = ber::common::construct(ber::common::Class::ContextSpecific(12),
+                                         ber::common::Payload::Primitive(vec![]));
+
+        assert!(Filter::decode(&tag).is_err());
+    }
+
+    #[test]
+    fn decode_rejects_empty_substrings()
+    {
+        let tag = ber::common::construct(ber::common::Class::ContextSpecific(4),
+            ber::common::Payload::Constructed(vec![
+                ber::common::construct(ber::common::Class::Universal(ber::common::UniversalTypes::OctetString),
+                                       ber::common::Payload::Primitive(b"cn".to_vec())),
+                ber::common::construct(ber::common::Class::Universal(ber::common::UniversalTypes::Sequence),
+                                       ber::common::Payload::Constructed(vec![])),
+            ]));
+
+        assert!(Filter::decode(&tag).is_err());
+    }
+
+    #[test]
+    fn parse_nested()
+    {
+        let filter = Filter::parse("(&(objectClass=user)(|(cn=bob)(cn=alice)))").unwrap();
+
+        assert_eq!(filter, Filter::And(vec![
+            Filter::Equality { attr: "objectClass".to_string(), value: b"user".to_vec() },
+            Filter::Or(vec![
+                Filter::Equality { attr: "cn".to_string(), value: b"bob".to_vec() },
+                Filter::Equality { attr: "cn".to_string(), value: b"alice".to_vec() },
+            ]),
+        ]));
+    }
+
+    #[test]
+    fn parse_whitespace_between_filters()
+    {
+        let filter = Filter::parse("(& (objectClass=person) (| (cn=Username) (SAMAccountName=Username)))").unwrap();
+
+        match filter
+        {
+            Filter::And(ref filters) => assert_eq!(filters.len(), 2),
+            _ => panic!("Expected an and filter"),
+        }
+    }
+
+    #[test]
+    fn parse_item_types()
+    {
+        assert_eq!(Filter::parse("(cn=*)").unwrap(), Filter::Present("cn".to_string()));
+        assert_eq!(Filter::parse("(!(cn=*))").unwrap(), Filter::Not(Box::new(Filter::Present("cn".to_string()))));
+        assert_eq!(Filter::parse("(uidNumber>=1000)").unwrap(),
+                   Filter::GreaterOrEqual { attr: "uidNumber".to_string(), value: b"1000".to_vec() });
+        assert_eq!(Filter::parse("(uidNumber<=1000)").unwrap(),
+                   Filter::LessOrEqual { attr: "uidNumber".to_string(), value: b"1000".to_vec() });
+        assert_eq!(Filter::parse("(sn~=smith)").unwrap(),
+                   Filter::ApproxMatch { attr: "sn".to_string(), value: b"smith".to_vec() });
+    }
+
+    #[test]
+    fn parse_substrings()
+    {
+        assert_eq!(Filter::parse("(cn=a*b*c)").unwrap(), Filter::Substrings
+        {
+            attr: "cn".to_string(),
+            initial: Some(b"a".to_vec()),
+            any: vec![b"b".to_vec()],
+            final_: Some(b"c".to_vec()),
+        });
+
+        assert_eq!(Filter::parse("(cn=*b*)").unwrap(), Filter::Substrings
+        {
+            attr: "cn".to_string(),
+            initial: None,
+            any: vec![b"b".to_vec()],
+            final_: None,
+        });
+    }
+
+    #[test]
+    fn extensible_match()
+    {
+        // Attribute only, (cn:dn:=Alice)
+        let filter = Filter::parse("(cn:dn:=Alice)").unwrap();
+        assert_eq!(filter, Filter::ExtensibleMatch
+        {
+            matching_rule: None,
+            attr: Some("cn".to_string()),
+            value: b"Alice".to_vec(),
+            dn_attributes: true,
+        });
+        let bytes = ber::encode(filter.encode(), 1).unwrap();
+        assert!(bytes.ends_with(&[
+            0xa9, 0x0e, 0x82, 0x02, 0x63, 0x6e, 0x83, 0x05, 0x41, 0x6c, 0x69, 0x63, 0x65, 0x84, 0x01, 0xff,
+        ]));
+        assert_eq!(Filter::decode(&filter.encode()).unwrap(), filter);
+
+        // Matching rule only, (:1.2.3:=foo)
+        let filter = Filter::parse("(:1.2.3:=foo)").unwrap();
+        assert_eq!(filter, Filter::ExtensibleMatch
+        {
+            matching_rule: Some("1.2.3".to_string()),
+            attr: None,
+            value: b"foo".to_vec(),
+            dn_attributes: false,
+        });
+        let bytes = ber::encode(filter.encode(), 1).unwrap();
+        assert!(bytes.ends_with(&[0xa9, 0x0c, 0x81, 0x05, 0x31, 0x2e, 0x32, 0x2e, 0x33, 0x83, 0x03, 0x66, 0x6f, 0x6f]));
+        assert_eq!(Filter::decode(&filter.encode()).unwrap(), filter);
+
+        assert_eq!(Filter::parse("(cn:caseExactMatch:=Alice)").unwrap(), Filter::ExtensibleMatch
+        {
+            matching_rule: Some("caseExactMatch".to_string()),
+            attr: Some("cn".to_string()),
+            value: b"Alice".to_vec(),
+            dn_attributes: false,
+        });
+        assert!(Filter::parse("(:dn:=foo)").is_err());
+        assert!(Filter::parse("(:=foo)").is_err());
+    }
+
+    #[test]
+    fn parse_escapes()
+    {
+        assert_eq!(Filter::parse("(cn=\\2a\\28x\\29\\5c)").unwrap(),
+                   Filter::Equality { attr: "cn".to_string(), value: b"*(x)\\".to_vec() });
+
+        // Escapes can produce values that aren't UTF-8
+        assert_eq!(Filter::parse("(objectGUID=\\ff\\00)").unwrap(),
+                   Filter::Equality { attr: "objectGUID".to_string(), value: vec![0xff, 0x00] });
+    }
+
+    #[test]
+    fn parse_errors_report_offset()
+    {
+        let offset = |input| match Filter::parse(input)
+        {
+            Err(LDAPError::FilterParse { offset, .. }) => offset,
+            other => panic!("Expected a parse error, got {:?}", other),
+        };
+
+        assert_eq!(offset("cn=alice"), 0);
+        assert_eq!(offset("(cn=alice"), 9);
+        assert_eq!(offset("(&)"), 2);
+        assert_eq!(offset("(cn=\\zz)"), 4);
+        assert_eq!(offset("(=alice)"), 1);
+        assert_eq!(offset("(cn>=a*)"), 5);
+        assert_eq!(offset("(cn=**)"), 4);
+        assert_eq!(offset("(cn=alice))"), 10);
+    }
+
+    #[test]
+    fn parse_rejects_deep_nesting()
+    {
+        let nested = |depth| {
+            let mut input = "(!".repeat(depth - 1);
+            input.push_str("(cn=alice)");
+            input.push_str(&")".repeat(depth - 1));
+            input
+        };
+
+        assert!(Filter::parse(&nested(ber::DEFAULT_MAX_DEPTH)).is_ok());
+
+        match Filter::parse(&nested(ber::DEFAULT_MAX_DEPTH + 1))
+        {
+            Err(LDAPError::FilterParse { offset, .. }) => assert_eq!(offset, 2 * ber::DEFAULT_MAX_DEPTH),
+            x => panic!("Expected FilterParse, got {:?}", x),
+        }
+
+        // Far too deep to recurse into without the limit
+        match Filter::parse(&"(!".repeat(100000))
+        {
+            Err(LDAPError::FilterParse { .. }) => {},
+            x => panic!("Expected FilterParse, got {:?}", x),
+        }
+    }
+}
@@ -1,7 +1,37 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate alloc;
 extern crate byteorder;
+#[cfg(feature = "chrono")]
+extern crate chrono;
+
+// Without std the crate only needs alloc. This stands in for the parts of std it uses.
+#[cfg(not(feature = "std"))]
+mod std
+{
+    pub use core::*;
+    pub use alloc::{boxed, fmt, string, vec};
+}
+
+// What the std prelude would provide
+mod prelude
+{
+    pub use std::boxed::Box;
+    pub use std::string::{String, ToString};
+    pub use std::vec::Vec;
+}
 
 pub mod ber;
+pub mod control;
+pub mod dn;
 pub mod error;
+pub mod filter;
+pub mod result;
+pub mod schema;
+pub mod search;
+pub mod url;
 
 use ber::common;
 use ber::types::ASNType;
@@ -23,6 +53,12 @@ pub fn construct_envelope(msgid: i32, protocolOp: common::Tag, controls: Option<
     plvec.into_ber_universal()
 }
 
+/// Wrap `op` into an LDAPMessage with the given message ID, appending `controls` if any
+pub fn wrap_message(msgid: i32, op: common::Tag, controls: &[control::Control]) -> common::Tag
+{
+    construct_envelope(msgid, op, control::build_controls(controls))
+}
+
 pub fn deconstruct_envelope(envelope: common::Tag) -> Result<(i32, common::Tag, Option<common::Tag>)>
 {
     let common::Tag { _value, .. } = envelope;
@@ -31,11 +67,12 @@ pub fn deconstruct_envelope(envelope: common::Tag) -> Result<(i32, common::Tag,
         common::Payload::Primitive(_) => { return Err(error::LDAPError::Protocol) },
     };
 
-    if tagvec.len() < 3 || tagvec.len() > 2 { return Err(error::LDAPError::Protocol) }
+    if tagvec.len() < 2 || tagvec.len() > 3 { return Err(error::LDAPError::Protocol) }
 
-    let mut msgidtag = tagvec.pop().unwrap();
-    let protocolOp = tagvec.pop().unwrap();
-    let controls = tagvec.pop();
+    let mut tags = tagvec.drain(..);
+    let mut msgidtag = tags.next().unwrap();
+    let protocolOp = tags.next().unwrap();
+    let controls = tags.next();
 
     let msgid = match i32::from_tag(&mut msgidtag) {
         Some(e) => e,
@@ -44,3 +81,44 @@ pub fn deconstruct_envelope(envelope: common::Tag) -> Result<(i32, common::Tag,
 
     Ok((msgid, protocolOp, controls))
 }
+
+#[cfg(test)]
+mod tests
+{
+    use ber::common;
+
+    #[test]
+    fn wrap_message_structure()
+    {
+        let op = {
+            let class = common::Class::Application(2);
+            let pl = common::Payload::Primitive(Vec::new());
+
+            common::construct(class, pl)
+        };
+
+        let envelope = super::wrap_message(5, op, &[]);
+
+        assert_eq!(envelope._type, common::Type {
+            class: common::Class::Universal(common::UniversalTypes::Sequence),
+            structure: common::Structure::Constructed,
+        });
+
+        let children = match envelope._value
+        {
+            common::Payload::Constructed(ref children) => children,
+            common::Payload::Primitive(_) => panic!("Expected a constructed envelope"),
+        };
+
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0]._type.class, common::Class::Universal(common::UniversalTypes::Integer));
+        assert_eq!(children[0]._value, common::Payload::Primitive(vec![5]));
+        assert_eq!(children[1]._type.class, common::Class::Application(2));
+        assert_eq!(envelope.size, 7);
+
+        let (msgid, op, controls) = super::deconstruct_envelope(envelope).unwrap();
+        assert_eq!(msgid, 5);
+        assert_eq!(op._type.class, common::Class::Application(2));
+        assert!(controls.is_none());
+    }
+}
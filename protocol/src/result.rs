@@ -1,62 +1,309 @@
+use ber::common::{self, Tag};
+use ber::types;
+use error::LDAPError;
+use Result;
+use prelude::*;
 
 // Base LDAPResult struct.
 // Functions should extend this struct as necessary
-struct LDAPResult
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LDAPResult
 {
-    result_code: LDAPResultCode,
-    matched_dn: String,
-    diagnostics_message: String,
-    referral: Option<String>,
+    pub result_code: ResultCode,
+    pub matched_dn: String,
+    pub diagnostic_message: String,
+    pub referral: Option<Vec<String>>,
+}
+
+impl LDAPResult
+{
+    /// Parse the LDAPResult components of a response's protocolOp.
+    ///
+    /// Returns the result and the remaining tags, which are specific to the operation.
+    pub fn from_tag(tag: Tag) -> Result<(LDAPResult, Vec<Tag>)>
+    {
+        let mut children = match tag._value
+        {
+            common::Payload::Constructed(children) => children,
+            common::Payload::Primitive(_) => return Err(LDAPError::Protocol),
+        };
+
+        // resultCode, matchedDN and diagnosticMessage are mandatory
+        if children.len() < 3
+        {
+            return Err(LDAPError::Protocol);
+        }
+
+        let mut rest = children.split_off(3);
+        let mut children = children.drain(..);
+
+        let result_code = match types::decode_enumerated(&children.next().unwrap())
+        {
+            Ok(code) => ResultCode::from_i64(code),
+            Err(_) => return Err(LDAPError::Protocol),
+        };
+        let matched_dn = try!(string_from_tag(children.next().unwrap()));
+        let diagnostic_message = try!(string_from_tag(children.next().unwrap()));
+
+        // The optional referral is always the first tag following
+        let has_referral = rest.first().map(|t| t._type.class == common::Class::ContextSpecific(3)).unwrap_or(false);
+        let referral = if has_referral
+        {
+            let urls = match rest.remove(0)._value
+            {
+                common::Payload::Constructed(urls) => urls,
+                common::Payload::Primitive(_) => return Err(LDAPError::Protocol),
+            };
+
+            let mut referral = Vec::with_capacity(urls.len());
+            for url in urls
+            {
+                referral.push(try!(string_from_tag(url)));
+            }
+            Some(referral)
+        }
+        else
+        {
+            None
+        };
+
+        Ok((LDAPResult
+        {
+            result_code: result_code,
+            matched_dn: matched_dn,
+            diagnostic_message: diagnostic_message,
+            referral: referral,
+        }, rest))
+    }
+}
+
+pub(crate) fn string_from_tag(tag: Tag) -> Result<String>
+{
+    match tag._value
+    {
+        common::Payload::Primitive(bytes) => String::from_utf8(bytes).map_err(|_| LDAPError::Protocol),
+        common::Payload::Constructed(_) => Err(LDAPError::Protocol),
+    }
 }
 
 // The names are taken literally from the RFC4511.
 #[allow(non_camel_case_types)]
-enum LDAPResultCode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultCode
 {
-    success = 0,
-    operationsError = 1,
-    protocolError = 2,
-    timeLimitExceeded = 3,
-    sizeLimitExceeded = 4,
-    compareFalse = 5,
-    compareTrue = 6,
-    authMethodNotSupported = 7,
-    strongerAuthRequired = 8,
+    success,
+    operationsError,
+    protocolError,
+    timeLimitExceeded,
+    sizeLimitExceeded,
+    compareFalse,
+    compareTrue,
+    authMethodNotSupported,
+    strongerAuthRequired,
     // -- 9 reserved --
-    referral = 10,
-    adminLimitExceeded = 11,
-    unavailableCriticalExtension = 12,
-    confidentialityRequired = 13,
-    saslBindInProgress = 14,
-    noSuchAttribute = 16,
-    undefinedAttributeType = 17,
-    inappropriateMatching = 18,
-    constraintViolation = 19,
-    attributeOrValueExists = 20,
-    invalidAttributeSyntax = 21,
+    referral,
+    adminLimitExceeded,
+    unavailableCriticalExtension,
+    confidentialityRequired,
+    saslBindInProgress,
+    noSuchAttribute,
+    undefinedAttributeType,
+    inappropriateMatching,
+    constraintViolation,
+    attributeOrValueExists,
+    invalidAttributeSyntax,
     // -- 22-31 unused --
-    noSuchObject = 32,
-    aliasProblem = 33,
-    invalidDNSyntax = 34,
+    noSuchObject,
+    aliasProblem,
+    invalidDNSyntax,
     // -- 35 reserved for undefined isLeaf --
-    aliasDereferencingProblem = 36,
+    aliasDereferencingProblem,
     // -- 37-47 unused --
-    inappropriateAuthentication = 48,
-    invalidCredentials = 49,
-    insufficientAccessRights = 50,
-    busy = 51,
-    unavailable = 52,
-    unwillingToPerform = 53,
-    loopDetect = 54,
+    inappropriateAuthentication,
+    invalidCredentials,
+    insufficientAccessRights,
+    busy,
+    unavailable,
+    unwillingToPerform,
+    loopDetect,
     // -- 55-63 unused --
-    namingViolation = 64,
-    objectClassViolation = 65,
-    notAllowedOnNonLeaf = 66,
-    notAllowedOnRDN = 67,
-    entryAlreadyExists = 68,
-    objectClassModsProhibited = 69,
+    namingViolation,
+    objectClassViolation,
+    notAllowedOnNonLeaf,
+    notAllowedOnRDN,
+    entryAlreadyExists,
+    objectClassModsProhibited,
     // -- 70 reserved for CLDAP --
-    affectsMultipleDSAs = 71,
+    affectsMultipleDSAs,
     // -- 72-79 unused --
-    other = 80,
+    other,
+    /// The filter of an assertion control didn't match (RFC4528)
+    assertionFailed,
+    /// The bound identity may not act on behalf of the one in a proxied authorization
+    /// control (RFC4370)
+    authorizationDenied,
+    /// Any code not defined by RFC4511 (other is the RFC's own code 80)
+    Other(i64),
+}
+
+impl ResultCode
+{
+    /// Map the numeric resultCode of a response to its name
+    pub fn from_i64(code: i64) -> ResultCode
+    {
+        match code
+        {
+            0   => ResultCode::success,
+            1   => ResultCode::operationsError,
+            2   => ResultCode::protocolError,
+            3   => ResultCode::timeLimitExceeded,
+            4   => ResultCode::sizeLimitExceeded,
+            5   => ResultCode::compareFalse,
+            6   => ResultCode::compareTrue,
+            7   => ResultCode::authMethodNotSupported,
+            8   => ResultCode::strongerAuthRequired,
+            10  => ResultCode::referral,
+            11  => ResultCode::adminLimitExceeded,
+            12  => ResultCode::unavailableCriticalExtension,
+            13  => ResultCode::confidentialityRequired,
+            14  => ResultCode::saslBindInProgress,
+            16  => ResultCode::noSuchAttribute,
+            17  => ResultCode::undefinedAttributeType,
+            18  => ResultCode::inappropriateMatching,
+            19  => ResultCode::constraintViolation,
+            20  => ResultCode::attributeOrValueExists,
+            21  => ResultCode::invalidAttributeSyntax,
+            32  => ResultCode::noSuchObject,
+            33  => ResultCode::aliasProblem,
+            34  => ResultCode::invalidDNSyntax,
+            36  => ResultCode::aliasDereferencingProblem,
+            48  => ResultCode::inappropriateAuthentication,
+            49  => ResultCode::invalidCredentials,
+            50  => ResultCode::insufficientAccessRights,
+            51  => ResultCode::busy,
+            52  => ResultCode::unavailable,
+            53  => ResultCode::unwillingToPerform,
+            54  => ResultCode::loopDetect,
+            64  => ResultCode::namingViolation,
+            65  => ResultCode::objectClassViolation,
+            66  => ResultCode::notAllowedOnNonLeaf,
+            67  => ResultCode::notAllowedOnRDN,
+            68  => ResultCode::entryAlreadyExists,
+            69  => ResultCode::objectClassModsProhibited,
+            71  => ResultCode::affectsMultipleDSAs,
+            80  => ResultCode::other,
+            122 => ResultCode::assertionFailed,
+            123 => ResultCode::authorizationDenied,
+            _   => ResultCode::Other(code),
+        }
+    }
+
+    /// Numeric resultCode as sent on the wire
+    pub fn to_i64(&self) -> i64
+    {
+        match *self
+        {
+            ResultCode::success                      => 0,
+            ResultCode::operationsError              => 1,
+            ResultCode::protocolError                => 2,
+            ResultCode::timeLimitExceeded            => 3,
+            ResultCode::sizeLimitExceeded            => 4,
+            ResultCode::compareFalse                 => 5,
+            ResultCode::compareTrue                  => 6,
+            ResultCode::authMethodNotSupported       => 7,
+            ResultCode::strongerAuthRequired         => 8,
+            ResultCode::referral                     => 10,
+            ResultCode::adminLimitExceeded           => 11,
+            ResultCode::unavailableCriticalExtension => 12,
+            ResultCode::confidentialityRequired      => 13,
+            ResultCode::saslBindInProgress           => 14,
+            ResultCode::noSuchAttribute              => 16,
+            ResultCode::undefinedAttributeType       => 17,
+            ResultCode::inappropriateMatching        => 18,
+            ResultCode::constraintViolation          => 19,
+            ResultCode::attributeOrValueExists       => 20,
+            ResultCode::invalidAttributeSyntax       => 21,
+            ResultCode::noSuchObject                 => 32,
+            ResultCode::aliasProblem                 => 33,
+            ResultCode::invalidDNSyntax              => 34,
+            ResultCode::aliasDereferencingProblem    => 36,
+            ResultCode::inappropriateAuthentication  => 48,
+            ResultCode::invalidCredentials           => 49,
+            ResultCode::insufficientAccessRights     => 50,
+            ResultCode::busy                         => 51,
+            ResultCode::unavailable                  => 52,
+            ResultCode::unwillingToPerform           => 53,
+            ResultCode::loopDetect                   => 54,
+            ResultCode::namingViolation              => 64,
+            ResultCode::objectClassViolation         => 65,
+            ResultCode::notAllowedOnNonLeaf          => 66,
+            ResultCode::notAllowedOnRDN              => 67,
+            ResultCode::entryAlreadyExists           => 68,
+            ResultCode::objectClassModsProhibited    => 69,
+            ResultCode::affectsMultipleDSAs          => 71,
+            ResultCode::other                        => 80,
+            ResultCode::assertionFailed              => 122,
+            ResultCode::authorizationDenied          => 123,
+            ResultCode::Other(code) => code,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{LDAPResult, ResultCode};
+    use ber;
+
+    #[test]
+    fn result_code_mapping()
+    {
+        assert_eq!(ResultCode::from_i64(0), ResultCode::success);
+        assert_eq!(ResultCode::from_i64(10), ResultCode::referral);
+        assert_eq!(ResultCode::from_i64(32), ResultCode::noSuchObject);
+        assert_eq!(ResultCode::from_i64(49), ResultCode::invalidCredentials);
+        assert_eq!(ResultCode::from_i64(80), ResultCode::other);
+        assert_eq!(ResultCode::from_i64(122), ResultCode::assertionFailed);
+        assert_eq!(ResultCode::assertionFailed.to_i64(), 122);
+        assert_eq!(ResultCode::from_i64(123), ResultCode::authorizationDenied);
+        assert_eq!(ResultCode::authorizationDenied.to_i64(), 123);
+
+        for code in 0..81
+        {
+            assert_eq!(ResultCode::from_i64(code).to_i64(), code);
+        }
+    }
+
+    #[test]
+    fn result_code_unknown()
+    {
+        // Reserved and unused codes are not mapped
+        assert_eq!(ResultCode::from_i64(9), ResultCode::Other(9));
+        assert_eq!(ResultCode::from_i64(4096), ResultCode::Other(4096));
+        assert_eq!(ResultCode::Other(4096).to_i64(), 4096);
+    }
+
+    #[test]
+    fn ldap_result_from_tag()
+    {
+        // SearchResultDone: noSuchObject, with matchedDN, message and a referral, followed
+        // by an operation specific [7]
+        let bytes = [
+            0x65, 0x2e, 0x0a, 0x01, 0x20, 0x04, 0x11, 0x64,
+            0x63, 0x3d, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c,
+            0x65, 0x2c, 0x64, 0x63, 0x3d, 0x63, 0x6f, 0x6d,
+            0x04, 0x02, 0x6e, 0x6f, 0xa3, 0x0f, 0x04, 0x0d,
+            0x6c, 0x64, 0x61, 0x70, 0x3a, 0x2f, 0x2f, 0x6f,
+            0x74, 0x68, 0x65, 0x72, 0x2f, 0x87, 0x01, 0xff,
+        ];
+        let tag = ber::decode(&bytes).unwrap();
+
+        let (result, rest) = LDAPResult::from_tag(tag).unwrap();
+
+        assert_eq!(result.result_code, ResultCode::noSuchObject);
+        assert_eq!(result.matched_dn, "dc=example,dc=com");
+        assert_eq!(result.diagnostic_message, "no");
+        assert_eq!(result.referral, Some(vec!["ldap://other/".to_string()]));
+        assert_eq!(rest.len(), 1);
+    }
 }
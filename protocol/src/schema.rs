@@ -0,0 +1,824 @@
+//! Schema definitions published in the subschema subentry (RFC4512)
+
+use error::LDAPError;
+use Result;
+use prelude::*;
+
+/// Syntaxes whose values are arbitrary bytes rather than UTF-8 strings
+const BINARY_SYNTAXES: &'static [&'static str] = &[
+    "1.3.6.1.4.1.1466.115.121.1.4",  // Audio
+    "1.3.6.1.4.1.1466.115.121.1.5",  // Binary
+    "1.3.6.1.4.1.1466.115.121.1.8",  // Certificate
+    "1.3.6.1.4.1.1466.115.121.1.9",  // Certificate List
+    "1.3.6.1.4.1.1466.115.121.1.10", // Certificate Pair
+    "1.3.6.1.4.1.1466.115.121.1.23", // Fax
+    "1.3.6.1.4.1.1466.115.121.1.28", // JPEG
+    "1.3.6.1.4.1.1466.115.121.1.40", // Octet String
+    "1.3.6.1.4.1.1466.115.121.1.49", // Supported Algorithm
+];
+
+/// Attributes commonly holding binary values, for when the schema isn't at hand
+const BINARY_ATTRIBUTES: &'static [&'static str] = &[
+    "audio", "authorityRevocationList", "cACertificate", "certificateRevocationList", "crossCertificatePair",
+    "deltaRevocationList", "jpegPhoto", "objectGUID", "objectSid", "photo", "thumbnailPhoto", "userCertificate",
+    "userPKCS12", "userSMIMECertificate",
+];
+
+/// OID of extensibleObject, which allows any attribute (RFC4512 4.3)
+const EXTENSIBLE_OBJECT_OID: &'static str = "1.3.6.1.4.1.1466.101.120.111";
+
+/// Keywords of definitions that aren't followed by a value
+const FLAGS: &'static [&'static str] = &[
+    "OBSOLETE", "SINGLE-VALUE", "COLLECTIVE", "NO-USER-MODIFICATION", "ABSTRACT", "STRUCTURAL", "AUXILIARY",
+];
+
+/// The definitions of a subschema subentry
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Schema
+{
+    /// Values of attributeTypes
+    pub attribute_types: Vec<AttributeType>,
+    /// Values of objectClasses
+    pub object_classes: Vec<ObjectClass>,
+    /// Values of matchingRules
+    pub matching_rules: Vec<MatchingRule>,
+}
+
+/// What an attribute is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Usage
+{
+    UserApplications,
+    DirectoryOperation,
+    DistributedOperation,
+    DSAOperation,
+}
+
+/// An attribute type definition (RFC4512 4.1.2)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeType
+{
+    pub oid: String,
+    /// Short names, the first one is the usual one
+    pub names: Vec<String>,
+    pub desc: Option<String>,
+    pub obsolete: bool,
+    /// Attribute type this one is derived from, taking its syntax and matching rules if not given
+    pub sup: Option<String>,
+    pub equality: Option<String>,
+    pub ordering: Option<String>,
+    pub substr: Option<String>,
+    /// OID of the syntax
+    pub syntax: Option<String>,
+    /// Suggested maximum length of values, given in braces after the syntax
+    pub syntax_len: Option<u32>,
+    pub single_value: bool,
+    pub collective: bool,
+    pub no_user_modification: bool,
+    pub usage: Usage,
+    /// `X-` extensions with their values
+    pub extensions: Vec<(String, Vec<String>)>,
+}
+
+/// Kind of an object class
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectClassKind
+{
+    Abstract,
+    Structural,
+    Auxiliary,
+}
+
+/// An object class definition (RFC4512 4.1.1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectClass
+{
+    pub oid: String,
+    pub names: Vec<String>,
+    pub desc: Option<String>,
+    pub obsolete: bool,
+    /// Superclasses
+    pub sup: Vec<String>,
+    pub kind: ObjectClassKind,
+    /// Attributes entries of this class need to have
+    pub must: Vec<String>,
+    /// Attributes entries of this class may have
+    pub may: Vec<String>,
+    pub extensions: Vec<(String, Vec<String>)>,
+}
+
+/// A matching rule definition (RFC4512 4.1.3)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchingRule
+{
+    pub oid: String,
+    pub names: Vec<String>,
+    pub desc: Option<String>,
+    pub obsolete: bool,
+    /// OID of the syntax of assertion values
+    pub syntax: String,
+    pub extensions: Vec<(String, Vec<String>)>,
+}
+
+impl Schema
+{
+    /// Definition of the attribute type with the name or OID `attr`
+    ///
+    /// Names are compared case insensitively, and options like `;lang-de` are ignored.
+    pub fn attribute_type(&self, attr: &str) -> Option<&AttributeType>
+    {
+        let attr = attr.split(';').next().unwrap_or(attr);
+
+        self.attribute_types.iter().find(|at| at.oid == attr || at.names.iter().any(|name| name.eq_ignore_ascii_case(attr)))
+    }
+
+    /// Definition of the object class with the name or OID `class`
+    pub fn object_class(&self, class: &str) -> Option<&ObjectClass>
+    {
+        self.object_classes.iter().find(|oc| oc.oid == class || oc.names.iter().any(|name| name.eq_ignore_ascii_case(class)))
+    }
+
+    /// Whether values of `attr` are bytes instead of UTF-8 strings
+    ///
+    /// True for attribute descriptions with the `binary` option and types with one of the
+    /// binary syntaxes, which may be inherited from their superior types. Types not in the
+    /// schema are looked up with `is_known_binary`.
+    pub fn is_binary(&self, attr: &str) -> bool
+    {
+        if has_binary_option(attr)
+        {
+            return true;
+        }
+
+        let mut at = match self.attribute_type(attr)
+        {
+            Some(at) => Some(at),
+            None => return is_known_binary(attr),
+        };
+        // Bounded in case the superior types lead in circles
+        for _ in 0..self.attribute_types.len()
+        {
+            match at
+            {
+                Some(&AttributeType { syntax: Some(ref syntax), .. }) => return BINARY_SYNTAXES.contains(&&syntax[..]),
+                Some(&AttributeType { sup: Some(ref sup), .. }) => at = self.attribute_type(sup),
+                _ => return false,
+            }
+        }
+
+        false
+    }
+
+    /// Check that an entry of the object `classes` with the attribute descriptions `attrs`
+    /// fits them
+    ///
+    /// Every MUST attribute of the classes and their superclasses has to be given, and all
+    /// others have to be allowed by a MAY unless the entry is an extensibleObject. objectClass
+    /// and operational attributes are always allowed. The first problem found is returned as
+    /// `ContentRule` error naming the attribute or class at fault.
+    pub fn check_entry(&self, classes: &[&str], attrs: &[&str]) -> Result<()>
+    {
+        let (must, may, extensible) = try!(self.content_rules(classes));
+
+        if let Some(missing) = must.iter().find(|&&required| !attrs.iter().any(|attr| self.same_attribute(attr, required)))
+        {
+            return Err(content_rule(missing, "Required attribute is missing"));
+        }
+
+        if extensible
+        {
+            return Ok(());
+        }
+
+        match attrs.iter().find(|attr| !self.allowed(attr, &must, &may))
+        {
+            Some(attr) => Err(content_rule(attr, "Attribute not allowed by the object classes")),
+            None => Ok(()),
+        }
+    }
+
+    /// Check a modification of an entry of the object `classes` against them
+    ///
+    /// The attributes in `added` get values, so they have to be allowed the same way as for
+    /// `check_entry`. Those in `removed` lose all of their values, which no MUST attribute
+    /// may. Errors are `ContentRule` as well.
+    pub fn check_modification(&self, classes: &[&str], added: &[&str], removed: &[&str]) -> Result<()>
+    {
+        let (must, may, extensible) = try!(self.content_rules(classes));
+
+        if let Some(attr) = removed.iter().find(|attr| must.iter().any(|required| self.same_attribute(attr, required)))
+        {
+            return Err(content_rule(attr, "Required attribute would be removed"));
+        }
+
+        if extensible
+        {
+            return Ok(());
+        }
+
+        match added.iter().find(|attr| !self.allowed(attr, &must, &may))
+        {
+            Some(attr) => Err(content_rule(attr, "Attribute not allowed by the object classes")),
+            None => Ok(()),
+        }
+    }
+
+    // MUST and MAY attributes of `classes` and their superclasses, and whether they include
+    // extensibleObject
+    fn content_rules(&self, classes: &[&str]) -> Result<(Vec<&str>, Vec<&str>, bool)>
+    {
+        if classes.is_empty()
+        {
+            return Err(content_rule("objectClass", "Entry has no object class"));
+        }
+
+        let mut must = Vec::new();
+        let mut may = Vec::new();
+        let mut extensible = false;
+        let mut seen = Vec::new();
+        let mut todo = classes.to_vec();
+
+        while let Some(class) = todo.pop()
+        {
+            let oc = match self.object_class(class)
+            {
+                Some(oc) => oc,
+                None => return Err(content_rule(class, "Object class not in the schema")),
+            };
+            // Superclasses shared by several classes, or leading in circles
+            if seen.contains(&&oc.oid[..])
+            {
+                continue;
+            }
+            seen.push(&oc.oid[..]);
+
+            extensible |= oc.oid == EXTENSIBLE_OBJECT_OID;
+            must.extend(oc.must.iter().map(|attr| &attr[..]));
+            may.extend(oc.may.iter().map(|attr| &attr[..]));
+            todo.extend(oc.sup.iter().map(|sup| &sup[..]));
+        }
+
+        Ok((must, may, extensible))
+    }
+
+    // objectClass and operational attributes are allowed in any entry
+    fn allowed(&self, attr: &str, must: &[&str], may: &[&str]) -> bool
+    {
+        let operational = match self.attribute_type(attr)
+        {
+            Some(at) => at.usage != Usage::UserApplications,
+            None => false,
+        };
+
+        operational || self.same_attribute(attr, "objectClass") ||
+            must.iter().chain(may.iter()).any(|&allowed| self.same_attribute(attr, allowed))
+    }
+
+    // Whether the descriptions `a` and `b` name the same attribute type, by OID if both are known
+    fn same_attribute(&self, a: &str, b: &str) -> bool
+    {
+        match (self.attribute_type(a), self.attribute_type(b))
+        {
+            (Some(a), Some(b)) => a.oid == b.oid,
+            _ => a.split(';').next().unwrap_or(a).eq_ignore_ascii_case(b.split(';').next().unwrap_or(b)),
+        }
+    }
+}
+
+impl AttributeType
+{
+    /// Parse an AttributeTypeDescription, a value of attributeTypes
+    pub fn parse(input: &str) -> Result<AttributeType>
+    {
+        let def = try!(Definition::parse(input));
+
+        let (syntax, syntax_len) = match def.value_at("SYNTAX")
+        {
+            Some((syntax, offset)) => try!(split_syntax(syntax, offset)),
+            None => (None, None),
+        };
+
+        let usage = match def.value_at("USAGE")
+        {
+            None => Usage::UserApplications,
+            Some((usage, offset)) => match usage
+            {
+                "userApplications" => Usage::UserApplications,
+                "directoryOperation" => Usage::DirectoryOperation,
+                "distributedOperation" => Usage::DistributedOperation,
+                "dSAOperation" => Usage::DSAOperation,
+                _ => return Err(error(offset, "Unknown USAGE")),
+            },
+        };
+
+        Ok(AttributeType
+        {
+            names: def.values("NAME"),
+            desc: def.value("DESC"),
+            obsolete: def.flag("OBSOLETE"),
+            sup: def.value("SUP"),
+            equality: def.value("EQUALITY"),
+            ordering: def.value("ORDERING"),
+            substr: def.value("SUBSTR"),
+            syntax: syntax,
+            syntax_len: syntax_len,
+            single_value: def.flag("SINGLE-VALUE"),
+            collective: def.flag("COLLECTIVE"),
+            no_user_modification: def.flag("NO-USER-MODIFICATION"),
+            usage: usage,
+            extensions: def.extensions(),
+            oid: def.oid,
+        })
+    }
+}
+
+impl ObjectClass
+{
+    /// Parse an ObjectClassDescription, a value of objectClasses
+    pub fn parse(input: &str) -> Result<ObjectClass>
+    {
+        let def = try!(Definition::parse(input));
+
+        let kind = if def.flag("ABSTRACT")
+        {
+            ObjectClassKind::Abstract
+        }
+        else if def.flag("AUXILIARY")
+        {
+            ObjectClassKind::Auxiliary
+        }
+        else
+        {
+            ObjectClassKind::Structural
+        };
+
+        Ok(ObjectClass
+        {
+            names: def.values("NAME"),
+            desc: def.value("DESC"),
+            obsolete: def.flag("OBSOLETE"),
+            sup: def.values("SUP"),
+            kind: kind,
+            must: def.values("MUST"),
+            may: def.values("MAY"),
+            extensions: def.extensions(),
+            oid: def.oid,
+        })
+    }
+}
+
+impl MatchingRule
+{
+    /// Parse a MatchingRuleDescription, a value of matchingRules
+    pub fn parse(input: &str) -> Result<MatchingRule>
+    {
+        let def = try!(Definition::parse(input));
+
+        let syntax = match def.value("SYNTAX")
+        {
+            Some(syntax) => syntax,
+            None => return Err(error(input.len(), "Expected SYNTAX in matching rule")),
+        };
+
+        Ok(MatchingRule
+        {
+            names: def.values("NAME"),
+            desc: def.value("DESC"),
+            obsolete: def.flag("OBSOLETE"),
+            syntax: syntax,
+            extensions: def.extensions(),
+            oid: def.oid,
+        })
+    }
+}
+
+/// Whether values of `attr` are bytes, going by its name alone
+///
+/// True for attribute descriptions with the `binary` option like `userCertificate;binary`, and
+/// for well-known binary attributes like `jpegPhoto` or `objectGUID`.
+pub fn is_known_binary(attr: &str) -> bool
+{
+    let name = attr.split(';').next().unwrap_or(attr);
+
+    has_binary_option(attr) || BINARY_ATTRIBUTES.iter().any(|binary| binary.eq_ignore_ascii_case(name))
+}
+
+fn has_binary_option(attr: &str) -> bool
+{
+    attr.split(';').skip(1).any(|option| option.eq_ignore_ascii_case("binary"))
+}
+
+fn error(offset: usize, reason: &'static str) -> LDAPError
+{
+    LDAPError::SchemaParse { offset: offset, reason: reason }
+}
+
+fn content_rule(attr: &str, reason: &'static str) -> LDAPError
+{
+    LDAPError::ContentRule { attr: attr.to_string(), reason: reason }
+}
+
+// noidlen = numericoid [ LCURLY len RCURLY ]
+fn split_syntax(noidlen: &str, offset: usize) -> Result<(Option<String>, Option<u32>)>
+{
+    match noidlen.find('{')
+    {
+        None => Ok((Some(noidlen.to_string()), None)),
+        Some(curly) =>
+        {
+            let len = &noidlen[curly + 1..];
+            match (len.ends_with('}'), len.trim_end_matches('}').parse::<u32>())
+            {
+                (true, Ok(len)) => Ok((Some(noidlen[..curly].to_string()), Some(len))),
+                _ => Err(error(offset, "Expected length in braces after the syntax")),
+            }
+        },
+    }
+}
+
+// The parts all definitions share, before they are told apart by their keywords
+struct Definition
+{
+    oid: String,
+    fields: Vec<Field>,
+}
+
+struct Field
+{
+    keyword: String,
+    // Empty for flags and empty lists
+    values: Vec<String>,
+    offset: usize,
+}
+
+impl Definition
+{
+    fn parse(input: &str) -> Result<Definition>
+    {
+        Parser { input: input, pos: 0 }.definition()
+    }
+
+    fn field(&self, keyword: &str) -> Option<&Field>
+    {
+        self.fields.iter().find(|field| field.keyword == keyword)
+    }
+
+    fn flag(&self, keyword: &str) -> bool
+    {
+        self.field(keyword).is_some()
+    }
+
+    fn value(&self, keyword: &str) -> Option<String>
+    {
+        self.field(keyword).and_then(|field| field.values.first().cloned())
+    }
+
+    // The value with the offset of its keyword, for error reporting
+    fn value_at(&self, keyword: &str) -> Option<(&str, usize)>
+    {
+        self.field(keyword).and_then(|field| field.values.first().map(|value| (&value[..], field.offset)))
+    }
+
+    fn values(&self, keyword: &str) -> Vec<String>
+    {
+        self.field(keyword).map(|field| field.values.clone()).unwrap_or_default()
+    }
+
+    fn extensions(&self) -> Vec<(String, Vec<String>)>
+    {
+        self.fields.iter()
+            .filter(|field| field.keyword.starts_with("X-"))
+            .map(|field| (field.keyword.clone(), field.values.clone()))
+            .collect()
+    }
+}
+
+// Parser for the generic form of definitions: LPAREN numericoid *(SP keyword [SP value]) RPAREN
+struct Parser<'a>
+{
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a>
+{
+    fn peek(&self) -> Option<u8>
+    {
+        self.input.as_bytes().get(self.pos).cloned()
+    }
+
+    fn skip_whitespace(&mut self)
+    {
+        while self.peek() == Some(b' ')
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn definition(&mut self) -> Result<Definition>
+    {
+        self.skip_whitespace();
+        if self.peek() != Some(b'(')
+        {
+            return Err(error(self.pos, "Expected '(' at the start"));
+        }
+        self.pos += 1;
+        self.skip_whitespace();
+
+        let oid = try!(self.word("Expected OID"));
+        let mut fields = Vec::new();
+
+        loop
+        {
+            self.skip_whitespace();
+            match self.peek()
+            {
+                Some(b')') => break,
+                None => return Err(error(self.pos, "Expected ')' at the end")),
+                _ => {},
+            }
+
+            let offset = self.pos;
+            let keyword = try!(self.word("Expected keyword"));
+            let values = if FLAGS.contains(&&keyword[..])
+            {
+                Vec::new()
+            }
+            else
+            {
+                self.skip_whitespace();
+                try!(self.values())
+            };
+
+            fields.push(Field { keyword: keyword, values: values, offset: offset });
+        }
+
+        self.pos += 1;
+        self.skip_whitespace();
+        if self.peek().is_some()
+        {
+            return Err(error(self.pos, "Unexpected characters after ')'"));
+        }
+
+        Ok(Definition { oid: oid, fields: fields })
+    }
+
+    // A single value, or a list of them in parentheses separated by spaces or `$`
+    fn values(&mut self) -> Result<Vec<String>>
+    {
+        match self.peek()
+        {
+            Some(b'(') =>
+            {
+                self.pos += 1;
+                let mut values = Vec::new();
+
+                loop
+                {
+                    self.skip_whitespace();
+                    match self.peek()
+                    {
+                        Some(b')') =>
+                        {
+                            self.pos += 1;
+                            return Ok(values);
+                        },
+                        Some(b'$') => self.pos += 1,
+                        Some(b'\'') => values.push(try!(self.quoted())),
+                        None => return Err(error(self.pos, "Expected ')' after the list")),
+                        _ => values.push(try!(self.word("Expected value"))),
+                    }
+                }
+            },
+            Some(b'\'') => Ok(vec![try!(self.quoted())]),
+            _ => Ok(vec![try!(self.word("Expected value"))]),
+        }
+    }
+
+    // keystring, numericoid, oidlen and the like, up to the next delimiter
+    fn word(&mut self, reason: &'static str) -> Result<String>
+    {
+        let start = self.pos;
+
+        while let Some(byte) = self.peek()
+        {
+            match byte
+            {
+                b' ' | b'(' | b')' | b'\'' | b'$' => break,
+                _ => self.pos += 1,
+            }
+        }
+
+        if start == self.pos
+        {
+            return Err(error(self.pos, reason));
+        }
+
+        // Delimiters are ASCII, so this is on character boundaries
+        Ok(self.input[start..self.pos].to_string())
+    }
+
+    // qdstring = SQUOTE dstring SQUOTE, with `'` and `\` escaped as \27 and \5C
+    fn quoted(&mut self) -> Result<String>
+    {
+        let start = self.pos;
+        self.pos += 1;
+        let mut value = String::new();
+
+        loop
+        {
+            match self.peek()
+            {
+                Some(b'\'') =>
+                {
+                    self.pos += 1;
+                    return Ok(value);
+                },
+                Some(b'\\') =>
+                {
+                    let escape = self.input.get(self.pos + 1..self.pos + 3).unwrap_or("");
+                    if escape == "27"
+                    {
+                        value.push('\'');
+                    }
+                    else if escape.eq_ignore_ascii_case("5C")
+                    {
+                        value.push('\\');
+                    }
+                    else
+                    {
+                        return Err(error(self.pos, "Expected \\27 or \\5C"));
+                    }
+                    self.pos += 3;
+                },
+                Some(_) =>
+                {
+                    let c = self.input[self.pos..].chars().next().unwrap();
+                    value.push(c);
+                    self.pos += c.len_utf8();
+                },
+                None => return Err(error(start, "Expected closing quote")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{is_known_binary, AttributeType, MatchingRule, ObjectClass, ObjectClassKind, Schema, Usage};
+    use error::LDAPError;
+
+    #[test]
+    fn parse_attribute_type()
+    {
+        let at = AttributeType::parse("( 2.5.4.41 NAME 'name' DESC 'RFC4519: common supertype of name attributes' \
+                                       EQUALITY caseIgnoreMatch SUBSTR caseIgnoreSubstringsMatch \
+                                       SYNTAX 1.3.6.1.4.1.1466.115.121.1.15{32768} X-ORIGIN 'RFC 4519' )").unwrap();
+
+        assert_eq!(at.oid, "2.5.4.41");
+        assert_eq!(at.names, vec!["name".to_string()]);
+        assert_eq!(at.desc, Some("RFC4519: common supertype of name attributes".to_string()));
+        assert_eq!(at.equality, Some("caseIgnoreMatch".to_string()));
+        assert_eq!(at.ordering, None);
+        assert_eq!(at.substr, Some("caseIgnoreSubstringsMatch".to_string()));
+        assert_eq!(at.syntax, Some("1.3.6.1.4.1.1466.115.121.1.15".to_string()));
+        assert_eq!(at.syntax_len, Some(32768));
+        assert!(!at.single_value && !at.obsolete);
+        assert_eq!(at.usage, Usage::UserApplications);
+        assert_eq!(at.extensions, vec![("X-ORIGIN".to_string(), vec!["RFC 4519".to_string()])]);
+
+        let at = AttributeType::parse("( 2.5.18.1 NAME ( 'createTimestamp' 'created' ) DESC 'It\\27s quoted' \
+                                       SUP name SINGLE-VALUE NO-USER-MODIFICATION USAGE directoryOperation )").unwrap();
+        assert_eq!(at.names, vec!["createTimestamp".to_string(), "created".to_string()]);
+        assert_eq!(at.desc, Some("It's quoted".to_string()));
+        assert_eq!(at.sup, Some("name".to_string()));
+        assert_eq!(at.syntax, None);
+        assert!(at.single_value && at.no_user_modification);
+        assert_eq!(at.usage, Usage::DirectoryOperation);
+    }
+
+    #[test]
+    fn parse_object_class_and_matching_rule()
+    {
+        let oc = ObjectClass::parse("( 2.5.6.6 NAME 'person' SUP top STRUCTURAL MUST ( sn $ cn ) \
+                                     MAY ( userPassword $ telephoneNumber $ seeAlso $ description ) )").unwrap();
+
+        assert_eq!(oc.names, vec!["person".to_string()]);
+        assert_eq!(oc.sup, vec!["top".to_string()]);
+        assert_eq!(oc.kind, ObjectClassKind::Structural);
+        assert_eq!(oc.must, vec!["sn".to_string(), "cn".to_string()]);
+        assert_eq!(oc.may.len(), 4);
+
+        let oc = ObjectClass::parse("( 2.5.6.0 NAME 'top' ABSTRACT MUST objectClass )").unwrap();
+        assert_eq!(oc.kind, ObjectClassKind::Abstract);
+        assert_eq!(oc.must, vec!["objectClass".to_string()]);
+
+        let mr = MatchingRule::parse("( 2.5.13.2 NAME 'caseIgnoreMatch' SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )").unwrap();
+        assert_eq!(mr.syntax, "1.3.6.1.4.1.1466.115.121.1.15");
+        assert!(MatchingRule::parse("( 2.5.13.2 NAME 'caseIgnoreMatch' )").is_err());
+    }
+
+    #[test]
+    fn binary_attributes()
+    {
+        let parse = |def| AttributeType::parse(def).unwrap();
+        let schema = Schema
+        {
+            attribute_types: vec![
+                parse("( 2.5.4.41 NAME 'name' SYNTAX 1.3.6.1.4.1.1466.115.121.1.15 )"),
+                parse("( 2.5.4.3 NAME ( 'cn' 'commonName' ) SUP name )"),
+                parse("( 0.9.2342.19200300.100.1.60 NAME 'jpegPhoto' SYNTAX 1.3.6.1.4.1.1466.115.121.1.28 )"),
+                parse("( 2.5.4.36 NAME 'userCertificate' SYNTAX 1.3.6.1.4.1.1466.115.121.1.8 )"),
+                parse("( 1.1 NAME 'loop' SUP loop )"),
+            ],
+            ..Schema::default()
+        };
+
+        assert!(schema.is_binary("jpegPhoto"));
+        assert!(schema.is_binary("JPEGPHOTO"));
+        assert!(schema.is_binary("userCertificate;binary"));
+        assert!(schema.is_binary("0.9.2342.19200300.100.1.60"));
+        assert!(!schema.is_binary("name"));
+        // Syntax inherited from the superior type
+        assert!(!schema.is_binary("commonName;lang-de"));
+        assert!(schema.is_binary("cn;binary"));
+        assert!(!schema.is_binary("unknown"));
+        assert!(schema.is_binary("objectGUID"));
+        assert!(!schema.is_binary("loop"));
+    }
+
+    #[test]
+    fn known_binary_attributes()
+    {
+        assert!(is_known_binary("jpegPhoto"));
+        assert!(is_known_binary("objectguid"));
+        assert!(is_known_binary("userCertificate;binary"));
+        assert!(is_known_binary("cn;binary"));
+        assert!(!is_known_binary("cn"));
+        assert!(!is_known_binary("mail;lang-de"));
+    }
+
+    #[test]
+    fn parse_error_messages()
+    {
+        let message = |def| AttributeType::parse(def).unwrap_err().to_string();
+
+        assert_eq!(message("2.5.4.3"), "Error: Expected '(' at the start at byte 0 of schema definition");
+        assert_eq!(message("( 2.5.4.3 NAME 'cn'"), "Error: Expected ')' at the end at byte 19 of schema definition");
+        assert_eq!(message("( 2.5.4.3 NAME 'cn )"), "Error: Expected closing quote at byte 15 of schema definition");
+        assert_eq!(message("( 2.5.4.3 DESC 'a\\zz' )"), "Error: Expected \\27 or \\5C at byte 17 of schema definition");
+        assert_eq!(message("( 2.5.4.3 SYNTAX 1.2{x} )"),
+                   "Error: Expected length in braces after the syntax at byte 10 of schema definition");
+        assert_eq!(message("( 2.5.4.3 USAGE nobody )"), "Error: Unknown USAGE at byte 10 of schema definition");
+        assert_eq!(message("( 2.5.4.3 ) x"), "Error: Unexpected characters after ')' at byte 12 of schema definition");
+        // No value in the list is no USAGE at all
+        assert_eq!(AttributeType::parse("( 2.5.4.3 USAGE ( ) )").unwrap().usage, Usage::UserApplications);
+    }
+
+    #[test]
+    fn check_entry_content_rules()
+    {
+        let schema = Schema
+        {
+            attribute_types: vec![
+                AttributeType::parse("( 2.5.4.3 NAME ( 'cn' 'commonName' ) )").unwrap(),
+                AttributeType::parse("( 2.5.18.1 NAME 'createTimestamp' USAGE directoryOperation )").unwrap(),
+            ],
+            object_classes: vec![
+                ObjectClass::parse("( 2.5.6.0 NAME 'top' ABSTRACT MUST objectClass )").unwrap(),
+                ObjectClass::parse("( 2.5.6.6 NAME 'person' SUP top STRUCTURAL MUST ( sn $ cn ) MAY description )").unwrap(),
+                ObjectClass::parse("( 1.3.6.1.4.1.1466.101.120.111 NAME 'extensibleObject' SUP top AUXILIARY )").unwrap(),
+            ],
+            ..Schema::default()
+        };
+        let rule = |classes: &[&str], attrs: &[&str]| match schema.check_entry(classes, attrs)
+        {
+            Ok(()) => None,
+            Err(LDAPError::ContentRule { attr, reason }) => Some((attr, reason)),
+            Err(x) => panic!("Expected ContentRule error, got {:?}", x),
+        };
+
+        assert_eq!(rule(&["person"], &["objectClass", "commonName", "SN;lang-de", "createTimestamp"]), None);
+        assert_eq!(rule(&["person"], &["objectClass", "cn"]), Some(("sn".to_string(), "Required attribute is missing")));
+        assert_eq!(rule(&["top"], &["objectClass"]), None);
+        assert_eq!(rule(&["person"], &["objectClass", "cn", "sn", "mail"]),
+                   Some(("mail".to_string(), "Attribute not allowed by the object classes")));
+        assert_eq!(rule(&["person", "extensibleObject"], &["objectClass", "cn", "sn", "mail"]), None);
+        assert_eq!(rule(&["people"], &["cn"]), Some(("people".to_string(), "Object class not in the schema")));
+        assert_eq!(rule(&[], &["cn"]), Some(("objectClass".to_string(), "Entry has no object class")));
+
+        let change = |classes: &[&str], added: &[&str], removed: &[&str]| match schema.check_modification(classes, added, removed)
+        {
+            Ok(()) => None,
+            Err(LDAPError::ContentRule { attr, reason }) => Some((attr, reason)),
+            Err(x) => panic!("Expected ContentRule error, got {:?}", x),
+        };
+
+        assert_eq!(change(&["person"], &["description", "commonName"], &["description"]), None);
+        assert_eq!(change(&["person"], &["mail"], &[]), Some(("mail".to_string(), "Attribute not allowed by the object classes")));
+        assert_eq!(change(&["person"], &[], &["commonName"]),
+                   Some(("commonName".to_string(), "Required attribute would be removed")));
+        assert_eq!(change(&["person", "extensibleObject"], &["mail"], &["mail"]), None);
+    }
+}
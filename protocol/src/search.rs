@@ -0,0 +1,323 @@
+//! Search operation (RFC4511 Section 4.5)
+
+use ber::common::{self, Tag};
+use ber::types::{self, ASNType};
+use error::LDAPError;
+use filter::Filter;
+use url::LdapUrl;
+use Result;
+use prelude::*;
+
+/// Scope of a SearchRequest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope
+{
+    /// Only the base object itself
+    BaseObject   = 0,
+    /// Only the immediate children of the base object
+    SingleLevel  = 1,
+    /// The base object and all its descendants
+    WholeSubtree = 2,
+}
+
+/// When the server dereferences alias entries during a search
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DerefAliases
+{
+    /// Never dereference aliases
+    Never          = 0,
+    /// Dereference aliases below the base object, but not the base object itself
+    InSearching    = 1,
+    /// Only dereference the base object
+    FindingBaseObj = 2,
+    /// Dereference aliases both when locating the base object and while searching
+    Always         = 3,
+}
+
+impl DerefAliases
+{
+    /// Map the ENUMERATED value back to its variant, `None` for values RFC4511 doesn't define
+    pub fn from_i64(value: i64) -> Option<DerefAliases>
+    {
+        match value
+        {
+            0 => Some(DerefAliases::Never),
+            1 => Some(DerefAliases::InSearching),
+            2 => Some(DerefAliases::FindingBaseObj),
+            3 => Some(DerefAliases::Always),
+            _ => None,
+        }
+    }
+
+    /// The ENUMERATED value sent for this variant
+    pub fn to_i64(self) -> i64
+    {
+        self as i64
+    }
+}
+
+/// Builder for the parameters of a SearchRequest
+///
+/// Defaults to searching the whole subtree without dereferencing aliases, without size or time
+/// limit and returning attribute values. Only the filter has to be set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchRequestBuilder
+{
+    base: String,
+    scope: Scope,
+    deref: DerefAliases,
+    size_limit: i32,
+    time_limit: i32,
+    types_only: bool,
+    filter: Option<Filter>,
+    attributes: Vec<String>,
+}
+
+impl SearchRequestBuilder
+{
+    /// Start a search below `base`
+    pub fn new(base: &str) -> SearchRequestBuilder
+    {
+        SearchRequestBuilder
+        {
+            base: base.to_string(),
+            scope: Scope::WholeSubtree,
+            deref: DerefAliases::Never,
+            size_limit: 0,
+            time_limit: 0,
+            types_only: false,
+            filter: None,
+            attributes: Vec::new(),
+        }
+    }
+
+    /// Which entries relative to the base to search
+    pub fn scope(mut self, scope: Scope) -> SearchRequestBuilder
+    {
+        self.scope = scope;
+        self
+    }
+
+    /// How to handle alias entries
+    pub fn deref(mut self, deref: DerefAliases) -> SearchRequestBuilder
+    {
+        self.deref = deref;
+        self
+    }
+
+    /// Maximum number of entries to return, 0 for no limit
+    pub fn size_limit(mut self, limit: i32) -> SearchRequestBuilder
+    {
+        self.size_limit = limit;
+        self
+    }
+
+    /// Maximum number of seconds the search may take, 0 for no limit
+    pub fn time_limit(mut self, limit: i32) -> SearchRequestBuilder
+    {
+        self.time_limit = limit;
+        self
+    }
+
+    /// Only return attribute descriptions, not their values
+    pub fn types_only(mut self, types_only: bool) -> SearchRequestBuilder
+    {
+        self.types_only = types_only;
+        self
+    }
+
+    /// Filter the entries have to match
+    pub fn filter(mut self, filter: Filter) -> SearchRequestBuilder
+    {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Attributes to return, all user attributes if left empty
+    pub fn attributes(mut self, attrs: &[&str]) -> SearchRequestBuilder
+    {
+        self.attributes = attrs.iter().map(|attr| attr.to_string()).collect();
+        self
+    }
+
+    /// The same search, sent to where the referral `url` points
+    ///
+    /// The URL's DN replaces the base if it has one. A `continuation` reference from within the
+    /// searched area names an entry already below the base, which a single level search only
+    /// has to look at itself (RFC4511 4.5.3).
+    pub fn referred(&self, url: &LdapUrl, continuation: bool) -> SearchRequestBuilder
+    {
+        let mut request = self.clone();
+
+        if !url.base_dn.is_empty()
+        {
+            request.base = url.base_dn.clone();
+        }
+        if continuation && self.scope == Scope::SingleLevel
+        {
+            request.scope = Scope::BaseObject;
+        }
+
+        request
+    }
+
+    /// Build the [APPLICATION 3] SearchRequest
+    pub fn build(&self) -> Result<Tag>
+    {
+        let filter = match self.filter
+        {
+            Some(ref filter) => filter.encode(),
+            None => return Err(LDAPError::MissingFilter),
+        };
+
+        let basetag = {
+            let class = common::Class::Universal(common::UniversalTypes::OctetString);
+            let pl = common::Payload::Primitive(self.base.as_bytes().to_vec());
+
+            common::construct(class, pl)
+        };
+
+        let scopetag = types::encode_enumerated(self.scope as i64);
+        let dereftag = types::encode_enumerated(self.deref.to_i64());
+
+        let sizetag = self.size_limit.into_ber_universal();
+        let timetag = self.time_limit.into_ber_universal();
+
+        let typesonlytag = {
+            let class = common::Class::Universal(common::UniversalTypes::Boolean);
+            let pl = common::Payload::Primitive(types::encode_boolean(self.types_only));
+
+            common::construct(class, pl)
+        };
+
+        let attrstag = types::sequence_of(&self.attributes);
+
+        let class = common::Class::Application(3);
+        let pl = common::Payload::Constructed(vec![basetag, scopetag, dereftag, sizetag, timetag,
+                                                   typesonlytag, filter, attrstag]);
+
+        Ok(common::construct(class, pl))
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{DerefAliases, Scope, SearchRequestBuilder};
+    use ber::common;
+    use ber;
+    use error::LDAPError;
+    use filter::Filter;
+    use url::LdapUrl;
+
+    #[test]
+    fn build_default_search()
+    {
+        let request = SearchRequestBuilder::new("dc=example")
+            .filter(Filter::Present("objectClass".to_string()))
+            .build()
+            .unwrap();
+        let bytes = ber::encode(request, 1).unwrap();
+
+        let expected = [
+            0x63, 0x2a,
+            // baseObject
+            0x04, 0x0a, 0x64, 0x63, 0x3d, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65,
+            // wholeSubtree, neverDerefAliases, no size and time limit, not typesOnly
+            0x0a, 0x01, 0x02, 0x0a, 0x01, 0x00, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x01, 0x01, 0x00,
+            // (objectClass=*)
+            0x87, 0x0b, 0x6f, 0x62, 0x6a, 0x65, 0x63, 0x74, 0x43, 0x6c, 0x61, 0x73, 0x73,
+            // no attributes
+            0x30, 0x00,
+        ];
+
+        assert!(bytes.ends_with(&expected));
+    }
+
+    #[test]
+    fn build_with_parameters()
+    {
+        let request = SearchRequestBuilder::new("")
+            .scope(Scope::BaseObject)
+            .size_limit(1000)
+            .time_limit(30)
+            .types_only(true)
+            .filter(Filter::Present("objectClass".to_string()))
+            .attributes(&["cn"])
+            .build()
+            .unwrap();
+        let bytes = ber::encode(request, 1).unwrap();
+
+        let expected = [
+            0x63, 0x25,
+            0x04, 0x00,
+            0x0a, 0x01, 0x00, 0x0a, 0x01, 0x00, 0x02, 0x02, 0x03, 0xe8, 0x02, 0x01, 0x1e, 0x01, 0x01, 0xff,
+            0x87, 0x0b, 0x6f, 0x62, 0x6a, 0x65, 0x63, 0x74, 0x43, 0x6c, 0x61, 0x73, 0x73,
+            0x30, 0x04, 0x04, 0x02, 0x63, 0x6e,
+        ];
+
+        assert!(bytes.ends_with(&expected));
+    }
+
+    #[test]
+    fn build_requires_filter()
+    {
+        match SearchRequestBuilder::new("dc=example").build()
+        {
+            Err(LDAPError::MissingFilter) => {},
+            x => panic!("Expected MissingFilter, got {:?}", x),
+        }
+    }
+
+    #[test]
+    fn deref_aliases_encoding()
+    {
+        let variants = [
+            (DerefAliases::Never, 0x00),
+            (DerefAliases::InSearching, 0x01),
+            (DerefAliases::FindingBaseObj, 0x02),
+            (DerefAliases::Always, 0x03),
+        ];
+
+        for &(deref, byte) in &variants
+        {
+            let request = SearchRequestBuilder::new("")
+                .deref(deref)
+                .filter(Filter::Present("objectClass".to_string()))
+                .build()
+                .unwrap();
+
+            let children = match request._value
+            {
+                common::Payload::Constructed(children) => children,
+                common::Payload::Primitive(_) => panic!("Expected a constructed SearchRequest"),
+            };
+
+            assert_eq!(children[2]._type.class, common::Class::Universal(common::UniversalTypes::Enumerated));
+            assert_eq!(children[2]._value, common::Payload::Primitive(vec![byte]));
+            assert_eq!(DerefAliases::from_i64(deref.to_i64()), Some(deref));
+        }
+
+        assert_eq!(DerefAliases::from_i64(4), None);
+    }
+
+    #[test]
+    fn referred_search()
+    {
+        let request = SearchRequestBuilder::new("dc=example")
+            .scope(Scope::SingleLevel)
+            .filter(Filter::Present("cn".to_string()));
+
+        let url = LdapUrl::parse("ldap://other/ou=People,dc=example").unwrap();
+        assert_eq!(request.referred(&url, false), SearchRequestBuilder::new("ou=People,dc=example")
+            .scope(Scope::SingleLevel)
+            .filter(Filter::Present("cn".to_string())));
+        assert_eq!(request.referred(&url, true), SearchRequestBuilder::new("ou=People,dc=example")
+            .scope(Scope::BaseObject)
+            .filter(Filter::Present("cn".to_string())));
+
+        // Without a DN the base stays
+        let url = LdapUrl::parse("ldap://other").unwrap();
+        assert_eq!(request.referred(&url, false), request);
+    }
+}
@@ -0,0 +1,366 @@
+//! LDAP URLs (RFC4516)
+
+use error::LDAPError;
+use search::Scope;
+use Result;
+use prelude::*;
+
+/// A parsed `ldap://host:port/baseDN?attributes?scope?filter?extensions` URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LdapUrl
+{
+    /// Whether the scheme was `ldaps`, so the connection has to use TLS from the start
+    pub ldaps: bool,
+    /// Host name or address, empty if the URL leaves it to the client
+    pub host: String,
+    /// The given port, else 389 for `ldap` and 636 for `ldaps`
+    pub port: u16,
+    /// Base object of the search, empty by default
+    pub base_dn: String,
+    /// Attributes to return, empty for all user attributes
+    pub attributes: Vec<String>,
+    /// Scope of the search, `BaseObject` by default
+    pub scope: Scope,
+    /// String representation of the filter, `(objectClass=*)` by default
+    pub filter: String,
+    /// Extensions, in the order they were given
+    pub extensions: Vec<Extension>,
+}
+
+/// An extension of an LDAP URL, like `!bindname=cn=Manager%2cdc=example`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extension
+{
+    /// Marked with `!`, so the URL must not be used by clients not knowing the extension
+    pub critical: bool,
+    /// Extension type, a name or OID
+    pub name: String,
+    /// Value following the `=`, if any
+    pub value: Option<String>,
+}
+
+impl LdapUrl
+{
+    /// Parse an `ldap://` or `ldaps://` URL
+    ///
+    /// Percent-encoded characters are decoded in every component. Everything after the host
+    /// is optional, and components left out or empty take the defaults RFC4516 gives them.
+    pub fn parse(input: &str) -> Result<LdapUrl>
+    {
+        let (ldaps, start) = if starts_with_ignore_case(input, "ldap://")
+        {
+            (false, 7)
+        }
+        else if starts_with_ignore_case(input, "ldaps://")
+        {
+            (true, 8)
+        }
+        else
+        {
+            return Err(error(0, "Expected ldap:// or ldaps://"));
+        };
+
+        let end = input[start..].find('/').map(|i| start + i).unwrap_or(input.len());
+        let (host, port) = try!(parse_hostport(&input[start..end], start));
+        let port = port.unwrap_or(if ldaps { 636 } else { 389 });
+
+        let mut url = LdapUrl
+        {
+            ldaps: ldaps,
+            host: host,
+            port: port,
+            base_dn: String::new(),
+            attributes: Vec::new(),
+            scope: Scope::BaseObject,
+            filter: "(objectClass=*)".to_string(),
+            extensions: Vec::new(),
+        };
+
+        if end == input.len()
+        {
+            return Ok(url);
+        }
+
+        // Each component with the offset it starts at
+        let mut offset = end + 1;
+        let mut components = Vec::new();
+        for component in input[end + 1..].split('?')
+        {
+            components.push((component, offset));
+            offset += component.len() + 1;
+        }
+        if components.len() > 5
+        {
+            return Err(error(components[5].1 - 1, "Unexpected '?' after the extensions"));
+        }
+
+        let (dn, offset) = components[0];
+        url.base_dn = try!(percent_decode(dn, offset));
+
+        if let Some(&(attrs, offset)) = components.get(1)
+        {
+            if !attrs.is_empty()
+            {
+                url.attributes = try!(split_list(attrs, offset).into_iter()
+                    .map(|(attr, offset)| percent_decode(attr, offset))
+                    .collect());
+            }
+        }
+
+        if let Some(&(scope, offset)) = components.get(2)
+        {
+            url.scope = match &*try!(percent_decode(scope, offset)).to_ascii_lowercase()
+            {
+                "" | "base" => Scope::BaseObject,
+                "one" => Scope::SingleLevel,
+                "sub" => Scope::WholeSubtree,
+                _ => return Err(error(offset, "Expected scope base, one or sub")),
+            };
+        }
+
+        if let Some(&(filter, offset)) = components.get(3)
+        {
+            if !filter.is_empty()
+            {
+                url.filter = try!(percent_decode(filter, offset));
+            }
+        }
+
+        if let Some(&(extensions, offset)) = components.get(4)
+        {
+            for (extension, offset) in split_list(extensions, offset)
+            {
+                url.extensions.push(try!(parse_extension(extension, offset)));
+            }
+        }
+
+        Ok(url)
+    }
+}
+
+fn error(offset: usize, reason: &'static str) -> LDAPError
+{
+    LDAPError::UrlParse { offset: offset, reason: reason }
+}
+
+fn starts_with_ignore_case(input: &str, prefix: &str) -> bool
+{
+    input.len() >= prefix.len() && input.as_bytes()[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes())
+}
+
+// [host [COLON port]], with IPv6 addresses in brackets
+fn parse_hostport(hostport: &str, offset: usize) -> Result<(String, Option<u16>)>
+{
+    let (host, port) = if hostport.starts_with('[')
+    {
+        match hostport.find(']')
+        {
+            Some(close) => (&hostport[1..close], &hostport[close + 1..]),
+            None => return Err(error(offset, "Expected ']' after IPv6 address")),
+        }
+    }
+    else
+    {
+        match hostport.find(':')
+        {
+            Some(colon) => (&hostport[..colon], &hostport[colon..]),
+            None => (hostport, ""),
+        }
+    };
+
+    let port_offset = offset + hostport.len() - port.len();
+    if hostport.contains('?')
+    {
+        return Err(error(offset + hostport.find('?').unwrap(), "Expected '/' before the DN"));
+    }
+
+    let port = if port.is_empty()
+    {
+        None
+    }
+    else if !port.starts_with(':')
+    {
+        return Err(error(port_offset, "Expected ':' before the port"));
+    }
+    else
+    {
+        match port[1..].parse::<u16>()
+        {
+            Ok(port) if port > 0 => Some(port),
+            _ => return Err(error(port_offset + 1, "Expected a port number from 1 to 65535")),
+        }
+    };
+
+    Ok((try!(percent_decode(host, if hostport.starts_with('[') { offset + 1 } else { offset })), port))
+}
+
+// [!]type[=value]
+fn parse_extension(extension: &str, offset: usize) -> Result<Extension>
+{
+    let (critical, extension, offset) = if extension.starts_with('!')
+    {
+        (true, &extension[1..], offset + 1)
+    }
+    else
+    {
+        (false, extension, offset)
+    };
+
+    let (name, value) = match extension.find('=')
+    {
+        Some(eq) => (&extension[..eq], Some((&extension[eq + 1..], offset + eq + 1))),
+        None => (extension, None),
+    };
+
+    if name.is_empty()
+    {
+        return Err(error(offset, "Expected extension type"));
+    }
+
+    Ok(Extension
+    {
+        critical: critical,
+        name: try!(percent_decode(name, offset)),
+        value: match value
+        {
+            Some((value, offset)) => Some(try!(percent_decode(value, offset))),
+            None => None,
+        },
+    })
+}
+
+// Commas inside list items are percent-encoded, so splitting comes before decoding
+fn split_list(list: &str, offset: usize) -> Vec<(&str, usize)>
+{
+    let mut items = Vec::new();
+    let mut offset = offset;
+
+    for item in list.split(',')
+    {
+        items.push((item, offset));
+        offset += item.len() + 1;
+    }
+
+    items
+}
+
+fn percent_decode(input: &str, offset: usize) -> Result<String>
+{
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut pos = 0;
+
+    while pos < bytes.len()
+    {
+        if bytes[pos] == b'%'
+        {
+            match (bytes.get(pos + 1).and_then(hex), bytes.get(pos + 2).and_then(hex))
+            {
+                (Some(high), Some(low)) => decoded.push(high << 4 | low),
+                _ => return Err(error(offset + pos, "Expected two hex digits after '%'")),
+            }
+            pos += 3;
+        }
+        else
+        {
+            decoded.push(bytes[pos]);
+            pos += 1;
+        }
+    }
+
+    String::from_utf8(decoded).map_err(|_| error(offset, "Decoded component is not valid UTF-8"))
+}
+
+fn hex(byte: &u8) -> Option<u8>
+{
+    match *byte
+    {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::{Extension, LdapUrl};
+    use error::LDAPError;
+    use search::Scope;
+
+    fn parse_error(url: &str) -> (usize, &'static str)
+    {
+        match LdapUrl::parse(url)
+        {
+            Err(LDAPError::UrlParse { offset, reason }) => (offset, reason),
+            x => panic!("Expected UrlParse for {}, got {:?}", url, x),
+        }
+    }
+
+    #[test]
+    fn parse_full_url()
+    {
+        let url = LdapUrl::parse("ldap://ldap.example.com:3389/ou=People,dc=example,dc=com?cn,mail?sub?(uid=j%c3%bcrgen)?!bindname=cn=Manager%2cdc=example,x-ext").unwrap();
+
+        assert_eq!(url, LdapUrl
+        {
+            ldaps: false,
+            host: "ldap.example.com".to_string(),
+            port: 3389,
+            base_dn: "ou=People,dc=example,dc=com".to_string(),
+            attributes: vec!["cn".to_string(), "mail".to_string()],
+            scope: Scope::WholeSubtree,
+            filter: "(uid=jürgen)".to_string(),
+            extensions: vec![
+                Extension { critical: true, name: "bindname".to_string(), value: Some("cn=Manager,dc=example".to_string()) },
+                Extension { critical: false, name: "x-ext".to_string(), value: None },
+            ],
+        });
+    }
+
+    #[test]
+    fn parse_minimal_url()
+    {
+        let url = LdapUrl::parse("ldap://host").unwrap();
+
+        assert_eq!((url.ldaps, &*url.host, url.port), (false, "host", 389));
+        assert_eq!(url.base_dn, "");
+        assert!(url.attributes.is_empty());
+        assert_eq!(url.scope, Scope::BaseObject);
+        assert_eq!(url.filter, "(objectClass=*)");
+        assert!(url.extensions.is_empty());
+
+        // Empty components take the defaults as well
+        assert_eq!(LdapUrl::parse("ldap://host/??").unwrap(), url);
+    }
+
+    #[test]
+    fn parse_ldaps_and_addresses()
+    {
+        let url = LdapUrl::parse("LDAPS://[2001:db8::1]/dc=example?objectClass?one").unwrap();
+        assert_eq!((url.ldaps, &*url.host, url.port), (true, "2001:db8::1", 636));
+        assert_eq!(url.scope, Scope::SingleLevel);
+
+        let url = LdapUrl::parse("ldaps://[::1]:1636").unwrap();
+        assert_eq!((&*url.host, url.port), ("::1", 1636));
+
+        // No host at all leaves it to the client
+        let url = LdapUrl::parse("ldap:///dc=example").unwrap();
+        assert_eq!((&*url.host, &*url.base_dn), ("", "dc=example"));
+    }
+
+    #[test]
+    fn parse_errors_report_offset()
+    {
+        assert_eq!(parse_error("http://host"), (0, "Expected ldap:// or ldaps://"));
+        assert_eq!(parse_error("ldap://host:x"), (12, "Expected a port number from 1 to 65535"));
+        assert_eq!(parse_error("ldap://host:70000"), (12, "Expected a port number from 1 to 65535"));
+        assert_eq!(parse_error("ldap://[::1"), (7, "Expected ']' after IPv6 address"));
+        assert_eq!(parse_error("ldap://host?cn"), (11, "Expected '/' before the DN"));
+        assert_eq!(parse_error("ldap://host/dc=x?cn?tree"), (20, "Expected scope base, one or sub"));
+        assert_eq!(parse_error("ldap://host/dc=%zz"), (15, "Expected two hex digits after '%'"));
+        assert_eq!(parse_error("ldap://host/????=x"), (16, "Expected extension type"));
+        assert_eq!(parse_error("ldap://host/?????"), (16, "Unexpected '?' after the extensions"));
+    }
+}